@@ -0,0 +1,6 @@
+// aborts, for testing a program killed by a signal
+
+fn main() {
+    println!("aborting");
+    std::process::abort();
+}
@@ -0,0 +1,21 @@
+//! Leave packed floats in a ymm register, to test showing vector registers.
+
+use std::arch::asm;
+
+#[target_feature(enable = "avx")]
+unsafe fn load_ymm15(vals: &[f32; 8]) {
+    asm!(
+        "vmovups ymm15, [{vals}]",
+        vals = in(reg) vals.as_ptr(),
+        out("ymm15") _,
+    );
+}
+
+fn main() {
+    if !is_x86_feature_detected!("avx") {
+        return;
+    }
+    let vals = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.5];
+    unsafe { load_ymm15(&vals) };
+    std::hint::black_box(());
+}
@@ -0,0 +1,45 @@
+//! Overwrite callee-saved registers in a function that saves them, to test
+//! restoring them when returning from it early.
+
+use std::arch::asm;
+
+#[no_mangle]
+#[inline(never)]
+extern "C" fn stop_here() {}
+
+/// Overwrite r12-r15, which the compiler saves in the prologue (and says
+/// where in eh_frame) and restores before returning.
+#[no_mangle]
+#[inline(never)]
+extern "C" fn clobber() {
+    unsafe {
+        asm!(
+            "mov r12, 0xdead",
+            "mov r13, 0xdead",
+            "mov r14, 0xdead",
+            "mov r15, 0xdead",
+            out("r12") _,
+            out("r13") _,
+            out("r14") _,
+            out("r15") _,
+        )
+    };
+    stop_here();
+}
+
+fn main() {
+    let (r12, r13, r14, r15): (u64, u64, u64, u64);
+    // call through asm so the registers' values around the call are known
+    unsafe {
+        asm!(
+            "call {clobber}",
+            clobber = sym clobber,
+            inout("r12") 0x12u64 => r12,
+            inout("r13") 0x13u64 => r13,
+            inout("r14") 0x14u64 => r14,
+            inout("r15") 0x15u64 => r15,
+            clobber_abi("C"),
+        )
+    };
+    println!("r12 = {r12:#x}, r13 = {r13:#x}, r14 = {r14:#x}, r15 = {r15:#x}");
+}
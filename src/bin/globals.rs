@@ -0,0 +1,18 @@
+// has global variables, for testing printing them
+
+static mut COUNTER: u32 = 41;
+static ENABLED: bool = true;
+
+fn main() {
+    unsafe { COUNTER += 1 };
+    if std::hint::black_box(ENABLED) {
+        println!("counter is {}", unsafe { COUNTER });
+    }
+}
+
+mod other {
+    // a global with the same name as the one above, which isn't otherwise
+    // used
+    #[used]
+    pub static ENABLED: bool = false;
+}
@@ -0,0 +1,16 @@
+//! Calls a function that is always inlined, to test backtraces through
+//! inlined frames.
+
+#[inline(always)]
+fn double(x: u64) -> u64 {
+    std::hint::black_box(x) * 2
+}
+
+#[inline(never)]
+fn compute(x: u64) -> u64 {
+    double(x) + 1
+}
+
+fn main() {
+    println!("{}", compute(std::hint::black_box(20)));
+}
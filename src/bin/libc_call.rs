@@ -0,0 +1,6 @@
+// calls into libc, which has no debug info, for testing stepping over it
+
+fn main() {
+    let pid = unsafe { libc::getpid() };
+    println!("pid {pid}");
+}
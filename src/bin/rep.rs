@@ -0,0 +1,20 @@
+//! Run a rep-prefixed string instruction, to test stepping over it.
+
+use std::arch::asm;
+
+// the label is only defined once, so it can be used to find the instruction
+#[allow(named_asm_labels)]
+fn main() {
+    let mut buf = [0u8; 64];
+    unsafe {
+        asm!(
+            ".globl rep_stos",
+            "rep_stos:",
+            "rep stosb",
+            inout("rcx") buf.len() => _,
+            inout("rdi") buf.as_mut_ptr() => _,
+            in("al") 0xaau8,
+        )
+    };
+    std::hint::black_box(&buf);
+}
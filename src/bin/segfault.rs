@@ -0,0 +1,6 @@
+// writes to an unmapped address, for testing segfault reports
+
+fn main() {
+    let bad = std::hint::black_box(0x10 as *mut u8);
+    unsafe { *bad = 1 };
+}
@@ -0,0 +1,21 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// raises a signal with a handler, for testing signal delivery
+
+static HANDLED: AtomicBool = AtomicBool::new(false);
+
+#[no_mangle]
+extern "C" fn handle_usr1(_signal: libc::c_int) {
+    HANDLED.store(true, Ordering::SeqCst);
+}
+
+fn main() {
+    unsafe {
+        libc::signal(
+            libc::SIGUSR1,
+            handle_usr1 as *const () as libc::sighandler_t,
+        );
+        libc::raise(libc::SIGUSR1);
+    }
+    println!("handled: {}", HANDLED.load(Ordering::SeqCst));
+}
@@ -0,0 +1,8 @@
+use std::{thread, time::Duration};
+
+// runs until interrupted, for testing Ctrl-C handling
+fn main() {
+    loop {
+        thread::sleep(Duration::from_millis(10));
+    }
+}
@@ -33,4 +33,58 @@ fn main() {
     use_vars();
     greeting();
     call_little_functions();
+    use_enums();
+    use_struct(countdown(3));
+    use_collections();
+}
+
+#[no_mangle]
+#[allow(unused_variables)]
+fn use_enums() {
+    let some_box: Option<Box<u64>> = Some(Box::new(7));
+    let none_box: Option<Box<u64>> = None;
+    let some_int: Option<u32> = Some(5);
+    let res: Result<u8, i16> = Err(-3);
+    let ordering = std::cmp::Ordering::Greater;
+    std::hint::black_box(ordering);
+}
+
+#[no_mangle]
+fn countdown(n: u64) -> u64 {
+    if n == 0 {
+        return 0;
+    }
+    let rest = countdown(n - 1);
+    rest + 1
+}
+
+#[allow(dead_code)]
+struct Point {
+    x: i32,
+    y: i32,
+}
+
+#[no_mangle]
+fn use_struct(n: u64) {
+    let p = Point {
+        x: identity(n as i32),
+        y: identity(-4i64) as i32,
+    };
+    let pp = &p;
+    std::hint::black_box(pp);
+    std::hint::black_box(());
+}
+
+#[inline(never)]
+fn identity<T>(x: T) -> T {
+    x
+}
+
+#[no_mangle]
+fn use_collections() {
+    let arr = [1u16, 2, 3];
+    let slice: &[u16] = &arr[1..];
+    let s = "hi";
+    std::hint::black_box((slice, s));
+    std::hint::black_box(());
 }
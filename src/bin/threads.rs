@@ -0,0 +1,40 @@
+use std::{
+    ptr::{self, addr_of, addr_of_mut},
+    thread,
+    time::Duration,
+};
+
+// a thread counts while the main thread sleeps, for testing how threads are
+// stopped and resumed
+
+static mut COUNT: u64 = 0;
+
+#[inline(never)]
+fn count() {
+    unsafe {
+        let count = ptr::read_volatile(addr_of!(COUNT));
+        ptr::write_volatile(addr_of_mut!(COUNT), count + 1);
+    }
+}
+
+fn read_count() -> u64 {
+    unsafe { ptr::read_volatile(addr_of!(COUNT)) }
+}
+
+/// How much the other thread counts while this one sleeps.
+#[inline(never)]
+fn count_while_sleeping() -> u64 {
+    let before = read_count();
+    thread::sleep(Duration::from_millis(10));
+    thread::sleep(Duration::from_millis(10));
+    read_count() - before
+}
+
+fn main() {
+    thread::spawn(|| loop {
+        count();
+    });
+    while read_count() == 0 {}
+    let counted = count_while_sleeping();
+    println!("counted {counted} while sleeping");
+}
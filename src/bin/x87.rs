@@ -0,0 +1,25 @@
+//! Leave values on the x87 register stack, to test showing the registers.
+
+use std::arch::asm;
+
+fn main() {
+    unsafe {
+        asm!(
+            "fldz",
+            "fld1",
+            "fldpi",
+            out("st(0)") _, out("st(1)") _, out("st(2)") _, out("st(3)") _,
+            out("st(4)") _, out("st(5)") _, out("st(6)") _, out("st(7)") _,
+        )
+    };
+    std::hint::black_box(());
+    unsafe {
+        asm!(
+            "fstp st(0)",
+            "fstp st(0)",
+            "fstp st(0)",
+            out("st(0)") _, out("st(1)") _, out("st(2)") _, out("st(3)") _,
+            out("st(4)") _, out("st(5)") _, out("st(6)") _, out("st(7)") _,
+        )
+    };
+}
@@ -1,13 +1,28 @@
-use clap::{IntoApp, Parser, Subcommand};
+use std::{env, ffi::OsString, iter, path::PathBuf};
 
-use crate::ptrace::Reg;
+use clap::{ArgAction, IntoApp, Parser, Subcommand};
+
+use crate::{
+    format::{Format, MemFormat, RegFormat},
+    fpregs::FpReg,
+    ptrace::Reg,
+    vecregs::{LaneFormat, VecReg},
+};
 
 fn parse_reg(s: &str) -> Result<Reg, String> {
     s.try_into()
 }
 
+fn parse_on_off(s: &str) -> Result<bool, String> {
+    match s {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err("expected on or off".to_string()),
+    }
+}
+
 #[allow(clippy::from_str_radix_10)]
-fn maybe_hex(s: &str) -> Result<u64, String> {
+pub fn maybe_hex(s: &str) -> Result<u64, String> {
     let r = if let Some(s) = s.strip_prefix("0x") {
         u64::from_str_radix(s, 16)
     } else if let Some(s) = s.strip_prefix("0b") {
@@ -55,6 +70,184 @@ impl BreakpointLoc {
     }
 }
 
+/// A comparison operator in a breakpoint condition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CmpOp {
+    // two-character operators come first so `<=` isn't parsed as `<`
+    const ALL: [(&'static str, CmpOp); 6] = [
+        ("==", CmpOp::Eq),
+        ("!=", CmpOp::Ne),
+        ("<=", CmpOp::Le),
+        (">=", CmpOp::Ge),
+        ("<", CmpOp::Lt),
+        (">", CmpOp::Gt),
+    ];
+
+    fn symbol(&self) -> &'static str {
+        Self::ALL.iter().find(|(_, op)| op == self).unwrap().0
+    }
+}
+
+/// A breakpoint condition comparing a register to an integer, like `rdi > 4`.
+///
+/// The comparison is signed, so `rax < 0` works as expected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub reg: Reg,
+    pub op: CmpOp,
+    pub val: i64,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (lhs, op, rhs) = CmpOp::ALL
+            .iter()
+            .find_map(|&(sym, op)| {
+                let (lhs, rhs) = text.split_once(sym)?;
+                Some((lhs, op, rhs))
+            })
+            .ok_or_else(|| format!("expected a comparison like 'rax == 0', got '{text}'"))?;
+        let lhs = lhs.trim();
+        let reg = parse_reg(lhs.strip_prefix('$').unwrap_or(lhs))?;
+        let rhs = rhs.trim();
+        let val = match rhs.strip_prefix('-') {
+            Some(num) => (maybe_hex(num)? as i64).wrapping_neg(),
+            None => maybe_hex(rhs)? as i64,
+        };
+        Ok(Self { reg, op, val })
+    }
+
+    /// Parse the words after `break LOC`, which are either nothing or `if
+    /// COND`.
+    pub fn parse_if(words: &[String]) -> Result<Option<Self>, String> {
+        match words.split_first() {
+            None => Ok(None),
+            Some((first, cond)) if first == "if" && !cond.is_empty() => {
+                Self::parse(&cond.join(" ")).map(Some)
+            }
+            Some(_) => Err("expected 'if CONDITION' after the breakpoint location".to_string()),
+        }
+    }
+
+    /// Check the condition given the register's value.
+    pub fn holds(&self, reg_val: u64) -> bool {
+        let x = reg_val as i64;
+        match self.op {
+            CmpOp::Eq => x == self.val,
+            CmpOp::Ne => x != self.val,
+            CmpOp::Lt => x < self.val,
+            CmpOp::Le => x <= self.val,
+            CmpOp::Gt => x > self.val,
+            CmpOp::Ge => x >= self.val,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        format!("{} {} {}", self.reg.name(), self.op.symbol(), self.val)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterGroup {
+    General,
+    Float,
+    Vector,
+    Debug,
+    /// a single vector register
+    VecReg(VecReg),
+    /// a single floating-point register
+    FpReg(FpReg),
+}
+
+impl RegisterGroup {
+    fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "general" => Ok(Self::General),
+            "float" => Ok(Self::Float),
+            "vector" => Ok(Self::Vector),
+            "debug" => Ok(Self::Debug),
+            _ => VecReg::parse(value)
+                .map(Self::VecReg)
+                .or_else(|| FpReg::parse(value).map(Self::FpReg))
+                .ok_or_else(|| {
+                    format!(
+                        "unknown register group '{value}' (expected general, float, vector, debug, or a vector or floating-point register)"
+                    )
+                }),
+        }
+    }
+}
+
+/// A change to how the debugger handles a signal, like gdb's `handle`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalAction {
+    /// stop and report when the target gets the signal
+    Stop,
+    /// let the target keep running
+    Nostop,
+    /// deliver the signal to the target
+    Pass,
+    /// discard the signal
+    Nopass,
+}
+
+impl SignalAction {
+    fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "stop" => Ok(Self::Stop),
+            "nostop" => Ok(Self::Nostop),
+            "pass" => Ok(Self::Pass),
+            "nopass" => Ok(Self::Nopass),
+            _ => Err(format!(
+                "unknown signal action '{value}' (expected stop, nostop, pass, or nopass)"
+            )),
+        }
+    }
+}
+
+/// Which threads may run while the debugger resumes one thread, like gdb's
+/// scheduler-locking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedulerLocking {
+    /// all threads run
+    Off,
+    /// only the current thread runs
+    On,
+    /// only the current thread runs while stepping, but all threads run on
+    /// continue
+    Step,
+}
+
+impl SchedulerLocking {
+    fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "off" => Ok(Self::Off),
+            "on" => Ok(Self::On),
+            "step" => Ok(Self::Step),
+            _ => Err(format!(
+                "unknown scheduler-locking mode '{value}' (expected off, on, or step)"
+            )),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::On => "on",
+            Self::Step => "step",
+        }
+    }
+}
+
 #[derive(Subcommand)]
 pub enum Command {
     /// continue executing target
@@ -65,36 +258,254 @@ pub enum Command {
     Break {
         #[clap(value_parser = BreakpointLoc::parse)]
         loc: BreakpointLoc,
+        /// `if REG OP NUM` to only stop when the condition holds
+        #[clap(value_parser, multiple_values = true)]
+        cond: Vec<String>,
+    },
+    /// set a temporary breakpoint, which is deleted the first time it's hit
+    #[clap(alias = "tb")]
+    Tbreak {
+        #[clap(value_parser = BreakpointLoc::parse)]
+        loc: BreakpointLoc,
+    },
+    /// stop at the breakpoint at an address only when a register comparison
+    /// (like `rdi > 4`) holds, or always if there's no condition
+    Condition {
+        #[clap(value_parser = maybe_hex)]
+        pc: u64,
+        #[clap(value_parser, multiple_values = true)]
+        cond: Vec<String>,
     },
-    /// delete a breakpoint
+    /// pass the breakpoint at an address COUNT times before stopping at it
+    Ignore {
+        #[clap(value_parser = maybe_hex)]
+        pc: u64,
+        #[clap(value_parser)]
+        count: u64,
+    },
+    /// change whether a signal (like SIGUSR1, USR1, or 10) stops the target and
+    /// whether it is delivered, or show the current handling
+    Handle {
+        #[clap(value_parser)]
+        signal: String,
+        #[clap(value_parser = SignalAction::parse, multiple_values = true)]
+        actions: Vec<SignalAction>,
+    },
+    /// disable the breakpoint at an address, keeping it listed
     Disable {
         #[clap(value_parser = maybe_hex)]
         pc: u64,
     },
+    /// re-enable the disabled breakpoint at an address
+    Enable {
+        #[clap(value_parser = maybe_hex)]
+        pc: u64,
+    },
+    /// delete the breakpoint at an address
+    #[clap(alias = "d")]
+    Delete {
+        #[clap(value_parser = maybe_hex)]
+        pc: u64,
+    },
     /// interact with registers
     #[clap(subcommand)]
     #[clap(alias = "reg")]
     Register(RegisterCommand),
-    /// step over a single instruction
-    Stepi,
+    /// step over a single instruction (or COUNT instructions)
+    Stepi {
+        #[clap(value_parser, default_value_t = 1)]
+        count: usize,
+    },
+    /// step over a single instruction, running a call until it returns
+    #[clap(alias = "ni")]
+    Nexti,
+    /// run until the next taken branch (jump, call, or return)
+    #[clap(alias = "sb")]
+    StepBranch,
     /// step out of the current function
     Finish,
-    /// step into the next function
+    /// return from the selected frame now, without running the rest of the
+    /// function, optionally returning VALUE
+    Return {
+        #[clap(value_parser = maybe_hex)]
+        value: Option<u64>,
+    },
+    /// step into the next function (COUNT times)
     #[clap(alias = "s")]
-    Step,
-    /// step over the next source line
-    Next,
-    /// lookup a symbol
+    Step {
+        #[clap(value_parser, default_value_t = 1)]
+        count: usize,
+    },
+    /// step over the next source line (or COUNT lines)
+    Next {
+        #[clap(value_parser, default_value_t = 1)]
+        count: usize,
+    },
+    /// disassemble the function containing a location (by default, the
+    /// current pc)
+    Disassemble {
+        #[clap(value_parser = BreakpointLoc::parse)]
+        loc: Option<BreakpointLoc>,
+    },
+    /// lookup a symbol by its exact name (or with -r, every symbol whose
+    /// name matches a regex)
     Symbol {
+        #[clap(short, long)]
+        regex: bool,
         #[clap(value_parser)]
         name: String,
     },
+    /// print the value of a variable or expression (optionally cast to a type
+    /// with (TYPE)EXPR or (*TYPE)ADDR), saving it in the history as $N; `print
+    /// str ADDR` prints the NUL-terminated string at ADDR
+    #[clap(alias = "p")]
+    Print {
+        #[clap(value_parser, required = true, multiple_values = true)]
+        expr: Vec<String>,
+    },
+    /// print the type of a variable or expression, without evaluating it
+    Whatis {
+        #[clap(value_parser, required = true, multiple_values = true)]
+        expr: Vec<String>,
+    },
+    /// print the type of a variable or expression, expanding the members of
+    /// structs and enums
+    Ptype {
+        #[clap(value_parser, required = true, multiple_values = true)]
+        expr: Vec<String>,
+    },
     /// print a backtrace
     #[clap(alias = "bt")]
-    Backtrace,
+    Backtrace {
+        /// only print the innermost LIMIT frames
+        #[clap(value_parser)]
+        limit: Option<usize>,
+        /// compare unwinding with frame pointers and with eh_frame
+        #[clap(long, hide = true)]
+        compare: bool,
+    },
+    /// select a frame by its number in the backtrace (from 1, the innermost
+    /// frame), for printing variables and registers; with no number, show
+    /// the selected frame
+    #[clap(alias = "f")]
+    Frame {
+        #[clap(value_parser)]
+        num: Option<usize>,
+    },
+    /// select the caller of the selected frame (or COUNT levels out)
+    Up {
+        #[clap(value_parser, default_value_t = 1)]
+        count: usize,
+    },
+    /// select the frame called by the selected frame (or COUNT levels in)
+    Down {
+        #[clap(value_parser, default_value_t = 1)]
+        count: usize,
+    },
+    /// list source around a line (FILE:LINE) or function, or continue the
+    /// last listing
+    #[clap(alias = "l")]
+    List {
+        #[clap(value_parser = BreakpointLoc::parse)]
+        loc: Option<BreakpointLoc>,
+    },
+    /// add a directory to search for source files
+    #[clap(alias = "dir")]
+    Directory {
+        #[clap(value_parser)]
+        dir: PathBuf,
+    },
+    /// print an expression each time the program stops (display/FMT EXPR)
+    Display {
+        #[clap(short, long, value_parser = Format::parse)]
+        format: Option<Format>,
+        #[clap(value_parser, multiple_values = true, allow_hyphen_values = true)]
+        expr: Vec<String>,
+    },
+    /// print memory at an address (x/FMT ADDR), where FMT is a count, a
+    /// format (x, d, or c), and a unit size (b, h, w, or g), like x/16xb
+    #[clap(alias = "x")]
+    Examine {
+        #[clap(short, long, value_parser = MemFormat::parse)]
+        format: Option<MemFormat>,
+        #[clap(
+            value_parser,
+            required = true,
+            multiple_values = true,
+            allow_hyphen_values = true
+        )]
+        addr: Vec<String>,
+    },
+    /// stop displaying an expression
+    Undisplay {
+        #[clap(value_parser)]
+        num: usize,
+    },
+    /// stop when a variable (or a field, deref, or element like `p.x`,
+    /// `*ptr`, or `arr[i]`, or the address an expression evaluates to) is
+    /// written, or with `watch -sw ADDR LEN` when any of LEN bytes at ADDR
+    /// change, by single-stepping (slow, but not limited in size)
+    Watch {
+        #[clap(
+            value_parser,
+            required = true,
+            multiple_values = true,
+            allow_hyphen_values = true
+        )]
+        expr: Vec<String>,
+    },
+    /// stop when a variable (or the address an expression evaluates to) is
+    /// read
+    Rwatch {
+        #[clap(value_parser, required = true, multiple_values = true)]
+        expr: Vec<String>,
+    },
+    /// stop when a variable (or the address an expression evaluates to) is
+    /// read or written
+    Awatch {
+        #[clap(value_parser, required = true, multiple_values = true)]
+        expr: Vec<String>,
+    },
+    /// delete a watchpoint
+    Unwatch {
+        #[clap(value_parser)]
+        num: usize,
+    },
+    /// save target memory to a file
+    #[clap(subcommand)]
+    Dump(DumpCommand),
+    /// load a file's contents into target memory at ADDR
+    Restore {
+        #[clap(value_parser)]
+        file: PathBuf,
+        #[clap(value_parser)]
+        addr: String,
+    },
+    /// write a 64-bit VALUE (or with --bytes, a sequence of bytes) to target
+    /// memory at ADDR
+    WriteMem {
+        /// write each value as a byte
+        #[clap(short, long)]
+        bytes: bool,
+        #[clap(value_parser)]
+        addr: String,
+        #[clap(value_parser = maybe_hex, required = true, multiple_values = true)]
+        values: Vec<u64>,
+    },
     /// get information
     #[clap(subcommand)]
     Info(InfoCommand),
+    /// change debugger settings
+    #[clap(subcommand)]
+    Set(SetCommand),
+    /// show debugger settings
+    #[clap(subcommand)]
+    Show(ShowCommand),
+    /// kill the program and run it again from the start, keeping breakpoints
+    #[clap(aliases = &["r", "restart"])]
+    Run,
+    /// stop debugging the program and let it keep running
+    Detach,
     /// exit debugger
     #[clap(alias = "q")]
     Quit,
@@ -105,12 +516,24 @@ pub enum Command {
 
 #[derive(Subcommand)]
 pub enum RegisterCommand {
-    /// print values of all registers
-    Dump,
+    /// print values of all registers (or with --fp, the floating-point
+    /// registers)
+    Dump {
+        #[clap(long)]
+        fp: bool,
+        /// show values in hex (x), unsigned decimal (d), signed decimal (s),
+        /// or binary (b)
+        #[clap(short, long, value_parser = RegFormat::parse)]
+        format: Option<RegFormat>,
+    },
     /// get register value
     Read {
         #[clap(value_parser = parse_reg)]
         reg: Reg,
+        /// show the value in hex (x), unsigned decimal (d), signed decimal
+        /// (s), or binary (b)
+        #[clap(short, long, value_parser = RegFormat::parse)]
+        format: Option<RegFormat>,
     },
     /// set register value
     Write {
@@ -127,14 +550,364 @@ pub enum InfoCommand {
     Breakpoints,
     /// synonym for backtrace command
     Backtrace,
+    /// show the floating-point status flags
+    Float,
+    /// show the vector registers (ymm with AVX, otherwise xmm) in hex and as
+    /// packed floats and doubles
+    Vector,
+    /// print the values of the variables in scope, other than arguments
+    Locals,
+    /// print the values of the current function's arguments
+    Args,
+    /// list the functions in the program, optionally only those whose names
+    /// match a regex
+    Functions {
+        #[clap(value_parser)]
+        pattern: Option<String>,
+    },
+    /// list the variables in scope at a location (like a breakpoint location)
+    Scope {
+        #[clap(value_parser = BreakpointLoc::parse)]
+        loc: BreakpointLoc,
+    },
+    /// show which memory mapping an address is in
+    Mapping {
+        #[clap(value_parser, required = true, multiple_values = true)]
+        expr: Vec<String>,
+    },
+    /// print file:line:function for each address, from the arguments or a file
+    /// with one address per line
+    LineForAddress {
+        #[clap(value_parser = maybe_hex, multiple_values = true)]
+        addrs: Vec<u64>,
+        #[clap(long, value_parser)]
+        file: Option<PathBuf>,
+    },
+    /// print the registers in a group (general, float, vector, or debug)
+    Registers {
+        #[clap(value_parser = RegisterGroup::parse)]
+        group: Option<RegisterGroup>,
+        /// how to split vector registers into lanes (/x, /b, /h, /w, /g, /f,
+        /// or /d)
+        #[clap(value_parser = LaneFormat::parse)]
+        lanes: Option<LaneFormat>,
+    },
+    /// print all the registers, including the debug registers
+    AllRegisters,
+    /// show information from /proc about the program's process
+    #[clap(subcommand)]
+    Proc(ProcCommand),
+    /// show the program being debugged
+    Target,
+    /// list the expressions to display when the program stops
+    Display,
+}
+
+#[derive(Subcommand)]
+pub enum ProcCommand {
+    /// show the program's command line, as it was executed
+    Cmdline,
+}
+
+#[derive(Subcommand)]
+pub enum DumpCommand {
+    /// save the memory in [START, END) to FILE
+    Memory {
+        #[clap(value_parser)]
+        file: PathBuf,
+        #[clap(value_parser)]
+        start: String,
+        #[clap(value_parser)]
+        end: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SetCommand {
+    /// set the program's arguments for the next run (parsed like a shell)
+    Args {
+        #[clap(value_parser, multiple_values = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// copy the program's output to a file (default rdb.log)
+    Logging {
+        #[clap(value_parser = parse_on_off, action = ArgAction::Set)]
+        on: bool,
+        #[clap(value_parser)]
+        file: Option<PathBuf>,
+    },
+    /// control whether other threads run while stepping (off, on, or step)
+    SchedulerLocking {
+        #[clap(value_parser = SchedulerLocking::parse)]
+        mode: SchedulerLocking,
+    },
+    /// stop for a key after each screenful of output (on or off)
+    Pagination {
+        #[clap(value_parser = parse_on_off, action = ArgAction::Set)]
+        on: bool,
+    },
+    /// show the instruction at the pc whenever the program stops (on or off)
+    DisassembleNextLine {
+        #[clap(value_parser = parse_on_off, action = ArgAction::Set)]
+        on: bool,
+    },
+    /// look for source files under TO instead of FROM
+    SubstitutePath {
+        #[clap(value_parser)]
+        from: String,
+        #[clap(value_parser)]
+        to: String,
+    },
+    /// set how many lines of source are shown around the current line
+    Listsize {
+        #[clap(value_parser)]
+        n: usize,
+    },
+    /// set a convenience variable with `set $NAME = EXPR`, or a program
+    /// variable with `set var NAME = EXPR`
+    #[clap(external_subcommand)]
+    Var(Vec<String>),
+}
+
+#[derive(Subcommand)]
+pub enum ShowCommand {
+    /// show the program's arguments
+    Args,
+    /// show where the program's output is being logged
+    Logging,
+    /// show the scheduler-locking mode
+    SchedulerLocking,
+    /// show whether long output is paged
+    Pagination,
+    /// show whether the instruction at the pc is shown on each stop
+    DisassembleNextLine,
+    /// show how many lines of source are shown around the current line
+    Listsize,
+    /// show the rewrites for source paths
+    SubstitutePath,
+    /// show the directories searched for source files
+    Directories,
+}
+
+/// Expand `$VAR` and `${VAR}` references to environment variables.
+///
+/// Follows shell rules for where expansion happens: nothing is expanded inside
+/// single quotes or after a backslash. The expanded values are quoted so they
+/// always form (part of) a single word when the result is split.
+fn expand_env_vars(s: &str) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars().peekable();
+    let mut in_single = false;
+    let mut in_double = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' if !in_double => in_single = !in_single,
+            '"' if !in_single => in_double = !in_double,
+            '\\' if !in_single => {
+                out.push(c);
+                if let Some(c) = chars.next() {
+                    out.push(c);
+                }
+                continue;
+            }
+            '$' if !in_single => {
+                let braced = chars.next_if_eq(&'{').is_some();
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    name.push(c);
+                }
+                if braced && chars.next_if_eq(&'}').is_none() {
+                    // not a well-formed reference, leave it alone
+                    out.push_str("${");
+                    out.push_str(&name);
+                    continue;
+                }
+                if name.is_empty() {
+                    out.push('$');
+                    continue;
+                }
+                let val = env::var(&name).unwrap_or_default();
+                if in_double {
+                    for c in val.chars() {
+                        if matches!(c, '"' | '\\' | '$' | '`') {
+                            out.push('\\');
+                        }
+                        out.push(c);
+                    }
+                } else {
+                    out.push_str(&shlex::try_quote(&val).unwrap_or_default());
+                }
+                continue;
+            }
+            _ => {}
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Parse the words of a `set args` command into program arguments.
+///
+/// The words are re-joined and then tokenized with shell quoting rules, after
+/// expanding environment variables.
+pub fn parse_target_args(words: &[String]) -> Result<Vec<OsString>, String> {
+    let line = expand_env_vars(&words.join(" "));
+    let args = shlex::split(&line).ok_or_else(|| "unterminated quote in arguments".to_string())?;
+    Ok(args.into_iter().map(OsString::from).collect())
+}
+
+impl Command {
+    /// Whether pressing Enter on an empty line should run this command again.
+    /// Commands that end the session, restart it, or would create duplicates
+    /// or clobber data when repeated are not repeated.
+    pub fn is_repeatable(&self) -> bool {
+        !matches!(
+            self,
+            Command::Quit
+                | Command::Detach
+                | Command::Run
+                | Command::Return { .. }
+                | Command::Break { .. }
+                | Command::Tbreak { .. }
+                | Command::Delete { .. }
+                | Command::Watch { .. }
+                | Command::Rwatch { .. }
+                | Command::Awatch { .. }
+                | Command::Unwatch { .. }
+                | Command::Display { .. }
+                | Command::Undisplay { .. }
+                | Command::Dump(_)
+                | Command::Restore { .. }
+                | Command::WriteMem { .. }
+        )
+    }
 }
 
 pub fn parse_line(line: &str) -> Result<Command, clap::Error> {
-    let args = ["rdb"].iter().copied();
-    let args = args.chain(line.split(' '));
+    let mut words = line.split(' ');
+    let cmd = words.next().unwrap_or("");
+    // a gdb-style format suffix (eg, display/x) is passed as --format
+    let (cmd, format) = match cmd.split_once('/') {
+        Some((cmd, format)) => (cmd, Some(format)),
+        None => (cmd, None),
+    };
+    let format = format.into_iter().flat_map(|f| ["--format", f]);
+    let args = ["rdb", cmd].into_iter().chain(format).chain(words);
     Input::try_parse_from(args).map(|input| input.command)
 }
 
+/// The names of all the commands and their aliases, sorted.
+pub fn command_names() -> Vec<String> {
+    let mut names: Vec<String> = Input::command()
+        .get_subcommands()
+        .flat_map(|cmd| iter::once(cmd.get_name()).chain(cmd.get_all_aliases()))
+        .map(String::from)
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Check if a command (or an alias, possibly with a format suffix like
+/// `print/x`) takes a function or variable name as an argument.
+pub fn takes_name(cmd: &str) -> bool {
+    let cmd = cmd.split('/').next().unwrap_or(cmd);
+    Input::command().find_subcommand(cmd).is_some_and(|cmd| {
+        matches!(
+            cmd.get_name(),
+            "break"
+                | "tbreak"
+                | "symbol"
+                | "print"
+                | "display"
+                | "whatis"
+                | "ptype"
+                | "watch"
+                | "rwatch"
+                | "awatch"
+                | "disassemble"
+                | "list"
+        )
+    })
+}
+
 pub fn print_help() {
     _ = Input::command().print_long_help();
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{env, ffi::OsString};
+
+    use clap::IntoApp;
+
+    use super::{parse_line, parse_target_args, CmpOp, Condition, Input};
+    use crate::ptrace::Reg;
+
+    #[test]
+    fn test_command_definitions() {
+        Input::command().debug_assert();
+    }
+
+    fn args(line: &str) -> Vec<OsString> {
+        let words: Vec<String> = line.split(' ').map(|s| s.to_string()).collect();
+        parse_target_args(&words).unwrap()
+    }
+
+    #[test]
+    fn test_target_args_quoting() {
+        assert_eq!(args("foo \"bar baz\""), vec!["foo", "bar baz"]);
+        assert_eq!(args("'a  b' c\\ d"), vec!["a  b", "c d"]);
+        assert!(args("").is_empty());
+        let words = vec!["\"unterminated".to_string()];
+        assert!(parse_target_args(&words).is_err());
+    }
+
+    #[test]
+    fn test_target_args_env() {
+        env::set_var("RDB_TEST_ARG", "x y");
+        assert_eq!(args("$RDB_TEST_ARG"), vec!["x y"]);
+        assert_eq!(args("\"${RDB_TEST_ARG}!\""), vec!["x y!"]);
+        assert_eq!(args("'$RDB_TEST_ARG'"), vec!["$RDB_TEST_ARG"]);
+        assert_eq!(args("\\$RDB_TEST_ARG"), vec!["$RDB_TEST_ARG"]);
+    }
+
+    #[test]
+    fn test_condition() {
+        let cond = Condition::parse("rdi > 4").unwrap();
+        assert_eq!(
+            cond,
+            Condition {
+                reg: Reg::Rdi,
+                op: CmpOp::Gt,
+                val: 4
+            }
+        );
+        assert!(cond.holds(5));
+        assert!(!cond.holds(4));
+        assert_eq!(Condition::parse("$rax<=0x10").unwrap().op, CmpOp::Le);
+        let neg = Condition::parse("rax == -1").unwrap();
+        assert!(neg.holds(u64::MAX));
+        assert_eq!(neg.describe(), "rax == -1");
+        assert!(Condition::parse("rax").is_err());
+        assert!(Condition::parse("foo == 1").is_err());
+
+        let words = |s: &str| s.split(' ').map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(Condition::parse_if(&[]), Ok(None));
+        assert!(Condition::parse_if(&words("if rax != 0"))
+            .unwrap()
+            .is_some());
+        assert!(Condition::parse_if(&words("when rax != 0")).is_err());
+    }
+    #[test]
+    fn test_repeatable_commands() {
+        let repeatable = |line: &str| parse_line(line).unwrap().is_repeatable();
+        assert!(repeatable("stepi"));
+        assert!(repeatable("next 2"));
+        assert!(repeatable("x/4x $rsp"));
+        assert!(!repeatable("quit"));
+        assert!(!repeatable("run"));
+        assert!(!repeatable("break main"));
+        assert!(!repeatable("return 0"));
+    }
+}
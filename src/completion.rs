@@ -0,0 +1,122 @@
+//! Tab completion for the interactive prompt.
+//!
+//! The first word completes to a command, and the arguments of commands that
+//! take a function or variable name (like `break` and `print`) complete to
+//! names from the program.
+
+use rustyline::{
+    completion::Completer, highlight::Highlighter, hint::Hinter, validate::Validator, Context,
+    Helper,
+};
+
+use crate::cli;
+
+pub struct RdbHelper {
+    commands: Vec<String>,
+    /// function and variable names from the program
+    names: Vec<String>,
+}
+
+impl RdbHelper {
+    pub fn new(names: Vec<String>) -> Self {
+        Self {
+            commands: cli::command_names(),
+            names,
+        }
+    }
+
+    /// Complete the end of line, returning where the completed word starts
+    /// and the candidates to replace it with.
+    fn candidates(&self, line: &str) -> (usize, Vec<String>) {
+        let args = line.trim_start();
+        let cmd = match args.split_once(char::is_whitespace) {
+            Some((cmd, _)) => cmd,
+            None => {
+                let start = line.len() - args.len();
+                return (start, matching(&self.commands, args));
+            }
+        };
+        if !cli::takes_name(cmd) {
+            return (line.len(), vec![]);
+        }
+        // a name can follow an operator, like in `print *ptr`
+        let start = line
+            .char_indices()
+            .rev()
+            .find(|&(_, c)| !is_name_char(c))
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        (start, matching(&self.names, &line[start..]))
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == ':'
+}
+
+fn matching(words: &[String], prefix: &str) -> Vec<String> {
+    words
+        .iter()
+        .filter(|word| word.starts_with(prefix))
+        .cloned()
+        .collect()
+}
+
+impl Completer for RdbHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.candidates(&line[..pos]))
+    }
+}
+
+impl Hinter for RdbHelper {
+    type Hint = String;
+}
+
+impl Highlighter for RdbHelper {}
+
+impl Validator for RdbHelper {}
+
+impl Helper for RdbHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::RdbHelper;
+
+    fn helper() -> RdbHelper {
+        let names = ["countdown", "test::main", "use_struct", "use_vars"];
+        RdbHelper::new(names.iter().map(|s| s.to_string()).collect())
+    }
+
+    #[test]
+    fn test_complete_command() {
+        let h = helper();
+        let (start, cands) = h.candidates("backt");
+        assert_eq!(start, 0);
+        assert_eq!(cands, vec!["backtrace"]);
+        let (start, cands) = h.candidates("  unw");
+        assert_eq!(start, 2);
+        assert_eq!(cands, vec!["unwatch"]);
+    }
+
+    #[test]
+    fn test_complete_name() {
+        let h = helper();
+        assert_eq!(
+            h.candidates("break use_"),
+            (6, vec!["use_struct".to_string(), "use_vars".to_string()])
+        );
+        assert_eq!(h.candidates("b count").1, vec!["countdown"]);
+        assert_eq!(
+            h.candidates("print/x *test::m"),
+            (9, vec!["test::main".to_string()])
+        );
+        // continue doesn't take a name
+        assert!(h.candidates("continue use_").1.is_empty());
+    }
+}
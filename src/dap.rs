@@ -0,0 +1,308 @@
+//! A server for the Debug Adapter Protocol (DAP), so editors like VS Code can
+//! use rdb as a debugger.
+//!
+//! Messages are JSON with a `Content-Length` header, exchanged over stdin and
+//! stdout. The program is launched (stopped at its first instruction) before
+//! the adapter starts, so `launch` and `attach` only acknowledge it. This
+//! supports source breakpoints, continuing and stepping, the stack trace, the
+//! innermost frame's variables, and evaluating expressions like `print`.
+//!
+//! See <https://microsoft.github.io/debug-adapter-protocol/specification>.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    os::fd::FromRawFd,
+};
+
+use serde_json::{json, Value};
+
+use crate::debugger::{Dbg, DbgError, Stop, StopReason};
+
+/// The program has only one thread, which DAP still needs an id for.
+const THREAD_ID: u64 = 1;
+
+/// The variablesReference of the innermost frame's locals (0 means no
+/// variables).
+const LOCALS_REF: u64 = 1;
+
+/// Read a message, or None at the end of the input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(n) = header.strip_prefix("Content-Length:") {
+            len = n.trim().parse::<usize>().ok();
+        }
+    }
+    let len = len.ok_or_else(|| io::Error::other("message has no Content-Length"))?;
+    let mut body = vec![0; len];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+struct Adapter<'a, 'data> {
+    dbg: &'a mut Dbg<'data>,
+    out: File,
+    seq: u64,
+    /// the breakpoint pcs set in each source file, which setBreakpoints
+    /// replaces
+    breakpoints: HashMap<String, Vec<u64>>,
+    /// launch's stopOnEntry argument
+    stop_on_entry: bool,
+}
+
+impl Adapter<'_, '_> {
+    fn send(&mut self, mut msg: Value) -> io::Result<()> {
+        self.seq += 1;
+        msg["seq"] = self.seq.into();
+        let body = msg.to_string();
+        write!(self.out, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+        self.out.flush()
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({"type": "event", "event": event, "body": body}))
+    }
+
+    fn respond(&mut self, req: &Value, result: Result<Value, String>) -> io::Result<()> {
+        let mut resp = json!({
+            "type": "response",
+            "request_seq": req["seq"],
+            "command": req["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => resp["body"] = body,
+            Err(msg) => resp["message"] = msg.into(),
+        }
+        self.send(resp)
+    }
+
+    /// Report that the program stopped, or that it's gone.
+    fn report_stop(&mut self, reason: &str) -> io::Result<()> {
+        if !self.dbg.is_running() {
+            return self.event("terminated", json!({}));
+        }
+        self.event(
+            "stopped",
+            json!({"reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true}),
+        )
+    }
+
+    /// Run a command that resumes the program, then report where it stopped.
+    fn resume(
+        &mut self,
+        req: &Value,
+        run: impl FnOnce(&mut Dbg) -> &'static str,
+    ) -> io::Result<()> {
+        if !self.dbg.is_running() {
+            return self.respond(req, Err("the program is not running".to_string()));
+        }
+        self.respond(req, Ok(json!({"allThreadsContinued": true})))?;
+        let reason = run(self.dbg);
+        self.report_stop(reason)
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let path = args["source"]["path"]
+            .as_str()
+            .ok_or("setBreakpoints needs a source path")?
+            .to_string();
+        for pc in self.breakpoints.remove(&path).unwrap_or_default() {
+            _ = self.dbg.disable_user_breakpoint(pc);
+        }
+        let mut set = vec![];
+        let mut pcs = vec![];
+        let lines = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        for bp in lines {
+            let line = bp["line"].as_u64().unwrap_or(0);
+            // the breakpoint may move to the next line with code
+            let (line, line_pcs) = self
+                .dbg
+                .source_location_pcs(&path, line as usize)
+                .unwrap_or((line as usize, vec![]));
+            if !line_pcs.is_empty() {
+                self.dbg.set_user_breakpoints(&line_pcs);
+            }
+            set.push(json!({"verified": !line_pcs.is_empty(), "line": line}));
+            pcs.extend(line_pcs);
+        }
+        self.breakpoints.insert(path, pcs);
+        Ok(json!({ "breakpoints": set }))
+    }
+
+    fn stack_trace(&self) -> Value {
+        if !self.dbg.is_running() {
+            return json!({"stackFrames": [], "totalFrames": 0});
+        }
+        let frames: Vec<_> = self
+            .dbg
+            .backtrace()
+            .into_iter()
+            .enumerate()
+            .map(|(id, frame)| {
+                let mut f = json!({
+                    "id": id,
+                    "name": frame.function.unwrap_or_else(|| format!("0x{:x}", frame.pc)),
+                    "line": frame.line.unwrap_or(0),
+                    "column": 0,
+                    "instructionPointerReference": format!("0x{:x}", frame.pc),
+                });
+                if let Some(file) = frame.file {
+                    let name = file.file_name().map(|n| n.to_string_lossy().into_owned());
+                    f["source"] = json!({"name": name, "path": file.display().to_string()});
+                }
+                f
+            })
+            .collect();
+        json!({"totalFrames": frames.len(), "stackFrames": frames})
+    }
+
+    fn variables(&self, reference: u64) -> Value {
+        if reference != LOCALS_REF || !self.dbg.is_running() {
+            return json!({ "variables": [] });
+        }
+        let vars: Vec<_> = self
+            .dbg
+            .local_variables()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, ty, value)| {
+                json!({"name": name, "type": ty, "value": value, "variablesReference": 0})
+            })
+            .collect();
+        json!({ "variables": vars })
+    }
+
+    fn handle(&mut self, req: &Value) -> io::Result<bool> {
+        let args = &req["arguments"];
+        let command = req["command"].as_str().unwrap_or("");
+        let result = match command {
+            "initialize" => {
+                self.respond(req, Ok(json!({"supportsConfigurationDoneRequest": true})))?;
+                return self.event("initialized", json!({})).map(|_| true);
+            }
+            // the program was already started
+            "launch" | "attach" => {
+                self.stop_on_entry = args["stopOnEntry"].as_bool() == Some(true);
+                Ok(json!({}))
+            }
+            "setBreakpoints" => self.set_breakpoints(args),
+            "configurationDone" => {
+                if self.stop_on_entry {
+                    self.respond(req, Ok(json!({})))?;
+                    return self.report_stop("entry").map(|_| true);
+                }
+                return self
+                    .resume(req, |dbg| stop_reason(dbg.continue_execution()))
+                    .map(|_| true);
+            }
+            "threads" => Ok(json!({"threads": [{"id": THREAD_ID, "name": "main"}]})),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => {
+                // only the innermost frame's variables are supported
+                let frame = args["frameId"].as_u64().unwrap_or(0);
+                let reference = if frame == 0 { LOCALS_REF } else { 0 };
+                Ok(json!({"scopes": [
+                    {"name": "Locals", "variablesReference": reference, "expensive": false}
+                ]}))
+            }
+            "variables" => Ok(self.variables(args["variablesReference"].as_u64().unwrap_or(0))),
+            "evaluate" => {
+                let expr = args["expression"].as_str().unwrap_or("");
+                self.dbg
+                    .evaluate(expr.trim())
+                    .map(|result| json!({"result": result, "variablesReference": 0}))
+            }
+            "continue" => {
+                return self
+                    .resume(req, |dbg| stop_reason(dbg.continue_execution()))
+                    .map(|_| true)
+            }
+            "next" => {
+                return self
+                    .resume(req, |dbg| stop_reason(dbg.step_over(1)))
+                    .map(|_| true)
+            }
+            "stepIn" => {
+                return self
+                    .resume(req, |dbg| stop_reason(dbg.step_in(1)))
+                    .map(|_| true)
+            }
+            "stepOut" => {
+                return self
+                    .resume(req, |dbg| stop_reason(dbg.step_out()))
+                    .map(|_| true)
+            }
+            "disconnect" => {
+                if let Err(err) = self.dbg.release_target() {
+                    eprintln!("{err}");
+                }
+                self.respond(req, Ok(json!({})))?;
+                return Ok(false);
+            }
+            _ => Err(format!("{command} is not supported")),
+        };
+        self.respond(req, result)?;
+        Ok(true)
+    }
+}
+
+/// The DAP reason for a stop from continuing or stepping.
+fn stop_reason(stop: Result<Stop, DbgError>) -> &'static str {
+    let stop = match stop {
+        Ok(stop) => stop,
+        Err(_) => return "exited",
+    };
+    if stop.returned.is_some() {
+        return "step";
+    }
+    match stop.reason {
+        StopReason::Breakpoint(_) => "breakpoint",
+        StopReason::Watchpoint(_) => "data breakpoint",
+        StopReason::Step | StopReason::InternalBreakpoint(_) | StopReason::Branch { .. } => "step",
+        StopReason::Signal(libc::SIGSTOP) => "pause",
+        StopReason::Signal(_) | StopReason::Fault { .. } => "exception",
+        StopReason::Exited(_) | StopReason::Killed(_) => "exited",
+    }
+}
+
+/// Serve the debugger over DAP on stdin and stdout.
+///
+/// The protocol has stdout to itself, so the debugger's own output (and the
+/// program's) goes to stderr, which editors show as the adapter's log.
+pub fn serve(dbg: &mut Dbg) -> io::Result<()> {
+    let out = unsafe {
+        let fd = libc::dup(libc::STDOUT_FILENO);
+        if fd < 0 || libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        File::from_raw_fd(fd)
+    };
+    let mut adapter = Adapter {
+        dbg,
+        out,
+        seq: 0,
+        breakpoints: HashMap::new(),
+        stop_on_entry: false,
+    };
+    let mut input = BufReader::new(io::stdin());
+    while let Some(req) = read_message(&mut input)? {
+        if req["type"] != "request" {
+            continue;
+        }
+        if !adapter.handle(&req)? {
+            break;
+        }
+    }
+    Ok(())
+}
@@ -1,25 +1,130 @@
 #![allow(clippy::needless_return)]
 use std::{
     borrow::Cow,
-    collections::HashMap,
-    fs,
-    io::{self, BufRead},
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    fmt, fs, io, mem,
+    ops::Range,
+    path::{Path, PathBuf},
 };
 
 use addr2line::Location;
-use libc::pid_t;
+use libc::{c_uint, pid_t, user_regs_struct};
 use object::{Object, ObjectKind};
 use regex::Regex;
+use serde_json::json;
 
-use crate::dwarf::{self, DbgInfo};
+use crate::cli::{self, BreakpointLoc, Condition, SchedulerLocking};
+use crate::debugregs;
+use crate::disasm;
+use crate::dwarf::{
+    self, CallerFrame, DbgInfo, FrameBase, InlineFrame, ReturnAddrEvaluator, VarLocation,
+};
+use crate::expr::{Assignment, Cast, EvalContext, Expr, Place};
+use crate::format::{self, Format, MemFormat};
+use crate::fpregs::{self, FpReg, X87Tag};
+use crate::interrupt::InterruptGuard;
+use crate::logging::TargetOutput;
+use crate::maps;
+use crate::pager::Pager;
+use crate::procinfo;
 use crate::ptrace;
-use crate::source::{print_source, print_source_loc};
+use crate::source;
+use crate::threads::{Resume, Threads};
+use crate::types::{Encoding, Type};
+use crate::value::{self, FormattedValue, Memory, Value, ValueLocation};
+use crate::vecregs::{self, VecReg};
 use ptrace::{Reg, WaitStatus};
 
+/// Limit on frames when unwinding, which might not terminate if the stack is
+/// corrupt.
+const MAX_FRAMES: usize = 1024;
+
+/// Limit on the length of strings `print str` reads, in case the pointer is
+/// bad and there's no NUL nearby.
+pub const MAX_STRING_LEN: usize = 4096;
+
+/// Registers of a caller's frame, for unwinding with eh_frame.
+///
+/// The stack pointer and the callee-saved registers are tracked, since a
+/// function restores those before returning; other registers keep the values
+/// they had when unwinding started.
+#[derive(Clone, Copy)]
+struct UnwindRegs {
+    target: ptrace::Target,
+    regs: user_regs_struct,
+}
+
+impl UnwindRegs {
+    /// Start unwinding from the target's registers, with the stack pointer
+    /// and callee-saved registers of the frame to unwind.
+    fn new(target: ptrace::Target, frame: &FrameRegs) -> Result<Self, io::Error> {
+        let mut regs = target.getregs()?;
+        Reg::Rsp.set_reg(&mut regs, frame.rsp);
+        Reg::Rbp.set_reg(&mut regs, frame.rbp);
+        for (reg, &val) in dwarf::CALLEE_SAVED.iter().zip(&frame.saved) {
+            reg.set_reg(&mut regs, val);
+        }
+        Ok(UnwindRegs { target, regs })
+    }
+
+    /// The registers of the frame being unwound, at `pc`.
+    fn frame(&self, pc: u64) -> FrameRegs {
+        FrameRegs {
+            pc,
+            rsp: Reg::Rsp.get_reg(&self.regs),
+            rbp: Reg::Rbp.get_reg(&self.regs),
+            saved: dwarf::CALLEE_SAVED.map(|reg| reg.get_reg(&self.regs)),
+        }
+    }
+
+    /// Move to the caller's frame.
+    fn set_caller(&mut self, frame: &CallerFrame) {
+        Reg::Rsp.set_reg(&mut self.regs, frame.cfa);
+        Reg::Rbp.set_reg(&mut self.regs, frame.fp);
+        for (reg, val) in dwarf::CALLEE_SAVED.iter().zip(frame.saved) {
+            if let Some(val) = val {
+                reg.set_reg(&mut self.regs, val);
+            }
+        }
+    }
+}
+
+impl ReturnAddrEvaluator for UnwindRegs {
+    fn get_reg(&self, reg: Reg) -> u64 {
+        reg.get_reg(&self.regs)
+    }
+
+    fn read_mem(&self, addr: u64) -> u64 {
+        // an unreadable address ends the unwind with a 0 return address
+        match self.target.read_mem(addr, 8) {
+            Ok(bytes) => u64::from_le_bytes(bytes.try_into().unwrap()),
+            Err(_) => 0,
+        }
+    }
+}
+
+/// The registers of a frame found by unwinding. Only the pc, stack pointer,
+/// and callee-saved registers are recovered, which is enough to find the
+/// frame's caller and its variables, and to return to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FrameRegs {
+    /// the (offset) pc
+    pc: u64,
+    rsp: u64,
+    rbp: u64,
+    /// the values of [`dwarf::CALLEE_SAVED`]
+    saved: [u64; 5],
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum BreakpointSource {
-    User,
-    Internal,
+    /// Set by the user. One user breakpoint can have several locations,
+    /// which share an id.
+    User { id: usize },
+    /// Set by a stepping command in a thread, so other threads that hit it
+    /// keep going.
+    Internal { thread: pid_t },
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -28,6 +133,12 @@ struct Breakpoint {
     addr: u64,
     saved_data: Option<u8>,
     source: BreakpointSource,
+    /// only stop if this holds (user breakpoints only)
+    condition: Option<Condition>,
+    /// delete the breakpoint the first time it's hit (user breakpoints only)
+    temporary: bool,
+    /// how many more hits to pass without stopping (user breakpoints only)
+    ignore_count: u64,
 }
 
 impl Breakpoint {
@@ -41,6 +152,9 @@ impl Breakpoint {
             addr,
             saved_data: None,
             source,
+            condition: None,
+            temporary: false,
+            ignore_count: 0,
         }
     }
 
@@ -49,47 +163,115 @@ impl Breakpoint {
     }
 
     fn is_internal(&self) -> bool {
-        self.source == BreakpointSource::Internal
+        matches!(self.source, BreakpointSource::Internal { .. })
     }
 
-    fn enable(&mut self) {
+    /// Write the int3, leaving the breakpoint disabled if that fails.
+    fn enable(&mut self) -> io::Result<()> {
         debug_assert!(!self.enabled(), "breakpoint is already enabled");
-        let old_data = self.target.peekdata(self.addr).expect("peek failed");
-        let saved = (old_data & 0xff) as u8;
+        let old_data = self.target.peekdata(self.addr)?;
         let new_data = (old_data & (!0xffu64)) | (Self::INT3_INSTR as u64);
-        if self.target.pokedata(self.addr, new_data).is_ok() {
-            self.saved_data = Some(saved);
-        } else {
-            // could not set breakpoint
-
-            // TODO: bubble up an appropriate report (depends on how this
-            // breakpoint's address was computed)
-        }
+        self.target.pokedata(self.addr, new_data)?;
+        self.saved_data = Some((old_data & 0xff) as u8);
+        Ok(())
     }
 
-    fn disable(&mut self) {
+    /// Restore the original byte. The breakpoint is disabled even if that
+    /// fails, since then there's no int3 left to remove (or no way to).
+    fn disable(&mut self) -> io::Result<()> {
         debug_assert!(self.enabled(), "breakpoint is not enabled");
-        let old_data = self.target.peekdata(self.addr).expect("peek failed");
-        let new_data = (old_data & (!0xffu64)) | (self.saved_data.unwrap() as u64);
-        self.target.pokedata(self.addr, new_data).unwrap();
-        self.saved_data = None;
+        let Some(saved) = self.saved_data.take() else {
+            return Ok(());
+        };
+        let old_data = self.target.peekdata(self.addr)?;
+        let new_data = (old_data & (!0xffu64)) | (saved as u64);
+        self.target.pokedata(self.addr, new_data)
     }
 }
 
 // taken from kernel
-const SI_KERNEL: i32 = 128;
-const TRAP_BRKPT: i32 = 1;
-const TRAP_TRACE: i32 = 2;
+pub(crate) const SI_KERNEL: i32 = 128;
+pub(crate) const TRAP_BRKPT: i32 = 1;
+pub(crate) const TRAP_TRACE: i32 = 2;
+const TRAP_HWBKPT: i32 = 4;
+// si_code values for SIGSEGV
+const SEGV_MAPERR: i32 = 1;
+const SEGV_ACCERR: i32 = 2;
 
 fn display_code(si_code: i32) -> String {
     match si_code {
         SI_KERNEL => "SI_KERNEL".to_string(),
         TRAP_BRKPT => "TRAP_BRKPT".to_string(),
         TRAP_TRACE => "TRAP_TRACE".to_string(),
+        TRAP_HWBKPT => "TRAP_HWBKPT".to_string(),
         _ => format!("{}", si_code),
     }
 }
 
+/// Names of the standard signals.
+const SIGNALS: [(i32, &str); 20] = [
+    (libc::SIGHUP, "SIGHUP"),
+    (libc::SIGINT, "SIGINT"),
+    (libc::SIGQUIT, "SIGQUIT"),
+    (libc::SIGILL, "SIGILL"),
+    (libc::SIGTRAP, "SIGTRAP"),
+    (libc::SIGABRT, "SIGABRT"),
+    (libc::SIGBUS, "SIGBUS"),
+    (libc::SIGFPE, "SIGFPE"),
+    (libc::SIGKILL, "SIGKILL"),
+    (libc::SIGUSR1, "SIGUSR1"),
+    (libc::SIGSEGV, "SIGSEGV"),
+    (libc::SIGUSR2, "SIGUSR2"),
+    (libc::SIGPIPE, "SIGPIPE"),
+    (libc::SIGALRM, "SIGALRM"),
+    (libc::SIGTERM, "SIGTERM"),
+    (libc::SIGCHLD, "SIGCHLD"),
+    (libc::SIGCONT, "SIGCONT"),
+    (libc::SIGSTOP, "SIGSTOP"),
+    (libc::SIGTSTP, "SIGTSTP"),
+    (libc::SIGSYS, "SIGSYS"),
+];
+
+/// Get the name of a signal, like SIGSEGV.
+pub(crate) fn signal_name(signal: i32) -> &'static str {
+    SIGNALS
+        .iter()
+        .find(|&&(num, _)| num == signal)
+        .map(|&(_, name)| name)
+        .unwrap_or("unknown signal")
+}
+
+/// Parse a signal given by name (with or without the SIG prefix) or number.
+fn parse_signal(text: &str) -> Option<i32> {
+    if let Ok(num) = text.parse::<i32>() {
+        return (1..=64).contains(&num).then_some(num);
+    }
+    let name = text.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    SIGNALS
+        .iter()
+        .find(|(_, n)| &n[3..] == name)
+        .map(|&(num, _)| num)
+}
+
+/// What happens when the target gets a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignalHandling {
+    /// stop the target and report the signal
+    pub stop: bool,
+    /// deliver the signal when the target resumes
+    pub pass: bool,
+}
+
+impl Default for SignalHandling {
+    fn default() -> Self {
+        Self {
+            stop: true,
+            pass: true,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 struct TempBreakpoints {
     to_delete: Vec<u64>,
@@ -102,432 +284,3879 @@ impl TempBreakpoints {
 
     fn ensure_breakpoint(&mut self, dbg: &mut Dbg, addr: u64) {
         if !dbg.breakpoints.contains_key(&addr) {
-            dbg.set_breakpoint_at_address(addr, BreakpointSource::Internal);
+            let source = BreakpointSource::Internal {
+                thread: dbg.target.pid(),
+            };
+            if let Err(err) = dbg.set_breakpoint_at_address(addr, source) {
+                dbg.warnings.push(err.to_string());
+            }
             self.to_delete.push(addr);
         }
     }
 
     fn delete_all(self, dbg: &mut Dbg) {
         for addr in self.to_delete.into_iter() {
-            let mut bp = dbg.breakpoints.remove(&addr).unwrap();
-            if bp.enabled() {
-                bp.disable();
+            let Some(mut bp) = dbg.breakpoints.remove(&addr) else {
+                continue;
+            };
+            if dbg.running && bp.enabled() {
+                if let Err(err) = bp.disable() {
+                    dbg.warnings.push(format!(
+                        "could not remove breakpoint at 0x{:x}: {err}",
+                        addr - dbg.load_addr
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// What accesses a watchpoint stops for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Write,
+    Read,
+    /// reads or writes
+    Access,
+}
+
+impl WatchKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WatchKind::Write => "watchpoint",
+            WatchKind::Read => "read watchpoint",
+            WatchKind::Access => "access watchpoint",
+        }
+    }
+}
+
+/// A hardware watchpoint, which stops the target when it accesses an address.
+///
+/// The address is computed once, when the watchpoint is set, so a watchpoint
+/// on a local variable keeps watching the same stack slot after its frame
+/// returns.
+#[derive(Debug, Clone)]
+struct Watchpoint {
+    num: usize,
+    /// the expression as the user wrote it
+    text: String,
+    addr: u64,
+    /// 1, 2, 4, or 8 bytes, with addr aligned to len
+    len: usize,
+    /// the type of a watched variable, for formatting its value
+    ty: Option<Type>,
+    /// the value the last time the watchpoint was set or hit
+    old: Option<Vec<u8>>,
+    kind: WatchKind,
+}
+
+impl Watchpoint {
+    /// The x86 debug registers DR0-DR3 hold watched addresses.
+    const SLOTS: usize = debugregs::SLOTS;
+    const DR6: usize = debugregs::DR6;
+    const DR7: usize = debugregs::DR7;
+
+    /// The DR7 bits that enable this watchpoint in a slot.
+    fn dr7_bits(&self, slot: usize) -> u64 {
+        // x86 can't break on reads alone, so a read watchpoint breaks on
+        // reads and writes, and ignores the writes that change the value
+        let rw: u64 = match self.kind {
+            WatchKind::Write => 0b01,
+            WatchKind::Read | WatchKind::Access => 0b11,
+        };
+        let len: u64 = match self.len {
+            1 => 0b00,
+            2 => 0b01,
+            8 => 0b10,
+            _ => 0b11,
+        };
+        let local_enable = 1 << (2 * slot);
+        local_enable | ((rw | (len << 2)) << (16 + 4 * slot))
+    }
+
+    /// The DR7 bits for a slot, whether or not they're enabled.
+    fn dr7_mask(slot: usize) -> u64 {
+        (0b11 << (2 * slot)) | (0b1111 << (16 + 4 * slot))
+    }
+
+    fn format(&self, mem: &impl Memory, bytes: &Option<Vec<u8>>) -> String {
+        match (bytes, &self.ty) {
+            (None, _) => "<unreadable>".to_string(),
+            (Some(bytes), Some(ty)) => {
+                value::read_typed_value(mem, ValueLocation::Bytes(bytes.clone()), ty).to_string()
+            }
+            (Some(bytes), None) => {
+                let mut buf = [0u8; 8];
+                buf[..bytes.len()].copy_from_slice(bytes);
+                format!("0x{:x}", u64::from_le_bytes(buf))
+            }
+        }
+    }
+}
+
+/// A software watchpoint, which single-steps the target and stops when the
+/// watched bytes change. Slow, but it can watch any number of bytes.
+#[derive(Debug, Clone)]
+struct SwWatchpoint {
+    num: usize,
+    /// the variable or address expression as the user wrote it
+    text: String,
+    addr: u64,
+    len: usize,
+    /// the bytes the last time the watchpoint was set or hit
+    old: Option<Vec<u8>>,
+}
+
+impl SwWatchpoint {
+    fn format(bytes: &Option<Vec<u8>>) -> String {
+        match bytes {
+            None => "<unreadable>".to_string(),
+            Some(bytes) => {
+                let hex: Vec<_> = bytes.iter().map(|b| format!("{b:02x}")).collect();
+                hex.join(" ")
             }
         }
     }
 }
 
+/// An expression to print whenever the target stops.
+#[derive(Debug, Clone)]
+struct Display {
+    num: usize,
+    /// the expression as the user wrote it
+    text: String,
+    expr: Expr,
+    format: Option<Format>,
+}
+
+impl Display {
+    fn describe(&self) -> String {
+        match self.format {
+            Some(format) => format!("/{} {}", format.letter(), self.text),
+            None => self.text.clone(),
+        }
+    }
+}
+
+/// Why the target stopped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    /// the program exited with a status
+    Exited(i32),
+    /// the program was killed by a signal
+    Killed(i32),
+    /// hit a user breakpoint at an (offset) pc
+    Breakpoint(u64),
+    /// hit a breakpoint the debugger set for a stepping command, at an
+    /// (offset) pc
+    InternalBreakpoint(u64),
+    /// watchpoints triggered
+    Watchpoint(Vec<WatchpointHit>),
+    /// finished a step
+    Step,
+    /// took a branch (from `step_to_branch`) at an (offset) address, to
+    /// another
+    Branch {
+        addr: u64,
+        text: String,
+        target: u64,
+    },
+    /// a SIGSEGV or SIGBUS from accessing an address
+    Fault {
+        signal: i32,
+        addr: u64,
+        reason: String,
+    },
+    /// got some other signal (including SIGSTOP from being interrupted), or
+    /// 0 for none
+    Signal(i32),
+}
+
+/// A watchpoint that triggered, with its value formatted for display.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchpointHit {
+    /// the kind of hardware watchpoint, or None for a software watchpoint
+    pub kind: Option<WatchKind>,
+    pub num: usize,
+    /// the expression as the user wrote it
+    pub text: String,
+    /// the value before the hit, or None if it didn't change (when a read
+    /// or access watchpoint is hit by a read)
+    pub old: Option<String>,
+    pub new: String,
+}
+
+/// A watchpoint that was just set, for a frontend to report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewWatchpoint {
+    /// the kind of hardware watchpoint, or None for a software watchpoint
+    pub kind: Option<WatchKind>,
+    pub num: usize,
+    /// the expression as the user wrote it
+    pub text: String,
+    pub addr: u64,
+    pub len: usize,
+    /// a warning that the address depends on a local, so the watchpoint
+    /// won't follow it
+    pub note: Option<String>,
+}
+
+/// The registers to pop frames with, from [`Dbg::prepare_return`].
+pub struct ForcedReturn {
+    regs: user_regs_struct,
+}
+
+/// Where a `finish` returned to, and what the function returned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Return {
+    /// the (offset) pc returned to
+    pub pc: u64,
+    /// the returned value's number in the value history and its formatted
+    /// value, or why it couldn't be read (None if there's no value)
+    pub value: Option<Result<(usize, String), String>>,
+}
+
+/// A stop of the target, for a frontend to report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stop {
+    pub reason: StopReason,
+    /// the id of a temporary breakpoint that was deleted because this stop
+    /// hit it
+    pub deleted_temporary: Option<usize>,
+    /// a finish this stop completed
+    pub returned: Option<Return>,
+}
+
+impl Stop {
+    fn new(reason: StopReason) -> Self {
+        Self {
+            reason,
+            deleted_temporary: None,
+            returned: None,
+        }
+    }
+}
+
+/// How single-stepping for software watchpoints ended.
+enum SwWatchStep {
+    /// watched bytes changed
+    Changed(Vec<WatchpointHit>),
+    /// something else stopped the target
+    Stopped(WaitStatus),
+}
+
+/// An error from debugging, which is reported to the user instead of
+/// stopping the debugger.
+#[derive(Debug)]
+pub enum DbgError {
+    /// a system call on the target (like ptrace) failed
+    Io(io::Error),
+    /// the debug info could not be read
+    Dwarf(gimli::Error),
+    Message(String),
+}
+
+impl fmt::Display for DbgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbgError::Io(err) => write!(f, "{}", ptrace::describe_error(err)),
+            DbgError::Dwarf(err) => write!(f, "could not read debug info: {err}"),
+            DbgError::Message(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+impl std::error::Error for DbgError {}
+
+impl From<io::Error> for DbgError {
+    fn from(err: io::Error) -> Self {
+        DbgError::Io(err)
+    }
+}
+
+impl From<gimli::Error> for DbgError {
+    fn from(err: gimli::Error) -> Self {
+        DbgError::Dwarf(err)
+    }
+}
+
+impl From<String> for DbgError {
+    fn from(msg: String) -> Self {
+        DbgError::Message(msg)
+    }
+}
+
+/// A frame of the call stack, for a frontend to show.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackFrame {
+    /// the frame's pc, as an offset
+    pub pc: u64,
+    pub function: Option<String>,
+    pub file: Option<PathBuf>,
+    pub line: Option<u32>,
+}
+
+/// A frame of a backtrace, for a frontend to show.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BacktraceFrame {
+    /// the frame's number, from 1 for the innermost frame
+    pub num: usize,
+    /// the frame's pc, as an offset
+    pub pc: u64,
+    /// the functions at pc, innermost first, each with its own location
+    /// (empty if there's no debug info for pc)
+    pub functions: Vec<InlineFrame>,
+    /// the symbol pc is in (plus an offset), for when there's no debug info
+    pub symbol: Option<String>,
+}
+
+/// The pcs the two unwinders find for a frame, from
+/// [`Dbg::compare_unwinders`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnwinderFrame {
+    /// the pc from following frame pointers
+    pub frame_pointer: Option<u64>,
+    /// the pc from unwinding with eh_frame
+    pub eh_frame: Option<u64>,
+    pub function: Option<String>,
+}
+
+/// A user breakpoint, for a frontend to list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BreakpointInfo {
+    pub id: usize,
+    /// the breakpoint's pc, as an offset
+    pub pc: u64,
+    pub enabled: bool,
+    pub condition: Option<Condition>,
+    pub temporary: bool,
+    /// how many more hits are passed without stopping
+    pub ignore_count: u64,
+}
+
+/// A user breakpoint that was just set, for a frontend to report.
+#[derive(Debug)]
+pub struct NewBreakpoint {
+    pub id: usize,
+    /// the line requested and the line the breakpoint was set at instead,
+    /// if the requested line has no code
+    pub moved: Option<(usize, usize)>,
+    pub locations: Vec<BreakpointLocation>,
+}
+
+/// One of the locations of a new breakpoint.
+#[derive(Debug)]
+pub struct BreakpointLocation {
+    /// the location's pc, as an offset
+    pub pc: u64,
+    /// the location's source, if there's debug info for it
+    pub desc: Option<String>,
+    /// why the breakpoint couldn't be inserted here
+    pub error: Option<DbgError>,
+}
+
+/// The source line of an address, like addr2line finds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressLine {
+    /// the address as given
+    pub addr: u64,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub function: Option<String>,
+}
+
+/// Lines of a source file, from `list`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listing {
+    pub path: PathBuf,
+    /// the number of the first line
+    pub start: usize,
+    /// the number of the last line asked for, which could be past the end of
+    /// the file
+    pub end: usize,
+    pub lines: Vec<String>,
+}
+
+/// Where an address is in the target's memory, from [`Dbg::mapping`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressMapping {
+    pub addr: u64,
+    /// the mapping the address is in, if it's mapped
+    pub mapping: Option<maps::Mapping>,
+    /// if the address is in the program's code, its offset pc, the function
+    /// it's in, and its offset into the function
+    pub function: Option<(u64, String, u64)>,
+}
+
+/// What the target process is running, from [`Dbg::target_info`].
+#[derive(Debug)]
+pub struct TargetInfo {
+    pub pid: pid_t,
+    pub exe: Result<PathBuf, DbgError>,
+    pub cmdline: Result<Vec<String>, DbgError>,
+    pub load_addr: u64,
+}
+
+/// The instructions of a function, from [`Dbg::disassemble`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disassembly {
+    /// the function's start, as an offset
+    pub start: u64,
+    /// the (offset) pc to mark, if it's in the function and the target is
+    /// running
+    pub pc: Option<u64>,
+    pub instrs: Vec<disasm::Instr>,
+}
+
+/// Memory formatted for `x`, a line at a time, from [`Dbg::examine_memory`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExaminedMemory {
+    /// each line's address and formatted units
+    pub lines: Vec<(u64, Vec<String>)>,
+    /// the address that couldn't be read, if that cut the output short
+    pub unreadable: Option<u64>,
+}
+
+/// An auto-display expression and its value, for a frontend to show.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisplayValue {
+    pub num: usize,
+    /// the expression, with its format
+    pub desc: String,
+    /// the formatted value, or why it couldn't be computed
+    pub value: String,
+}
+
+/// A `finish` that was interrupted by another stop before the function
+/// returned.
+struct PendingFinish {
+    return_address: u64,
+    temp_bp: TempBreakpoints,
+    /// the type of value the function returns, if any
+    return_type: Option<Type>,
+}
+
 pub struct Dbg<'data> {
+    /// the current thread, which the debugger reads and steps
     target: ptrace::Target,
+    /// all of the target's threads
+    threads: Threads,
+    /// the thread the last stop was reported in
+    reported_thread: pid_t,
     load_addr: u64,
+    /// whether the program is position independent, and so needs a load
+    /// address
+    kind: ObjectKind,
     info: DbgInfo<'data>,
     running: bool,
     breakpoints: HashMap<u64, Breakpoint>,
+    /// the program being debugged, which the run command starts again
+    program: PathBuf,
+    /// arguments to pass to the program when it is run
+    args: Vec<OsString>,
+    /// an interrupt raced with the target stopping for another reason, so
+    /// there's a SIGSTOP still pending for it
+    stale_interrupt: bool,
+    displays: Vec<Display>,
+    /// problems that didn't stop a command, like a breakpoint that couldn't
+    /// be removed, for the frontend to report
+    warnings: Vec<String>,
+    next_display_num: usize,
+    /// the target's output, if the debugger launched it
+    output: Option<TargetOutput>,
+    /// report stops and exits as JSON events
+    json_events: bool,
+    /// watchpoints, indexed by the debug register they use
+    watchpoints: [Option<Watchpoint>; Watchpoint::SLOTS],
+    /// software watchpoints, which `continue` single-steps to check
+    sw_watchpoints: Vec<SwWatchpoint>,
+    next_watchpoint_num: usize,
+    next_breakpoint_id: usize,
+    /// which threads run when the current one is resumed
+    scheduler_locking: SchedulerLocking,
+    /// a stepping command (rather than, say, continue) is running
+    stepping: bool,
+    /// show the instruction at the pc each time the target stops
+    disassemble_next_line: bool,
+    /// lines of source shown on either side of the current line
+    listsize: usize,
+    /// the source file and next line for a bare `list` to show
+    list_pos: Option<(PathBuf, usize)>,
+    /// prefix rewrites for source paths, from `set substitute-path`
+    substitute_paths: Vec<(String, String)>,
+    /// more directories to look for source files in, from `directory`
+    source_dirs: Vec<PathBuf>,
+    /// the index of the frame that variables and registers are read from,
+    /// where 0 is the innermost frame (reset each time the target stops)
+    selected_frame: usize,
+    /// the values printed so far, which expressions can refer to as `$N`
+    history: Vec<Value>,
+    /// convenience variables (`$name`), which only exist in the debugger
+    convenience_vars: HashMap<String, Value>,
+    /// a finish whose return breakpoint hasn't been hit yet
+    pending_finish: Option<PendingFinish>,
+    /// pages the output of commands that print a lot
+    pager: Pager,
+    /// the signal the target last stopped with, which is delivered when it
+    /// resumes (0 for none)
+    pending_signal: c_uint,
+    /// changes to how signals are handled, from the handle command
+    signal_handling: HashMap<i32, SignalHandling>,
+    /// the target was already running when the debugger attached to it, so
+    /// it's detached from rather than killed at the end
+    attached: bool,
 }
 
 impl<'data> Dbg<'data> {
     fn get_load_address(pid: pid_t) -> Result<u64, io::Error> {
-        let f = fs::File::open(format!("/proc/{pid}/maps")).expect("could not open memory mapping");
-        let f = io::BufReader::new(f);
-        let re = Regex::new(
-            r"(?P<start>[0-9a-f]*)-([0-9a-f]*) (?P<mode>[^ ]*) (?P<offset>[0-9a-f]*) ([^ ]*) ([^ ]*) *(?P<path>.*)",
-        ).unwrap();
-        for line in f.lines() {
-            let line = line?;
-            if let Some(captures) = re.captures(&line) {
-                let off = captures.name("offset").unwrap().as_str();
-                let off = u64::from_str_radix(off, 16).expect("could not parse offset");
-                if off == 0 {
-                    let start = captures.name("start").unwrap().as_str();
-                    let start = u64::from_str_radix(start, 16).expect("could not parse start");
-                    return Ok(start);
-                }
+        let maps = maps::read_maps(pid)?;
+        let exe = maps
+            .iter()
+            .find(|m| m.offset == 0)
+            .ok_or_else(|| io::Error::other("could not find the program's mapping"))?;
+        Ok(exe.start)
+    }
+
+    /// Wait for a newly traced target to stop, and get its load address.
+    fn wait_for_start(target: ptrace::Target, kind: ObjectKind) -> Result<u64, io::Error> {
+        match target.wait()? {
+            WaitStatus::Stopped { .. } => {}
+            // if the target couldn't be traced or exec failed, it reports why
+            // itself and exits
+            _ => {
+                return Err(io::Error::other(
+                    "the program exited before it could be traced",
+                ))
             }
         }
-        panic!("could not parse map file")
+
+        // make sure to get load address after waiting for target
+        if kind == ObjectKind::Dynamic {
+            Self::get_load_address(target.pid())
+        } else {
+            Ok(0)
+        }
     }
 
     /// Create a new debugger using a loaded object file for resolving symbols
     /// and tracing a given target pid.
-    pub fn new(file: object::File<'data>, pid: pid_t) -> Self {
+    ///
+    /// Fails if the target never stops to be traced, for example because
+    /// ptrace is not permitted.
+    pub fn new(file: object::File<'data>, pid: pid_t) -> Result<Self, DbgError> {
         let kind = file.kind();
-        let info = DbgInfo::new(file).expect("could not load dwarf file");
+        let info = DbgInfo::new(file)?;
         let target = ptrace::Target::new(pid);
-        target.wait().unwrap();
+        let load_addr = Self::wait_for_start(target, kind)?;
+        let threads = Threads::new(pid);
+        threads.trace_clones()?;
 
-        // make sure to get load address after waiting for target
-        let load_addr = if kind == ObjectKind::Dynamic {
-            Self::get_load_address(pid).expect("could not get load address")
-        } else {
-            0
-        };
-
-        Self {
+        Ok(Self {
             target,
+            threads,
+            reported_thread: pid,
             load_addr,
+            kind,
             info,
             running: true,
             breakpoints: HashMap::new(),
-        }
+            program: PathBuf::new(),
+            args: vec![],
+            stale_interrupt: false,
+            displays: vec![],
+            warnings: vec![],
+            next_display_num: 1,
+            output: None,
+            json_events: false,
+            watchpoints: Default::default(),
+            sw_watchpoints: vec![],
+            next_watchpoint_num: 1,
+            next_breakpoint_id: 1,
+            scheduler_locking: SchedulerLocking::Off,
+            stepping: false,
+            disassemble_next_line: false,
+            listsize: 3,
+            list_pos: None,
+            substitute_paths: vec![],
+            source_dirs: vec![],
+            selected_frame: 0,
+            history: vec![],
+            convenience_vars: HashMap::new(),
+            pending_finish: None,
+            pager: Pager::new(),
+            pending_signal: 0,
+            signal_handling: HashMap::new(),
+            attached: false,
+        })
     }
 
-    fn handle_sigtrap(&self, siginfo: libc::siginfo_t) {
+    fn handle_sigtrap(&mut self, siginfo: libc::siginfo_t) -> Result<StopReason, DbgError> {
         let code = siginfo.si_code;
         if code == SI_KERNEL || code == TRAP_BRKPT {
-            let pc = self.get_pc() - 1;
-            self.set_pc(pc);
-            let is_internal = self
-                .breakpoints
-                .get(&pc)
-                .map(|bp| bp.is_internal())
-                .unwrap_or(false);
-            if !is_internal {
-                println!("hit breakpoint 0x{:x}", pc - self.load_addr);
-            }
-            let offset_pc = pc - self.load_addr;
-            let loc = self
-                .info
-                .source_for_pc(offset_pc)
-                .expect("could not lookup source");
-            if let Some(loc) = loc {
-                print_source_loc(&loc, 1);
+            let pc = self.get_pc()? - 1;
+            self.set_pc(pc)?;
+            // internal breakpoints belong to a stepping command, which reports
+            // where it stopped itself
+            if self.stopped_at_internal_breakpoint() {
+                return Ok(StopReason::InternalBreakpoint(pc - self.load_addr));
             }
+            Ok(StopReason::Breakpoint(pc - self.load_addr))
+        } else if code == TRAP_HWBKPT {
+            Ok(StopReason::Watchpoint(self.watchpoint_hits()))
         } else if code == TRAP_TRACE {
-            // from single-stepping
-            return;
+            // from single-stepping, which can also trigger a watchpoint
+            let hits = self.watchpoint_hits();
+            if hits.is_empty() {
+                Ok(StopReason::Step)
+            } else {
+                Ok(StopReason::Watchpoint(hits))
+            }
         } else {
-            eprintln!("unknown SIGTRAP code {}", code);
+            // an int3 in the program, say, which isn't a breakpoint
+            Ok(StopReason::Signal(libc::SIGTRAP))
         }
     }
 
-    /// Resume execution until a breakpoint or the target terminates.
-    pub fn continue_execution(&mut self) -> Result<(), io::Error> {
-        self.step_over_breakpoint();
-        self.target.cont(0)?;
-        let s = self.target.wait()?;
-
-        if let WaitStatus::Exited { status } = s {
-            if status == 0 {
-                println!("program exited");
-            } else {
-                eprintln!("program exited with status {status}");
-            }
-            self.running = false;
-            return Ok(());
-        }
+    /// Wait for a thread to stop, after any output it produced before
+    /// stopping has been shown, and make it the current thread.
+    fn wait(&mut self) -> Result<WaitStatus, io::Error> {
+        let (thread, s) = self.threads.wait()?;
+        self.stopped(thread);
+        Ok(s)
+    }
 
-        let siginfo = self.target.getsiginfo()?;
-        let signo = siginfo.si_signo;
-        if signo == 0 {
-            // no signal
-            return Ok(());
+    /// Make a thread that stopped the current thread.
+    fn stopped(&mut self, thread: ptrace::Target) {
+        if thread != self.target && self.pending_signal != 0 {
+            // the old thread's signal wasn't delivered because this thread's
+            // stop came first, so send it again to be delivered once the old
+            // thread runs
+            let signal = self.take_pending_signal();
+            _ = self.target.tkill(signal as i32);
         }
-        if signo == libc::SIGTRAP {
-            self.handle_sigtrap(siginfo);
-        } else if signo == libc::SIGSEGV {
-            println!("yay segfault: {}", display_code(siginfo.si_code));
-        } else {
-            println!("got signal {}", siginfo.si_signo);
+        self.target = thread;
+        self.selected_frame = 0;
+        if let Some(output) = &self.output {
+            output.sync();
         }
-        Ok(())
     }
 
-    /// Set a breakpoint based on address
-    ///
-    /// The pc here is an offset into the binary, not the actual program counter
-    /// (which will be offset by the load address).
-    pub fn set_user_breakpoint(&mut self, pc: u64) {
-        self.set_breakpoint_at_address(self.load_addr + pc, BreakpointSource::User);
-        if let Ok(mut frame) = self.info.frame_for_pc(pc) {
-            let path = frame.file.take().unwrap_or("??");
-            let dir = frame.comp_dir.take().unwrap_or(Cow::Borrowed(""));
-            let file = path.strip_prefix(&format!("{dir}/")).unwrap_or(path);
-            let line = frame
-                .line
-                .take()
-                .map(|l| l.to_string())
-                .unwrap_or_else(|| "??".to_string());
-            let func = frame.inner_function().unwrap_or(Cow::Borrowed("??"));
-            println!("set breakpoint at 0x{pc}: file {file}, line {line} (in {func})");
+    /// Resume the current thread, and the other threads too unless
+    /// scheduler-locking keeps them stopped.
+    fn resume(&mut self, how: Resume) -> Result<(), io::Error> {
+        let others = match self.scheduler_locking {
+            SchedulerLocking::Off => true,
+            SchedulerLocking::On => false,
+            SchedulerLocking::Step => !self.stepping,
+        };
+        let signal = self.take_pending_signal();
+        if !self.threads.resume(self.target, how, signal, others)? {
+            // another thread already stopped, and waiting reports that first
+            self.pending_signal = signal;
         }
+        Ok(())
     }
 
-    /// internal method to add a breakpoint
-    fn set_breakpoint_at_address(&mut self, addr: u64, source: BreakpointSource) {
-        let bp = self
-            .breakpoints
-            .entry(addr)
-            .or_insert_with(|| Breakpoint::new(self.target, addr, source));
-        // TODO: ought to set bp.source to source if source if User (and then
-        // make sure it doesn't get cleaned up accidentally)
-        if bp.enabled() {
-            eprintln!("already have a breakpoint at 0x{:x}", addr - self.load_addr);
-            return;
-        }
-        bp.enable();
+    /// Run a stepping command, which scheduler-locking `step` runs with the
+    /// other threads stopped.
+    fn stepping<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.stepping = true;
+        let r = f(self);
+        self.stepping = false;
+        r
     }
 
-    /// Set a breakpoint by source location (file and line number)
-    pub fn set_breakpoint_at_source_location(&mut self, file: &str, line: usize) {
-        if let Some(pc) = self
-            .info
-            .pc_for_source_loc(|path| path.ends_with(file), line)
-            .expect("could not lookup source")
+    /// Resume the target and wait for it to stop, forwarding Ctrl-C to the
+    /// target in the meantime.
+    fn resume_and_wait(&mut self) -> Result<WaitStatus, io::Error> {
+        let guard = InterruptGuard::install(self.target.pid())?;
+        let s = loop {
+            self.resume(Resume::Continue)?;
+            let s = self.wait()?;
+            if s == (WaitStatus::Stopped {
+                signal: libc::SIGSTOP,
+            }) && self.stale_interrupt
+            {
+                // left over from a previous continue, not a real stop
+                self.stale_interrupt = false;
+                continue;
+            }
+            break s;
+        };
+        if guard.finish()
+            && s != (WaitStatus::Stopped {
+                signal: libc::SIGSTOP,
+            })
         {
-            self.set_user_breakpoint(pc);
-        } else {
-            eprintln!("could not find {}:{}", file, line);
+            self.stale_interrupt = true;
         }
+        Ok(s)
     }
 
-    /// Set a breakpoint at a function's start, by name.
-    pub fn set_breakpoint_at_function(&mut self, needle: &str) {
-        let pc = self
-            .info
-            .pc_for_function_pred(|name| name == needle)
-            .unwrap();
-        match pc {
-            None => eprintln!("couldn't find function matching {}", needle),
-            Some(pc) => {
-                let lines = self.info.function_lines_from_pc(pc).unwrap();
-                let begin = if lines.len() > 1 { lines[1] } else { lines[0] };
-                self.set_user_breakpoint(begin);
-            }
-        }
+    /// Get the signal to deliver when resuming the target, clearing it.
+    fn take_pending_signal(&mut self) -> c_uint {
+        std::mem::take(&mut self.pending_signal)
     }
 
-    /// Disable a user breakpoint by address
+    /// Resume execution until a breakpoint or the target terminates.
     ///
-    /// See [`set_user_breakpoint`](#set_user_breakpoint) for the interpretation of pc.
-    pub fn disable_user_breakpoint(&mut self, pc: u64) {
-        self.disable_breakpoint_at_address(self.load_addr + pc);
-    }
-
-    fn disable_breakpoint_at_address(&mut self, addr: u64) {
-        match self.breakpoints.get_mut(&addr) {
-            None => {
-                eprintln!("no such breakpoint");
-                return;
+    /// If this hits the return breakpoint of an interrupted finish, the finish
+    /// is completed.
+    pub fn continue_execution(&mut self) -> Result<Stop, DbgError> {
+        let s = if self.sw_watchpoints.is_empty() {
+            loop {
+                self.step_over_breakpoint()?;
+                let s = self.resume_and_wait()?;
+                if !self.skip_breakpoint_hit(s)?
+                    && !self.skip_watchpoint_hit(s)?
+                    && !self.skip_signal(s)
+                {
+                    break s;
+                }
             }
-            Some(bp) => {
-                bp.disable();
+        } else {
+            match self.step_until_sw_watchpoint()? {
+                SwWatchStep::Stopped(s) => s,
+                SwWatchStep::Changed(hits) => return Ok(Stop::new(StopReason::Watchpoint(hits))),
             }
+        };
+        let mut stop = Stop::new(self.handle_stop(s)?);
+        if let StopReason::Breakpoint(pc) = stop.reason {
+            stop.deleted_temporary = self.delete_hit_temporary_breakpoint(self.load_addr + pc);
         }
-    }
-
-    /// Print all the target's registers.
-    ///
-    /// Excludes some extra x86-64 registers, like floating-pointer and vector
-    /// registers.
-    pub fn dump_registers(&self) {
-        let regs = self.target.getregs().unwrap();
-        let width = enum_iterator::all::<Reg>()
-            .map(|r| r.name().len())
-            .max()
-            .unwrap();
-        for r in enum_iterator::all::<Reg>() {
-            let val = r.get_reg(&regs);
-            println!("{:width$} 0x{:016x}", r.name(), val, width = width);
+        if let (StopReason::InternalBreakpoint(pc) | StopReason::Breakpoint(pc), Some(finish)) =
+            (&stop.reason, &self.pending_finish)
+        {
+            if self.load_addr + pc == finish.return_address {
+                let finish = self.pending_finish.take().unwrap();
+                finish.temp_bp.delete_all(self);
+                stop.returned = Some(self.finish_return(finish.return_type)?);
+            }
         }
+        Ok(stop)
     }
 
-    /// Get the value of a single register.
-    pub fn read_register(&self, r: Reg) {
-        let val = self.target.getreg(r).unwrap();
-        println!("0x{:x}", val);
-    }
-
-    /// Set a register in the target.
-    pub fn write_register(&self, r: Reg, val: u64) {
-        self.target.setreg(r, val).unwrap();
-    }
-
-    fn get_pc(&self) -> u64 {
-        self.target.getreg(Reg::Rip).unwrap()
+    /// Single-step, checking the software watchpoints after each instruction,
+    /// until one changes or something else stops the target.
+    fn step_until_sw_watchpoint(&mut self) -> Result<SwWatchStep, io::Error> {
+        let guard = InterruptGuard::install(self.target.pid())?;
+        // the target may have stopped at a breakpoint, which the first step
+        // has to get past, but stepping onto a breakpoint after that runs its
+        // int3 so that it's hit
+        let mut from_breakpoint = true;
+        let s = loop {
+            let s = if from_breakpoint {
+                self.single_step_instruction()?
+            } else {
+                self.resume(Resume::Step)?;
+                self.wait()?
+            };
+            from_breakpoint = false;
+            if self.stopped_for_step(s)? {
+                let hits = self.sw_watchpoint_hits();
+                if !hits.is_empty() {
+                    return Ok(SwWatchStep::Changed(hits));
+                }
+                continue;
+            }
+            if self.skip_breakpoint_hit(s)? {
+                from_breakpoint = true;
+                continue;
+            }
+            if !self.skip_watchpoint_hit(s)? && !self.skip_signal(s) {
+                break s;
+            }
+        };
+        drop(guard);
+        Ok(SwWatchStep::Stopped(s))
     }
 
-    fn get_offset_pc(&self) -> u64 {
-        self.get_pc() - self.load_addr
+    /// Check if a single-step stopped the target only because the step
+    /// finished.
+    fn stopped_for_step(&self, s: WaitStatus) -> Result<bool, io::Error> {
+        if s != (WaitStatus::Stopped {
+            signal: libc::SIGTRAP,
+        }) {
+            return Ok(false);
+        }
+        Ok(match self.target.getsiginfo()?.si_code {
+            TRAP_TRACE => true,
+            // stepping over a syscall instruction reports a trap like an int3,
+            // so only an int3 that's a breakpoint counts as one
+            SI_KERNEL | TRAP_BRKPT => !self.breakpoint_enabled_at(self.get_pc()? - 1),
+            _ => false,
+        })
     }
 
-    fn set_pc(&self, pc: u64) {
-        self.target.setreg(Reg::Rip, pc).unwrap();
+    /// Note that the program has ended.
+    fn target_ended(&mut self) {
+        self.running = false;
+        // the target's breakpoints went with it
+        self.pending_finish = None;
     }
 
-    /// when stopped at a breakpoint, step past it
-    fn step_over_breakpoint(&mut self) {
-        let pc = self.get_pc();
-        if pc == 0 {
-            return;
-        }
-        if let Some(bp) = self.breakpoints.get_mut(&pc) {
-            if bp.enabled() {
-                bp.disable();
-                self.target.singlestep().unwrap();
-                self.target.wait().unwrap();
-                bp.enable();
+    /// Find the software watchpoints whose bytes changed.
+    fn sw_watchpoint_hits(&mut self) -> Vec<WatchpointHit> {
+        let mut hits = vec![];
+        for wp in &mut self.sw_watchpoints {
+            let new = self.target.read_mem(wp.addr, wp.len).ok();
+            if new == wp.old {
+                continue;
             }
+            hits.push(WatchpointHit {
+                kind: None,
+                num: wp.num,
+                text: wp.text.clone(),
+                old: Some(SwWatchpoint::format(&wp.old)),
+                new: SwWatchpoint::format(&new),
+            });
+            wp.old = new;
         }
+        hits
     }
 
-    fn single_step_instruction(&mut self) {
-        let pc = self.get_pc();
-        if self.breakpoints.contains_key(&pc) {
-            self.step_over_breakpoint();
-        } else {
-            self.target.singlestep().unwrap();
-            self.target.wait().unwrap();
+    /// Find out why the target stopped (or exited).
+    fn handle_stop(&mut self, s: WaitStatus) -> Result<StopReason, DbgError> {
+        if let WaitStatus::Exited { status } = s {
+            self.target_ended();
+            return Ok(StopReason::Exited(status.into()));
+        }
+        if let WaitStatus::Signaled { signal } = s {
+            self.target_ended();
+            return Ok(StopReason::Killed(signal));
         }
-    }
-
-    /// Run for a single instruction.
-    pub fn single_step(&mut self) {
-        self.single_step_instruction();
-    }
 
-    fn get_prev_frame(&self, fp: u64) -> (u64, u64) {
-        let frame_pointer = self.target.peekdata(fp).unwrap();
-        let return_addr = self.target.peekdata(fp + 8).unwrap();
-        (frame_pointer, return_addr)
-    }
+        let siginfo = self.target.getsiginfo()?;
+        let signo = siginfo.si_signo;
+        if signo == 0 {
+            // no signal
+            return Ok(StopReason::Signal(0));
+        }
+        if signo == libc::SIGTRAP {
+            return self.handle_sigtrap(siginfo);
+        }
+        // the target gets the signal when it resumes (a SIGSTOP is from
+        // interrupting it, so it isn't the target's)
+        if signo != libc::SIGSTOP && self.signal_handling(signo).pass {
+            self.pending_signal = signo as c_uint;
+        }
+        if signo == libc::SIGSEGV || signo == libc::SIGBUS {
+            let code = siginfo.si_code;
+            let reason = match code {
+                SEGV_MAPERR => "address not mapped".to_string(),
+                SEGV_ACCERR => "invalid permissions".to_string(),
+                _ => display_code(code),
+            };
+            return Ok(StopReason::Fault {
+                signal: signo,
+                addr: unsafe { siginfo.si_addr() } as u64,
+                reason,
+            });
+        }
+        Ok(StopReason::Signal(signo))
+    }
+
+    /// Describe where the target is stopped, as an offset pc with its source
+    /// location if it's in the program, and otherwise (like in a library) as
+    /// the actual pc.
+    pub fn describe_current_location(&self) -> String {
+        let pc = match self.get_pc() {
+            Ok(pc) => pc,
+            Err(err) => return format!("an unknown pc ({})", ptrace::describe_error(&err)),
+        };
+        let offset_pc = pc.wrapping_sub(self.load_addr);
+        match self.info.function_for_pc(offset_pc) {
+            Ok(Some(_)) => {
+                let desc = self.describe_pc(offset_pc).unwrap_or_default();
+                format!("0x{offset_pc:x}: {desc}")
+            }
+            _ => format!("0x{pc:x}, outside the program"),
+        }
+    }
+
+    fn signal_handling(&self, signal: i32) -> SignalHandling {
+        self.signal_handling
+            .get(&signal)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Check if the target stopped with a signal that shouldn't stop it, in
+    /// which case it's queued for delivery (if it should be passed) and the
+    /// target can continue.
+    fn skip_signal(&mut self, s: WaitStatus) -> bool {
+        let signal = match s {
+            WaitStatus::Stopped { signal }
+                if signal != libc::SIGTRAP && signal != libc::SIGSTOP =>
+            {
+                signal
+            }
+            _ => return false,
+        };
+        let handling = self.signal_handling(signal);
+        if handling.stop {
+            return false;
+        }
+        if handling.pass {
+            self.pending_signal = signal as c_uint;
+        }
+        true
+    }
+
+    /// Change how a signal is handled, returning its number and its handling.
+    pub fn handle_signal(
+        &mut self,
+        signal: &str,
+        actions: &[cli::SignalAction],
+    ) -> Result<(i32, SignalHandling), DbgError> {
+        let signo = parse_signal(signal).ok_or_else(|| format!("unknown signal {signal}"))?;
+        if !actions.is_empty() && (signo == libc::SIGTRAP || signo == libc::SIGSTOP) {
+            return Err(format!("{} is used by the debugger", signal_name(signo)).into());
+        }
+        let mut handling = self.signal_handling(signo);
+        for action in actions {
+            match action {
+                cli::SignalAction::Stop => handling.stop = true,
+                cli::SignalAction::Nostop => handling.stop = false,
+                cli::SignalAction::Pass => handling.pass = true,
+                cli::SignalAction::Nopass => handling.pass = false,
+            }
+        }
+        self.signal_handling.insert(signo, handling);
+        Ok((signo, handling))
+    }
+
+    /// Describe where the target stopped as a JSON event, or None if it isn't
+    /// running.
+    pub fn stop_event(&self) -> Option<serde_json::Value> {
+        if !self.running {
+            return None;
+        }
+        let pc = self.get_offset_pc().ok()?;
+        let mut event = json!({"event": "stop", "pc": pc});
+        if let Ok(frame) = self.info.frame_for_pc(pc) {
+            event["file"] = frame.file_suffix_or("??").into();
+            event["line"] = frame.line.into();
+            event["function"] = frame.inner_function().map(|f| f.into_owned()).into();
+        }
+        Some(event)
+    }
+
+    /// Whether stops and exits are reported as JSON events.
+    pub fn json_events(&self) -> bool {
+        self.json_events
+    }
+
+    pub fn set_json_events(&mut self, on: bool) {
+        self.json_events = on;
+        // the output is for a program to read
+        if on {
+            self.pager.set_enabled(false);
+        }
+    }
+
+    /// Describe the source location of an offset pc as "file F, line L (in
+    /// func)", or None if there's no debug info for it.
+    pub fn describe_pc(&self, pc: u64) -> Option<String> {
+        let frame = self.info.frame_for_pc(pc).ok()?;
+        let file = frame.file_suffix_or("??");
+        let line = frame.line_or("??");
+        let func = frame.inner_function().unwrap_or(Cow::Borrowed("??"));
+        Some(format!("file {file}, line {line} (in {func})"))
+    }
+
+    /// Find the source line of each address, like addr2line.
+    ///
+    /// The addresses are read from the file (if any), one per line, followed
+    /// by addrs. Runtime addresses are converted to offsets into the binary.
+    pub fn lines_for_addresses(
+        &self,
+        addrs: &[u64],
+        file: Option<&Path>,
+    ) -> Result<Vec<AddressLine>, DbgError> {
+        let mut all = vec![];
+        if let Some(file) = file {
+            let contents = fs::read_to_string(file)
+                .map_err(|err| format!("could not read {}: {err}", file.display()))?;
+            for (i, line) in contents.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                let addr = cli::maybe_hex(line).map_err(|err| {
+                    format!("{}:{}: bad address {line}: {err}", file.display(), i + 1)
+                })?;
+                all.push(addr);
+            }
+        }
+        all.extend_from_slice(addrs);
+        Ok(all
+            .into_iter()
+            .map(|addr| {
+                let pc = if addr >= self.load_addr {
+                    addr - self.load_addr
+                } else {
+                    addr
+                };
+                let mut found = AddressLine {
+                    addr,
+                    file: None,
+                    line: None,
+                    function: None,
+                };
+                if let Ok(frame) = self.info.frame_for_pc(pc) {
+                    found.file = frame.file.map(|_| frame.file_suffix_or("??").to_string());
+                    found.line = frame.line;
+                    found.function = frame.inner_function().map(|f| f.into_owned());
+                }
+                found
+            })
+            .collect())
+    }
+
+    /// Find the source line of the current pc, if there is one, and continue
+    /// a bare `list` after the lines around it.
+    pub fn current_source(&mut self) -> Result<Option<(PathBuf, usize)>, DbgError> {
+        match self.get_offset_pc() {
+            Ok(pc) => self.source_for_pc(pc),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Find the source line of an offset pc, if there is one, and continue a
+    /// bare `list` after the lines around it.
+    fn source_for_pc(&mut self, pc: u64) -> Result<Option<(PathBuf, usize)>, DbgError> {
+        let loc = self
+            .info
+            .source_for_pc(pc)
+            .map_err(|err| format!("could not look up source: {err}"))?;
+        // code without line info (say, from the standard library) has nothing
+        // to show
+        let Some((file, line)) = loc.and_then(|loc| Some((loc.file?, loc.line? as usize))) else {
+            return Ok(None);
+        };
+        let path = self.source_path(file, pc);
+        self.list_pos = Some((path.clone(), line + self.listsize + 1));
+        Ok(Some((path, line)))
+    }
+
+    /// Find a source file from the debug info for the code at pc on this
+    /// machine.
+    fn source_path(&self, file: &str, pc: u64) -> PathBuf {
+        let comp_dir = self.info.compilation_dir(pc);
+        source::find_source(
+            Path::new(file),
+            comp_dir.as_deref(),
+            &self.substitute_paths,
+            &self.source_dirs,
+        )
+    }
+
+    fn breakpoint_enabled_at(&self, addr: u64) -> bool {
+        self.breakpoints
+            .get(&addr)
+            .map(|bp| bp.enabled())
+            .unwrap_or(false)
+    }
+
+    fn stopped_at_internal_breakpoint(&self) -> bool {
+        self.get_pc().is_ok_and(|pc| {
+            self.breakpoints
+                .get(&pc)
+                .map(|bp| bp.is_internal())
+                .unwrap_or(false)
+        })
+    }
+
+    /// Set a breakpoint based on address
+    ///
+    /// The pc here is an offset into the binary, not the actual program counter
+    /// (which will be offset by the load address).
+    pub fn set_user_breakpoint(&mut self, pc: u64) -> NewBreakpoint {
+        self.set_user_breakpoints(&[pc])
+    }
+
+    /// Set one user breakpoint at several locations, given as offset pcs.
+    pub fn set_user_breakpoints(&mut self, pcs: &[u64]) -> NewBreakpoint {
+        let id = self.next_breakpoint_id;
+        self.next_breakpoint_id += 1;
+        let locations = pcs
+            .iter()
+            .map(|&pc| BreakpointLocation {
+                pc,
+                error: self
+                    .set_breakpoint_at_address(self.load_addr + pc, BreakpointSource::User { id })
+                    .err(),
+                desc: self.describe_pc(pc),
+            })
+            .collect();
+        NewBreakpoint {
+            id,
+            moved: None,
+            locations,
+        }
+    }
+
+    /// internal method to add a breakpoint
+    fn set_breakpoint_at_address(
+        &mut self,
+        addr: u64,
+        source: BreakpointSource,
+    ) -> Result<(), DbgError> {
+        let bp = self
+            .breakpoints
+            .entry(addr)
+            .or_insert_with(|| Breakpoint::new(self.threads.main(), addr, source));
+        // TODO: ought to set bp.source to source if source if User (and then
+        // make sure it doesn't get cleaned up accidentally)
+        if bp.enabled() {
+            return Err(
+                format!("already have a breakpoint at 0x{:x}", addr - self.load_addr).into(),
+            );
+        }
+        bp.enable().map_err(|err| {
+            format!(
+                "could not set breakpoint at 0x{:x}: {err}",
+                addr - self.load_addr
+            )
+            .into()
+        })
+    }
+
+    /// Find the pcs for a source location (file and line number), along with
+    /// the line they're on, which is the next line with code if this one has
+    /// none.
+    pub fn source_location_pcs(
+        &self,
+        file: &str,
+        line: usize,
+    ) -> Result<(usize, Vec<u64>), DbgError> {
+        let (found_line, pcs) = self
+            .info
+            .pcs_for_source_loc(|path| path.ends_with(file), line)
+            .map_err(|err| format!("could not look up source: {err}"))?;
+        if pcs.is_empty() {
+            return Err(format!("could not find {}:{}", file, line).into());
+        }
+        Ok((found_line, pcs))
+    }
+
+    /// Find the pc where a function's body starts, after its prologue.
+    fn function_start_pc(&self, needle: &str) -> Result<u64, DbgError> {
+        let pc = self
+            .info
+            .pc_for_function_pred(|name| name == needle)?
+            .ok_or_else(|| format!("couldn't find function matching {}", needle))?;
+        Ok(self.info.function_body_pc(pc)?)
+    }
+
+    /// Resolve a location to an offset pc, the same way as for a breakpoint.
+    fn location_pc(&self, loc: &BreakpointLoc) -> Result<u64, DbgError> {
+        match loc {
+            BreakpointLoc::Addr { pc } => Ok(*pc),
+            // TODO: a line can have several locations, but this only uses the
+            // first
+            BreakpointLoc::Line { file, line } => Ok(self.source_location_pcs(file, *line)?.1[0]),
+            BreakpointLoc::Function { name } => self.function_start_pc(name),
+        }
+    }
+
+    /// Find the source file and line for a location, to center a listing on.
+    fn list_center(&self, loc: &BreakpointLoc) -> Result<(PathBuf, usize), DbgError> {
+        let pc = match loc {
+            // any code in the file will do to find its path, so that lines
+            // without code can be listed too
+            BreakpointLoc::Line { file, .. } => self.source_location_pcs(file, 1)?.1[0],
+            _ => self.location_pc(loc)?,
+        };
+        let found = self
+            .info
+            .source_for_pc(pc)
+            .map_err(|err| format!("could not look up source: {err}"))?;
+        let (file, found_line) = found
+            .and_then(|l| Some((l.file?, l.line?)))
+            .ok_or_else(|| format!("no source for 0x{pc:x}"))?;
+        let line = match loc {
+            BreakpointLoc::Line { line, .. } => *line,
+            _ => found_line as usize,
+        };
+        Ok((self.source_path(file, pc), line))
+    }
+
+    /// Get the source around a location, or with no location continue the
+    /// last listing (starting around `main` if there hasn't been one).
+    pub fn list_source(&mut self, loc: Option<&BreakpointLoc>) -> Result<Listing, DbgError> {
+        let (path, start) = match (loc, self.list_pos.clone()) {
+            (None, Some(pos)) => pos,
+            (loc, _) => {
+                let main = BreakpointLoc::Function {
+                    name: "main".to_string(),
+                };
+                let (path, line) = self.list_center(loc.unwrap_or(&main))?;
+                (path, line.saturating_sub(self.listsize).max(1))
+            }
+        };
+        let end = start + 2 * self.listsize;
+        let lines = source::read_lines(&path, start, end)
+            .map_err(|err| format!("could not print source from {}: {}", path.display(), err))?;
+        if lines.is_empty() {
+            return Err(format!("line {start} is past the end of {}", path.display()).into());
+        }
+        self.list_pos = Some((path.clone(), end + 1));
+        Ok(Listing {
+            path,
+            start,
+            end,
+            lines,
+        })
+    }
+
+    /// Set a breakpoint by source location (file and line number)
+    pub fn set_breakpoint_at_source_location(
+        &mut self,
+        file: &str,
+        line: usize,
+    ) -> Result<NewBreakpoint, DbgError> {
+        let (found_line, pcs) = self.source_location_pcs(file, line)?;
+        let mut bp = self.set_user_breakpoints(&pcs);
+        if found_line != line {
+            bp.moved = Some((line, found_line));
+        }
+        Ok(bp)
+    }
+
+    /// Set a breakpoint at a function's start, by name.
+    pub fn set_breakpoint_at_function(&mut self, needle: &str) -> Result<NewBreakpoint, DbgError> {
+        let pc = self.function_start_pc(needle)?;
+        Ok(self.set_user_breakpoint(pc))
+    }
+
+    /// Make every location of user breakpoint `id` conditional.
+    pub fn set_breakpoint_condition(&mut self, id: usize, cond: Condition) {
+        for bp in self.breakpoints.values_mut() {
+            if bp.source == (BreakpointSource::User { id }) {
+                bp.condition = Some(cond);
+            }
+        }
+    }
+
+    /// Make user breakpoint `id` temporary, so it's deleted when first hit.
+    pub fn set_breakpoint_temporary(&mut self, id: usize) {
+        for bp in self.breakpoints.values_mut() {
+            if bp.source == (BreakpointSource::User { id }) {
+                bp.temporary = true;
+            }
+        }
+    }
+
+    /// If the target stopped at a temporary breakpoint, delete all of its
+    /// locations, returning its id.
+    fn delete_hit_temporary_breakpoint(&mut self, pc: u64) -> Option<usize> {
+        let source = match self.breakpoints.get(&pc) {
+            Some(bp) if bp.temporary => bp.source,
+            _ => return None,
+        };
+        let load_addr = self.load_addr;
+        self.breakpoints.retain(|_, bp| {
+            if bp.source != source {
+                return true;
+            }
+            if bp.enabled() {
+                if let Err(err) = bp.disable() {
+                    self.warnings.push(format!(
+                        "could not remove breakpoint at 0x{:x}: {err}",
+                        bp.addr - load_addr
+                    ));
+                }
+            }
+            false
+        });
+        match source {
+            BreakpointSource::User { id } => Some(id),
+            BreakpointSource::Internal { .. } => None,
+        }
+    }
+
+    /// Set or remove (with None) the condition of the user breakpoint at a pc
+    /// (an offset into the binary).
+    pub fn set_condition_at(&mut self, pc: u64, cond: Option<Condition>) -> Result<(), DbgError> {
+        match self.breakpoints.get_mut(&(self.load_addr + pc)) {
+            Some(bp) if !bp.is_internal() => bp.condition = cond,
+            _ => return Err("no such breakpoint".to_string().into()),
+        }
+        Ok(())
+    }
+
+    /// Set how many more times the user breakpoint at a pc (an offset into
+    /// the binary) is passed before it stops the target.
+    pub fn ignore_breakpoint(&mut self, pc: u64, count: u64) -> Result<(), DbgError> {
+        match self.breakpoints.get_mut(&(self.load_addr + pc)) {
+            Some(bp) if !bp.is_internal() => bp.ignore_count = count,
+            _ => return Err("no such breakpoint".to_string().into()),
+        }
+        Ok(())
+    }
+
+    /// Check if the target stopped at a user breakpoint that should be passed
+    /// silently, because its condition is false or it has hits left to
+    /// ignore. If so, the pc is moved back to the breakpoint so it can
+    /// continue.
+    fn skip_breakpoint_hit(&mut self, s: WaitStatus) -> Result<bool, io::Error> {
+        if s != (WaitStatus::Stopped {
+            signal: libc::SIGTRAP,
+        }) {
+            return Ok(false);
+        }
+        let code = self.target.getsiginfo()?.si_code;
+        if code != SI_KERNEL && code != TRAP_BRKPT {
+            return Ok(false);
+        }
+        let pc = self.get_pc()? - 1;
+        let bp = match self.breakpoints.get(&pc) {
+            Some(bp) => *bp,
+            None => return Ok(false),
+        };
+        if let BreakpointSource::Internal { thread } = bp.source {
+            if thread == self.target.pid() {
+                return Ok(false);
+            }
+            // another thread's stepping command set it: step this thread
+            // past it and go back to the thread that's stepping
+            self.set_pc(pc)?;
+            self.step_over_breakpoint()?;
+            self.target = ptrace::Target::new(thread);
+            return Ok(true);
+        }
+
+        if let Some(cond) = bp.condition {
+            if !cond.holds(self.target.getreg(cond.reg)?) {
+                self.set_pc(pc)?;
+                return Ok(true);
+            }
+        }
+        if bp.ignore_count > 0 {
+            self.breakpoints.get_mut(&pc).unwrap().ignore_count -= 1;
+            self.set_pc(pc)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Check if the target stopped only for writes to read watchpoints, which
+    /// x86 can't tell apart from reads, so it should keep going.
+    fn skip_watchpoint_hit(&mut self, s: WaitStatus) -> Result<bool, io::Error> {
+        if s != (WaitStatus::Stopped {
+            signal: libc::SIGTRAP,
+        }) {
+            return Ok(false);
+        }
+        if self.target.getsiginfo()?.si_code != TRAP_HWBKPT {
+            return Ok(false);
+        }
+        let dr6 = self.target.get_debugreg(Watchpoint::DR6)?;
+        let mut writes = vec![];
+        for slot in (0..Watchpoint::SLOTS).filter(|slot| dr6 & (1 << slot) != 0) {
+            match &self.watchpoints[slot] {
+                Some(wp) if wp.kind == WatchKind::Read => {
+                    let new = self.target.read_mem(wp.addr, wp.len).ok();
+                    if new == wp.old {
+                        return Ok(false);
+                    }
+                    writes.push((slot, new));
+                }
+                _ => return Ok(false),
+            }
+        }
+        for (slot, new) in writes {
+            self.watchpoints[slot].as_mut().unwrap().old = new;
+        }
+        self.target.set_debugreg(Watchpoint::DR6, dr6 & !0b1111)?;
+        Ok(true)
+    }
+
+    /// Get the variables in scope at a location, along with the location's
+    /// offset pc.
+    pub fn scope_variables(
+        &self,
+        loc: &BreakpointLoc,
+    ) -> Result<(u64, Vec<dwarf::Variable>), DbgError> {
+        let pc = self.location_pc(loc)?;
+        Ok((pc, self.info.variables_in_scope(pc)?))
+    }
+
+    /// Disable a user breakpoint by address
+    ///
+    /// See [`set_user_breakpoint`](#set_user_breakpoint) for the interpretation of pc.
+    pub fn disable_user_breakpoint(&mut self, pc: u64) -> Result<(), DbgError> {
+        self.disable_breakpoint_at_address(self.load_addr + pc)
+    }
+
+    /// Turn a disabled breakpoint at a pc (an offset into the binary) back on.
+    pub fn enable_user_breakpoint(&mut self, pc: u64) -> Result<(), DbgError> {
+        match self.breakpoints.get_mut(&(self.load_addr + pc)) {
+            None => Err("no such breakpoint".to_string().into()),
+            Some(bp) if bp.enabled() => {
+                Err(format!("breakpoint at 0x{pc:x} is already enabled").into())
+            }
+            Some(bp) => bp
+                .enable()
+                .map_err(|err| format!("could not enable breakpoint at 0x{pc:x}: {err}").into()),
+        }
+    }
+
+    /// Remove the user breakpoint at a pc (an offset into the binary), so the
+    /// address is free for a new breakpoint.
+    pub fn delete_user_breakpoint(&mut self, pc: u64) -> Result<(), DbgError> {
+        let addr = self.load_addr + pc;
+        let bp = match self.breakpoints.get_mut(&addr) {
+            None => return Err("no such breakpoint".to_string().into()),
+            Some(bp) if bp.is_internal() => {
+                return Err(
+                    format!("cannot delete the debugger's internal breakpoint at 0x{pc:x}").into(),
+                )
+            }
+            Some(bp) => bp,
+        };
+        // once the program is gone there's no int3 to remove
+        let result = if self.running && bp.enabled() {
+            bp.disable()
+                .map_err(|err| format!("could not remove breakpoint at 0x{pc:x}: {err}").into())
+        } else {
+            Ok(())
+        };
+        self.breakpoints.remove(&addr);
+        result
+    }
+
+    fn disable_breakpoint_at_address(&mut self, addr: u64) -> Result<(), DbgError> {
+        let running = self.running;
+        match self.breakpoints.get_mut(&addr) {
+            None => Err("no such breakpoint".to_string().into()),
+            Some(bp) if !bp.enabled() => Err(format!(
+                "breakpoint at 0x{:x} is already disabled",
+                addr - self.load_addr
+            )
+            .into()),
+            // once the program is gone there's no int3 to remove
+            Some(bp) if !running => {
+                bp.saved_data = None;
+                Ok(())
+            }
+            Some(bp) => bp
+                .disable()
+                .map_err(|err| format!("could not disable breakpoint: {err}").into()),
+        }
+    }
+
+    /// Get all the target's general-purpose registers.
+    ///
+    /// Excludes some extra x86-64 registers, like floating-pointer and vector
+    /// registers.
+    pub fn registers(&self) -> Result<Vec<(Reg, u64)>, io::Error> {
+        let regs = self.target.getregs()?;
+        Ok(enum_iterator::all::<Reg>()
+            .map(|r| (r, r.get_reg(&regs)))
+            .collect())
+    }
+
+    /// Describe an (absolute) address as a symbol plus an offset, like
+    /// `func+0x1c`.
+    pub fn symbol_offset(&self, addr: u64) -> Option<String> {
+        let (sym, offset) = self
+            .info
+            .symbol_for_addr(addr.checked_sub(self.load_addr)?)?;
+        if offset == 0 {
+            Some(sym.name)
+        } else {
+            Some(format!("{}+0x{offset:x}", sym.name))
+        }
+    }
+
+    /// Get the floating-point registers, which [`format_float_register`]
+    /// shows.
+    pub fn float_registers(&self) -> Result<libc::user_fpregs_struct, DbgError> {
+        self.target
+            .getfpregs()
+            .map_err(|err| format!("could not read floating-point registers: {err}").into())
+    }
+
+    /// Get the bytes of the vector registers (or just reg): the ymm registers
+    /// with AVX, and otherwise the xmm registers.
+    pub fn vector_registers(
+        &self,
+        reg: Option<VecReg>,
+    ) -> Result<Vec<(VecReg, Vec<u8>)>, DbgError> {
+        let (ymm, size) = self.read_vector_registers()?;
+        let regs = match reg {
+            Some(reg) if reg.size > size => {
+                return Err(format!("{} is not available without AVX", reg.name()).into())
+            }
+            Some(reg) => vec![reg],
+            None => (0..vecregs::NUM_REGS)
+                .map(|num| VecReg { num, size })
+                .collect(),
+        };
+        Ok(regs
+            .into_iter()
+            .map(|reg| (reg, ymm[reg.num][..reg.size].to_vec()))
+            .collect())
+    }
+
+    /// Read the vector registers, along with how many bytes of each there
+    /// are: 32 (ymm) with AVX, and otherwise 16 (xmm).
+    fn read_vector_registers(&self) -> Result<(Vec<[u8; 32]>, usize), String> {
+        let xstate = match self.target.getxstate() {
+            Ok(xstate) => xstate,
+            // without XSAVE, the xmm registers are in the FXSAVE area
+            Err(_) => {
+                let fpregs = self
+                    .target
+                    .getfpregs()
+                    .map_err(|err| format!("could not read vector registers: {err}"))?;
+                let bytes: Vec<u8> = fpregs
+                    .xmm_space
+                    .iter()
+                    .flat_map(|w| w.to_le_bytes())
+                    .collect();
+                let xmm = bytes
+                    .chunks(16)
+                    .take(vecregs::NUM_REGS)
+                    .map(|b| {
+                        let mut ymm = [0u8; 32];
+                        ymm[..16].copy_from_slice(b);
+                        ymm
+                    })
+                    .collect();
+                return Ok((xmm, 16));
+            }
+        };
+        let avx_offset = vecregs::avx_offset().filter(|_| vecregs::has_avx(&xstate));
+        let size = if avx_offset.is_some() { 32 } else { 16 };
+        Ok((vecregs::ymm_registers(&xstate, avx_offset), size))
+    }
+
+    /// Get the target's command line, as read from /proc.
+    pub fn cmdline(&self) -> Result<Vec<String>, DbgError> {
+        if !self.running {
+            return Err("the program is not running".to_string().into());
+        }
+        procinfo::read_cmdline(self.target_pid())
+            .map_err(|err| format!("could not read command line: {err}").into())
+    }
+
+    /// Get what the target process is running.
+    pub fn target_info(&self) -> Result<TargetInfo, DbgError> {
+        if !self.running {
+            return Err("the program is not running".to_string().into());
+        }
+        let pid = self.target_pid();
+        Ok(TargetInfo {
+            pid,
+            exe: procinfo::read_exe(pid)
+                .map_err(|err| format!("could not read executable: {err}").into()),
+            cmdline: self.cmdline(),
+            load_addr: self.load_addr,
+        })
+    }
+
+    /// Get the debug registers, by number: the address registers dr0-dr3,
+    /// then the status and control registers, dr6 and dr7.
+    pub fn debug_registers(&self) -> Result<Vec<(usize, u64)>, DbgError> {
+        (0..debugregs::SLOTS)
+            .chain([debugregs::DR6, debugregs::DR7])
+            .map(|n| match self.target.get_debugreg(n) {
+                Ok(val) => Ok((n, val)),
+                Err(err) => Err(format!("could not read dr{n}: {err}").into()),
+            })
+            .collect()
+    }
+
+    /// Find the mapping an address is in, and what's there if it's in the
+    /// program.
+    pub fn mapping(&self, text: &str) -> Result<AddressMapping, DbgError> {
+        let addr = Expr::parse(text).and_then(|e| e.eval(self))?;
+        let maps = maps::read_maps(self.threads.main().pid())
+            .map_err(|err| format!("could not read mappings: {err}"))?;
+        let mapping = maps.into_iter().find(|m| m.contains(addr));
+        let exe = fs::read_link(format!("/proc/{}/exe", self.threads.main().pid()));
+        let in_program = mapping
+            .as_ref()
+            .is_some_and(|m| exe.is_ok_and(|exe| exe == Path::new(&m.path)));
+        let function = if in_program {
+            let pc = addr - self.load_addr;
+            match self.info.function_for_pc(pc) {
+                Ok(Some((func, start))) => Some((pc, func, pc - start)),
+                _ => None,
+            }
+        } else {
+            None
+        };
+        Ok(AddressMapping {
+            addr,
+            mapping,
+            function,
+        })
+    }
+
+    /// Get the value of a single register.
+    pub fn read_register(&self, r: Reg) -> Result<u64, io::Error> {
+        self.target.getreg(r)
+    }
+
+    /// Set a register in the target.
+    pub fn write_register(&self, r: Reg, val: u64) -> Result<(), io::Error> {
+        self.target.setreg(r, val)
+    }
+
+    fn get_pc(&self) -> Result<u64, io::Error> {
+        self.target.getreg(Reg::Rip)
+    }
+
+    fn get_offset_pc(&self) -> Result<u64, io::Error> {
+        Ok(self.get_pc()? - self.load_addr)
+    }
+
+    fn set_pc(&self, pc: u64) -> Result<(), io::Error> {
+        self.target.setreg(Reg::Rip, pc)
+    }
+
+    /// If the pc is at an enabled breakpoint, step past it, returning how the
+    /// target stopped after the step.
+    fn step_over_breakpoint(&mut self) -> Result<Option<WaitStatus>, io::Error> {
+        let pc = self.get_pc()?;
+        match self.breakpoints.get_mut(&pc) {
+            Some(bp) if pc != 0 && bp.enabled() => bp.disable()?,
+            _ => return Ok(None),
+        }
+        // the other threads stay stopped, so they can't run past the
+        // breakpoint while it's removed
+        let signal = self.take_pending_signal();
+        let stepped = self
+            .threads
+            .step_alone(self.target, signal)
+            .map(|(thread, s)| {
+                self.stopped(thread);
+                s
+            });
+        // put the breakpoint back even if the step failed (unless the step
+        // ended the program)
+        if self.running {
+            if let Some(bp) = self.breakpoints.get_mut(&pc) {
+                bp.enable()?;
+            }
+        }
+        stepped.map(Some)
+    }
+
+    /// Run for a single instruction, returning how the target stopped.
+    fn single_step_instruction(&mut self) -> Result<WaitStatus, io::Error> {
+        if let Some(s) = self.step_over_breakpoint()? {
+            return Ok(s);
+        }
+        self.resume(Resume::Step)?;
+        self.wait()
+    }
+
+    /// Run for a single instruction, returning the stop if the program ended
+    /// instead.
+    fn step_instruction_or_exit(&mut self) -> Result<Option<Stop>, DbgError> {
+        let thread = self.target;
+        match self.single_step_instruction()? {
+            s @ (WaitStatus::Exited { .. } | WaitStatus::Signaled { .. }) => {
+                Ok(Some(Stop::new(self.handle_stop(s)?)))
+            }
+            // another thread stopped before the step finished
+            s if self.target != thread => Ok(Some(Stop::new(self.handle_stop(s)?))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Run for a single instruction.
+    ///
+    /// A `rep`-prefixed string instruction is run to completion, rather than
+    /// for one iteration.
+    pub fn single_step(&mut self) -> Result<Stop, DbgError> {
+        let pc = self.get_pc()?;
+        let repeated = matches!(self.instruction_at(pc), Ok(Some(instr)) if instr.repeated);
+        if let Some(stop) = self.step_instruction_or_exit()? {
+            return Ok(stop);
+        }
+        while repeated && self.get_pc()? == pc {
+            // stop if something other than the step (such as a fault) stopped
+            // the target in the middle of the instruction
+            match self.target.getsiginfo() {
+                Ok(siginfo) if siginfo.si_signo == libc::SIGTRAP => {}
+                _ => break,
+            }
+            if let Some(stop) = self.step_instruction_or_exit()? {
+                return Ok(stop);
+            }
+        }
+        Ok(Stop::new(StopReason::Step))
+    }
+
+    /// Run for a single instruction, but run a call until it returns.
+    pub fn next_instruction(&mut self) -> Result<Stop, DbgError> {
+        self.stepping(|dbg| {
+            let pc = dbg.get_pc()?;
+            let return_address = match dbg.instruction_at(pc) {
+                Ok(Some(instr)) if instr.call => instr.next_addr(),
+                _ => return dbg.single_step(),
+            };
+            let sp = dbg.target.getreg(Reg::Rsp)?;
+            let stop = dbg.run_until_return(return_address, sp)?;
+            Ok(stop.unwrap_or_else(|| Stop::new(StopReason::Step)))
+        })
+    }
+
+    /// Run until a call made with the stack pointer at sp returns to
+    /// return_address, returning the stop if something else stops the target
+    /// first.
+    fn run_until_return(&mut self, return_address: u64, sp: u64) -> Result<Option<Stop>, DbgError> {
+        let mut temp_bp = TempBreakpoints::new();
+        temp_bp.ensure_breakpoint(self, return_address);
+        let stop = loop {
+            let stop = match self.continue_execution() {
+                Ok(stop) => stop,
+                Err(err) => {
+                    temp_bp.delete_all(self);
+                    return Err(err);
+                }
+            };
+            if !matches!(stop.reason, StopReason::InternalBreakpoint(_)) || stop.returned.is_some()
+            {
+                break Some(stop);
+            }
+            let pc = self.get_pc()?;
+            if pc != return_address {
+                break Some(stop);
+            }
+            // a recursive call reaches the return address in a deeper frame,
+            // with a lower stack pointer than before the call
+            if self.target.getreg(Reg::Rsp)? >= sp {
+                break None;
+            }
+        };
+        temp_bp.delete_all(self);
+        Ok(stop)
+    }
+
+    /// Find the branch that took the target from start to pc, by following
+    /// the code from start.
+    ///
+    /// Returns None if pc is reached without taking a branch.
+    fn find_taken_branch(&self, start: u64, pc: u64) -> Option<disasm::Instr> {
+        // bound the search in case the branch isn't found
+        const MAX_INSTRS: usize = 4096;
+        let mut addr = start;
+        for _ in 0..MAX_INSTRS {
+            let instr = self.instruction_at(addr).ok()??;
+            match instr.branch {
+                None => {}
+                Some(branch) if branch.target == Some(pc) => return Some(instr),
+                // conditional branches that weren't taken fall through
+                Some(branch) if branch.conditional => {}
+                // the first unconditional control transfer must have been the
+                // one that was taken
+                Some(_) => return Some(instr),
+            }
+            if instr.next_addr() == pc {
+                return None;
+            }
+            addr = instr.next_addr();
+        }
+        None
+    }
+
+    /// Run until the target takes a branch (a jump, call, or return), and
+    /// return the branch's source and destination.
+    pub fn step_to_branch(&mut self) -> Result<Stop, DbgError> {
+        self.stepping(Self::step_to_taken_branch)
+    }
+
+    fn step_to_taken_branch(&mut self) -> Result<Stop, DbgError> {
+        loop {
+            let start = self.get_pc()?;
+            if self.breakpoint_enabled_at(start) {
+                // single-step past the int3 first
+                self.step_over_breakpoint()?;
+            } else {
+                let thread = self.target;
+                self.resume(Resume::Block)?;
+                let s = self.wait()?;
+                let siginfo = match s {
+                    WaitStatus::Stopped {
+                        signal: libc::SIGTRAP,
+                    } if self.target == thread => self.target.getsiginfo()?,
+                    _ => return Ok(Stop::new(self.handle_stop(s)?)),
+                };
+                if siginfo.si_code != TRAP_TRACE {
+                    // hit a breakpoint before any branch
+                    return Ok(Stop::new(self.handle_sigtrap(siginfo)?));
+                }
+            }
+            let pc = self.get_pc()?;
+            // no branch is found when stepping past a breakpoint, or when BTF
+            // isn't supported and the kernel single-stepped instead
+            if let Some(branch) = self.find_taken_branch(start, pc) {
+                return Ok(Stop::new(StopReason::Branch {
+                    addr: branch.addr - self.load_addr,
+                    text: branch.text,
+                    target: pc - self.load_addr,
+                }));
+            }
+        }
+    }
+
+    /// Follow a saved frame pointer to the caller's frame pointer and return
+    /// address, or None if it doesn't point to readable memory.
+    fn get_prev_frame(&self, fp: u64) -> Option<(u64, u64)> {
+        let frame_pointer = self.target.peekdata(fp).ok()?;
+        let return_addr = self.target.peekdata(fp + 8).ok()?;
+        Some((frame_pointer, return_addr))
+    }
+
+    /// Unwind the current frame with eh_frame. Returns None if eh_frame
+    /// doesn't cover the pc.
+    fn current_caller_frame(&self) -> Result<Option<CallerFrame>, DbgError> {
+        let frame = self.innermost_frame_regs()?;
+        let regs = UnwindRegs::new(self.target, &frame)?;
+        Ok(self.info.get_unwind_caller_frame(frame.pc, &regs)?)
+    }
+
+    /// Get the current function's return address, from eh_frame if it covers
+    /// the pc (which works without frame pointers) and otherwise from the
+    /// saved frame pointer. Returns None in the outermost frame, where there's
+    /// no frame pointer.
+    fn get_current_return_address(&self) -> Result<Option<u64>, DbgError> {
+        if let Some(frame) = self.current_caller_frame()? {
+            return Ok(Some(frame.ra));
+        }
+        let frame_pointer = self.target.getreg(Reg::Rbp)?;
+        Ok(self.target.peekdata(frame_pointer + 8).ok())
+    }
+
+    /// Step until the current function exits.
+    ///
+    /// The stop has where the function returned to once it returns.
+    ///
+    /// If something else stops the target first (like a breakpoint in the
+    /// function), the return breakpoint stays set, so that continuing still
+    /// stops when the function returns.
+    pub fn step_out(&mut self) -> Result<Stop, DbgError> {
+        // a new finish replaces one that was interrupted
+        if let Some(finish) = self.pending_finish.take() {
+            finish.temp_bp.delete_all(self);
+        }
+        let return_address = match self.get_current_return_address()? {
+            Some(return_address) => return_address,
+            // returning from the outermost frame means exiting
+            None => return self.continue_execution(),
+        };
+
+        let return_type = match self.get_offset_pc() {
+            Ok(pc) => self.info.return_type_for_pc(pc).unwrap_or_else(|err| {
+                self.warnings
+                    .push(format!("could not read debug info: {err}"));
+                None
+            }),
+            Err(_) => None,
+        };
+        let mut temp_bp = TempBreakpoints::new();
+        temp_bp.ensure_breakpoint(self, return_address);
+        self.pending_finish = Some(PendingFinish {
+            return_address,
+            temp_bp,
+            return_type,
+        });
+
+        self.continue_execution()
+    }
+
+    /// Work out how to pop the selected frame (and the frames it called)
+    /// without running the rest of its code, returning to its caller with
+    /// `value` in rax if given. Nothing changes until the result is passed to
+    /// [`Dbg::force_return`], so a frontend can warn first.
+    ///
+    /// The caller's stack pointer and callee-saved registers are the ones
+    /// unwinding recovers from eh_frame.
+    pub fn prepare_return(&self, value: Option<u64>) -> Result<ForcedReturn, DbgError> {
+        if !self.running {
+            return Err("the program is not running".to_string().into());
+        }
+        let (frames, _) = self.unwind();
+        let caller = match frames.get(self.selected_frame + 1) {
+            Some(caller) => *caller,
+            None => return Err("can't return from the outermost frame".to_string().into()),
+        };
+        let mut regs = self.target.getregs()?;
+        Reg::Rip.set_reg(&mut regs, caller.pc + self.load_addr);
+        Reg::Rsp.set_reg(&mut regs, caller.rsp);
+        Reg::Rbp.set_reg(&mut regs, caller.rbp);
+        for (reg, &val) in dwarf::CALLEE_SAVED.iter().zip(&caller.saved) {
+            reg.set_reg(&mut regs, val);
+        }
+        if let Some(value) = value {
+            Reg::Rax.set_reg(&mut regs, value);
+        }
+        Ok(ForcedReturn { regs })
+    }
+
+    /// Pop frames as worked out by [`Dbg::prepare_return`], and select the
+    /// frame returned to.
+    pub fn force_return(&mut self, ret: ForcedReturn) -> Result<(), DbgError> {
+        self.target.setregs(&ret.regs)?;
+        self.selected_frame = 0;
+        Ok(())
+    }
+
+    /// Get the location a finish returned to, and the value the function
+    /// returned (saving it in the value history).
+    fn finish_return(&mut self, return_type: Option<Type>) -> Result<Return, DbgError> {
+        let pc = self.get_offset_pc()?;
+        let ty = match return_type {
+            Some(ty) if ty.size() > 0 => ty,
+            _ => return Ok(Return { pc, value: None }),
+        };
+        let value = self.read_return_value(&ty).map(|bytes| {
+            let formatted = self.read_typed_value(ValueLocation::Bytes(bytes.clone()), &ty);
+            self.history.push(Value {
+                bytes,
+                ty: Some(ty),
+            });
+            (self.history.len(), formatted.to_string())
+        });
+        Ok(Return {
+            pc,
+            value: Some(value),
+        })
+    }
+
+    /// Read a value a function just returned, from the registers the System V
+    /// ABI returns it in: xmm0 for a float, and otherwise rax and then rdx.
+    ///
+    /// TODO: small structs with float fields are returned in xmm registers,
+    /// and are read wrongly
+    fn read_return_value(&self, ty: &Type) -> Result<Vec<u8>, String> {
+        let size = ty.size();
+        if size > 16 {
+            return Err(format!(
+                "value returned has type {}, which is returned in memory and can't be read",
+                ty.name()
+            ));
+        }
+        let mut bytes = vec![];
+        if matches!(
+            ty,
+            Type::Base {
+                encoding: Encoding::Float,
+                ..
+            }
+        ) {
+            let fpregs = self
+                .target
+                .getfpregs()
+                .map_err(|err| format!("could not read floating-point registers: {err}"))?;
+            bytes.extend(fpregs.xmm_space[..4].iter().flat_map(|w| w.to_le_bytes()));
+        } else {
+            for reg in [Reg::Rax, Reg::Rdx] {
+                let val = self
+                    .target
+                    .getreg(reg)
+                    .map_err(|err| format!("could not read registers: {err}"))?;
+                bytes.extend(val.to_le_bytes());
+            }
+        }
+        bytes.truncate(size);
+        Ok(bytes)
+    }
+
+    /// Run a stepping command count times, stopping early if something else
+    /// stops the target (step returns the stop) or it reaches a user
+    /// breakpoint.
+    ///
+    /// Returns the stop that ended the last step.
+    fn repeat_step(
+        &mut self,
+        count: usize,
+        step: fn(&mut Self) -> Result<Option<Stop>, DbgError>,
+    ) -> Result<Stop, DbgError> {
+        self.stepping(|dbg| {
+            for i in 0..count {
+                if let Some(stop) = step(dbg)? {
+                    return Ok(stop);
+                }
+                if i + 1 < count && dbg.stopped_at_user_breakpoint() {
+                    return Ok(Stop::new(StopReason::Breakpoint(dbg.get_offset_pc()?)));
+                }
+            }
+            Ok(Stop::new(StopReason::Step))
+        })
+    }
+
+    fn stopped_at_user_breakpoint(&self) -> bool {
+        self.get_pc().is_ok_and(|pc| {
+            self.breakpoints
+                .get(&pc)
+                .is_some_and(|bp| bp.enabled() && !bp.is_internal())
+        })
+    }
+
+    /// Run for count instructions.
+    pub fn step_instructions(&mut self, count: usize) -> Result<Stop, DbgError> {
+        self.repeat_step(count, |dbg| {
+            let stop = dbg.single_step()?;
+            Ok((stop.reason != StopReason::Step).then_some(stop))
+        })
+    }
+
+    /// Step into the next function, or to the next line, count times.
+    pub fn step_in(&mut self, count: usize) -> Result<Stop, DbgError> {
+        self.repeat_step(count, Self::step_line)
+    }
+
+    /// Run until the source line changes, returning the stop if something
+    /// else (like the program exiting) stops the target first.
+    fn step_line(&mut self) -> Result<Option<Stop>, DbgError> {
+        let normalize_loc = |loc: Location| (loc.file.map(|f| f.to_string()), loc.line);
+        let old = self
+            .info
+            .source_for_pc(self.get_offset_pc()?)?
+            .map(normalize_loc);
+        loop {
+            let pc = self.get_pc()?;
+            let call = match self.instruction_at(pc) {
+                Ok(Some(instr)) if instr.call => Some(instr),
+                _ => None,
+            };
+            let sp = self.target.getreg(Reg::Rsp)?;
+            if let Some(stop) = self.step_instruction_or_exit()? {
+                return Ok(Some(stop));
+            }
+            // don't descend into a function with no source to show, like one
+            // in libc or the standard library, and instead run until it
+            // returns
+            if let Some(call) = call {
+                if !self.has_program_source(self.get_offset_pc()?) {
+                    if let Some(stop) = self.run_until_return(call.next_addr(), sp)? {
+                        return Ok(Some(stop));
+                    }
+                }
+            }
+            let loc = self
+                .info
+                .source_for_pc(self.get_offset_pc()?)?
+                .map(normalize_loc);
+            if loc != old {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Whether the code at an offset pc is part of the program, with source
+    /// that can be shown.
+    ///
+    /// Code is part of the program if it was compiled in the same directory
+    /// as `main`, which rules out the precompiled standard library. Generic
+    /// library code instantiated in the program passes that test, but its
+    /// source usually isn't on this machine.
+    fn has_program_source(&self, pc: u64) -> bool {
+        let file = match self.info.source_for_pc(pc) {
+            Ok(Some(loc)) => match loc.file {
+                Some(file) => file,
+                None => return false,
+            },
+            _ => return false,
+        };
+        let main_dir = match self.info.pc_for_function_pred(|name| name == "main") {
+            Ok(Some(main)) => self.info.compilation_dir(main),
+            _ => None,
+        };
+        if main_dir.is_some() && self.info.compilation_dir(pc) != main_dir {
+            return false;
+        }
+        self.source_path(file, pc).exists()
+    }
+
+    /// The source file and line of an offset pc, if it has one.
+    fn line_for_pc(&self, pc: u64) -> Option<(&str, u32)> {
+        let loc = self.info.source_for_pc(pc).ok()??;
+        Some((loc.file?, loc.line?))
+    }
+
+    /// Step over the current source line, count times.
+    pub fn step_over(&mut self, count: usize) -> Result<Stop, DbgError> {
+        self.repeat_step(count, Self::next_line)
+    }
+
+    /// Run to the next source line in this function (or its caller),
+    /// returning the stop if something else stopped the target first.
+    fn next_line(&mut self) -> Result<Option<Stop>, DbgError> {
+        let pc = self.get_offset_pc()?;
+        let locs = self.info.function_lines_from_pc(pc)?;
+        // the current line can span several rows of the line table, none of
+        // which should end the step
+        let start_line = self.line_for_pc(pc);
+        let stops: Vec<u64> = locs
+            .into_iter()
+            .filter(|&line_pc| {
+                line_pc != pc && (start_line.is_none() || self.line_for_pc(line_pc) != start_line)
+            })
+            .collect();
+        // Use eh_frame for the return address, since it's correct anywhere in
+        // the function, including after the frame is torn down for a tail
+        // call. The tail-called function returns straight to our caller.
+        let frame = self.current_caller_frame()?;
+        let return_address = self.get_current_return_address()?;
+        let mut temp_bp = TempBreakpoints::new();
+        for line_pc in stops {
+            temp_bp.ensure_breakpoint(self, self.load_addr + line_pc);
+        }
+        if let Some(return_address) = return_address {
+            temp_bp.ensure_breakpoint(self, return_address);
+        }
+
+        let stop = loop {
+            let stop = match self.continue_execution() {
+                Ok(stop) => stop,
+                Err(err) => {
+                    temp_bp.delete_all(self);
+                    return Err(err);
+                }
+            };
+            if !matches!(stop.reason, StopReason::InternalBreakpoint(_)) || stop.returned.is_some()
+            {
+                break Some(stop);
+            }
+            // a recursive call hits the same breakpoints in a deeper frame,
+            // which has a lower CFA
+            let start_cfa = frame.map(|f| f.cfa);
+            let cfa = match self.current_caller_frame() {
+                Ok(frame) => frame.map(|f| f.cfa),
+                Err(err) => {
+                    temp_bp.delete_all(self);
+                    return Err(err);
+                }
+            };
+
+            match (start_cfa, cfa) {
+                (Some(start_cfa), Some(cfa)) if cfa < start_cfa => continue,
+                _ => break None,
+            }
+        };
+        temp_bp.delete_all(self);
+        Ok(stop)
+    }
+
+    /// The names of the program's functions and globals, sorted, for
+    /// completing names.
+    pub fn completion_names(&self) -> Vec<String> {
+        let mut names = self.info.function_names().unwrap_or_default();
+        names.extend(self.info.symbol_names());
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    pub fn lookup_symbol(&self, name: &str) -> Vec<dwarf::Symbol> {
+        self.info.lookup_symbol(name)
+    }
+
+    /// Find the symbols named name, or if regex is set, the symbols whose
+    /// names match it as a regex.
+    pub fn symbols(&self, name: &str, regex: bool) -> Result<Vec<dwarf::Symbol>, DbgError> {
+        if !regex {
+            return Ok(self.lookup_symbol(name));
+        }
+        let re = Regex::new(name).map_err(|err| format!("invalid regex: {err}"))?;
+        Ok(self.info.search_symbols(&re))
+    }
+
+    /// Find the functions whose names match a regex (or all of them), with
+    /// their (offset) addresses.
+    pub fn functions(&self, pattern: Option<&str>) -> Result<Vec<dwarf::Symbol>, DbgError> {
+        let re = pattern
+            .map(Regex::new)
+            .transpose()
+            .map_err(|err| format!("invalid regex: {err}"))?;
+        Ok(self.info.functions(re.as_ref()))
+    }
+
+    /// Check if pc is in the outermost frame a backtrace should show.
+    fn is_outermost_frame(&self, pc: u64) -> bool {
+        let frame = match self.info.frame_for_pc(pc) {
+            Ok(frame) => frame,
+            // no frame info
+            Err(_) => return true,
+        };
+        match frame.frames.last() {
+            None => return true,
+            Some(frame) => {
+                // check if we reached the main function
+                if let Some(Ok(name)) = frame.function.as_ref().map(|f| f.demangle()) {
+                    return name == "main" || name.ends_with("::main");
+                }
+                return false;
+            }
+        }
+    }
+
+    /// Get the call stack, innermost frame first, by unwinding with
+    /// eh_frame.
+    pub fn backtrace(&self) -> Vec<StackFrame> {
+        self.eh_frame_pcs()
+            .into_iter()
+            .enumerate()
+            .map(|(i, pc)| {
+                // a return address follows the call, which is what to show
+                let lookup_pc = if i == 0 { pc } else { pc - 1 };
+                let mut frame = StackFrame {
+                    pc,
+                    function: None,
+                    file: None,
+                    line: None,
+                };
+                if let Ok(info) = self.info.frame_for_pc(lookup_pc) {
+                    frame.function = info.inner_function().map(|f| f.into_owned());
+                    frame.file = info.file.map(PathBuf::from);
+                    frame.line = info.line;
+                }
+                frame
+            })
+            .collect()
+    }
+
+    /// Get the frames of a backtrace, innermost first, with why unwinding
+    /// stopped early if it looks like the stack is corrupt.
+    pub fn backtrace_frames(&self) -> (Vec<BacktraceFrame>, Option<&'static str>) {
+        let (frames, stopped) = self.unwind();
+        let frames = frames
+            .iter()
+            .enumerate()
+            .map(|(i, frame)| self.describe_frame(i, frame.pc))
+            .collect();
+        (frames, stopped)
+    }
+
+    /// Describe the frame at index i (0 for the innermost frame) with an
+    /// (offset) pc, with the functions inlined into it.
+    fn describe_frame(&self, i: usize, pc: u64) -> BacktraceFrame {
+        // show the line of a caller's call, not the instruction after it
+        let lookup_pc = if i == 0 { pc } else { pc - 1 };
+        let mut functions = match self.info.frame_for_pc(lookup_pc) {
+            Ok(frame) => frame.inline_frames(),
+            Err(_) => vec![],
+        };
+        let mut symbol = None;
+        if functions
+            .iter()
+            .all(|f| f.function.is_none() && f.file.is_none())
+        {
+            // no debug info, but the symbol table might say where pc is
+            functions.clear();
+            symbol = self.symbol_offset(self.load_addr + pc);
+        }
+        BacktraceFrame {
+            num: i + 1,
+            pc,
+            functions,
+            symbol,
+        }
+    }
+
+    /// Select frame number num (as numbered in a backtrace, from 1 for the
+    /// innermost frame), or with no number, get the selected frame.
+    pub fn select_frame(&mut self, num: Option<usize>) -> Result<BacktraceFrame, DbgError> {
+        match num {
+            Some(0) => Err("frames are numbered from 1".to_string().into()),
+            Some(num) => self.move_to_frame(num - 1),
+            None => self.move_to_frame(self.selected_frame),
+        }
+    }
+
+    /// Select the frame count levels out from the selected one, toward the
+    /// outermost frame.
+    pub fn frame_up(&mut self, count: usize) -> Result<BacktraceFrame, DbgError> {
+        self.move_to_frame(self.selected_frame + count)
+    }
+
+    /// Select the frame count levels in from the selected one, toward the
+    /// innermost frame.
+    pub fn frame_down(&mut self, count: usize) -> Result<BacktraceFrame, DbgError> {
+        match self.selected_frame.checked_sub(count) {
+            Some(i) => self.move_to_frame(i),
+            None => Err("the innermost frame is selected; cannot go down"
+                .to_string()
+                .into()),
+        }
+    }
+
+    /// Select the frame at index i.
+    fn move_to_frame(&mut self, i: usize) -> Result<BacktraceFrame, DbgError> {
+        if !self.running {
+            return Err("the program is not running".to_string().into());
+        }
+        let frames = self.eh_frames();
+        let frame = frames.get(i).ok_or_else(|| {
+            format!(
+                "no frame #{} (the outermost frame is #{})",
+                i + 1,
+                frames.len()
+            )
+        })?;
+        self.selected_frame = i;
+        Ok(self.describe_frame(i, frame.pc))
+    }
+
+    /// Find the source line the selected frame is at, if there is one, and
+    /// continue a bare `list` after the lines around it.
+    pub fn selected_frame_source(&mut self) -> Result<Option<(PathBuf, usize)>, DbgError> {
+        match self.scope_pc() {
+            Ok(pc) => self.source_for_pc(pc),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// The registers of the selected frame, or None if it's the innermost
+    /// frame, whose registers are the target's.
+    fn selected_frame_regs(&self) -> Option<FrameRegs> {
+        if self.selected_frame == 0 {
+            return None;
+        }
+        self.eh_frames().get(self.selected_frame).copied()
+    }
+
+    /// The (offset) pc to look up the selected frame's scope at. In a caller,
+    /// this is the call, since the return address after it could be in a
+    /// different scope (or function).
+    fn scope_pc(&self) -> Result<u64, String> {
+        match self.selected_frame_regs() {
+            Some(frame) => Ok(frame.pc - 1),
+            None => self.get_offset_pc().map_err(|err| err.to_string()),
+        }
+    }
+
+    /// Read a register in the selected frame. In a caller, only the
+    /// registers unwinding recovers are available.
+    fn frame_reg(&self, reg: Reg) -> Result<u64, String> {
+        let frame = match self.selected_frame_regs() {
+            Some(frame) => frame,
+            None => return self.target.getreg(reg).map_err(|err| err.to_string()),
+        };
+        if let Some(i) = dwarf::CALLEE_SAVED.iter().position(|&r| r == reg) {
+            return Ok(frame.saved[i]);
+        }
+        match reg {
+            Reg::Rip => Ok(self.load_addr + frame.pc),
+            Reg::Rsp => Ok(frame.rsp),
+            Reg::Rbp => Ok(frame.rbp),
+            _ => Err(format!(
+                "{} is not available in frame #{}",
+                reg.name(),
+                self.selected_frame + 1
+            )),
+        }
+    }
+
+    /// Unwind the selected frame with eh_frame. Returns None if eh_frame
+    /// doesn't cover the frame's pc.
+    fn selected_caller_frame(&self) -> Result<Option<CallerFrame>, DbgError> {
+        let frame = match self.selected_frame_regs() {
+            Some(frame) => frame,
+            None => return self.current_caller_frame(),
+        };
+        let regs = UnwindRegs::new(self.target, &frame)?;
+        Ok(self.info.get_unwind_caller_frame(frame.pc - 1, &regs)?)
+    }
+
+    /// The registers of the innermost frame, from the target.
+    fn innermost_frame_regs(&self) -> Result<FrameRegs, io::Error> {
+        let regs = self.target.getregs()?;
+        Ok(FrameRegs {
+            pc: self.get_offset_pc()?,
+            rsp: Reg::Rsp.get_reg(&regs),
+            rbp: Reg::Rbp.get_reg(&regs),
+            saved: dwarf::CALLEE_SAVED.map(|reg| reg.get_reg(&regs)),
+        })
+    }
+
+    /// Get the (offset) pc of each frame by following saved frame pointers.
+    fn frame_pointer_pcs(&self) -> Vec<u64> {
+        let (mut pc, mut fp) = match self.innermost_frame_regs() {
+            Ok(frame) => (frame.pc, frame.rbp),
+            Err(_) => return vec![],
+        };
+        let mut pcs = vec![];
+        loop {
+            pcs.push(pc);
+            if self.is_outermost_frame(pc) || pcs.len() >= MAX_FRAMES || fp == 0 {
+                return pcs;
+            }
+            let ra;
+            (fp, ra) = match self.get_prev_frame(fp) {
+                Some(frame) => frame,
+                None => return pcs,
+            };
+            pc = ra.wrapping_sub(self.load_addr);
+        }
+    }
+
+    /// Get the (offset) pc of each frame by unwinding with eh_frame.
+    fn eh_frame_pcs(&self) -> Vec<u64> {
+        self.eh_frames().into_iter().map(|frame| frame.pc).collect()
+    }
+
+    /// Get the registers of each frame by unwinding with eh_frame.
+    fn eh_frames(&self) -> Vec<FrameRegs> {
+        self.unwind().0
+    }
+
+    /// Unwind the stack with eh_frame, returning the registers of each frame
+    /// and, if unwinding stopped before the outermost frame because the
+    /// stack looks corrupt, why.
+    fn unwind(&self) -> (Vec<FrameRegs>, Option<&'static str>) {
+        let innermost = match self.innermost_frame_regs() {
+            Ok(frame) => frame,
+            Err(_) => return (vec![], Some("could not read registers")),
+        };
+        let mut regs = match UnwindRegs::new(self.target, &innermost) {
+            Ok(regs) => regs,
+            Err(_) => return (vec![], Some("could not read registers")),
+        };
+        let mut pc = innermost.pc;
+        let mut frames = vec![];
+        // (pc, stack pointer) of each frame, to detect a cycle
+        let mut seen = HashSet::new();
+        loop {
+            let frame = regs.frame(pc);
+            if !seen.insert((pc, frame.rsp)) {
+                return (frames, Some("previous frame identical"));
+            }
+            frames.push(frame);
+            if self.is_outermost_frame(pc) {
+                return (frames, None);
+            }
+            if frames.len() >= MAX_FRAMES {
+                return (frames, Some("too many frames"));
+            }
+            // a return address follows the call, which could be the last
+            // instruction in the function, so look up the call instead
+            let lookup_pc = if frames.len() == 1 { pc } else { pc - 1 };
+            match self.info.get_unwind_caller_frame(lookup_pc, &regs) {
+                Ok(Some(frame)) if frame.ra != 0 => {
+                    regs.set_caller(&frame);
+                    pc = frame.ra.wrapping_sub(self.load_addr);
+                }
+                _ => return (frames, None),
+            }
+            // stop at a caller outside the program, like libc's
+            // __libc_start_call_main
+            if !matches!(self.info.function_for_pc(pc.wrapping_sub(1)), Ok(Some(_))) {
+                return (frames, None);
+            }
+        }
+    }
+
+    /// Get the backtraces from frame pointers and from eh_frame side by side,
+    /// to debug unwinding.
+    pub fn compare_unwinders(&self) -> Vec<UnwinderFrame> {
+        let fp_pcs = self.frame_pointer_pcs();
+        let eh_pcs = self.eh_frame_pcs();
+        (0..fp_pcs.len().max(eh_pcs.len()))
+            .map(|i| {
+                let (fp_pc, eh_pc) = (fp_pcs.get(i).copied(), eh_pcs.get(i).copied());
+                let function = eh_pc
+                    .or(fp_pc)
+                    .and_then(|pc| self.info.frame_for_pc(pc).ok())
+                    .and_then(|frame| frame.inner_function().map(|f| f.into_owned()));
+                UnwinderFrame {
+                    frame_pointer: fp_pc,
+                    eh_frame: eh_pc,
+                    function,
+                }
+            })
+            .collect()
+    }
+
+    /// Get the user's breakpoints, by address.
+    pub fn breakpoints(&self) -> Vec<BreakpointInfo> {
+        let mut bps: Vec<_> = self
+            .breakpoints
+            .values()
+            .filter_map(|bp| match bp.source {
+                BreakpointSource::User { id } => Some(BreakpointInfo {
+                    id,
+                    pc: bp.addr - self.load_addr,
+                    enabled: bp.enabled(),
+                    condition: bp.condition,
+                    temporary: bp.temporary,
+                    ignore_count: bp.ignore_count,
+                }),
+                BreakpointSource::Internal { .. } => None,
+            })
+            .collect();
+        bps.sort_by_key(|bp| bp.pc);
+        bps
+    }
+
+    /// Read target memory, showing the original code wherever a breakpoint is
+    /// installed.
+    fn read_code(&self, addr: u64, len: usize) -> Result<Vec<u8>, io::Error> {
+        let mut code = self.target.read_mem(addr, len)?;
+        for bp in self.breakpoints.values() {
+            if let Some(saved) = bp.saved_data {
+                if (addr..addr + len as u64).contains(&bp.addr) {
+                    code[(bp.addr - addr) as usize] = saved;
+                }
+            }
+        }
+        Ok(code)
+    }
+
+    fn eval_addr(&self, text: &str) -> Result<u64, DbgError> {
+        Ok(Expr::parse(text).and_then(|e| e.eval(self))?)
+    }
+
+    /// Save the target's memory in [start, end) to a file, returning how many
+    /// bytes were saved.
+    ///
+    /// If part of the range can't be read, saves the readable prefix and
+    /// also returns the range that couldn't be read.
+    pub fn dump_memory(
+        &self,
+        path: &Path,
+        start: &str,
+        end: &str,
+    ) -> Result<(usize, Option<Range<u64>>), DbgError> {
+        let (start, end) = (self.eval_addr(start)?, self.eval_addr(end)?);
+        if end < start {
+            return Err(format!("end 0x{end:x} is before start 0x{start:x}").into());
+        }
+        // read a page at a time so an unmapped page only loses the rest of
+        // the range
+        const PAGE_SIZE: u64 = 4096;
+        let mut bytes = vec![];
+        let mut addr = start;
+        while addr < end {
+            let len = (PAGE_SIZE - addr % PAGE_SIZE).min(end - addr);
+            match self.read_code(addr, len as usize) {
+                Ok(chunk) => bytes.extend_from_slice(&chunk),
+                Err(_) => break,
+            }
+            addr += len;
+        }
+        fs::write(path, &bytes)
+            .map_err(|err| format!("could not write {}: {err}", path.display()))?;
+        Ok((bytes.len(), (addr < end).then_some(addr..end)))
+    }
+
+    /// Copy a file's contents into the target's memory at addr, returning
+    /// the address and how many bytes were written.
+    pub fn restore_memory(&mut self, path: &Path, addr: &str) -> Result<(u64, usize), DbgError> {
+        let addr = self.eval_addr(addr)?;
+        let bytes =
+            fs::read(path).map_err(|err| format!("could not read {}: {err}", path.display()))?;
+        self.write_memory(addr, &bytes)
+            .map_err(|err| format!("could not write to 0x{addr:x}: {err}"))?;
+        Ok((addr, bytes.len()))
+    }
+
+    /// Write bytes to the target's memory, keeping breakpoints in the range
+    /// installed: the new bytes replace what the breakpoints saved.
+    pub fn write_memory(&mut self, addr: u64, bytes: &[u8]) -> Result<(), io::Error> {
+        self.target.write_mem(addr, bytes)?;
+        // the int3s were overwritten, so put them back
+        let range = addr..addr + bytes.len() as u64;
+        for bp in self.breakpoints.values_mut() {
+            if bp.enabled() && range.contains(&bp.addr) {
+                bp.saved_data = Some(bytes[(bp.addr - addr) as usize]);
+                self.target.write_mem(bp.addr, &[Breakpoint::INT3_INSTR])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write values given on the command line to memory: a single 64-bit
+    /// value, or a sequence of bytes. Returns the address and how many bytes
+    /// were written.
+    pub fn write_values(
+        &mut self,
+        addr: &str,
+        values: &[u64],
+        bytes: bool,
+    ) -> Result<(u64, usize), DbgError> {
+        let addr = self.eval_addr(addr)?;
+        let data = if bytes {
+            values
+                .iter()
+                .map(|&v| u8::try_from(v))
+                .collect::<Result<_, _>>()
+                .map_err(|_| "byte values must be at most 255".to_string())?
+        } else {
+            match values {
+                [val] => val.to_le_bytes().to_vec(),
+                _ => {
+                    return Err("expected one 64-bit value (use --bytes to write bytes)"
+                        .to_string()
+                        .into())
+                }
+            }
+        };
+        self.write_memory(addr, &data)
+            .map_err(|_| format!("cannot access memory at 0x{addr:x}"))?;
+        Ok((addr, data.len()))
+    }
+
+    /// Disassemble the function containing a location (or the current pc).
+    pub fn disassemble(&self, loc: Option<&BreakpointLoc>) -> Result<Disassembly, DbgError> {
+        let pc = self.get_offset_pc().ok();
+        let loc_pc = match (loc, pc) {
+            (Some(loc), _) => self.location_pc(loc)?,
+            (None, Some(pc)) => pc,
+            (None, None) => return Err("the program is not running".to_string().into()),
+        };
+        let range = self
+            .info
+            .get_function_range_from_pc(loc_pc)?
+            .ok_or_else(|| format!("no function contains 0x{loc_pc:x}"))?;
+        let len = (range.end - range.start) as usize;
+        let code = self
+            .read_code(self.load_addr + range.start, len)
+            .map_err(|_| format!("cannot access memory at 0x{:x}", range.start))?;
+        Ok(Disassembly {
+            start: range.start,
+            pc: pc.filter(|_| self.running),
+            instrs: disasm::decode_all(&code, range.start),
+        })
+    }
+
+    /// Disassemble the instruction at the pc, for `disassemble-next-line`,
+    /// returning the (offset) pc and the instruction (None if it's invalid).
+    pub fn next_instruction_at_pc(&self) -> Option<(u64, Option<disasm::Instr>)> {
+        if !self.running {
+            return None;
+        }
+        let abs_pc = self.get_pc().ok()?;
+        let pc = abs_pc - self.load_addr;
+        // decode at the offset pc so branch targets are offsets, too
+        let instr = self
+            .read_code(abs_pc, disasm::MAX_INSTR_LEN)
+            .or_else(|_| self.read_code(abs_pc, 1))
+            .ok()
+            .and_then(|code| disasm::decode_one(&code, pc));
+        Some((pc, instr))
+    }
+
+    /// Disassemble the instruction at addr.
+    fn instruction_at(&self, addr: u64) -> Result<Option<disasm::Instr>, io::Error> {
+        let code = self.read_code(addr, disasm::MAX_INSTR_LEN).or_else(|_| {
+            // the instruction might be right before the end of a mapping
+            self.read_code(addr, 1)
+        })?;
+        Ok(disasm::decode_one(&code, addr))
+    }
+
+    /// Read the bytes of a NUL-terminated string (not including the NUL), up
+    /// to max bytes.
+    fn read_c_bytes(&self, addr: u64, max: usize) -> Result<Vec<u8>, io::Error> {
+        // read in small aligned chunks so we never read across a page
+        // boundary past the end of the string
+        const CHUNK: u64 = 64;
+        let mut bytes = vec![];
+        let mut addr = addr;
+        while bytes.len() < max {
+            let len = (CHUNK - addr % CHUNK) as usize;
+            let chunk = self.target.read_mem(addr, len)?;
+            match chunk.iter().position(|&b| b == 0) {
+                Some(end) => {
+                    bytes.extend_from_slice(&chunk[..end]);
+                    break;
+                }
+                None => bytes.extend_from_slice(&chunk),
+            }
+            addr += len as u64;
+        }
+        bytes.truncate(max);
+        Ok(bytes)
+    }
+
+    /// Read a NUL-terminated string, up to max bytes, with non-printable
+    /// bytes escaped as `\xNN`.
+    pub fn read_c_string(&self, addr: u64, max: usize) -> Result<String, io::Error> {
+        Ok(escape_c_string(&self.read_c_bytes(addr, max)?))
+    }
+
+    /// Format an expression's value according to format.
+    fn format_value(&self, val: u64, format: Option<Format>) -> String {
+        match format.unwrap_or(Format::Hex) {
+            Format::Hex => format!("0x{val:x}"),
+            Format::Decimal => format!("{}", val as i64),
+            Format::Char => format::format_char(val as u8),
+            Format::Instruction => match self.instruction_at(val) {
+                Ok(Some(instr)) => format!("0x{val:x}: {}", instr.text),
+                Ok(None) => format!("0x{val:x}: (bad)"),
+                Err(_) => format!("<cannot access memory at 0x{val:x}>"),
+            },
+            Format::String => match self.read_c_string(val, 200) {
+                Ok(s) => format!("0x{val:x} \"{s}\""),
+                Err(_) => format!("<cannot access memory at 0x{val:x}>"),
+            },
+        }
+    }
+
+    /// Read and format memory starting at the address an expression
+    /// evaluates to, like gdb's `x/FMT ADDR`.
+    pub fn examine_memory(&self, text: &str, fmt: MemFormat) -> Result<ExaminedMemory, DbgError> {
+        if !self.running {
+            return Err("the program is not running".to_string().into());
+        }
+        let mut addr = self.eval_addr(text)?;
+        let mut examined = ExaminedMemory {
+            lines: vec![],
+            unreadable: None,
+        };
+        let mut remaining = fmt.count;
+        while remaining > 0 {
+            let n = remaining.min(fmt.per_line());
+            let mut units = vec![];
+            for i in 0..n {
+                let unit_addr = addr + (i * fmt.size) as u64;
+                match self.read_code(unit_addr, fmt.size) {
+                    Ok(bytes) => units.push(fmt.format_unit(&bytes)),
+                    Err(_) => {
+                        if !units.is_empty() {
+                            examined.lines.push((addr, units));
+                        }
+                        examined.unreadable = Some(unit_addr);
+                        return Ok(examined);
+                    }
+                }
+            }
+            examined.lines.push((addr, units));
+            addr += (n * fmt.size) as u64;
+            remaining -= n;
+        }
+        Ok(examined)
+    }
+
+    /// Compute the selected frame's frame base, which variables are located
+    /// relative to.
+    fn frame_base(&self) -> Result<u64, String> {
+        let base = self
+            .info
+            .frame_base(self.scope_pc()?)
+            .map_err(|err| format!("could not read debug info: {err}"))?;
+        match base {
+            Some(FrameBase::Register(reg)) => self.frame_reg(reg),
+            Some(FrameBase::RegisterOffset(reg, offset)) => {
+                let base = self.frame_reg(reg)?;
+                Ok(base.wrapping_add(offset as u64))
+            }
+            Some(FrameBase::Cfa) => self
+                .selected_caller_frame()
+                .map_err(|err| err.to_string())?
+                .map(|frame| frame.cfa)
+                .ok_or_else(|| "could not find the frame's CFA".to_string()),
+
+            None => Err("unsupported frame base".to_string()),
+        }
+    }
+
+    /// Find a variable in the selected frame's scope, or else a global.
+    fn find_variable(&self, name: &str) -> Result<Option<dwarf::Variable>, String> {
+        let var = match self.info.find_variable(self.scope_pc()?, name) {
+            Ok(None) => self.info.find_global(name),
+            var => var,
+        };
+        var.map_err(|err| format!("could not read debug info: {err}"))
+    }
+
+    /// Find a variable in the current scope, or else a global, and compute
+    /// its address.
+    fn locate_variable(&self, name: &str) -> Result<Option<(dwarf::Variable, u64)>, String> {
+        let var = match self.find_variable(name)? {
+            Some(var) => var,
+            None => return Ok(None),
+        };
+        let addr = self.variable_address(name, &var)?;
+        Ok(Some((var, addr)))
+    }
+
+    /// Compute the address of a variable in memory.
+    fn variable_address(&self, name: &str, var: &dwarf::Variable) -> Result<u64, String> {
+        let addr = match var.location {
+            Some(VarLocation::FrameOffset(offset)) => {
+                self.frame_base()?.wrapping_add(offset as u64)
+            }
+            Some(VarLocation::RegisterOffset(reg, offset)) => {
+                let base = self.frame_reg(reg)?;
+                base.wrapping_add(offset as u64)
+            }
+            Some(VarLocation::Addr(addr)) => self.load_addr + addr,
+            Some(VarLocation::Register(reg)) => {
+                return Err(format!("{name} is in {}, so it has no address", reg.name()))
+            }
+            None => return Err(format!("location of {name} is not supported")),
+        };
+        Ok(addr)
+    }
+
+    /// Compute the address and type of a place, like `p.x` or `arr[i]`.
+    ///
+    /// Fields are found through pointers automatically, as in Rust, so
+    /// `pp.x` and `pp->x` are the same.
+    fn locate_place(&self, place: &Place) -> Result<(u64, Type), String> {
+        match place {
+            Place::Var(name) => match self.locate_variable(name)? {
+                Some((var, addr)) => Ok((addr, var.ty)),
+                None => Err(format!(
+                    "no variable {name} in the current scope or globals"
+                )),
+            },
+            Place::Deref(p) => {
+                let (addr, ty) = self.locate_place(p)?;
+                self.deref_place(addr, &ty)
+            }
+            Place::Field(p, field) => {
+                let (mut addr, mut ty) = self.locate_place(p)?;
+                if let Type::Pointer { .. } = ty {
+                    (addr, ty) = self.deref_place(addr, &ty)?;
+                }
+                let members = match &ty {
+                    Type::Struct { members, .. } => members,
+                    _ => return Err(format!("{} has no field {field}", ty.name())),
+                };
+                match members.iter().find(|m| &m.name == field) {
+                    Some(m) => Ok((addr + m.offset as u64, m.ty.clone())),
+                    None => Err(format!("{} has no field {field}", ty.name())),
+                }
+            }
+            Place::Index(p, index) => {
+                let (addr, ty) = self.locate_place(p)?;
+                let index = self.eval_print(index)?.as_u64()?;
+                let (base, elem, count) = match ty {
+                    Type::Array { elem, count, .. } => (addr, elem, count as u64),
+                    Type::Slice {
+                        elem,
+                        ptr_offset,
+                        len_offset,
+                        ..
+                    } => {
+                        let ptr = self.read_pointer(addr + ptr_offset as u64)?;
+                        let len = self.read_pointer(addr + len_offset as u64)?;
+                        (ptr, elem, len)
+                    }
+                    ty => return Err(format!("cannot index into a {}", ty.name())),
+                };
+                if index >= count {
+                    return Err(format!("index {index} is out of bounds (length {count})"));
+                }
+                Ok((base + index * elem.size() as u64, *elem))
+            }
+        }
+    }
+
+    /// Follow the pointer stored at addr, returning its target and the type
+    /// it points to.
+    fn deref_place(&self, addr: u64, ty: &Type) -> Result<(u64, Type), String> {
+        if !matches!(ty, Type::Pointer { .. }) {
+            return Err(format!("cannot dereference a {}", ty.name()));
+        }
+        // pointer types only record their name, so look up the pointee by name
+        let name = ty.name();
+        let pointee = ["&mut ", "&", "*mut ", "*const "]
+            .into_iter()
+            .find_map(|prefix| name.strip_prefix(prefix))
+            .ok_or_else(|| format!("cannot find the type {name} points to"))?;
+        Ok((self.read_pointer(addr)?, self.lookup_type(pointee)?))
+    }
+
+    fn read_pointer(&self, addr: u64) -> Result<u64, String> {
+        match self.target.read_mem(addr, 8) {
+            Ok(bytes) => Ok(u64::from_le_bytes(bytes.try_into().unwrap())),
+            Err(_) => Err(format!("cannot access memory at 0x{addr:x}")),
+        }
+    }
 
-    fn get_current_return_address(&self) -> u64 {
-        let frame_pointer = self.target.getreg(Reg::Rbp).unwrap();
-        self.target.peekdata(frame_pointer + 8).unwrap()
+    /// Read the NUL-terminated string at the address in `text`, up to
+    /// [`MAX_STRING_LEN`] bytes.
+    pub fn c_string_at(&self, text: &str) -> Result<Vec<u8>, DbgError> {
+        let addr = self.eval_addr(text)?;
+        self.read_c_bytes(addr, MAX_STRING_LEN)
+            .map_err(|_| format!("cannot access memory at 0x{addr:x}").into())
     }
 
-    /// Step until the current function exits.
-    pub fn step_out(&mut self) {
-        let return_address = self.get_current_return_address();
+    /// Evaluate what print accepts and format the value, saving it in the
+    /// value history.
+    pub fn evaluate(&mut self, text: &str) -> Result<String, String> {
+        let val = match Assignment::parse(text) {
+            Some(assign) => assign.and_then(|assign| self.assign(&assign))?,
+            None => self.eval_print(text)?,
+        };
+        let formatted = match &val.ty {
+            Some(ty) => self
+                .read_typed_value(ValueLocation::Bytes(val.bytes.clone()), ty)
+                .to_string(),
+            None => self.format_value(val.as_u64().unwrap(), None),
+        };
+        self.history.push(val);
+        Ok(formatted)
+    }
 
-        let mut temp_bp = TempBreakpoints::new();
-        temp_bp.ensure_breakpoint(self, return_address);
+    /// The number of values in the value history, which is the number of the
+    /// latest one.
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
 
-        self.continue_execution().unwrap();
+    /// Get each variable in scope at the pc with its type and formatted value
+    /// (or why it couldn't be read). Shadowed variables are left out.
+    pub fn local_variables(&self) -> Result<Vec<(String, String, String)>, DbgError> {
+        let pc = self.scope_pc()?;
+        Ok(self.variable_values(self.info.variables_in_scope(pc)?))
+    }
 
-        temp_bp.delete_all(self);
+    /// Format the name, type, and value of each variable, skipping variables
+    /// shadowed by a later one with the same name.
+    fn variable_values(&self, vars: Vec<dwarf::Variable>) -> Vec<(String, String, String)> {
+        let mut values: Vec<(String, String, String)> = vec![];
+        // inner scopes come last
+        for var in vars.into_iter().rev() {
+            if values.iter().any(|(name, _, _)| name == &var.name) {
+                continue;
+            }
+            let name = var.name.clone();
+            let ty = var.ty.clone();
+            let val = match self.variable_value(var) {
+                Ok(val) => self
+                    .read_typed_value(ValueLocation::Bytes(val.bytes), &ty)
+                    .to_string(),
+                Err(err) => format!("<{err}>"),
+            };
+            values.push((name, ty.name().to_string(), val));
+        }
+        values.reverse();
+        values
     }
 
-    /// Step into the next function.
-    pub fn step_in(&mut self) {
-        let normalize_loc = |loc: Location| (loc.file.unwrap().to_string(), loc.line);
-        let old = self
+    /// Get the name, type, and value of each variable in scope, other than
+    /// the function's arguments.
+    pub fn locals(&self) -> Result<Vec<(String, String, String)>, DbgError> {
+        if !self.running {
+            return Err("the program is not running".to_string().into());
+        }
+        let pc = self.scope_pc()?;
+        let params = self.info.formal_params_for_pc(pc)?;
+        let vars = self
             .info
-            .source_for_pc(self.get_offset_pc())
-            .expect("dwarf error getting current source")
-            .map(normalize_loc);
-        loop {
-            self.single_step_instruction();
-            let loc = self
-                .info
-                .source_for_pc(self.get_offset_pc())
-                .expect("dwarf error getting current source")
-                .map(normalize_loc);
-            if loc != old {
-                if let Some((file, line)) = loc {
-                    print_source(file, line.unwrap() as usize, 1);
+            .variables_in_scope(pc)?
+            .into_iter()
+            .filter(|var| !params.contains(var))
+            .collect();
+        Ok(self.variable_values(vars))
+    }
+
+    /// Get the name, type, and value of each argument to the current
+    /// function.
+    pub fn arguments(&self) -> Result<Vec<(String, String, String)>, DbgError> {
+        if !self.running {
+            return Err("the program is not running".to_string().into());
+        }
+        let pc = self.scope_pc()?;
+        Ok(self.variable_values(self.info.formal_params_for_pc(pc)?))
+    }
+
+    /// Evaluate the argument to print.
+    fn eval_print(&self, text: &str) -> Result<Value, String> {
+        if let Some((cast, rest)) = Cast::split(text) {
+            return self.eval_cast(&cast, rest);
+        }
+        if is_identifier(text) {
+            return self.read_variable(text);
+        }
+        match Expr::parse(text)? {
+            // keep the type of a value from the history or a variable
+            Expr::History(num) => self.history_value(num).cloned(),
+            Expr::Var(name) => self.convenience_var(&name).cloned(),
+            expr => Ok(Value::number(expr.eval(self)?)),
+        }
+    }
+
+    /// Get the type of an expression, without evaluating it, for `whatis`
+    /// and `ptype`.
+    pub fn expression_type(&self, text: &str) -> Result<Type, DbgError> {
+        Ok(self.type_of(text.trim())?)
+    }
+
+    /// Find the type of an expression in the same forms as print accepts, or
+    /// a reference `&name` to a variable.
+    fn type_of(&self, text: &str) -> Result<Type, String> {
+        if let Some((cast, _)) = Cast::split(text) {
+            return self.lookup_type(&cast.type_name);
+        }
+        if let Some(name) = text.strip_prefix('&') {
+            let name = name.trim();
+            if !is_identifier(name) {
+                return Err(format!("cannot take the address of {name}"));
+            }
+            let ty = self.variable_type(name)?;
+            return Ok(Type::Pointer {
+                name: format!("*mut {}", ty.name()),
+                size: 8,
+            });
+        }
+        if is_identifier(text) {
+            return self.variable_type(text);
+        }
+        let ty = match Expr::parse(text)? {
+            Expr::History(num) => self.history_value(num)?.ty.clone(),
+            Expr::Var(name) => self.convenience_var(&name)?.ty.clone(),
+            _ => None,
+        };
+        // expressions otherwise compute 64-bit numbers
+        Ok(ty.unwrap_or_else(|| Type::primitive("u64").unwrap()))
+    }
+
+    /// The type of a variable in the current scope, or a global.
+    fn variable_type(&self, name: &str) -> Result<Type, String> {
+        if !self.running {
+            return Err("the program is not running".to_string());
+        }
+        match self.find_variable(name)? {
+            Some(var) => Ok(var.ty),
+            None => Err(format!(
+                "no variable {name} in the current scope or globals"
+            )),
+        }
+    }
+
+    /// Set a convenience variable, from `$name = expr`, or a variable in the
+    /// program, from `var name = expr`.
+    pub fn set_var(&mut self, text: &str) -> Result<(), DbgError> {
+        // `set var NAME = EXPR` sets a variable in the program
+        if let Some(rest) = text.strip_prefix("var ") {
+            return match rest.split_once('=') {
+                Some((name, value)) if is_identifier(name.trim()) => {
+                    Ok(self.set_variable(name.trim(), value)?)
                 }
-                return;
+                _ => Err(format!("expected var NAME = EXPR, got '{text}'").into()),
+            };
+        }
+        match Assignment::parse(text) {
+            Some(assign) => {
+                assign.and_then(|assign| self.assign(&assign))?;
+                Ok(())
             }
+            None => Err(format!("expected $NAME = EXPR, got '{text}'").into()),
         }
     }
 
-    /// Step over the current source line.
-    pub fn step_over(&mut self) {
-        let pc = self.get_offset_pc();
-        let locs = self
-            .info
-            .function_lines_from_pc(pc)
-            .expect("could not get lines for function");
-        // TODO: somehow need to get the "start line", which seems to be the
-        // result of going from pc -> line -> pc
-        //
-        // currently just use pc
-        let start_line = pc;
-        let mut temp_bp = TempBreakpoints::new();
-        for line_pc in locs.into_iter() {
-            if line_pc != start_line {
-                temp_bp.ensure_breakpoint(self, self.load_addr + line_pc);
+    /// Evaluate an assignment and set the variable, returning its new value.
+    fn assign(&mut self, assign: &Assignment) -> Result<Value, String> {
+        let val = self.eval_print(assign.value.trim())?;
+        self.convenience_vars
+            .insert(assign.name.to_string(), val.clone());
+        Ok(val)
+    }
+
+    fn convenience_var(&self, name: &str) -> Result<&Value, String> {
+        self.convenience_vars
+            .get(name)
+            .ok_or_else(|| format!("${name} is not set"))
+    }
+
+    /// Get a value from the history, numbered from 1 (or the last one for
+    /// None).
+    fn history_value(&self, num: Option<usize>) -> Result<&Value, String> {
+        match num {
+            None => self
+                .history
+                .last()
+                .ok_or_else(|| "history is empty".to_string()),
+            Some(num) => num
+                .checked_sub(1)
+                .and_then(|i| self.history.get(i))
+                .ok_or_else(|| format!("history has no value ${num}")),
+        }
+    }
+
+    /// Look up a type by name, first as a primitive and then in the DWARF.
+    fn lookup_type(&self, name: &str) -> Result<Type, String> {
+        if let Some(ty) = Type::primitive(name) {
+            return Ok(ty);
+        }
+        match self.info.find_type(name) {
+            Ok(Some(ty)) => Ok(ty),
+            Ok(None) => Err(format!("no type named {name}")),
+            Err(err) => Err(format!("could not read debug info: {err}")),
+        }
+    }
+
+    fn eval_cast(&self, cast: &Cast, rest: &str) -> Result<Value, String> {
+        if !self.running {
+            return Err("the program is not running".to_string());
+        }
+        let ty = self.lookup_type(&cast.type_name)?;
+        let val = Expr::parse(rest)?.eval(self)?;
+        let bytes = if cast.deref {
+            self.target
+                .read_mem(val, ty.size())
+                .map_err(|_| format!("cannot access memory at 0x{val:x}"))?
+        } else {
+            // reinterpret the low bytes of the value
+            if ty.size() > 8 {
+                return Err(format!(
+                    "cannot cast a 64-bit value to {}, which is {} bytes",
+                    cast.type_name,
+                    ty.size()
+                ));
             }
+            val.to_le_bytes()[..ty.size()].to_vec()
+        };
+        Ok(Value {
+            bytes,
+            ty: Some(ty),
+        })
+    }
+
+    /// Read the value of a variable in the current scope, or a global.
+    fn read_variable(&self, name: &str) -> Result<Value, String> {
+        if !self.running {
+            return Err("the program is not running".to_string());
         }
-        let return_address = self.get_current_return_address();
-        temp_bp.ensure_breakpoint(self, return_address);
+        let var = self
+            .find_variable(name)?
+            .ok_or_else(|| format!("no variable {name} in the current scope or globals"))?;
+        self.variable_value(var)
+    }
 
-        self.continue_execution().unwrap();
+    /// Read the value of a variable from wherever its location says it is.
+    fn variable_value(&self, var: dwarf::Variable) -> Result<Value, String> {
+        let bytes = match var.location {
+            Some(VarLocation::Register(reg)) => {
+                let mut bytes = self.frame_reg(reg)?.to_le_bytes().to_vec();
+                bytes.truncate(var.ty.size());
+                bytes
+            }
+            _ => {
+                let addr = self.variable_address(&var.name, &var)?;
+                self.target
+                    .read_mem(addr, var.ty.size())
+                    .map_err(|_| format!("cannot access memory at 0x{addr:x}"))?
+            }
+        };
+        Ok(Value {
+            bytes,
+            ty: Some(var.ty),
+        })
+    }
 
-        temp_bp.delete_all(self)
+    /// Set an integer variable in the current scope (or a global) to the
+    /// value of an expression.
+    pub fn set_variable(&mut self, name: &str, value: &str) -> Result<(), String> {
+        if !self.running {
+            return Err("the program is not running".to_string());
+        }
+        let var = self
+            .find_variable(name)?
+            .ok_or_else(|| format!("no variable {name} in the current scope or globals"))?;
+        let size = var.ty.size();
+        let is_integer = match &var.ty {
+            Type::Base { encoding, .. } => *encoding != Encoding::Float,
+            Type::Pointer { .. } | Type::Enumeration { .. } => true,
+            _ => false,
+        };
+        if !is_integer || size > 8 {
+            return Err(format!(
+                "can only set integer variables ({name} is a {})",
+                var.ty.name()
+            ));
+        }
+        let val = self.eval_print(value.trim())?.as_u64()?;
+        if !fits_in(val, size) {
+            return Err(format!(
+                "{} does not fit in {name}, which is a {} ({size} bytes)",
+                value.trim(),
+                var.ty.name()
+            ));
+        }
+        match var.location {
+            Some(VarLocation::Register(reg)) if self.selected_frame != 0 => Err(format!(
+                "{name} is in {}, which can only be set in the innermost frame",
+                reg.name()
+            )),
+            Some(VarLocation::Register(reg)) => {
+                // the variable is in the low bytes of the register
+                let mask = if size == 8 {
+                    u64::MAX
+                } else {
+                    (1 << (8 * size)) - 1
+                };
+                self.target
+                    .getreg(reg)
+                    .and_then(|old| self.target.setreg(reg, (old & !mask) | (val & mask)))
+                    .map_err(|err| format!("could not set {}: {err}", reg.name()))
+            }
+            _ => {
+                let addr = self.variable_address(name, &var)?;
+                self.write_memory(addr, &val.to_le_bytes()[..size])
+                    .map_err(|_| format!("cannot access memory at 0x{addr:x}"))
+            }
+        }
     }
 
-    pub fn lookup_symbol(&self, name: &str) -> Vec<dwarf::Symbol> {
-        self.info.lookup_symbol(name)
+    /// Read a value of type ty from the target and format it.
+    pub fn read_typed_value(&self, loc: ValueLocation, ty: &Type) -> FormattedValue {
+        value::read_typed_value(self, loc, ty)
     }
 
-    /// returns true if we should stop continuing
-    fn backtrace_print_frame(&self, frame_num: usize, pc: u64) -> bool {
-        if let Ok(frame) = self.info.frame_for_pc(pc) {
-            println!(
-                "frame #{frame_num} at 0x{pc:x}, file {file} at line {line} (in {func})",
-                file = frame.file_suffix_or("??"),
-                line = frame.line_or("??"),
-                func = frame.inner_function().unwrap_or(Cow::Borrowed("??"))
-            );
-            match frame.frames.last() {
-                None => return true,
-                Some(frame) => {
-                    // check if we reached the main function
-                    if let Some(f) = &frame.function {
-                        let name = f.demangle().unwrap();
-                        return name == "main" || name.ends_with("::main");
-                    }
-                    return false;
+    /// Set a hardware watchpoint on a variable in scope (or a field, deref, or
+    /// element of one), or else on the address an expression evaluates to.
+    ///
+    /// The address is resolved once, now; see [`Watchpoint`].
+    pub fn add_watchpoint(
+        &mut self,
+        text: &str,
+        kind: WatchKind,
+    ) -> Result<NewWatchpoint, DbgError> {
+        if !self.running {
+            return Err("the program is not running".to_string().into());
+        }
+        let text = text.trim();
+        let (addr, ty, note) = self.resolve_watch(text)?;
+        let len = match &ty {
+            Some(ty) => {
+                let len = ty.size();
+                if !matches!(len, 1 | 2 | 4 | 8) || addr % len as u64 != 0 {
+                    return Err(format!(
+                        "can only watch aligned 1, 2, 4, or 8 byte values ({text} is {len} bytes)"
+                    )
+                    .into());
                 }
+                len
             }
-        } else {
-            // no frame info
-            println!("frame #{frame_num} at 0x{pc:x}");
-            return true;
+            // watch as much as the address's alignment allows
+            None => [8, 4, 2, 1]
+                .into_iter()
+                .find(|&len| addr % len as u64 == 0)
+                .unwrap(),
+        };
+        let slot = match self.watchpoints.iter().position(|w| w.is_none()) {
+            Some(slot) => slot,
+            None => {
+                return Err(
+                    format!("all {} hardware watchpoints are in use", Watchpoint::SLOTS).into(),
+                )
+            }
+        };
+        let wp = Watchpoint {
+            num: self.next_watchpoint_num,
+            text: text.to_string(),
+            addr,
+            len,
+            ty,
+            old: self.target.read_mem(addr, len).ok(),
+            kind,
+        };
+        if let Err(err) = self.target.get_debugreg(Watchpoint::DR7).and_then(|dr7| {
+            let dr7 = (dr7 & !Watchpoint::dr7_mask(slot)) | wp.dr7_bits(slot);
+            self.threads.set_debugreg(slot, addr)?;
+            self.threads.set_debugreg(Watchpoint::DR7, dr7)
+        }) {
+            return Err(format!("could not set watchpoint: {err}").into());
+        }
+        let new = NewWatchpoint {
+            kind: Some(kind),
+            num: wp.num,
+            text: text.to_string(),
+            addr,
+            len,
+            note,
+        };
+        self.next_watchpoint_num += 1;
+        self.watchpoints[slot] = Some(wp);
+        Ok(new)
+    }
+
+    /// Set a software watchpoint on `len` bytes at a variable in scope (or a
+    /// field, deref, or element of one), or else at the address an expression
+    /// evaluates to.
+    pub fn add_software_watchpoint(
+        &mut self,
+        text: &str,
+        len: usize,
+    ) -> Result<NewWatchpoint, DbgError> {
+        if !self.running {
+            return Err("the program is not running".to_string().into());
         }
+        let text = text.trim();
+        let (addr, _, note) = self.resolve_watch(text)?;
+        let old = self
+            .target
+            .read_mem(addr, len)
+            .map_err(|err| format!("cannot watch 0x{addr:x}: {err}"))?;
+        let num = self.next_watchpoint_num;
+        self.next_watchpoint_num += 1;
+        self.sw_watchpoints.push(SwWatchpoint {
+            num,
+            text: text.to_string(),
+            addr,
+            len,
+            old: Some(old),
+        });
+        Ok(NewWatchpoint {
+            kind: None,
+            num,
+            text: text.to_string(),
+            addr,
+            len,
+            note,
+        })
+    }
+
+    /// Resolve what a watchpoint watches: a place like `p.x` or `arr[i]`
+    /// (with its type), or else an address expression.
+    ///
+    /// The address is computed once, so if it depends on a local (including
+    /// the index variable in `arr[i]`), the watchpoint stays on that address
+    /// even after the local changes or its frame returns. The last component
+    /// of the result is a note saying so.
+    fn resolve_watch(&self, text: &str) -> Result<(u64, Option<Type>, Option<String>), String> {
+        let place = match Place::parse(text) {
+            Some(place) => place?,
+            None => return Ok((Expr::parse(text)?.eval(self)?, None, None)),
+        };
+        let (addr, ty) = self.locate_place(&place)?;
+        let is_local = |name: &str| {
+            self.find_variable(name).ok().flatten().is_some_and(|var| {
+                matches!(
+                    var.location,
+                    Some(VarLocation::FrameOffset(_) | VarLocation::RegisterOffset(..))
+                )
+            })
+        };
+        let note = match &place {
+            Place::Var(name) if is_local(name) => Some(format!(
+                "note: {text} is a local, so this watches 0x{addr:x} even after its frame returns"
+            )),
+            Place::Var(_) => None,
+            _ => place.vars().into_iter().find(|name| is_local(name)).map(|name| {
+                format!("note: {text} uses the local {name}, so this watches 0x{addr:x} even if {name} changes or its frame returns")
+            }),
+        };
+        Ok((addr, Some(ty), note))
     }
 
-    pub fn print_backtrace(&self) {
-        let mut pc = self.get_offset_pc();
-        let mut fp = self.target.getreg(Reg::Rbp).unwrap();
-        let mut frame_num = 1;
-        let mut done = false;
-        while !done {
-            done = self.backtrace_print_frame(frame_num, pc);
-            (fp, pc) = self.get_prev_frame(fp);
-            pc -= self.load_addr;
-            frame_num += 1;
+    /// Delete a watchpoint, by its number.
+    pub fn delete_watchpoint(&mut self, num: usize) -> Result<(), DbgError> {
+        if let Some(i) = self.sw_watchpoints.iter().position(|w| w.num == num) {
+            self.sw_watchpoints.remove(i);
+            return Ok(());
+        }
+        let slot = self
+            .watchpoints
+            .iter()
+            .position(|w| w.as_ref().is_some_and(|w| w.num == num))
+            .ok_or_else(|| format!("no watchpoint number {num}"))?;
+        self.watchpoints[slot] = None;
+        if !self.running {
+            return Ok(());
         }
+        self.target
+            .get_debugreg(Watchpoint::DR7)
+            .and_then(|dr7| {
+                self.threads
+                    .set_debugreg(Watchpoint::DR7, dr7 & !Watchpoint::dr7_mask(slot))
+            })
+            .map_err(|err| format!("could not clear watchpoint: {err}"))?;
+        Ok(())
     }
 
-    pub fn print_breakpoints(&self) {
-        let mut bps: Vec<_> = self
-            .breakpoints
-            .values()
-            .filter(|bp| bp.enabled() && bp.source == BreakpointSource::User)
-            .collect();
-        bps.sort_by_key(|bp| bp.addr);
-        for bp in bps.into_iter() {
-            let pc = bp.addr - self.load_addr;
-            let frame = self.info.frame_for_pc(pc).unwrap();
-            let file = frame.file_suffix_or("??");
-            let line = frame.line_or("??");
-            let func = frame.inner_function().unwrap_or(Cow::Borrowed("??"));
-            println!("breakpoint at 0x{pc}: file {file}, line {line} (in {func})");
+    /// Find the watchpoints that triggered the current stop, if any.
+    fn watchpoint_hits(&mut self) -> Vec<WatchpointHit> {
+        // DR6 has a bit for each debug register that triggered, which the
+        // kernel leaves for us to clear
+        let dr6 = match self.target.get_debugreg(Watchpoint::DR6) {
+            Ok(dr6) => dr6,
+            Err(_) => return vec![],
+        };
+        if dr6 & 0b1111 == 0 {
+            return vec![];
+        }
+        let mut hits = vec![];
+        for slot in 0..Watchpoint::SLOTS {
+            if dr6 & (1 << slot) == 0 {
+                continue;
+            }
+            let wp = match &self.watchpoints[slot] {
+                Some(wp) => wp,
+                None => continue,
+            };
+            let new = self.target.read_mem(wp.addr, wp.len).ok();
+            let changed = new != wp.old;
+            if wp.kind == WatchKind::Read && changed {
+                // a write, which a read watchpoint also triggers on
+                self.watchpoints[slot].as_mut().unwrap().old = new;
+                continue;
+            }
+            let old = if changed || wp.kind == WatchKind::Write {
+                Some(wp.format(self, &wp.old))
+            } else {
+                None
+            };
+            hits.push(WatchpointHit {
+                kind: Some(wp.kind),
+                num: wp.num,
+                text: wp.text.clone(),
+                old,
+                new: wp.format(self, &new),
+            });
+            self.watchpoints[slot].as_mut().unwrap().old = new;
+        }
+        _ = self.target.set_debugreg(Watchpoint::DR6, dr6 & !0b1111);
+        hits
+    }
+
+    fn display_value(&self, display: &Display) -> DisplayValue {
+        let value = match display.expr.eval(self) {
+            Ok(val) => self.format_value(val, display.format),
+            Err(err) => format!("<{err}>"),
+        };
+        DisplayValue {
+            num: display.num,
+            desc: display.describe(),
+            value,
+        }
+    }
+
+    /// Add an expression to show each time the target stops, in an optional
+    /// format, returning its value now if the target is running.
+    pub fn add_display(
+        &mut self,
+        text: &str,
+        format: Option<Format>,
+    ) -> Result<Option<DisplayValue>, DbgError> {
+        let expr = Expr::parse(text)?;
+        let display = Display {
+            num: self.next_display_num,
+            text: text.trim().to_string(),
+            expr,
+            format,
+        };
+        self.next_display_num += 1;
+        let value = self.running.then(|| self.display_value(&display));
+        self.displays.push(display);
+        Ok(value)
+    }
+
+    /// Stop displaying an expression, by its display number.
+    pub fn delete_display(&mut self, num: usize) -> Result<(), DbgError> {
+        let len = self.displays.len();
+        self.displays.retain(|d| d.num != num);
+        if self.displays.len() == len {
+            return Err(format!("no display number {num}").into());
+        }
+        Ok(())
+    }
+
+    /// Evaluate all of the auto-display expressions, if the target is
+    /// running.
+    pub fn display_values(&self) -> Vec<DisplayValue> {
+        if !self.running {
+            return vec![];
+        }
+        self.displays
+            .iter()
+            .map(|display| self.display_value(display))
+            .collect()
+    }
+
+    /// List the auto-display expressions (without evaluating them), by
+    /// number.
+    pub fn displays(&self) -> Vec<(usize, String)> {
+        self.displays
+            .iter()
+            .map(|display| (display.num, display.describe()))
+            .collect()
+    }
+
+    /// Get the arguments the program is run with.
+    pub fn args(&self) -> &[OsString] {
+        &self.args
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Get the path of the program being debugged.
+    pub fn program(&self) -> &Path {
+        &self.program
+    }
+
+    /// Set the path of the program being debugged.
+    pub fn set_program(&mut self, program: PathBuf) {
+        self.program = program;
+    }
+
+    /// Set the arguments to run the program with.
+    ///
+    /// These take effect the next time the program is started.
+    pub fn set_args(&mut self, args: Vec<OsString>) {
+        self.args = args;
+    }
+
+    pub fn scheduler_locking(&self) -> SchedulerLocking {
+        self.scheduler_locking
+    }
+
+    pub fn pagination(&self) -> bool {
+        self.pager.enabled()
+    }
+
+    pub fn set_pagination(&self, on: bool) {
+        self.pager.set_enabled(on);
+    }
+
+    pub fn disassemble_next_line(&self) -> bool {
+        self.disassemble_next_line
+    }
+
+    pub fn set_disassemble_next_line(&mut self, on: bool) {
+        self.disassemble_next_line = on;
+    }
+
+    /// Rewrite source paths starting with `from` to start with `to` instead,
+    /// replacing any earlier rule for `from`.
+    pub fn set_substitute_path(&mut self, from: &str, to: &str) {
+        self.substitute_paths.retain(|(f, _)| f != from);
+        self.substitute_paths
+            .push((from.to_string(), to.to_string()));
+    }
+
+    pub fn substitute_paths(&self) -> &[(String, String)] {
+        &self.substitute_paths
+    }
+
+    /// Add a directory to search for source files.
+    pub fn add_source_dir(&mut self, dir: PathBuf) {
+        if !self.source_dirs.contains(&dir) {
+            self.source_dirs.push(dir);
+        }
+    }
+
+    pub fn source_dirs(&self) -> &[PathBuf] {
+        &self.source_dirs
+    }
+
+    pub fn listsize(&self) -> usize {
+        self.listsize
+    }
+
+    pub fn set_listsize(&mut self, n: usize) {
+        self.listsize = n;
+    }
+
+    /// The pager for output that could be long, shared with the frontend so
+    /// its output counts toward a page.
+    pub(crate) fn pager(&self) -> &Pager {
+        &self.pager
+    }
+
+    /// Start paging the output of a new command.
+    pub fn reset_pager(&self) {
+        self.pager.reset();
+    }
+
+    pub fn set_scheduler_locking(&mut self, mode: SchedulerLocking) {
+        self.scheduler_locking = mode;
+    }
+
+    /// Capture the target's output, to support logging it.
+    pub fn set_target_output(&mut self, output: TargetOutput) {
+        self.output = Some(output);
+    }
+
+    /// Start (with a path) or stop (with None) logging the target's output.
+    pub fn set_logging(&mut self, path: Option<&Path>) -> Result<(), DbgError> {
+        let output = self
+            .output
+            .as_mut()
+            .ok_or_else(|| "program output is not being captured".to_string())?;
+        match path {
+            Some(path) => output
+                .start_logging(path)
+                .map_err(|err| format!("could not open {}: {err}", path.display()).into()),
+            None => {
+                output.stop_logging();
+                Ok(())
+            }
+        }
+    }
+
+    /// Get the file the target's output is being logged to, if any.
+    pub fn logging_path(&self) -> Option<&Path> {
+        self.output.as_ref().and_then(|output| output.log_path())
+    }
+
+    /// Finish copying the target's output, once it has exited or been killed.
+    pub fn finish_output(&mut self) {
+        if let Some(output) = self.output.take() {
+            output.finish();
         }
     }
 
     /// Get the pid of the target being debugged.
     pub fn target_pid(&self) -> pid_t {
-        self.target.pid()
+        self.threads.main().pid()
+    }
+
+    /// Take the warnings from the commands run since the last call.
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        mem::take(&mut self.warnings)
+    }
+
+    /// The thread the target stopped in, if it isn't the one the last stop
+    /// was reported in.
+    pub fn take_thread_switch(&mut self) -> Option<pid_t> {
+        let thread = self.target.pid();
+        if thread == self.reported_thread {
+            return None;
+        }
+        self.reported_thread = thread;
+        Some(thread)
     }
 
     /// Attempt to kill the running target.
     pub fn kill_target_if_running(&self) {
         if self.running {
-            _ = self.target.kill();
+            _ = self.threads.main().kill();
+        }
+    }
+
+    /// Kill the target, if it's running, and wait for it to be gone.
+    pub fn kill_target(&mut self) {
+        if !self.running {
+            return;
+        }
+        _ = self.threads.main().kill();
+        // reap it, so the old pid doesn't linger as a zombie
+        self.threads.reap();
+        self.running = false;
+    }
+
+    /// Start debugging a new run of the program, launched with [`run_target`]
+    /// after killing the old one.
+    ///
+    /// User breakpoints are carried over at the same offsets, since the program
+    /// may be loaded somewhere else; internal and temporary ones are dropped.
+    ///
+    /// [`run_target`]: crate::run_target
+    pub fn restart(&mut self, pid: pid_t, output: TargetOutput) -> Result<(), io::Error> {
+        // if the new target fails to start, its output explains why
+        self.output = Some(output);
+        let target = ptrace::Target::new(pid);
+        let load_addr = Self::wait_for_start(target, self.kind)?;
+        let old_load_addr = self.load_addr;
+        self.target = target;
+        self.threads = Threads::new(pid);
+        self.threads.trace_clones()?;
+        self.reported_thread = pid;
+        self.load_addr = load_addr;
+        self.running = true;
+        self.selected_frame = 0;
+        self.stale_interrupt = false;
+        self.pending_finish = None;
+        self.pending_signal = 0;
+
+        let old_breakpoints = mem::take(&mut self.breakpoints);
+        for bp in old_breakpoints.into_values() {
+            if bp.is_internal() || bp.temporary {
+                continue;
+            }
+            let addr = bp.addr - old_load_addr + load_addr;
+            let mut new_bp = Breakpoint {
+                target,
+                addr,
+                saved_data: None,
+                ..bp
+            };
+            if bp.enabled() {
+                if let Err(err) = new_bp.enable() {
+                    self.warnings.push(format!(
+                        "could not set breakpoint at 0x{:x}: {err}",
+                        bp.addr - old_load_addr
+                    ));
+                }
+            }
+            self.breakpoints.insert(addr, new_bp);
+        }
+
+        // the debug registers start out clear in the new process
+        let mut dr7 = 0;
+        for (slot, wp) in self.watchpoints.iter_mut().enumerate() {
+            if let Some(wp) = wp {
+                _ = self.threads.set_debugreg(slot, wp.addr);
+                wp.old = target.read_mem(wp.addr, wp.len).ok();
+                dr7 |= wp.dr7_bits(slot);
+            }
+        }
+        if dr7 != 0 {
+            _ = self.threads.set_debugreg(Watchpoint::DR7, dr7);
+        }
+        for wp in &mut self.sw_watchpoints {
+            wp.old = target.read_mem(wp.addr, wp.len).ok();
+        }
+        Ok(())
+    }
+
+    /// Record that the target was attached to rather than launched.
+    pub fn set_attached(&mut self, attached: bool) {
+        self.attached = attached;
+    }
+
+    /// Trace the threads of a process that was attached to, which already
+    /// existed.
+    pub fn attach_threads(&mut self) -> Result<(), io::Error> {
+        self.threads.attach_existing()
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.attached
+    }
+
+    /// End the session: detach from an attached target, so it keeps running,
+    /// and kill a launched one.
+    pub fn release_target(&mut self) -> Result<(), DbgError> {
+        if !self.attached {
+            self.kill_target_if_running();
+        } else if self.running {
+            self.detach()?;
+        }
+        Ok(())
+    }
+
+    /// Stop tracing the target and let it run, first removing breakpoints and
+    /// watchpoints so they don't kill it.
+    pub fn detach(&mut self) -> Result<(), DbgError> {
+        if !self.running {
+            return Err("the program is not running".to_string().into());
+        }
+        let load_addr = self.load_addr;
+        for bp in self.breakpoints.values_mut() {
+            if bp.enabled() {
+                if let Err(err) = bp.disable() {
+                    self.warnings.push(format!(
+                        "could not remove breakpoint at 0x{:x}: {err}",
+                        bp.addr - load_addr
+                    ));
+                }
+            }
+        }
+        self.breakpoints.clear();
+        self.pending_finish = None;
+        if self.watchpoints.iter().any(|w| w.is_some()) {
+            self.watchpoints = Default::default();
+            _ = self.threads.set_debugreg(Watchpoint::DR7, 0);
+        }
+        self.sw_watchpoints.clear();
+        self.threads
+            .detach()
+            .map_err(|err| format!("could not detach: {err}"))?;
+        self.running = false;
+        Ok(())
+    }
+}
+
+impl Memory for Dbg<'_> {
+    fn read_memory(&self, addr: u64, len: usize) -> Result<Vec<u8>, String> {
+        self.target
+            .read_mem(addr, len)
+            .map_err(|err| err.to_string())
+    }
+}
+
+impl EvalContext for Dbg<'_> {
+    fn get_reg(&self, reg: Reg) -> Result<u64, String> {
+        self.frame_reg(reg)
+    }
+
+    fn history(&self, num: Option<usize>) -> Result<u64, String> {
+        self.history_value(num)?.as_u64()
+    }
+
+    fn var(&self, name: &str) -> Result<u64, String> {
+        self.convenience_var(name)?.as_u64()
+    }
+}
+
+/// Check that a number fits in `size` bytes, either as an unsigned number or
+/// as a sign-extended negative number.
+fn fits_in(val: u64, size: usize) -> bool {
+    if size >= 8 {
+        return true;
+    }
+    let bits = 8 * size;
+    val >> bits == 0 || (val as i64) >> (bits - 1) == -1
+}
+
+/// Format a floating-point register: an x87 stack register with its tag and
+/// value, or a control/status register decoded into its flags.
+pub fn format_float_register(regs: &libc::user_fpregs_struct, reg: FpReg) -> String {
+    let name = reg.name();
+    match reg {
+        FpReg::St(index) => {
+            let st = fpregs::st_registers(regs)[index];
+            let name = st.name();
+            if st.tag == X87Tag::Empty {
+                return format!("{name:<4} empty");
+            }
+            let raw = format!("0x{:04x}{:016x}", st.value.sign_exp, st.value.mantissa);
+            format!(
+                "{name:<4} {:<7} {} (raw {raw})",
+                st.tag.name(),
+                st.value.to_f64()
+            )
+        }
+        FpReg::Fctrl => format!(
+            "{name} 0x{:04x} {}",
+            regs.cwd,
+            fpregs::decode_fpu_control(regs.cwd)
+        ),
+        FpReg::Fstat => format!(
+            "{name} 0x{:04x} {}",
+            regs.swd,
+            fpregs::decode_fpu_status(regs.swd)
+        ),
+        FpReg::Mxcsr => format!(
+            "{name} 0x{:04x} {}",
+            regs.mxcsr,
+            fpregs::decode_mxcsr(regs.mxcsr)
+        ),
+    }
+}
+
+/// Check if text is a (possibly qualified) name, like a variable.
+fn is_identifier(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+/// Render bytes as the contents of a string literal, escaping anything that
+/// isn't printable ASCII.
+pub fn escape_c_string(bytes: &[u8]) -> String {
+    let mut s = String::new();
+    for &b in bytes {
+        match b {
+            b'"' | b'\\' => {
+                s.push('\\');
+                s.push(b as char);
+            }
+            0x20..=0x7e => s.push(b as char),
+            _ => s.push_str(&format!("\\x{b:02x}")),
         }
     }
+    s
 }
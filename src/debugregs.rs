@@ -0,0 +1,96 @@
+//! Decode the x86 debug registers.
+//!
+//! DR0-DR3 hold the linear addresses of up to four hardware breakpoints (or
+//! watchpoints), DR6 reports which of them fired, and DR7 enables each one and
+//! says what kind of access it breaks on and how many bytes it covers.
+
+/// Number of address registers (DR0-DR3).
+pub const SLOTS: usize = 4;
+
+/// The status register's number.
+pub const DR6: usize = 6;
+
+/// The control register's number.
+pub const DR7: usize = 7;
+
+fn bracketed(items: Vec<String>) -> String {
+    if items.is_empty() {
+        return "[ ]".to_string();
+    }
+    format!("[ {} ]", items.join(" "))
+}
+
+/// Decode DR6 into the slots that fired and the other debug conditions.
+pub fn decode_dr6(dr6: u64) -> String {
+    let mut items: Vec<String> = (0..SLOTS)
+        .filter(|slot| dr6 & (1 << slot) != 0)
+        .map(|slot| format!("b{slot}"))
+        .collect();
+    for (bit, name) in [
+        (13, "debug-access"),
+        (14, "single-step"),
+        (15, "task-switch"),
+    ] {
+        if dr6 & (1 << bit) != 0 {
+            items.push(name.to_string());
+        }
+    }
+    bracketed(items)
+}
+
+/// Decode DR7 into what each enabled slot breaks on.
+pub fn decode_dr7(dr7: u64) -> String {
+    let mut items = vec![];
+    for slot in 0..SLOTS {
+        let local = dr7 & (1 << (2 * slot)) != 0;
+        let global = dr7 & (1 << (2 * slot + 1)) != 0;
+        if !local && !global {
+            continue;
+        }
+        let access = match (dr7 >> (16 + 4 * slot)) & 0b11 {
+            0b00 => "exec",
+            0b01 => "write",
+            0b10 => "io",
+            _ => "rw",
+        };
+        let len = match (dr7 >> (18 + 4 * slot)) & 0b11 {
+            0b00 => 1,
+            0b01 => 2,
+            0b10 => 8,
+            _ => 4,
+        };
+        let scope = match (local, global) {
+            (true, true) => "local,global",
+            (true, false) => "local",
+            _ => "global",
+        };
+        items.push(format!("dr{slot}={access}/{len}({scope})"));
+    }
+    if dr7 & (1 << 13) != 0 {
+        items.push("general-detect".to_string());
+    }
+    bracketed(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_dr6, decode_dr7};
+
+    #[test]
+    fn test_decode_dr6() {
+        assert_eq!(decode_dr6(0), "[ ]");
+        assert_eq!(decode_dr6(0b0101 | (1 << 14)), "[ b0 b2 single-step ]");
+    }
+
+    #[test]
+    fn test_decode_dr7() {
+        assert_eq!(decode_dr7(0), "[ ]");
+        // slot 1: local, break on writes of 4 bytes
+        let dr7 = (1 << 2) | (0b1101 << 20);
+        assert_eq!(decode_dr7(dr7), "[ dr1=write/4(local) ]");
+        // the R/W and LEN fields don't matter for a disabled slot
+        assert_eq!(decode_dr7(0b1111 << 16), "[ ]");
+        let dr7 = 0b10 | (0b1011 << 16);
+        assert_eq!(decode_dr7(dr7), "[ dr0=rw/8(global) ]");
+    }
+}
@@ -0,0 +1,93 @@
+//! Disassemble x86-64 machine code.
+
+use iced_x86::{Decoder, DecoderOptions, FlowControl, Formatter, IntelFormatter};
+
+/// The longest possible x86 instruction, in bytes.
+pub const MAX_INSTR_LEN: usize = 15;
+
+/// How an instruction transfers control, if it does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Branch {
+    /// true for conditional jumps, which might fall through
+    pub conditional: bool,
+    /// the destination, if it's encoded in the instruction
+    pub target: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instr {
+    pub addr: u64,
+    pub len: usize,
+    /// the instruction in Intel syntax
+    pub text: String,
+    /// set for jumps, calls, returns, and other control transfers
+    pub branch: Option<Branch>,
+    /// set for calls, which return to the following instruction
+    pub call: bool,
+    /// set for `rep`-prefixed string instructions, which single-step one
+    /// iteration at a time
+    pub repeated: bool,
+}
+
+impl Instr {
+    /// The address of the following instruction.
+    pub fn next_addr(&self) -> u64 {
+        self.addr + self.len as u64
+    }
+}
+
+/// Decode all the instructions in `code`, which is located at `addr`,
+/// stopping at the first invalid instruction.
+pub fn decode_all(code: &[u8], addr: u64) -> Vec<Instr> {
+    let mut instrs = vec![];
+    let mut offset = 0;
+    while offset < code.len() {
+        match decode_one(&code[offset..], addr + offset as u64) {
+            Some(instr) => {
+                offset += instr.len;
+                instrs.push(instr);
+            }
+            None => break,
+        }
+    }
+    instrs
+}
+
+/// Decode the first instruction in `code`, which is located at `addr`.
+///
+/// Returns None if the bytes aren't a valid instruction.
+pub fn decode_one(code: &[u8], addr: u64) -> Option<Instr> {
+    let mut decoder = Decoder::with_ip(64, code, addr, DecoderOptions::NONE);
+    let instr = decoder.decode();
+    if instr.is_invalid() {
+        return None;
+    }
+    let mut text = String::new();
+    IntelFormatter::new().format(&instr, &mut text);
+    let direct_target = Some(instr.near_branch_target()).filter(|&t| t != 0);
+    let branch = match instr.flow_control() {
+        FlowControl::Next => None,
+        FlowControl::ConditionalBranch => Some(Branch {
+            conditional: true,
+            target: direct_target,
+        }),
+        _ => Some(Branch {
+            conditional: false,
+            target: direct_target,
+        }),
+    };
+    let call = matches!(
+        instr.flow_control(),
+        FlowControl::Call | FlowControl::IndirectCall
+    );
+    let repeated = instr.is_string_instruction()
+        && (instr.has_rep_prefix() || instr.has_repe_prefix() || instr.has_repne_prefix());
+    Some(Instr {
+        addr,
+        len: instr.len(),
+        text,
+        branch,
+        call,
+        repeated,
+    })
+}
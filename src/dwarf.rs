@@ -8,16 +8,20 @@
 //! extremely useful for understanding gimli.
 
 #![allow(unused_variables)]
-use std::{borrow::Cow, fmt, ops::Range, rc::Rc};
+use std::{borrow::Cow, collections::HashSet, fmt, ops::Range, rc::Rc};
 
 use addr2line::{fallible_iterator::FallibleIterator, Location};
 use gimli::{
     AttributeValue, BaseAddresses, DebuggingInformationEntry, Dwarf, EhFrame, EndianRcSlice,
-    EndianSlice, LittleEndian, Reader, Register, Unit, UnwindContext, UnwindSection,
+    EndianSlice, EntriesTreeNode, LittleEndian, Operation, Reader, Register, Unit, UnitOffset,
+    UnwindContext, UnwindSection,
 };
 use object::{Object, ObjectSection, ObjectSymbol, SymbolKind};
+use regex::Regex;
 
-pub use self::ret_addr::ReturnAddrRule;
+pub use self::ret_addr::{CallerFrame, ReturnAddrEvaluator, ReturnAddrRule, CALLEE_SAVED};
+use crate::ptrace::Reg;
+use crate::types::{Encoding, Member, Type, Variant};
 
 /// Identify the type of a symbol.
 ///
@@ -68,8 +72,103 @@ pub struct Symbol {
     pub type_: SymbolType,
     pub name: String,
     pub addr: u64,
+    pub size: u64,
 }
 
+/// Where a variable is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VarLocation {
+    /// at an offset from the frame base
+    FrameOffset(i64),
+    /// at an offset from the address in a register
+    RegisterOffset(Reg, i64),
+    /// in a register, rather than in memory
+    Register(Reg),
+    /// at a fixed address
+    Addr(u64),
+}
+
+/// What a function's frame base (which variables are located relative to)
+/// is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameBase {
+    /// the value of a register
+    Register(Reg),
+    /// an offset from the value of a register
+    RegisterOffset(Reg, i64),
+    /// the canonical frame address, from the unwind info
+    Cfa,
+}
+
+/// Demangle a symbol table name, which could be from Rust or C++ (the symbol
+/// table doesn't say which), leaving names that aren't mangled as they are.
+fn demangle_symbol(name: &str) -> String {
+    addr2line::demangle_auto(Cow::Borrowed(name), None).into_owned()
+}
+
+/// Interpret a DW_AT_frame_base expression.
+fn frame_base<R: Reader>(
+    expr: gimli::Expression<R>,
+    encoding: gimli::Encoding,
+) -> gimli::Result<Option<FrameBase>> {
+    let mut ops = expr.operations(encoding);
+    let base = match ops.next()? {
+        Some(Operation::Register { register }) => {
+            Reg::from_dwarf(register.0).map(FrameBase::Register)
+        }
+        Some(Operation::RegisterOffset {
+            register, offset, ..
+        }) => Reg::from_dwarf(register.0).map(|reg| FrameBase::RegisterOffset(reg, offset)),
+        Some(Operation::CallFrameCFA) => Some(FrameBase::Cfa),
+        _ => None,
+    };
+    if ops.next()?.is_some() {
+        return Ok(None);
+    }
+    Ok(base)
+}
+
+/// Interpret a DW_AT_location expression, if it's one of the simple forms
+/// that give the variable's address.
+fn var_location<R: Reader>(
+    expr: gimli::Expression<R>,
+    encoding: gimli::Encoding,
+) -> gimli::Result<Option<VarLocation>> {
+    let mut ops = expr.operations(encoding);
+    let location = match ops.next()? {
+        Some(Operation::FrameOffset { offset }) => VarLocation::FrameOffset(offset),
+        Some(Operation::RegisterOffset {
+            register, offset, ..
+        }) => match Reg::from_dwarf(register.0) {
+            Some(reg) => VarLocation::RegisterOffset(reg, offset),
+            None => return Ok(None),
+        },
+        Some(Operation::Register { register }) => match Reg::from_dwarf(register.0) {
+            Some(reg) => VarLocation::Register(reg),
+            None => return Ok(None),
+        },
+        Some(Operation::Address { address }) => VarLocation::Addr(address),
+        _ => return Ok(None),
+    };
+    // anything more computes a value rather than the address
+    if ops.next()?.is_some() {
+        return Ok(None);
+    }
+    Ok(Some(location))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variable {
+    pub name: String,
+    /// None if the variable's location isn't supported
+    pub location: Option<VarLocation>,
+    pub ty: Type,
+}
+
+/// How many pointers to follow when reading a type (for slices, whose element
+/// type is behind a pointer).
+const MAX_TYPE_DEPTH: usize = 4;
+
 type Die<'abbrev, 'unit, R> =
     DebuggingInformationEntry<'abbrev, 'unit, R, <R as gimli::Reader>::Offset>;
 
@@ -88,11 +187,26 @@ fn at_high_pc(low_pc: u64, high_pc: AttributeValue<impl gimli::Reader>) -> u64 {
     match high_pc {
         AttributeValue::Addr(a) => a,
         AttributeValue::Sdata(n) => low_pc + n as u64,
-        AttributeValue::Udata(n) => low_pc + n as u64,
+        AttributeValue::Udata(n) => low_pc + n,
         _ => panic!("invalid high_pc type"),
     }
 }
 
+fn at_type(die: &Die<R>) -> gimli::Result<Option<UnitOffset>> {
+    match die.attr_value(gimli::DW_AT_type)? {
+        Some(AttributeValue::UnitRef(offset)) => Ok(Some(offset)),
+        _ => Ok(None),
+    }
+}
+
+fn at_udata(die: &Die<impl gimli::Reader>, name: gimli::DwAt) -> gimli::Result<Option<u64>> {
+    Ok(die.attr_value(name)?.and_then(|v| {
+        // negative values are returned as their two's complement
+        v.udata_value()
+            .or_else(|| v.sdata_value().map(|v| v as u64))
+    }))
+}
+
 fn at_pc_range(die: &Die<impl gimli::Reader>) -> gimli::Result<Range<u64>> {
     let low_pc = at_low_pc(die)?.unwrap_or(0);
     let high_pc = die
@@ -119,128 +233,230 @@ pub struct DbgInfo<'data> {
 }
 
 mod ret_addr {
-    use gimli::{CfaRule, EndianSlice, LittleEndian, RegisterRule};
+    use gimli::{CfaRule, EndianSlice, Expression, LittleEndian, Operation, RegisterRule};
 
-    use crate::ptrace::{self, Reg};
+    use crate::ptrace::Reg;
 
     fn dwarf_to_reg(dwarf_r: gimli::Register) -> Result<Reg, String> {
-        #[derive(Debug)]
-        struct RegDescriptor {
-            reg: Reg,
-            dwarf_r: u16,
-        }
-
-        const fn desc(reg: Reg, dwarf_r: u16) -> RegDescriptor {
-            RegDescriptor { reg, dwarf_r }
-        }
-
-        const REGS: [RegDescriptor; 27] = [
-            desc(Reg::R15, 5),
-            desc(Reg::R14, 4),
-            desc(Reg::R13, 3),
-            desc(Reg::R12, 2),
-            desc(Reg::Rbp, 6),
-            desc(Reg::Rbx, 3),
-            desc(Reg::R11, 1),
-            desc(Reg::R10, 0),
-            desc(Reg::R9, 9),
-            desc(Reg::R8, 8),
-            desc(Reg::Rax, 0),
-            desc(Reg::Rcx, 2),
-            desc(Reg::Rdx, 1),
-            desc(Reg::Rsi, 4),
-            desc(Reg::Rdi, 5),
-            desc(Reg::Orig_rax, 1),
-            desc(Reg::Rip, 1),
-            desc(Reg::Cs, 1),
-            desc(Reg::Rflags, 9),
-            desc(Reg::Rsp, 7),
-            desc(Reg::Ss, 2),
-            desc(Reg::Fs_base, 8),
-            desc(Reg::Gs_base, 9),
-            desc(Reg::Ds, 3),
-            desc(Reg::Es, 0),
-            desc(Reg::Fs, 4),
-            desc(Reg::Gs, 5),
-        ];
-
-        let dwarf_r = dwarf_r.0;
-
-        REGS.iter()
-            .find(|r| r.dwarf_r == dwarf_r)
-            .map(|r| r.reg)
-            .ok_or_else(|| "invalid dwarf register".to_string())
+        Reg::from_dwarf(dwarf_r.0).ok_or_else(|| "invalid dwarf register".to_string())
     }
 
+    /// The registers and memory of the frame being unwound. Reads can't fail:
+    /// implementations read the registers before unwinding starts, and read
+    /// unreadable memory as 0.
     pub trait ReturnAddrEvaluator {
         fn get_reg(&self, reg: Reg) -> u64;
         fn read_mem(&self, addr: u64) -> u64;
     }
 
+    /// The callee-saved registers besides rsp (the CFA) and rbp (the frame
+    /// pointer), which a function restores before returning.
+    pub const CALLEE_SAVED: [Reg; 5] = [Reg::Rbx, Reg::R12, Reg::R13, Reg::R14, Reg::R15];
+
     #[derive(Debug, Clone, PartialEq, Eq)]
     pub struct ReturnAddrRule<'a> {
         pub cfa: CfaRule<EndianSlice<'a, LittleEndian>>,
         pub ra: RegisterRule<EndianSlice<'a, LittleEndian>>,
+        /// rule for the caller's frame pointer
+        pub fp: RegisterRule<EndianSlice<'a, LittleEndian>>,
+        /// rules for the caller's [`CALLEE_SAVED`] registers, in order
+        pub saved: [RegisterRule<EndianSlice<'a, LittleEndian>>; 5],
+    }
+
+    /// The state of the caller, recovered by unwinding one frame.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct CallerFrame {
+        /// canonical frame address, which is the caller's stack pointer
+        pub cfa: u64,
+        pub ra: u64,
+        pub fp: u64,
+        /// the caller's [`CALLEE_SAVED`] registers, or None for the ones this
+        /// frame didn't save (and so didn't change)
+        pub saved: [Option<u64>; 5],
+    }
+
+    /// Evaluate a DWARF expression from an unwind rule, with `initial` pushed
+    /// on the stack first (register rules start with the CFA).
+    ///
+    /// Only the operations that show up in practice in eh_frame are
+    /// supported: registers plus offsets, constants, arithmetic, and reading
+    /// memory. Returns None for anything else, or an invalid expression.
+    fn evaluate_expression<E: ReturnAddrEvaluator>(
+        expr: Expression<EndianSlice<'_, LittleEndian>>,
+        initial: Option<u64>,
+        eval: &E,
+    ) -> Option<u64> {
+        let encoding = gimli::Encoding {
+            address_size: 8,
+            format: gimli::Format::Dwarf32,
+            version: 4,
+        };
+        let mut stack: Vec<u64> = initial.into_iter().collect();
+        let mut ops = expr.operations(encoding);
+        while let Some(op) = ops.next().ok()? {
+            let val = match op {
+                Operation::RegisterOffset {
+                    register, offset, ..
+                } => {
+                    let reg = dwarf_to_reg(register).ok()?;
+                    (eval.get_reg(reg) as i64).wrapping_add(offset) as u64
+                }
+                Operation::SignedConstant { value } => value as u64,
+                Operation::UnsignedConstant { value } => value,
+                Operation::PlusConstant { value } => stack.pop()?.wrapping_add(value),
+                Operation::Plus | Operation::Minus | Operation::And => {
+                    let y = stack.pop()?;
+                    let x = stack.pop()?;
+                    match op {
+                        Operation::Plus => x.wrapping_add(y),
+                        Operation::Minus => x.wrapping_sub(y),
+                        _ => x & y,
+                    }
+                }
+                Operation::Deref { size, .. } => {
+                    let val = eval.read_mem(stack.pop()?);
+                    if size < 8 {
+                        val & ((1 << (size * 8)) - 1)
+                    } else {
+                        val
+                    }
+                }
+                _ => return None,
+            };
+            stack.push(val);
+        }
+        stack.pop()
+    }
+
+    /// Evaluate a register rule, or return None if it isn't supported.
+    fn evaluate_rule<E: ReturnAddrEvaluator>(
+        rule: &RegisterRule<EndianSlice<'_, LittleEndian>>,
+        cfa: u64,
+        eval: &E,
+    ) -> Option<u64> {
+        let val = match *rule {
+            RegisterRule::Offset(n) => {
+                let a = (cfa as i64 + n) as u64;
+                eval.read_mem(a)
+            }
+            RegisterRule::ValOffset(n) => (cfa as i64 + n) as u64,
+            RegisterRule::Register(register) => eval.get_reg(dwarf_to_reg(register).ok()?),
+            RegisterRule::Expression(expr) => {
+                eval.read_mem(evaluate_expression(expr, Some(cfa), eval)?)
+            }
+            RegisterRule::ValExpression(expr) => evaluate_expression(expr, Some(cfa), eval)?,
+            _ => return None,
+        };
+        Some(val)
     }
 
     impl ReturnAddrRule<'_> {
-        pub fn evaluate<E: ReturnAddrEvaluator>(&self, eval: E) -> u64 {
+        /// Recover the caller's frame, or None if the rules use something
+        /// unsupported (which ends the unwind at this frame).
+        pub fn evaluate_frame<E: ReturnAddrEvaluator>(&self, eval: &E) -> Option<CallerFrame> {
             let cfa: u64 = match self.cfa {
                 CfaRule::RegisterAndOffset { register, offset } => {
-                    let reg = dwarf_to_reg(register).expect("unexpected dwarf register");
+                    let reg = dwarf_to_reg(register).ok()?;
                     ((eval.get_reg(reg) as i64) + offset) as u64
                 }
-                CfaRule::Expression(_) => unimplemented!("evaluating dwarf expressions for unwind"),
+                CfaRule::Expression(expr) => evaluate_expression(expr, None, eval)?,
             };
-            match self.ra {
-                RegisterRule::Offset(n) => {
-                    let a = (cfa as i64 + n) as u64;
-                    eval.read_mem(a)
-                }
-                RegisterRule::ValOffset(n) => (cfa as i64 + n) as u64,
-                RegisterRule::Register(register) => {
-                    let reg = dwarf_to_reg(register).expect("unexpected dwarf register");
-                    eval.get_reg(reg)
-                }
-                _ => unimplemented!("unsupported register rule {:?}", self.ra),
+            let ra = evaluate_rule(&self.ra, cfa, eval)?;
+            let fp = match self.fp {
+                // rbp is callee-saved, so if it isn't saved it wasn't changed
+                RegisterRule::Undefined | RegisterRule::SameValue => eval.get_reg(Reg::Rbp),
+                ref rule => evaluate_rule(rule, cfa, eval)?,
+            };
+            let mut saved = [None; 5];
+            for (val, rule) in saved.iter_mut().zip(&self.saved) {
+                *val = match rule {
+                    RegisterRule::Undefined | RegisterRule::SameValue => None,
+                    rule => Some(evaluate_rule(rule, cfa, eval)?),
+                };
             }
+            Some(CallerFrame { cfa, ra, fp, saved })
         }
     }
+}
 
-    impl ReturnAddrEvaluator for ptrace::Target {
-        fn get_reg(&self, reg: Reg) -> u64 {
-            self.getreg(reg).expect("could not get register")
-        }
-
-        fn read_mem(&self, addr: u64) -> u64 {
-            self.peekdata(addr)
-                .unwrap_or_else(|_| panic!("could not read mem at 0x{:x}", addr))
-        }
-    }
+/// A source-level function at a pc, which might have been inlined into a
+/// caller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InlineFrame {
+    pub function: Option<String>,
+    /// the file, relative to the compilation directory if it's inside it
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    /// whether this function was inlined into the next frame
+    pub inlined: bool,
 }
 
 pub struct FrameInfo<'a, R: Reader> {
     pub comp_dir: Option<Cow<'a, str>>,
     pub file: Option<&'a str>,
     pub line: Option<u32>,
+    pub column: Option<u32>,
     pub frames: Vec<addr2line::Frame<'a, R>>,
 }
 
 impl<'a, R: Reader> FrameInfo<'a, R> {
-    pub fn inner_function(&self) -> Option<Cow<str>> {
+    pub fn inner_function(&self) -> Option<Cow<'_, str>> {
         self.frames
             .first()
             .and_then(|f| f.function.as_ref().map(|f| f.demangle().unwrap()))
     }
 
     pub fn file_suffix_or<'b>(&'b self, default: &'b str) -> &'b str {
-        match (self.comp_dir.as_ref(), self.file) {
-            (_, None) => default,
-            (Some(dir), Some(f)) => f.strip_prefix(&format!("{dir}/")).unwrap_or(f),
-            (_, Some(f)) => f,
+        match self.file {
+            None => default,
+            Some(f) => self.strip_comp_dir(f),
         }
     }
 
+    fn strip_comp_dir(&self, file: &'a str) -> &'a str {
+        match self.comp_dir.as_ref() {
+            Some(dir) => file.strip_prefix(&format!("{dir}/")).unwrap_or(file),
+            None => file,
+        }
+    }
+
+    /// The functions at this pc, innermost first, each with its own location
+    /// (for a function another was inlined into, the location of the inlined
+    /// call).
+    pub fn inline_frames(&self) -> Vec<InlineFrame> {
+        if self.frames.is_empty() {
+            return vec![InlineFrame {
+                function: None,
+                file: self.file.map(|f| self.strip_comp_dir(f).to_string()),
+                line: self.line,
+                column: self.column,
+                inlined: false,
+            }];
+        }
+        let n = self.frames.len();
+        self.frames
+            .iter()
+            .enumerate()
+            .map(|(i, frame)| {
+                let loc = frame.location.as_ref();
+                InlineFrame {
+                    function: frame
+                        .function
+                        .as_ref()
+                        .and_then(|f| f.demangle().ok())
+                        .map(|name| name.into_owned()),
+                    file: loc
+                        .and_then(|loc| loc.file)
+                        .map(|f| self.strip_comp_dir(f).to_string()),
+                    line: loc.and_then(|loc| loc.line),
+                    column: loc.and_then(|loc| loc.column),
+                    inlined: i + 1 < n,
+                }
+            })
+            .collect()
+    }
+
     pub fn line_or(&self, default: &str) -> String {
         self.line
             .map(|l| l.to_string())
@@ -301,7 +517,7 @@ impl<'data> DbgInfo<'data> {
         Ok(Some(val))
     }
 
-    fn get_function_range_from_pc(&self, pc: u64) -> Result<Option<Range<u64>>, gimli::Error> {
+    pub fn get_function_range_from_pc(&self, pc: u64) -> Result<Option<Range<u64>>, gimli::Error> {
         let unit = match self.ctx.find_dwarf_unit(pc) {
             Some(unit) => unit,
             None => {
@@ -333,7 +549,10 @@ impl<'data> DbgInfo<'data> {
         let mut locs = vec![];
         let iter = self.ctx.find_location_range(range.start, range.end)?;
         for (start, end, loc) in iter {
-            locs.push(start);
+            // rows for line 0 are code not attributable to any line
+            if loc.line.is_some() {
+                locs.push(start);
+            }
         }
         if locs.is_empty() {
             return Ok(vec![range.start]);
@@ -341,28 +560,80 @@ impl<'data> DbgInfo<'data> {
         Ok(locs)
     }
 
-    pub fn source_for_pc(&self, pc: u64) -> Result<Option<Location>, gimli::Error> {
+    /// Find where the body of the function starting at low_pc begins, after
+    /// its prologue: the second is_stmt row of the line table in the
+    /// function, like gdb uses. Falls back to low_pc if there's only one.
+    pub fn function_body_pc(&self, low_pc: u64) -> Result<u64, gimli::Error> {
+        let range = match self.get_function_range_from_pc(low_pc)? {
+            Some(range) => range,
+            None => return Ok(low_pc),
+        };
+        let mut rows = match self
+            .ctx
+            .find_dwarf_unit(low_pc)
+            .and_then(|unit| unit.line_program.clone())
+        {
+            Some(ilnp) => ilnp.rows(),
+            None => return Ok(low_pc),
+        };
+        let mut pcs = vec![];
+        while let Some((_, row)) = rows.next_row()? {
+            if row.is_stmt() && !row.end_sequence() && range.contains(&row.address()) {
+                pcs.push(row.address());
+            }
+        }
+        pcs.sort();
+        pcs.dedup();
+        Ok(pcs.get(1).copied().unwrap_or(low_pc))
+    }
+
+    pub fn source_for_pc(&self, pc: u64) -> Result<Option<Location<'_>>, gimli::Error> {
         self.ctx.find_location(pc)
     }
 
+    /// The directory the code at pc was compiled in (`DW_AT_comp_dir`).
+    pub fn compilation_dir(&self, pc: u64) -> Option<Cow<'_, str>> {
+        self.ctx
+            .find_dwarf_unit(pc)
+            .and_then(|unit| unit.comp_dir.as_ref().map(|dir| dir.to_string().unwrap()))
+    }
+
     pub fn frame_for_pc(&self, pc: u64) -> Result<FrameInfo<'_, R>, gimli::Error> {
         let loc = self.source_for_pc(pc)?;
         let loc = loc.as_ref();
         let file = loc.and_then(|loc| loc.file);
         let line = loc.and_then(|loc| loc.line);
-        let comp_dir = self
-            .ctx
-            .find_dwarf_unit(pc)
-            .and_then(|unit| unit.comp_dir.as_ref().map(|dir| dir.to_string().unwrap()));
+        let column = loc.and_then(|loc| loc.column);
+        let comp_dir = self.compilation_dir(pc);
         let frames = self.ctx.find_frames(pc)?.collect()?;
         Ok(FrameInfo {
             comp_dir,
             file,
             line,
+            column,
             frames,
         })
     }
 
+    /// Find the (non-inlined) function containing pc, with its start address.
+    pub fn function_for_pc(&self, pc: u64) -> gimli::Result<Option<(String, u64)>> {
+        if self.ctx.find_dwarf_unit(pc).is_none() {
+            return Ok(None);
+        }
+        let range = match self.get_function_range_from_pc(pc)? {
+            Some(range) => range,
+            None => return Ok(None),
+        };
+        let frame = self.frame_for_pc(pc)?;
+        let name = frame
+            .frames
+            .last()
+            .and_then(|f| f.function.as_ref())
+            .and_then(|f| f.demangle().ok())
+            .map(|name| name.into_owned());
+        Ok(name.map(|name| (name, range.start)))
+    }
+
     pub fn pc_for_function_pred<F>(&self, pred: F) -> Result<Option<u64>, gimli::Error>
     where
         F: for<'a> Fn(&'a str) -> bool,
@@ -391,15 +662,47 @@ impl<'data> DbgInfo<'data> {
         Ok(None)
     }
 
-    pub fn pc_for_source_loc<F>(
+    /// Get the names of the functions in the debug info, sorted.
+    pub fn function_names(&self) -> gimli::Result<Vec<String>> {
+        let mut names = vec![];
+        let mut units = self.ctx.dwarf().units();
+        while let Some(header) = units.next()? {
+            let unit = self.ctx.dwarf().unit(header)?;
+            let mut entries = unit.entries();
+            while let Some((_, entry)) = entries.next_dfs()? {
+                if entry.tag() != gimli::DW_TAG_subprogram {
+                    continue;
+                }
+                if let Some(name) = self.at_name(&unit, entry)? {
+                    names.push(name.to_string()?.into_owned());
+                }
+            }
+        }
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+
+    /// Find the pcs for a source line.
+    ///
+    /// If the line has no code (say it's a comment or blank), the nearest
+    /// following line in the same file with code is used instead, and
+    /// returned along with the pcs.
+    ///
+    /// A line can have code in several functions (for example, in each
+    /// instantiation of a generic function, or where it's inlined). Returns
+    /// the first pc for the line in each function, sorted.
+    pub fn pcs_for_source_loc<F>(
         &self,
         file_pred: F,
         line: usize,
-    ) -> Result<Option<u64>, gimli::Error>
+    ) -> Result<(usize, Vec<u64>), gimli::Error>
     where
         F: for<'a> Fn(&'a str) -> bool,
     {
         let dwarf = self.dwarf();
+        let mut best_line = None;
+        let mut pcs = vec![];
         let mut units = dwarf.units();
         while let Some(header) = units.next()? {
             let unit = dwarf.unit(header)?;
@@ -409,8 +712,9 @@ impl<'data> DbgInfo<'data> {
             };
             while let Some((header, row)) = rows.next_row()? {
                 // is_stmt marks the instructions the compiler thinks are the
-                // best places for a breakpoint
-                if !row.is_stmt() {
+                // best places for a breakpoint, while an end_sequence row is
+                // just past the end of the code
+                if !row.is_stmt() || row.end_sequence() {
                     continue;
                 }
                 // TODO: could cache these checks based on the row.file_index()
@@ -424,26 +728,55 @@ impl<'data> DbgInfo<'data> {
                     }
                 }
                 // file matches, now check line number
-                if let Some(this_line) = row.line() {
-                    if this_line.get() as usize == line {
-                        return Ok(Some(row.address()));
-                    }
+                let this_line = match row.line() {
+                    Some(this_line) if this_line.get() as usize >= line => this_line.get() as usize,
+                    _ => continue,
+                };
+                if best_line.is_none_or(|best| this_line < best) {
+                    best_line = Some(this_line);
+                    pcs.clear();
+                }
+                if best_line == Some(this_line) {
+                    pcs.push(row.address());
                 }
             }
         }
-        Ok(None)
+        pcs.sort();
+        pcs.dedup();
+        // later rows for the line in the same function are other pieces of
+        // the same code
+        let mut functions = HashSet::new();
+        let mut first_pcs = vec![];
+        for pc in pcs {
+            match self.get_function_range_from_pc(pc)? {
+                Some(range) if !functions.insert(range.start) => {}
+                _ => first_pcs.push(pc),
+            }
+        }
+        Ok((best_line.unwrap_or(line), first_pcs))
     }
 
     /// Find a symbol in the symbol table by name, gathering any matches
     pub fn lookup_symbol(&self, name: &str) -> Vec<Symbol> {
-        let needle = name;
+        self.symbols_where(|sym_name| sym_name == name)
+    }
+
+    /// Find the symbols whose (demangled) names match a regex anywhere, so a
+    /// plain string finds the names containing it.
+    pub fn search_symbols(&self, re: &Regex) -> Vec<Symbol> {
+        self.symbols_where(|name| re.is_match(name))
+    }
+
+    fn symbols_where<F>(&self, pred: F) -> Vec<Symbol>
+    where
+        F: Fn(&str) -> bool,
+    {
         self.file
             .symbols()
             .filter_map(|sym| {
                 sym.name().ok().and_then(|name| {
-                    let name = addr2line::demangle(name, gimli::DW_LANG_Rust)
-                        .unwrap_or_else(|| name.to_string());
-                    if name != needle {
+                    let name = demangle_symbol(name);
+                    if !pred(&name) {
                         return None;
                     }
                     // found a matching symbol
@@ -451,12 +784,660 @@ impl<'data> DbgInfo<'data> {
                         type_,
                         name,
                         addr: sym.address(),
+                        size: sym.size(),
                     })
                 })
             })
             .collect()
     }
 
+    /// Get the (demangled) names of the function and data symbols defined in
+    /// the program.
+    pub fn symbol_names(&self) -> Vec<String> {
+        self.file
+            .symbols()
+            .filter(|sym| {
+                matches!(sym.kind(), SymbolKind::Text | SymbolKind::Data) && !sym.is_undefined()
+            })
+            .filter_map(|sym| Some(demangle_symbol(sym.name().ok()?)))
+            .collect()
+    }
+
+    /// Get the function symbols defined in the program, optionally only those
+    /// whose (demangled) name matches a pattern, sorted by address.
+    pub fn functions(&self, pattern: Option<&Regex>) -> Vec<Symbol> {
+        let mut syms: Vec<Symbol> = self
+            .file
+            .symbols()
+            .filter(|sym| sym.kind() == SymbolKind::Text && !sym.is_undefined())
+            .filter_map(|sym| {
+                let name = sym.name().ok()?;
+                let name = demangle_symbol(name);
+                if pattern.is_some_and(|re| !re.is_match(&name)) {
+                    return None;
+                }
+                Some(Symbol {
+                    type_: SymbolType::Func,
+                    name,
+                    addr: sym.address(),
+                    size: sym.size(),
+                })
+            })
+            .collect();
+        syms.sort_by(|a, b| (a.addr, &a.name).cmp(&(b.addr, &b.name)));
+        syms
+    }
+
+    /// Find the function or object symbol containing addr, and the offset of
+    /// addr into it.
+    pub fn symbol_for_addr(&self, addr: u64) -> Option<(Symbol, u64)> {
+        let sym = self.file.symbols().find(|sym| {
+            matches!(sym.kind(), SymbolKind::Text | SymbolKind::Data)
+                && sym.address() <= addr
+                && addr < sym.address() + sym.size()
+        })?;
+        let name = sym.name().ok()?;
+        let name = demangle_symbol(name);
+        let symbol = Symbol {
+            type_: sym.kind().try_into().ok()?,
+            name,
+            addr: sym.address(),
+            size: sym.size(),
+        };
+        Some((symbol, addr - sym.address()))
+    }
+
+    fn name_or(&self, unit: &Unit<R>, die: &Die<R>, default: &str) -> gimli::Result<String> {
+        Ok(match self.at_name(unit, die)? {
+            Some(name) => name.to_string()?.into_owned(),
+            None => default.to_string(),
+        })
+    }
+
+    fn die_contains_pc(&self, unit: &Unit<R>, die: &Die<R>, pc: u64) -> gimli::Result<bool> {
+        let mut ranges = self.dwarf().die_ranges(unit, die)?;
+        while let Some(range) = ranges.next()? {
+            if (range.begin..range.end).contains(&pc) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Find a named type (a struct, enum, union, typedef, or base type).
+    ///
+    /// The name can be qualified with its module path, but only the last
+    /// component is compared, so the first type with that name wins.
+    pub fn find_type(&self, name: &str) -> gimli::Result<Option<Type>> {
+        let needle = name.rsplit("::").next().unwrap_or(name);
+        let dwarf = self.dwarf();
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let mut entries = unit.entries();
+            while let Some((_, entry)) = entries.next_dfs()? {
+                if !matches!(
+                    entry.tag(),
+                    gimli::DW_TAG_structure_type
+                        | gimli::DW_TAG_union_type
+                        | gimli::DW_TAG_enumeration_type
+                        | gimli::DW_TAG_typedef
+                        | gimli::DW_TAG_base_type
+                ) {
+                    continue;
+                }
+                // a declaration has no layout
+                if entry.attr_value(gimli::DW_AT_declaration)?.is_some() {
+                    continue;
+                }
+                match self.at_name(&unit, entry)? {
+                    Some(n) if n.to_string()? == needle => {}
+                    _ => continue,
+                }
+                return Ok(Some(self.read_type(&unit, entry.offset())?));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Get the frame base of the function containing pc, from its
+    /// DW_AT_frame_base.
+    pub fn frame_base(&self, pc: u64) -> gimli::Result<Option<FrameBase>> {
+        let unit = match self.ctx.find_dwarf_unit(pc) {
+            Some(unit) => unit,
+            None => return Ok(None),
+        };
+        let mut entries = unit.entries();
+        while let Some((_, entry)) = entries.next_dfs()? {
+            if entry.tag() != gimli::DW_TAG_subprogram || !self.die_contains_pc(unit, entry, pc)? {
+                continue;
+            }
+            return match entry.attr_value(gimli::DW_AT_frame_base)? {
+                Some(AttributeValue::Exprloc(expr)) => frame_base(expr, unit.encoding()),
+                _ => Ok(None),
+            };
+        }
+        Ok(None)
+    }
+
+    /// Get the formal parameters of the function containing pc, in order.
+    pub fn formal_params_for_pc(&self, pc: u64) -> gimli::Result<Vec<Variable>> {
+        let unit = match self.ctx.find_dwarf_unit(pc) {
+            Some(unit) => unit,
+            None => return Ok(vec![]),
+        };
+        let mut entries = unit.entries();
+        while let Some((_, entry)) = entries.next_dfs()? {
+            if entry.tag() != gimli::DW_TAG_subprogram || !self.die_contains_pc(unit, entry, pc)? {
+                continue;
+            }
+            let mut params = vec![];
+            let mut tree = unit.entries_tree(Some(entry.offset()))?;
+            let root = tree.root()?;
+            let mut children = root.children();
+            while let Some(child) = children.next()? {
+                let entry = child.entry();
+                if entry.tag() != gimli::DW_TAG_formal_parameter {
+                    continue;
+                }
+                if let Some(var) = self.read_variable(unit, entry)? {
+                    params.push(var);
+                }
+            }
+            return Ok(params);
+        }
+        Ok(vec![])
+    }
+
+    /// Find the return type of the function containing pc, or None if it
+    /// doesn't return a value.
+    pub fn return_type_for_pc(&self, pc: u64) -> gimli::Result<Option<Type>> {
+        let unit = match self.ctx.find_dwarf_unit(pc) {
+            Some(unit) => unit,
+            None => return Ok(None),
+        };
+        let mut entries = unit.entries();
+        while let Some((_, entry)) = entries.next_dfs()? {
+            if entry.tag() != gimli::DW_TAG_subprogram || !self.die_contains_pc(unit, entry, pc)? {
+                continue;
+            }
+            // functions returning () have no type
+            return match at_type(entry)? {
+                Some(offset) => Ok(Some(self.read_type(unit, offset)?)),
+                None => Ok(None),
+            };
+        }
+        Ok(None)
+    }
+
+    /// Find a variable by name in the scope of pc.
+    ///
+    /// The innermost variable with that name wins.
+    pub fn find_variable(&self, pc: u64, name: &str) -> gimli::Result<Option<Variable>> {
+        let vars = self.variables_in_scope(pc)?;
+        Ok(vars.into_iter().rev().find(|var| var.name == name))
+    }
+
+    /// Get the variables (including parameters) in scope at pc.
+    ///
+    /// Searches the function containing pc and the lexical blocks within it
+    /// that contain pc. Variables in outer scopes come first.
+    pub fn variables_in_scope(&self, pc: u64) -> gimli::Result<Vec<Variable>> {
+        let unit = match self.ctx.find_dwarf_unit(pc) {
+            Some(unit) => unit,
+            None => return Ok(vec![]),
+        };
+        let mut tree = unit.entries_tree(None)?;
+        let mut vars = vec![];
+        self.collect_variables(unit, tree.root()?, pc, false, &mut vars)?;
+        Ok(vars)
+    }
+
+    fn collect_variables(
+        &self,
+        unit: &Unit<R>,
+        node: EntriesTreeNode<R>,
+        pc: u64,
+        in_function: bool,
+        vars: &mut Vec<Variable>,
+    ) -> gimli::Result<()> {
+        // scopes nested in this one are searched afterward, so their variables
+        // come after (and shadow) this scope's
+        let mut scopes = vec![];
+        let mut children = node.children();
+        while let Some(child) = children.next()? {
+            let entry = child.entry();
+            match entry.tag() {
+                gimli::DW_TAG_namespace => scopes.push((entry.offset(), in_function)),
+                gimli::DW_TAG_subprogram | gimli::DW_TAG_lexical_block
+                    if self.die_contains_pc(unit, entry, pc)? =>
+                {
+                    scopes.push((entry.offset(), true))
+                }
+                gimli::DW_TAG_variable | gimli::DW_TAG_formal_parameter if in_function => {
+                    if let Some(var) = self.read_variable(unit, entry)? {
+                        vars.push(var);
+                    }
+                }
+                _ => {}
+            }
+        }
+        for (offset, in_function) in scopes {
+            let mut tree = unit.entries_tree(Some(offset))?;
+            self.collect_variables(unit, tree.root()?, pc, in_function, vars)?;
+        }
+        Ok(())
+    }
+
+    /// Read a variable or parameter DIE, or None if it has no name.
+    fn read_variable(&self, unit: &Unit<R>, entry: &Die<R>) -> gimli::Result<Option<Variable>> {
+        let name = match self.at_name(unit, entry)? {
+            Some(name) => name.to_string()?.into_owned(),
+            None => return Ok(None),
+        };
+        let location = match entry.attr_value(gimli::DW_AT_location)? {
+            Some(AttributeValue::Exprloc(expr)) => var_location(expr, unit.encoding())?,
+            _ => None,
+        };
+        let ty = match at_type(entry)? {
+            Some(offset) => self.read_type(unit, offset)?,
+            None => Type::Unknown {
+                name: "()".to_string(),
+                size: 0,
+            },
+        };
+        Ok(Some(Variable { name, location, ty }))
+    }
+
+    /// Find a global (static) variable by name.
+    ///
+    /// The name can be qualified with its full module path (including the
+    /// crate), like `globals::COUNTER`. An unqualified name matches a global
+    /// in any module, and the first one found wins.
+    pub fn find_global(&self, name: &str) -> gimli::Result<Option<Variable>> {
+        let dwarf = self.dwarf();
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let mut tree = unit.entries_tree(None)?;
+            if let Some(var) = self.find_global_in(&unit, tree.root()?, &mut vec![], name)? {
+                return Ok(Some(var));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Search for a global in a unit's namespaces, skipping functions (whose
+    /// variables are locals). The path holds the names of the namespaces
+    /// node is in.
+    fn find_global_in(
+        &self,
+        unit: &Unit<R>,
+        node: EntriesTreeNode<R>,
+        path: &mut Vec<String>,
+        name: &str,
+    ) -> gimli::Result<Option<Variable>> {
+        let matches = |var_name: &str, path: &[String]| {
+            if !name.contains("::") {
+                return var_name == name;
+            }
+            let components = path.iter().map(|s| s.as_str()).chain([var_name]);
+            name.split("::").eq(components)
+        };
+        let mut namespaces = vec![];
+        let mut children = node.children();
+        while let Some(child) = children.next()? {
+            let entry = child.entry();
+            match entry.tag() {
+                gimli::DW_TAG_namespace => namespaces.push(entry.offset()),
+                gimli::DW_TAG_variable => match self.read_variable(unit, entry)? {
+                    // a declaration has no location
+                    Some(var)
+                        if matches(&var.name, path)
+                            && matches!(var.location, Some(VarLocation::Addr(_))) =>
+                    {
+                        return Ok(Some(var))
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+        for offset in namespaces {
+            let mut tree = unit.entries_tree(Some(offset))?;
+            let root = tree.root()?;
+            let ns = match self.at_name(unit, root.entry())? {
+                Some(ns) => ns.to_string()?.into_owned(),
+                None => String::new(),
+            };
+            path.push(ns);
+            let found = self.find_global_in(unit, root, path, name)?;
+            path.pop();
+            if found.is_some() {
+                return Ok(found);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Read a struct type, which is an enum if it has a variant part, or a
+    /// slice if it has a `data_ptr` and `length`.
+    fn read_struct(
+        &self,
+        unit: &Unit<R>,
+        node: EntriesTreeNode<R>,
+        name: String,
+        size: usize,
+        depth: usize,
+    ) -> gimli::Result<Type> {
+        let mut members = vec![];
+        // the type data_ptr points to, if it's a pointer
+        let mut data_ptr_target = None;
+        let mut children = node.children();
+        while let Some(child) = children.next()? {
+            let entry = child.entry();
+            match entry.tag() {
+                gimli::DW_TAG_member => {
+                    let member = self.read_member(unit, entry, depth)?;
+                    if member.name == "data_ptr" {
+                        if let Some(ptr) = at_type(entry)? {
+                            data_ptr_target = at_type(&unit.entry(ptr)?)?;
+                        }
+                    }
+                    members.push(member);
+                }
+                gimli::DW_TAG_variant_part => {
+                    let (discr, variants) = self.read_variant_part(unit, child, depth)?;
+                    return Ok(Type::Enum {
+                        name,
+                        size,
+                        discr: discr.map(Box::new),
+                        variants,
+                    });
+                }
+                _ => {}
+            }
+        }
+        let member_offset = |name: &str| members.iter().find(|m| m.name == name).map(|m| m.offset);
+        if let (Some(ptr_offset), Some(len_offset), Some(elem)) = (
+            member_offset("data_ptr"),
+            member_offset("length"),
+            data_ptr_target,
+        ) {
+            // following the pointer is how a type can contain itself, so
+            // limit how far it goes
+            let elem = if depth < MAX_TYPE_DEPTH {
+                self.read_type_at_depth(unit, elem, depth + 1)?
+            } else {
+                Type::Unknown {
+                    name: "...".to_string(),
+                    size: 0,
+                }
+            };
+            return Ok(Type::Slice {
+                name,
+                size,
+                elem: Box::new(elem),
+                ptr_offset,
+                len_offset,
+            });
+        }
+        Ok(Type::Struct {
+            name,
+            size,
+            members,
+        })
+    }
+
+    fn read_member(&self, unit: &Unit<R>, die: &Die<R>, depth: usize) -> gimli::Result<Member> {
+        let ty = match at_type(die)? {
+            Some(offset) => self.read_type_at_depth(unit, offset, depth)?,
+            None => Type::Unknown {
+                name: "??".to_string(),
+                size: 0,
+            },
+        };
+        Ok(Member {
+            name: self.name_or(unit, die, "")?,
+            offset: at_udata(die, gimli::DW_AT_data_member_location)?.unwrap_or(0) as usize,
+            ty,
+        })
+    }
+
+    /// Read the discriminant and variants of a Rust enum.
+    ///
+    /// rustc describes enums as a struct with a `DW_TAG_variant_part`, whose
+    /// `DW_AT_discr` refers to the member holding the discriminant. For
+    /// niche-optimized enums the "discriminant" is really part of one
+    /// variant's payload, and that variant has no `DW_AT_discr_value`.
+    fn read_variant_part(
+        &self,
+        unit: &Unit<R>,
+        node: EntriesTreeNode<R>,
+        depth: usize,
+    ) -> gimli::Result<(Option<Member>, Vec<Variant>)> {
+        let discr_offset = match node.entry().attr_value(gimli::DW_AT_discr)? {
+            Some(AttributeValue::UnitRef(offset)) => Some(offset),
+            _ => None,
+        };
+        let mut discr = None;
+        let mut variants = vec![];
+        let mut children = node.children();
+        while let Some(child) = children.next()? {
+            let entry = child.entry();
+            match entry.tag() {
+                gimli::DW_TAG_member if Some(entry.offset()) == discr_offset => {
+                    discr = Some(self.read_member(unit, entry, depth)?);
+                }
+                gimli::DW_TAG_variant => {
+                    let discr_value = at_udata(entry, gimli::DW_AT_discr_value)?;
+                    let mut members = child.children();
+                    while let Some(member) = members.next()? {
+                        if member.entry().tag() == gimli::DW_TAG_member {
+                            variants.push(Variant {
+                                discr_value,
+                                member: self.read_member(unit, member.entry(), depth)?,
+                            });
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok((discr, variants))
+    }
+
+    /// Get the name of the type at offset, as it would be written in Rust.
+    ///
+    /// rustc names most types itself (including pointers like `&[T]` and
+    /// generic types like `Option<T>`), but others, like arrays, have to be
+    /// constructed from the types they're made of.
+    pub fn type_name(&self, unit: &Unit<R>, offset: UnitOffset) -> gimli::Result<String> {
+        self.type_name_seen(unit, offset, &mut vec![])
+    }
+
+    /// Get a type's name, where seen has the types whose names are being
+    /// constructed, to stop at a type that contains itself.
+    fn type_name_seen(
+        &self,
+        unit: &Unit<R>,
+        offset: UnitOffset,
+        seen: &mut Vec<UnitOffset>,
+    ) -> gimli::Result<String> {
+        if seen.contains(&offset) {
+            return Ok("...".to_string());
+        }
+        let mut tree = unit.entries_tree(Some(offset))?;
+        let node = tree.root()?;
+        let entry = node.entry();
+        if entry.tag() != gimli::DW_TAG_array_type {
+            if let Some(name) = self.at_name(unit, entry)? {
+                return Ok(name.to_string()?.into_owned());
+            }
+        }
+        seen.push(offset);
+        let tag = entry.tag();
+        let inner_offset = at_type(entry)?;
+        // the name of the type this one is built from, or () if there isn't
+        // one (as for a void pointer)
+        let inner = match inner_offset {
+            Some(inner) => self.type_name_seen(unit, inner, seen)?,
+            None => "()".to_string(),
+        };
+        let name = match tag {
+            gimli::DW_TAG_pointer_type => format!("*const {inner}"),
+            gimli::DW_TAG_reference_type | gimli::DW_TAG_rvalue_reference_type => {
+                format!("&{inner}")
+            }
+            gimli::DW_TAG_array_type => {
+                let mut dims = vec![];
+                let mut children = node.children();
+                while let Some(child) = children.next()? {
+                    let entry = child.entry();
+                    if entry.tag() != gimli::DW_TAG_subrange_type {
+                        continue;
+                    }
+                    let count = match at_udata(entry, gimli::DW_AT_count)? {
+                        Some(count) => Some(count),
+                        None => at_udata(entry, gimli::DW_AT_upper_bound)?.map(|ub| ub + 1),
+                    };
+                    dims.push(count);
+                }
+                // the first dimension is the outermost array
+                dims.iter().rev().fold(inner, |name, count| match count {
+                    Some(count) => format!("[{name}; {count}]"),
+                    None => format!("[{name}]"),
+                })
+            }
+            gimli::DW_TAG_subroutine_type => {
+                let mut params = vec![];
+                let mut children = node.children();
+                while let Some(child) = children.next()? {
+                    let entry = child.entry();
+                    if entry.tag() != gimli::DW_TAG_formal_parameter {
+                        continue;
+                    }
+                    params.push(match at_type(entry)? {
+                        Some(ty) => self.type_name_seen(unit, ty, seen)?,
+                        None => "()".to_string(),
+                    });
+                }
+                let params = params.join(", ");
+                if inner_offset.is_some() {
+                    format!("fn({params}) -> {inner}")
+                } else {
+                    format!("fn({params})")
+                }
+            }
+            // Rust has no const or volatile types
+            gimli::DW_TAG_const_type | gimli::DW_TAG_volatile_type | gimli::DW_TAG_typedef => inner,
+            _ => "??".to_string(),
+        };
+        seen.pop();
+        Ok(name)
+    }
+
+    /// Read the description of the type at offset.
+    fn read_type(&self, unit: &Unit<R>, offset: UnitOffset) -> gimli::Result<Type> {
+        self.read_type_at_depth(unit, offset, 0)
+    }
+
+    /// Read a type, where depth is how many pointers have been followed to
+    /// get to it (see [`MAX_TYPE_DEPTH`]).
+    fn read_type_at_depth(
+        &self,
+        unit: &Unit<R>,
+        offset: UnitOffset,
+        depth: usize,
+    ) -> gimli::Result<Type> {
+        let mut tree = unit.entries_tree(Some(offset))?;
+        let node = tree.root()?;
+        let entry = node.entry();
+        let name = self.type_name(unit, offset)?;
+        let size = at_udata(entry, gimli::DW_AT_byte_size)?.unwrap_or(0) as usize;
+        let ty = match entry.tag() {
+            gimli::DW_TAG_base_type => {
+                let encoding = match entry.attr_value(gimli::DW_AT_encoding)? {
+                    Some(AttributeValue::Encoding(e)) => e,
+                    _ => gimli::DW_ATE_unsigned,
+                };
+                let encoding = match encoding {
+                    gimli::DW_ATE_signed | gimli::DW_ATE_signed_char => Encoding::Signed,
+                    gimli::DW_ATE_unsigned | gimli::DW_ATE_unsigned_char => Encoding::Unsigned,
+                    gimli::DW_ATE_boolean => Encoding::Bool,
+                    gimli::DW_ATE_UTF => Encoding::Char,
+                    gimli::DW_ATE_float => Encoding::Float,
+                    _ => return Ok(Type::Unknown { name, size }),
+                };
+                Type::Base {
+                    name,
+                    encoding,
+                    size,
+                }
+            }
+            gimli::DW_TAG_pointer_type | gimli::DW_TAG_reference_type => Type::Pointer {
+                name,
+                size: if size == 0 { 8 } else { size },
+            },
+            gimli::DW_TAG_structure_type | gimli::DW_TAG_union_type => {
+                self.read_struct(unit, node, name, size, depth)?
+            }
+            gimli::DW_TAG_array_type => {
+                let elem = match at_type(entry)? {
+                    Some(offset) => self.read_type_at_depth(unit, offset, depth)?,
+                    None => return Ok(Type::Unknown { name, size }),
+                };
+                let mut count = None;
+                let mut children = node.children();
+                while let Some(child) = children.next()? {
+                    let entry = child.entry();
+                    if entry.tag() != gimli::DW_TAG_subrange_type {
+                        continue;
+                    }
+                    count = match at_udata(entry, gimli::DW_AT_count)? {
+                        Some(count) => Some(count),
+                        None => at_udata(entry, gimli::DW_AT_upper_bound)?.map(|ub| ub + 1),
+                    };
+                    break;
+                }
+                let count = count.unwrap_or(0) as usize;
+                Type::Array {
+                    name,
+                    size: elem.size() * count,
+                    elem: Box::new(elem),
+                    count,
+                }
+            }
+            gimli::DW_TAG_enumeration_type => {
+                let mut enumerators = vec![];
+                let mut children = node.children();
+                while let Some(child) = children.next()? {
+                    let entry = child.entry();
+                    if entry.tag() != gimli::DW_TAG_enumerator {
+                        continue;
+                    }
+                    let val = at_udata(entry, gimli::DW_AT_const_value)?.unwrap_or(0);
+                    enumerators.push((self.name_or(unit, entry, "??")?, val as i64));
+                }
+                Type::Enumeration {
+                    name,
+                    size,
+                    signed: enumerators.iter().any(|(_, v)| *v < 0),
+                    enumerators,
+                }
+            }
+            gimli::DW_TAG_typedef | gimli::DW_TAG_const_type | gimli::DW_TAG_volatile_type => {
+                match at_type(entry)? {
+                    Some(offset) => self.read_type_at_depth(unit, offset, depth)?,
+                    None => Type::Unknown { name, size },
+                }
+            }
+            _ => Type::Unknown { name, size },
+        };
+        Ok(ty)
+    }
+
     /// Get the debug info on the return address from a particular pc.
     ///
     /// Returns only the information on how to get the return address, not the actual value.
@@ -466,6 +1447,20 @@ impl<'data> DbgInfo<'data> {
         pc: u64,
         eval: impl ret_addr::ReturnAddrEvaluator,
     ) -> gimli::Result<Option<u64>> {
+        Ok(self
+            .get_unwind_caller_frame(pc, &eval)?
+            .map(|frame| frame.ra))
+    }
+
+    /// Unwind one frame from pc using the eh_frame information.
+    ///
+    /// Returns None if pc has no unwind information, or its rules aren't
+    /// supported.
+    pub fn get_unwind_caller_frame(
+        &self,
+        pc: u64,
+        eval: &impl ret_addr::ReturnAddrEvaluator,
+    ) -> gimli::Result<Option<CallerFrame>> {
         let eh_frame = self.eh_frame();
         let bases = BaseAddresses::default().set_eh_frame(self.unwind.addr);
         let mut ctx = UnwindContext::new();
@@ -482,10 +1477,204 @@ impl<'data> DbgInfo<'data> {
         let cfa = info.cfa();
         // 16 is the return address dwarf register number (at least for x86-64)
         let ra = info.register(Register(16));
+        // and 6 is rbp
+        let fp = info.register(Register(6));
+        let saved = ret_addr::CALLEE_SAVED.map(|reg| {
+            info.register(Register(
+                reg.dwarf_number()
+                    .expect("callee-saved register has a dwarf number"),
+            ))
+        });
         let rule = ReturnAddrRule {
             cfa: cfa.clone(),
             ra,
+            fp,
+            saved,
         };
-        Ok(Some(rule.evaluate(eval)))
+
+        Ok(rule.evaluate_frame(eval))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use gimli::{CfaRule, EndianSlice, Expression, LittleEndian, RegisterRule};
+
+    use super::{
+        demangle_symbol, frame_base, var_location, FrameBase, ReturnAddrEvaluator, ReturnAddrRule,
+        VarLocation, CALLEE_SAVED,
+    };
+    use crate::ptrace::Reg;
+
+    struct FakeFrame {
+        regs: HashMap<Reg, u64>,
+        mem: HashMap<u64, u64>,
+    }
+
+    impl ReturnAddrEvaluator for FakeFrame {
+        fn get_reg(&self, reg: Reg) -> u64 {
+            self.regs[&reg]
+        }
+
+        fn read_mem(&self, addr: u64) -> u64 {
+            self.mem[&addr]
+        }
+    }
+
+    fn expr(bytes: &[u8]) -> Expression<EndianSlice<'_, LittleEndian>> {
+        Expression(EndianSlice::new(bytes, LittleEndian))
+    }
+
+    /// Rules for a frame that doesn't save any other callee-saved registers.
+    fn unsaved() -> [RegisterRule<EndianSlice<'static, LittleEndian>>; 5] {
+        CALLEE_SAVED.map(|_| RegisterRule::SameValue)
+    }
+
+    #[test]
+    fn test_unwind_expressions() {
+        let frame = FakeFrame {
+            regs: HashMap::from([(Reg::Rbp, 0x1000), (Reg::Rsp, 0x800)]),
+            mem: HashMap::from([(0xff0, 0x2000), (0x2000, 0x3000)]),
+        };
+        // like gcc's realigned frames: DW_OP_breg6 -16; DW_OP_deref
+        let cfa = [0x76, 0x70, 0x06];
+        // DW_OP_consts -8; DW_OP_plus
+        let ra = [0x11, 0x78, 0x22];
+        let rule = ReturnAddrRule {
+            cfa: CfaRule::Expression(expr(&cfa)),
+            ra: RegisterRule::ValExpression(expr(&ra)),
+            fp: RegisterRule::Expression(expr(&[])),
+            saved: unsaved(),
+        };
+        let caller = rule.evaluate_frame(&frame).unwrap();
+        assert_eq!(caller.cfa, 0x2000);
+        assert_eq!(caller.ra, 0x1ff8);
+        // an empty expression leaves the CFA, so the caller's rbp is at it
+        assert_eq!(caller.fp, 0x3000);
+
+        // DW_OP_bregx 7 8; DW_OP_deref
+        let rule = ReturnAddrRule {
+            cfa: CfaRule::Expression(expr(&[0x92, 7, 8])),
+            ra: RegisterRule::Expression(expr(&[0x11, 0x78, 0x22])),
+            fp: RegisterRule::SameValue,
+            saved: unsaved(),
+        };
+        let frame = FakeFrame {
+            regs: HashMap::from([(Reg::Rsp, 0x800), (Reg::Rbp, 0x1000)]),
+            mem: HashMap::from([(0x800, 0x42)]),
+        };
+        let caller = rule.evaluate_frame(&frame).unwrap();
+        assert_eq!(caller.cfa, 0x808);
+        assert_eq!(caller.ra, 0x42);
+        assert_eq!(caller.fp, 0x1000);
+        assert_eq!(caller.saved, [None; 5]);
+
+        // a frame that pushed rbx and r12, like `push rbx; push r12`
+        let mut saved = unsaved();
+        saved[0] = RegisterRule::Offset(-16);
+        saved[1] = RegisterRule::Offset(-24);
+        let rule = ReturnAddrRule {
+            cfa: CfaRule::RegisterAndOffset {
+                register: gimli::Register(7),
+                offset: 24,
+            },
+            ra: RegisterRule::Offset(-8),
+            fp: RegisterRule::SameValue,
+            saved,
+        };
+        let frame = FakeFrame {
+            regs: HashMap::from([(Reg::Rsp, 0x800), (Reg::Rbp, 0x1000)]),
+            mem: HashMap::from([(0x800, 0x12), (0x808, 0x3), (0x810, 0x42)]),
+        };
+        let caller = rule.evaluate_frame(&frame).unwrap();
+        assert_eq!(caller.cfa, 0x818);
+        assert_eq!(caller.ra, 0x42);
+        assert_eq!(caller.saved, [Some(0x3), Some(0x12), None, None, None]);
+
+        // unsupported rules and expressions end the unwind rather than
+        // panicking: DW_OP_dup, then a DW_OP_plus with only one value
+        for ra in [&[0x12][..], &[0x22]] {
+            let rule = ReturnAddrRule {
+                cfa: CfaRule::Expression(expr(&[0x92, 7, 8])),
+                ra: RegisterRule::ValExpression(expr(ra)),
+                fp: RegisterRule::SameValue,
+                saved: unsaved(),
+            };
+            assert_eq!(rule.evaluate_frame(&frame), None);
+        }
+        let rule = ReturnAddrRule {
+            cfa: CfaRule::Expression(expr(&[0x92, 7, 8])),
+            ra: RegisterRule::Architectural,
+            fp: RegisterRule::SameValue,
+            saved: unsaved(),
+        };
+        assert_eq!(rule.evaluate_frame(&frame), None);
+    }
+
+    const ENCODING: gimli::Encoding = gimli::Encoding {
+        format: gimli::Format::Dwarf32,
+        version: 4,
+        address_size: 8,
+    };
+
+    #[test]
+    fn test_frame_bases() {
+        let base = |bytes: &[u8]| frame_base(expr(bytes), ENCODING).unwrap();
+        // DW_OP_reg6 (rbp)
+        assert_eq!(base(&[0x56]), Some(FrameBase::Register(Reg::Rbp)));
+        // DW_OP_breg6 (rbp) 16
+        assert_eq!(
+            base(&[0x76, 0x10]),
+            Some(FrameBase::RegisterOffset(Reg::Rbp, 16))
+        );
+        // DW_OP_call_frame_cfa
+        assert_eq!(base(&[0x9c]), Some(FrameBase::Cfa));
+        // DW_OP_lit0
+        assert_eq!(base(&[0x30]), None);
+    }
+
+    #[test]
+    fn test_var_locations() {
+        let location = |bytes: &[u8]| var_location(expr(bytes), ENCODING).unwrap();
+        // DW_OP_fbreg -24
+        assert_eq!(location(&[0x91, 0x68]), Some(VarLocation::FrameOffset(-24)));
+        // DW_OP_breg7 (rsp) 16
+        assert_eq!(
+            location(&[0x77, 0x10]),
+            Some(VarLocation::RegisterOffset(Reg::Rsp, 16))
+        );
+        // DW_OP_addr 0x1000
+        assert_eq!(
+            location(&[0x03, 0, 0x10, 0, 0, 0, 0, 0, 0]),
+            Some(VarLocation::Addr(0x1000))
+        );
+        // DW_OP_reg0 (rax)
+        assert_eq!(location(&[0x50]), Some(VarLocation::Register(Reg::Rax)));
+        // DW_OP_reg0; DW_OP_piece 4 is only part of a register
+        assert_eq!(location(&[0x50, 0x93, 0x04]), None);
+        // DW_OP_fbreg -24; DW_OP_deref computes an address to read instead
+        assert_eq!(location(&[0x91, 0x68, 0x06]), None);
+    }
+
+    #[test]
+    fn test_demangle_symbol() {
+        // legacy Rust mangling, which is also valid Itanium C++ mangling
+        assert_eq!(
+            demangle_symbol("_ZN4test4main17h0123456789abcdefE"),
+            "test::main"
+        );
+        // v0 Rust mangling
+        assert_eq!(demangle_symbol("_RNvCs1234_4test4main"), "test::main");
+        // C++
+        assert_eq!(demangle_symbol("_ZN3foo3barEv"), "foo::bar()");
+        assert_eq!(
+            demangle_symbol("_ZNSt6vectorIiSaIiEE9push_backERKi"),
+            "std::vector<int, std::allocator<int> >::push_back(int const&)"
+        );
+        // C, or no mangling
+        assert_eq!(demangle_symbol("main"), "main");
+        assert_eq!(demangle_symbol("use_vars"), "use_vars");
     }
 }
@@ -0,0 +1,399 @@
+//! Expressions used as arguments to commands like `display`.
+//!
+//! The grammar is small: integer literals (decimal or 0x-prefixed hex),
+//! registers (`$rax`, plus the aliases `$pc`, `$sp`, and `$fp`), values from
+//! the `print` history (`$1`, or `$` for the last one), convenience variables
+//! (any other `$name`), parentheses, and addition and subtraction. Convenience
+//! variables are set with an assignment (see [`Assignment`]). `print` also accepts a leading C-style cast
+//! to a type (see [`Cast`]). Watchpoints name memory in the program with a
+//! [`Place`].
+
+use crate::ptrace::Reg;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Num(u64),
+    Reg(Reg),
+    /// `$N`, or `$` (None) for the last value
+    History(Option<usize>),
+    /// a convenience variable
+    Var(String),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+}
+
+/// Access to the target state an expression depends on.
+pub trait EvalContext {
+    fn get_reg(&self, reg: Reg) -> Result<u64, String>;
+    /// Get a value from the history, numbered from 1 (or the last one for
+    /// None).
+    fn history(&self, num: Option<usize>) -> Result<u64, String>;
+    fn var(&self, name: &str) -> Result<u64, String>;
+}
+
+impl Expr {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut p = Parser { s };
+        let e = p.expr()?;
+        p.skip_whitespace();
+        if !p.s.is_empty() {
+            return Err(format!("unexpected input '{}'", p.s));
+        }
+        Ok(e)
+    }
+
+    pub fn eval(&self, ctx: &impl EvalContext) -> Result<u64, String> {
+        match self {
+            Expr::Num(n) => Ok(*n),
+            Expr::Reg(r) => ctx.get_reg(*r),
+            Expr::History(num) => ctx.history(*num),
+            Expr::Var(name) => ctx.var(name),
+            Expr::Add(e1, e2) => Ok(e1.eval(ctx)?.wrapping_add(e2.eval(ctx)?)),
+            Expr::Sub(e1, e2) => Ok(e1.eval(ctx)?.wrapping_sub(e2.eval(ctx)?)),
+        }
+    }
+}
+
+/// An assignment to a convenience variable, `$name = expr`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assignment<'a> {
+    pub name: &'a str,
+    /// the unparsed right-hand side
+    pub value: &'a str,
+}
+
+impl<'a> Assignment<'a> {
+    /// Parse s as an assignment, if it is one.
+    ///
+    /// Registers, history values, and `$` itself can't be assigned to.
+    pub fn parse(s: &'a str) -> Option<Result<Self, String>> {
+        let (lhs, value) = s.split_once('=')?;
+        let name = lhs.trim().strip_prefix('$')?;
+        let is_name =
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !is_name {
+            return None;
+        }
+        if name.parse::<usize>().is_ok() || parse_reg(name).is_ok() {
+            return Some(Err(format!("cannot assign to ${name}")));
+        }
+        Some(Ok(Assignment { name, value }))
+    }
+}
+
+/// A cast at the start of a `print` expression: `(T)e` reinterprets the value
+/// of e as a T, while `(*T)e` reads a T from memory at address e.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cast {
+    pub type_name: String,
+    pub deref: bool,
+}
+
+impl Cast {
+    /// Split a leading cast off of s, returning it and the rest of s.
+    ///
+    /// Parentheses around an expression (like `(1 + 2)` or `($rax)`) aren't a
+    /// cast, since a type name can't start with a digit or `$`.
+    pub fn split(s: &str) -> Option<(Cast, &str)> {
+        let (inner, rest) = s.trim_start().strip_prefix('(')?.split_once(')')?;
+        let inner = inner.trim();
+        let (deref, type_name) = match inner.strip_prefix('*') {
+            Some(name) => (true, name.trim()),
+            None => (false, inner),
+        };
+        let is_type = type_name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && type_name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_:<>, ".contains(c));
+        if !is_type {
+            return None;
+        }
+        let cast = Cast {
+            type_name: type_name.to_string(),
+            deref,
+        };
+        Some((cast, rest))
+    }
+}
+
+/// A place in the program's memory, named by a variable followed by field
+/// accesses, derefs, and indexes, like `p.x`, `*pp`, `pp->x`, or `arr[i]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Place {
+    Var(String),
+    Deref(Box<Place>),
+    /// `p.name` or `p->name` (a field through a pointer is dereferenced
+    /// either way, as in Rust)
+    Field(Box<Place>, String),
+    /// `p[index]`, with the index unparsed, since it can be a variable or an
+    /// expression
+    Index(Box<Place>, String),
+}
+
+impl Place {
+    /// Parse s as a place, if it starts with a variable name (possibly after
+    /// some `*`s).
+    pub fn parse(s: &str) -> Option<Result<Self, String>> {
+        let mut derefs = 0;
+        let mut s = s.trim();
+        while let Some(rest) = s.strip_prefix('*') {
+            derefs += 1;
+            s = rest.trim_start();
+        }
+        if !s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            return None;
+        }
+        let end = s
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == ':'))
+            .unwrap_or(s.len());
+        let mut place = Place::Var(s[..end].to_string());
+        let mut p = Parser { s: &s[end..] };
+        loop {
+            p.skip_whitespace();
+            if p.s.is_empty() {
+                break;
+            }
+            let arrow = p.s.strip_prefix("->");
+            if arrow.is_some() || p.eat('.') {
+                if let Some(rest) = arrow {
+                    p.s = rest;
+                }
+                p.skip_whitespace();
+                let field = p.word();
+                if field.is_empty() {
+                    return Some(Err("expected a field name".to_string()));
+                }
+                place = Place::Field(Box::new(place), field.to_string());
+            } else if p.eat('[') {
+                let (index, rest) = match p.s.split_once(']') {
+                    Some(split) => split,
+                    None => return Some(Err("expected ']'".to_string())),
+                };
+                p.s = rest;
+                place = Place::Index(Box::new(place), index.trim().to_string());
+            } else {
+                return Some(Err(format!("unexpected input '{}'", p.s)));
+            }
+        }
+        for _ in 0..derefs {
+            place = Place::Deref(Box::new(place));
+        }
+        Some(Ok(place))
+    }
+
+    /// The variables this place's address depends on: the one it starts
+    /// from, and any used as an index.
+    pub fn vars(&self) -> Vec<&str> {
+        match self {
+            Place::Var(name) => vec![name],
+            Place::Deref(p) | Place::Field(p, _) => p.vars(),
+            Place::Index(p, index) => {
+                let mut vars = p.vars();
+                if index.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+                    vars.push(index);
+                }
+                vars
+            }
+        }
+    }
+}
+
+fn parse_reg(name: &str) -> Result<Reg, String> {
+    Reg::try_from(name).map_err(|_| format!("unknown register ${name}"))
+}
+
+struct Parser<'a> {
+    /// remaining input
+    s: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        self.s = self.s.trim_start();
+    }
+
+    /// Consume `c` if it's the next non-whitespace character.
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if let Some(s) = self.s.strip_prefix(c) {
+            self.s = s;
+            return true;
+        }
+        false
+    }
+
+    fn word(&mut self) -> &'a str {
+        let end = self
+            .s
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(self.s.len());
+        let (word, rest) = self.s.split_at(end);
+        self.s = rest;
+        word
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut e = self.term()?;
+        loop {
+            if self.eat('+') {
+                e = Expr::Add(Box::new(e), Box::new(self.term()?));
+            } else if self.eat('-') {
+                e = Expr::Sub(Box::new(e), Box::new(self.term()?));
+            } else {
+                return Ok(e);
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        if self.eat('(') {
+            let e = self.expr()?;
+            if !self.eat(')') {
+                return Err("expected ')'".to_string());
+            }
+            return Ok(e);
+        }
+        if self.eat('$') {
+            let word = self.word();
+            if word.is_empty() {
+                return Ok(Expr::History(None));
+            }
+            if let Ok(num) = word.parse::<usize>() {
+                return Ok(Expr::History(Some(num)));
+            }
+            // registers take precedence over convenience variables
+            return Ok(match parse_reg(word) {
+                Ok(reg) => Expr::Reg(reg),
+                Err(_) => Expr::Var(word.to_string()),
+            });
+        }
+        self.skip_whitespace();
+        let word = self.word();
+        let n = if let Some(hex) = word.strip_prefix("0x") {
+            u64::from_str_radix(hex, 16)
+        } else {
+            word.parse::<u64>()
+        };
+        match n {
+            Ok(n) => Ok(Expr::Num(n)),
+            Err(_) if word.is_empty() => Err("expected an expression".to_string()),
+            Err(_) => Err(format!("invalid number '{word}'")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Assignment, Cast, EvalContext, Expr, Place};
+    use crate::ptrace::Reg;
+
+    struct Regs;
+
+    impl EvalContext for Regs {
+        fn get_reg(&self, reg: Reg) -> Result<u64, String> {
+            match reg {
+                Reg::Rsp => Ok(0x1000),
+                Reg::Rip => Ok(0x40),
+                _ => Err("unavailable".to_string()),
+            }
+        }
+
+        fn history(&self, num: Option<usize>) -> Result<u64, String> {
+            match num {
+                None | Some(2) => Ok(20),
+                Some(1) => Ok(10),
+                Some(_) => Err("no such value".to_string()),
+            }
+        }
+
+        fn var(&self, name: &str) -> Result<u64, String> {
+            match name {
+                "base" => Ok(0x100),
+                _ => Err(format!("${name} is not set")),
+            }
+        }
+    }
+
+    fn eval(s: &str) -> Result<u64, String> {
+        Expr::parse(s)?.eval(&Regs)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Expr::parse("0x10"), Ok(Expr::Num(16)));
+        assert_eq!(Expr::parse(" $rsp "), Ok(Expr::Reg(Reg::Rsp)));
+        assert_eq!(Expr::parse("$pc"), Ok(Expr::Reg(Reg::Rip)));
+        assert_eq!(Expr::parse("$2"), Ok(Expr::History(Some(2))));
+        assert_eq!(Expr::parse("$ "), Ok(Expr::History(None)));
+        assert_eq!(Expr::parse("$foo"), Ok(Expr::Var("foo".to_string())));
+        assert!(Expr::parse("$").is_ok());
+        assert!(Expr::parse("1 +").is_err());
+        assert!(Expr::parse("(1").is_err());
+        assert!(Expr::parse("1 2").is_err());
+    }
+
+    #[test]
+    fn test_eval() {
+        assert_eq!(eval("$sp + 8"), Ok(0x1008));
+        assert_eq!(eval("$rsp - (4 + 4)"), Ok(0xff8));
+        assert_eq!(eval("$pc+1-2"), Ok(0x3f));
+        assert!(eval("$rax").is_err());
+        assert_eq!(eval("$1 + $"), Ok(30));
+        assert!(eval("$3").is_err());
+        assert_eq!(eval("$base + $sp"), Ok(0x1100));
+        assert!(eval("$other").is_err());
+    }
+
+    #[test]
+    fn test_assignment() {
+        let assign = Assignment::parse("$base = $rsp + 8").unwrap().unwrap();
+        assert_eq!(assign.name, "base");
+        assert_eq!(assign.value, " $rsp + 8");
+        assert!(Assignment::parse("$rsp = 1").unwrap().is_err());
+        assert!(Assignment::parse("$2 = 1").unwrap().is_err());
+        assert_eq!(Assignment::parse("$rsp + 8"), None);
+        assert_eq!(Assignment::parse("x = 1"), None);
+    }
+
+    #[test]
+    fn test_cast() {
+        let (cast, rest) = Cast::split("(u32)$rax").unwrap();
+        assert_eq!(cast.type_name, "u32");
+        assert!(!cast.deref);
+        assert_eq!(rest, "$rax");
+        let (cast, rest) = Cast::split(" ( *test::Point ) 0x1000").unwrap();
+        assert_eq!(cast.type_name, "test::Point");
+        assert!(cast.deref);
+        assert_eq!(rest, " 0x1000");
+        assert_eq!(Cast::split("(1 + 2) - 1"), None);
+        assert_eq!(Cast::split("($rsp)"), None);
+        assert_eq!(Cast::split("$rsp"), None);
+    }
+
+    #[test]
+    fn test_place() {
+        let var = |name: &str| Box::new(Place::Var(name.to_string()));
+        assert_eq!(Place::parse("a"), Some(Ok(Place::Var("a".to_string()))));
+        assert_eq!(
+            Place::parse("*pp . x"),
+            Some(Ok(Place::Deref(Box::new(Place::Field(
+                var("pp"),
+                "x".to_string()
+            )))))
+        );
+        assert_eq!(
+            Place::parse("pp->x"),
+            Some(Ok(Place::Field(var("pp"), "x".to_string())))
+        );
+        let index = Place::parse("test::ARR[ $i + 1 ]").unwrap().unwrap();
+        assert_eq!(index, Place::Index(var("test::ARR"), "$i + 1".to_string()));
+        assert_eq!(index.vars(), vec!["test::ARR"]);
+        let index = Place::parse("arr[i].x").unwrap().unwrap();
+        assert_eq!(index.vars(), vec!["arr", "i"]);
+        assert_eq!(Place::parse("$rsp"), None);
+        assert_eq!(Place::parse("0x1000"), None);
+        assert!(Place::parse("p.").unwrap().is_err());
+        assert!(Place::parse("arr[1").unwrap().is_err());
+        assert!(Place::parse("a + 1").unwrap().is_err());
+    }
+}
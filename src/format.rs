@@ -0,0 +1,198 @@
+//! Output formats for values, using gdb's format letters.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// hexadecimal integer (`x`)
+    Hex,
+    /// signed decimal integer (`d`)
+    Decimal,
+    /// the low byte as a character (`c`)
+    Char,
+    /// the instruction at an address (`i`)
+    Instruction,
+    /// the NUL-terminated string at an address (`s`)
+    String,
+}
+
+impl Format {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "x" => Ok(Format::Hex),
+            "d" => Ok(Format::Decimal),
+            "c" => Ok(Format::Char),
+            "i" => Ok(Format::Instruction),
+            "s" => Ok(Format::String),
+            _ => Err(format!("unknown format '{s}' (expected x, d, c, i, or s)")),
+        }
+    }
+
+    /// The gdb letter for this format.
+    pub fn letter(&self) -> char {
+        match self {
+            Format::Hex => 'x',
+            Format::Decimal => 'd',
+            Format::Char => 'c',
+            Format::Instruction => 'i',
+            Format::String => 's',
+        }
+    }
+}
+
+/// How to show a register's value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegFormat {
+    /// hexadecimal (`x`)
+    Hex,
+    /// unsigned decimal (`d`)
+    Decimal,
+    /// signed decimal, treating the value as an i64 (`s`)
+    Signed,
+    /// binary (`b`)
+    Binary,
+}
+
+impl RegFormat {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "x" => Ok(RegFormat::Hex),
+            "d" => Ok(RegFormat::Decimal),
+            "s" => Ok(RegFormat::Signed),
+            "b" => Ok(RegFormat::Binary),
+            _ => Err(format!(
+                "unknown register format '{s}' (expected x, d, s, or b)"
+            )),
+        }
+    }
+
+    pub fn format(&self, val: u64) -> String {
+        match self {
+            RegFormat::Hex => format!("0x{val:x}"),
+            RegFormat::Decimal => format!("{val}"),
+            RegFormat::Signed => format!("{}", val as i64),
+            RegFormat::Binary => format!("0b{val:b}"),
+        }
+    }
+}
+
+/// Show a byte as a character, like `97 'a'`.
+pub fn format_char(b: u8) -> String {
+    format!("{b} {:?}", b as char)
+}
+
+/// How `x` shows memory: a number of units of some size, each formatted the
+/// same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemFormat {
+    pub count: usize,
+    /// hex, decimal, or char
+    pub format: Format,
+    /// the size of a unit in bytes
+    pub size: usize,
+}
+
+impl MemFormat {
+    /// Parse a gdb format like `16xb`: an optional count, then a format
+    /// letter (x, d, or c) and a size letter (b, h, w, or g), in either order.
+    ///
+    /// The default is one hex word (4 bytes), and chars default to bytes.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let count = if digits == 0 {
+            1
+        } else {
+            s[..digits]
+                .parse()
+                .map_err(|_| format!("invalid count '{}'", &s[..digits]))?
+        };
+        let mut format = Format::Hex;
+        let mut size = None;
+        for c in s[digits..].chars() {
+            match c {
+                'b' => size = Some(1),
+                'h' => size = Some(2),
+                'w' => size = Some(4),
+                'g' => size = Some(8),
+                'x' => format = Format::Hex,
+                'd' => format = Format::Decimal,
+                'c' => format = Format::Char,
+                _ => {
+                    return Err(format!(
+                    "unknown format letter '{c}' (expected x, d, or c, and a size b, h, w, or g)"
+                ))
+                }
+            }
+        }
+        let size = size.unwrap_or(if format == Format::Char { 1 } else { 4 });
+        Ok(Self {
+            count,
+            format,
+            size,
+        })
+    }
+
+    /// How many units to show on each line.
+    pub fn per_line(&self) -> usize {
+        match self.size {
+            1 | 2 => 8,
+            4 => 4,
+            _ => 2,
+        }
+    }
+
+    /// Format one unit, given its (little-endian) bytes.
+    pub fn format_unit(&self, bytes: &[u8]) -> String {
+        let mut buf = [0; 8];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        let val = u64::from_le_bytes(buf);
+        match self.format {
+            Format::Decimal => {
+                // sign-extend from the unit's size
+                let shift = 64 - 8 * bytes.len();
+                format!("{}", ((val << shift) as i64) >> shift)
+            }
+            Format::Char => format_char(bytes[0]),
+            _ => format!("0x{val:0width$x}", width = 2 * bytes.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Format, MemFormat, RegFormat};
+
+    #[test]
+    fn test_mem_format() {
+        let fmt = MemFormat::parse("16xb").unwrap();
+        assert_eq!(
+            fmt,
+            MemFormat {
+                count: 16,
+                format: Format::Hex,
+                size: 1
+            }
+        );
+        assert_eq!(MemFormat::parse("").unwrap().size, 4);
+        assert_eq!(MemFormat::parse("c").unwrap().size, 1);
+        assert_eq!(MemFormat::parse("2gd").unwrap().count, 2);
+        assert!(MemFormat::parse("4q").is_err());
+
+        assert_eq!(fmt.format_unit(&[0xa]), "0x0a");
+        let words = MemFormat::parse("xw").unwrap();
+        assert_eq!(words.format_unit(&[1, 0, 0, 0]), "0x00000001");
+        let decimal = MemFormat::parse("dh").unwrap();
+        assert_eq!(decimal.format_unit(&[0xfe, 0xff]), "-2");
+        let chars = MemFormat::parse("c").unwrap();
+        assert_eq!(chars.format_unit(b"a"), "97 'a'");
+    }
+
+    #[test]
+    fn test_reg_format() {
+        let minus_two = (-2i64) as u64;
+        assert_eq!(RegFormat::Hex.format(minus_two), "0xfffffffffffffffe");
+        assert_eq!(RegFormat::Decimal.format(minus_two), "18446744073709551614");
+        assert_eq!(RegFormat::Signed.format(minus_two), "-2");
+        assert_eq!(RegFormat::Binary.format(5), "0b101");
+        assert_eq!(RegFormat::parse("s"), Ok(RegFormat::Signed));
+        assert!(RegFormat::parse("c").is_err());
+    }
+}
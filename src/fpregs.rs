@@ -0,0 +1,357 @@
+//! Decode the x87 floating-point registers.
+//!
+//! PTRACE_GETFPREGS returns the FXSAVE layout, which stores `st0`-`st7` in
+//! stack order but only has an abridged tag word, with one bit per physical
+//! register saying whether it is empty. The full tag of a non-empty register
+//! (valid, zero, or special) is recovered from its value, the same way FSAVE
+//! computes it.
+
+use libc::user_fpregs_struct;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum X87Tag {
+    Valid,
+    Zero,
+    /// NaN, infinity, or a denormal or unsupported encoding
+    Special,
+    Empty,
+}
+
+impl X87Tag {
+    pub fn name(&self) -> &'static str {
+        match self {
+            X87Tag::Valid => "valid",
+            X87Tag::Zero => "zero",
+            X87Tag::Special => "special",
+            X87Tag::Empty => "empty",
+        }
+    }
+}
+
+/// An 80-bit extended-precision float.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct F80 {
+    /// the significand, including the explicit integer bit (bit 63)
+    pub mantissa: u64,
+    /// the sign bit and 15-bit biased exponent
+    pub sign_exp: u16,
+}
+
+impl F80 {
+    const EXP_BIAS: i32 = 16383;
+    const EXP_MAX: u16 = 0x7fff;
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            mantissa: u64::from_le_bytes(bytes[..8].try_into().unwrap()),
+            sign_exp: u16::from_le_bytes(bytes[8..10].try_into().unwrap()),
+        }
+    }
+
+    fn exponent(self) -> u16 {
+        self.sign_exp & Self::EXP_MAX
+    }
+
+    fn negative(self) -> bool {
+        self.sign_exp & 0x8000 != 0
+    }
+
+    /// Classify a non-empty register by its value.
+    fn tag(self) -> X87Tag {
+        let integer_bit = self.mantissa >> 63 != 0;
+        match self.exponent() {
+            Self::EXP_MAX => X87Tag::Special,
+            0 if self.mantissa == 0 => X87Tag::Zero,
+            0 => X87Tag::Special,
+            // "unnormals" (without the integer bit) aren't supported by the FPU
+            _ if !integer_bit => X87Tag::Special,
+            _ => X87Tag::Valid,
+        }
+    }
+
+    /// Convert to the nearest f64 (which may lose precision or overflow).
+    pub fn to_f64(self) -> f64 {
+        let sign = if self.negative() { -1.0 } else { 1.0 };
+        let exp = self.exponent();
+        if exp == Self::EXP_MAX {
+            // the fraction bits (below the integer bit) distinguish NaN
+            if self.mantissa << 1 == 0 {
+                return sign * f64::INFINITY;
+            }
+            return f64::NAN;
+        }
+        // denormals use the same scale as the smallest normal exponent
+        let exp = (exp.max(1) as i32) - Self::EXP_BIAS - 63;
+        // apply the scale in two steps so an intermediate doesn't overflow
+        // before the mantissa brings it back into range
+        let half = exp / 2;
+        sign * (self.mantissa as f64) * 2f64.powi(half) * 2f64.powi(exp - half)
+    }
+}
+
+/// One of the x87 stack registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StReg {
+    /// the index i of st(i), relative to the top of the stack
+    pub index: usize,
+    pub tag: X87Tag,
+    pub value: F80,
+}
+
+impl StReg {
+    pub fn name(&self) -> String {
+        format!("st{}", self.index)
+    }
+}
+
+/// A floating-point register that can be shown on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FpReg {
+    /// `st0`-`st7`
+    St(usize),
+    /// the x87 control word
+    Fctrl,
+    /// the x87 status word
+    Fstat,
+    Mxcsr,
+}
+
+impl FpReg {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "fctrl" => Some(Self::Fctrl),
+            "fstat" => Some(Self::Fstat),
+            "mxcsr" => Some(Self::Mxcsr),
+            _ => {
+                let index: usize = name.strip_prefix("st")?.parse().ok()?;
+                if index < 8 {
+                    Some(Self::St(index))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Self::St(index) => format!("st{index}"),
+            Self::Fctrl => "fctrl".to_string(),
+            Self::Fstat => "fstat".to_string(),
+            Self::Mxcsr => "mxcsr".to_string(),
+        }
+    }
+}
+
+/// Get `st0` through `st7`, with their tags.
+pub fn st_registers(regs: &user_fpregs_struct) -> Vec<StReg> {
+    // the stack top is bits 11-13 of the status word
+    let top = ((regs.swd >> 11) & 7) as usize;
+    let bytes: Vec<u8> = regs.st_space.iter().flat_map(|w| w.to_le_bytes()).collect();
+    (0..8)
+        .map(|index| {
+            // each register takes 16 bytes, of which the first 10 are used
+            let value = F80::from_bytes(&bytes[index * 16..index * 16 + 10]);
+            // the abridged tag is indexed by physical register, not by stack
+            // position
+            let physical = (top + index) % 8;
+            let tag = if regs.ftw & (1 << physical) == 0 {
+                X87Tag::Empty
+            } else {
+                value.tag()
+            };
+            StReg { index, tag, value }
+        })
+        .collect()
+}
+
+/// The floating-point exceptions, in the order of their bits in both the x87
+/// status word and MXCSR.
+const EXCEPTIONS: [&str; 6] = [
+    "invalid",
+    "denormal",
+    "divide-by-zero",
+    "overflow",
+    "underflow",
+    "precision",
+];
+
+/// Names of the exceptions whose bits are set in the low 6 bits of flags.
+fn exception_names(flags: u32) -> Vec<&'static str> {
+    EXCEPTIONS
+        .iter()
+        .enumerate()
+        .filter(|&(bit, _)| flags & (1 << bit) != 0)
+        .map(|(_, &name)| name)
+        .collect()
+}
+
+fn bracketed(items: Vec<String>) -> String {
+    if items.is_empty() {
+        return "[ ]".to_string();
+    }
+    format!("[ {} ]", items.join(" "))
+}
+
+/// Name the rounding mode in the 2-bit rounding control field (the same in
+/// the x87 control word and MXCSR).
+fn rounding_name(bits: u32) -> &'static str {
+    match bits & 3 {
+        0 => "nearest",
+        1 => "down",
+        2 => "up",
+        _ => "toward-zero",
+    }
+}
+
+/// Describe which of the exceptions in the low 6 bits of mask are masked.
+fn masked_item(mask: u32) -> Option<String> {
+    let masked = exception_names(mask);
+    match masked.len() {
+        0 => None,
+        n if n == EXCEPTIONS.len() => Some("masked=all".to_string()),
+        _ => Some(format!("masked={}", masked.join(","))),
+    }
+}
+
+/// Decode the x87 control word into its masked exceptions, precision, and
+/// rounding mode.
+pub fn decode_fpu_control(cwd: u16) -> String {
+    let cwd = cwd as u32;
+    let mut items: Vec<String> = masked_item(cwd).into_iter().collect();
+    let precision = match (cwd >> 8) & 3 {
+        0 => "single",
+        2 => "double",
+        3 => "extended",
+        _ => "reserved",
+    };
+    items.push(format!("precision={precision}"));
+    items.push(format!("rounding={}", rounding_name(cwd >> 10)));
+    bracketed(items)
+}
+
+/// Decode the x87 status word into its raised exceptions and stack top.
+pub fn decode_fpu_status(swd: u16) -> String {
+    let mut items: Vec<String> = exception_names(swd as u32)
+        .into_iter()
+        .map(String::from)
+        .collect();
+    if swd & (1 << 6) != 0 {
+        items.push("stack-fault".to_string());
+    }
+    items.push(format!("top={}", (swd >> 11) & 7));
+    bracketed(items)
+}
+
+/// Decode the SSE control/status register.
+pub fn decode_mxcsr(mxcsr: u32) -> String {
+    let mut items: Vec<String> = exception_names(mxcsr)
+        .into_iter()
+        .map(String::from)
+        .collect();
+    items.extend(masked_item(mxcsr >> 7));
+    items.push(format!("rounding={}", rounding_name(mxcsr >> 13)));
+    if mxcsr & (1 << 15) != 0 {
+        items.push("flush-to-zero".to_string());
+    }
+    if mxcsr & (1 << 6) != 0 {
+        items.push("denormals-are-zero".to_string());
+    }
+    bracketed(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decode_fpu_control, decode_fpu_status, decode_mxcsr, st_registers, FpReg, X87Tag, F80,
+    };
+
+    fn f80(sign_exp: u16, mantissa: u64) -> F80 {
+        F80 { mantissa, sign_exp }
+    }
+
+    #[test]
+    fn test_f80_to_f64() {
+        assert_eq!(f80(0x3fff, 1 << 63).to_f64(), 1.0);
+        assert_eq!(f80(0xc000, 0xc000_0000_0000_0000).to_f64(), -3.0);
+        assert_eq!(f80(0, 0).to_f64(), 0.0);
+        let pi = f80(0x4000, 0xc90f_daa2_2168_c235).to_f64();
+        assert_eq!(pi, std::f64::consts::PI);
+        assert_eq!(f80(0x7fff, 1 << 63).to_f64(), f64::INFINITY);
+        assert!(f80(0x7fff, 0xc000_0000_0000_0000).to_f64().is_nan());
+    }
+
+    #[test]
+    fn test_tags() {
+        assert_eq!(f80(0x3fff, 1 << 63).tag(), X87Tag::Valid);
+        assert_eq!(f80(0x8000, 0).tag(), X87Tag::Zero);
+        assert_eq!(f80(0x7fff, 1 << 63).tag(), X87Tag::Special);
+        // denormal
+        assert_eq!(f80(0, 1).tag(), X87Tag::Special);
+        // unnormal
+        assert_eq!(f80(0x3fff, 1).tag(), X87Tag::Special);
+    }
+
+    #[test]
+    fn test_st_registers() {
+        let mut regs: libc::user_fpregs_struct = unsafe { std::mem::zeroed() };
+        // two values pushed: top is 6, and physical registers 6 and 7 are in use
+        regs.swd = 6 << 11;
+        regs.ftw = 0b1100_0000;
+        // st0 = 1.0
+        regs.st_space[1] = 0x8000_0000;
+        regs.st_space[2] = 0x3fff;
+        // st1 = 0.0 (all zero)
+        let st = st_registers(&regs);
+        assert_eq!(st[0].tag, X87Tag::Valid);
+        assert_eq!(st[0].value.to_f64(), 1.0);
+        assert_eq!(st[1].tag, X87Tag::Zero);
+        assert!(st[2..].iter().all(|r| r.tag == X87Tag::Empty));
+        assert_eq!(st[7].name(), "st7");
+    }
+
+    #[test]
+    fn test_fp_reg_names() {
+        assert_eq!(FpReg::parse("st0"), Some(FpReg::St(0)));
+        assert_eq!(FpReg::parse("st7").unwrap().name(), "st7");
+        assert_eq!(FpReg::parse("st8"), None);
+        assert_eq!(FpReg::parse("fctrl"), Some(FpReg::Fctrl));
+        assert_eq!(FpReg::parse("mxcsr").unwrap().name(), "mxcsr");
+        assert_eq!(FpReg::parse("rax"), None);
+    }
+
+    #[test]
+    fn test_decode_fpu_control() {
+        // the default value
+        assert_eq!(
+            decode_fpu_control(0x037f),
+            "[ masked=all precision=extended rounding=nearest ]"
+        );
+        assert_eq!(
+            decode_fpu_control(0x0b7a),
+            "[ masked=denormal,overflow,underflow,precision precision=extended rounding=up ]"
+        );
+    }
+
+    #[test]
+    fn test_decode_fpu_status() {
+        assert_eq!(decode_fpu_status(0), "[ top=0 ]");
+        // invalid and stack fault from popping an empty stack, with top 7
+        assert_eq!(decode_fpu_status(0x3841), "[ invalid stack-fault top=7 ]");
+    }
+
+    #[test]
+    fn test_decode_mxcsr() {
+        // the default value
+        assert_eq!(decode_mxcsr(0x1f80), "[ masked=all rounding=nearest ]");
+        assert_eq!(
+            decode_mxcsr(0x1f80 | 0x24),
+            "[ divide-by-zero precision masked=all rounding=nearest ]"
+        );
+        assert_eq!(
+            decode_mxcsr(0x0100 | (3 << 13) | (1 << 15) | (1 << 6)),
+            "[ masked=denormal rounding=toward-zero flush-to-zero denormals-are-zero ]"
+        );
+        assert_eq!(decode_mxcsr(0), "[ rounding=nearest ]");
+    }
+}
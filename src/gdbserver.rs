@@ -0,0 +1,598 @@
+//! Serve a traced program over the gdb Remote Serial Protocol, so gdb (or an
+//! IDE that speaks the protocol) can debug it with `target remote`.
+//!
+//! This handles the packets gdb needs to connect, read and write registers
+//! and memory, set software breakpoints, and continue or step. The target is
+//! driven directly through [`Target`]; breakpoints are the server's own,
+//! since gdb decides when to insert and remove them.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use libc::c_uint;
+
+use crate::{
+    debugger::{SI_KERNEL, TRAP_BRKPT},
+    logging::TargetOutput,
+    ptrace::{Reg, Target, WaitStatus},
+};
+
+const INT3: u8 = 0xcc;
+
+/// The largest packet the server accepts or sends, which it advertises in
+/// its `qSupported` reply.
+const PACKET_SIZE: usize = 0x4000;
+
+/// How a resume request asks the target to run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resume {
+    Continue,
+    Step,
+}
+
+fn protocol_error(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &b| sum.wrapping_add(b))
+}
+
+/// Frame a packet as `$data#checksum`, escaping the bytes that are special
+/// in the protocol.
+fn encode_packet(data: &[u8]) -> Vec<u8> {
+    let mut body = vec![];
+    for &b in data {
+        if matches!(b, b'$' | b'#' | b'}' | b'*') {
+            body.push(b'}');
+            body.push(b ^ 0x20);
+        } else {
+            body.push(b);
+        }
+    }
+    let mut packet = vec![b'$'];
+    packet.extend(&body);
+    packet.extend(format!("#{:02x}", checksum(&body)).bytes());
+    packet
+}
+
+/// Undo the escaping and run-length encoding in the body of a packet.
+fn decode_body(body: &[u8]) -> Result<Vec<u8>, String> {
+    let mut data: Vec<u8> = vec![];
+    let mut bytes = body.iter();
+    while let Some(&b) = bytes.next() {
+        match b {
+            b'}' => {
+                let next = bytes.next().ok_or("packet ends in an escape")?;
+                data.push(next ^ 0x20);
+            }
+            // `X*n` repeats X another n - 29 times
+            b'*' => {
+                let count = bytes.next().ok_or("packet ends in a repeat")?;
+                let prev = *data.last().ok_or("repeat at the start of a packet")?;
+                let count = count
+                    .checked_sub(29)
+                    .ok_or_else(|| format!("invalid repeat count {count}"))?;
+                data.extend(std::iter::repeat_n(prev, count as usize));
+            }
+            _ => data.push(b),
+        }
+    }
+    Ok(data)
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Decode hex-encoded bytes. gdbserver sends `xx` for bytes it can't read
+/// (like an unavailable register), which are decoded as 0.
+fn decode_hex(s: &str) -> Result<Vec<u8>, String> {
+    if !s.len().is_multiple_of(2) {
+        return Err(format!("odd-length hex string '{s}'"));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| match &s[i..i + 2] {
+            "xx" => Ok(0),
+            byte => u8::from_str_radix(byte, 16).map_err(|_| format!("invalid hex '{byte}'")),
+        })
+        .collect()
+}
+
+/// The general-purpose registers in the order gdb numbers them on x86-64,
+/// which is also their order in the `g` packet.
+const GDB_REGS: [Reg; 24] = [
+    Reg::Rax,
+    Reg::Rbx,
+    Reg::Rcx,
+    Reg::Rdx,
+    Reg::Rsi,
+    Reg::Rdi,
+    Reg::Rbp,
+    Reg::Rsp,
+    Reg::R8,
+    Reg::R9,
+    Reg::R10,
+    Reg::R11,
+    Reg::R12,
+    Reg::R13,
+    Reg::R14,
+    Reg::R15,
+    Reg::Rip,
+    Reg::Rflags,
+    Reg::Cs,
+    Reg::Ss,
+    Reg::Ds,
+    Reg::Es,
+    Reg::Fs,
+    Reg::Gs,
+];
+
+/// The size in bytes of register number n: rflags and the segment registers
+/// are 32 bits in gdb's description.
+fn reg_size(n: usize) -> usize {
+    if n >= 17 {
+        4
+    } else {
+        8
+    }
+}
+
+/// One end of a connection, which sends and receives packets.
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        })
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut b = [0];
+        self.reader.read_exact(&mut b)?;
+        Ok(b[0])
+    }
+
+    /// Send a packet, resending it until the other end acknowledges it.
+    fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        let packet = encode_packet(data);
+        loop {
+            self.writer.write_all(&packet)?;
+            match self.read_byte()? {
+                b'+' => return Ok(()),
+                b'-' => continue,
+                b => return Err(protocol_error(format!("unexpected ack {:?}", b as char))),
+            }
+        }
+    }
+
+    /// Receive a packet, asking for it again if it was corrupted.
+    fn receive(&mut self) -> io::Result<String> {
+        loop {
+            // skip anything before the packet (such as a notification)
+            while self.read_byte()? != b'$' {}
+            let mut body = vec![];
+            self.reader.read_until(b'#', &mut body)?;
+            if body.pop() != Some(b'#') {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let mut sum = [0; 2];
+            self.reader.read_exact(&mut sum)?;
+            let sum = std::str::from_utf8(&sum)
+                .ok()
+                .and_then(|sum| u8::from_str_radix(sum, 16).ok());
+            if sum != Some(checksum(&body)) {
+                self.writer.write_all(b"-")?;
+                continue;
+            }
+            self.writer.write_all(b"+")?;
+            let data = decode_body(&body).map_err(protocol_error)?;
+            return Ok(String::from_utf8_lossy(&data).into_owned());
+        }
+    }
+}
+
+struct Server {
+    conn: Connection,
+    target: Target,
+    /// the original byte under each inserted breakpoint
+    breakpoints: HashMap<u64, u8>,
+    output: TargetOutput,
+    /// the reply describing the last stop, which gdb asks for again with `?`
+    last_stop: String,
+    /// the reply to every request once the target is gone (or detached)
+    exit_reply: Option<String>,
+}
+
+/// Parse a hex number from a packet.
+fn parse_hex(s: &str) -> Result<u64, String> {
+    u64::from_str_radix(s, 16).map_err(|_| format!("invalid hex number '{s}'"))
+}
+
+/// Parse the `addr,len` argument of a memory or breakpoint packet.
+fn parse_addr_len(s: &str) -> Result<(u64, u64), String> {
+    let (addr, len) = s
+        .split_once(',')
+        .ok_or_else(|| format!("expected ADDR,LENGTH, got '{s}'"))?;
+    Ok((parse_hex(addr)?, parse_hex(len)?))
+}
+
+impl Server {
+    fn regs_hex(&self) -> io::Result<String> {
+        let regs = self.target.getregs()?;
+        Ok(GDB_REGS
+            .iter()
+            .enumerate()
+            .map(|(n, r)| encode_hex(&r.get_reg(&regs).to_le_bytes()[..reg_size(n)]))
+            .collect())
+    }
+
+    fn set_regs_hex(&self, hex: &str) -> Result<(), String> {
+        let bytes = decode_hex(hex)?;
+        let mut regs = self.target.getregs().map_err(|err| err.to_string())?;
+        let mut offset = 0;
+        for (n, r) in GDB_REGS.iter().enumerate() {
+            let size = reg_size(n);
+            // gdb may send fewer registers than we have
+            let Some(val) = bytes.get(offset..offset + size) else {
+                break;
+            };
+            let mut buf = [0; 8];
+            buf[..size].copy_from_slice(val);
+            r.set_reg(&mut regs, u64::from_le_bytes(buf));
+            offset += size;
+        }
+        self.target.setregs(&regs).map_err(|err| err.to_string())
+    }
+
+    /// Read memory as gdb sees it, without the breakpoints' int3s.
+    fn read_mem(&self, addr: u64, len: usize) -> io::Result<Vec<u8>> {
+        let mut bytes = self.target.read_mem(addr, len)?;
+        for (&bp, &orig) in &self.breakpoints {
+            if let Some(offset) = bp.checked_sub(addr).filter(|&o| o < len as u64) {
+                bytes[offset as usize] = orig;
+            }
+        }
+        Ok(bytes)
+    }
+
+    fn insert_breakpoint(&mut self, addr: u64) -> io::Result<()> {
+        if self.breakpoints.contains_key(&addr) {
+            return Ok(());
+        }
+        let orig = self.target.read_mem(addr, 1)?[0];
+        self.target.write_mem(addr, &[INT3])?;
+        self.breakpoints.insert(addr, orig);
+        Ok(())
+    }
+
+    fn remove_breakpoint(&mut self, addr: u64) -> io::Result<()> {
+        if let Some(orig) = self.breakpoints.remove(&addr) {
+            self.target.write_mem(addr, &[orig])?;
+        }
+        Ok(())
+    }
+
+    /// Wait for the target and describe why it stopped.
+    fn wait(&mut self) -> io::Result<String> {
+        let status = self.target.wait()?;
+        self.output.sync();
+        self.stop_reply(status)
+    }
+
+    fn stop_reply(&mut self, status: WaitStatus) -> io::Result<String> {
+        let reply = match status {
+            WaitStatus::Exited { status } => format!("W{status:02x}"),
+            WaitStatus::Signaled { signal } => format!("X{signal:02x}"),
+            WaitStatus::Stopped { signal } => {
+                let pc = self.target.getreg(Reg::Rip)?;
+                let code = self.target.getsiginfo()?.si_code;
+                let int3 = signal == libc::SIGTRAP && (code == SI_KERNEL || code == TRAP_BRKPT);
+                let reply = if int3 && self.breakpoints.contains_key(&(pc - 1)) {
+                    // report the breakpoint's address, not the one after the
+                    // int3
+                    self.target.setreg(Reg::Rip, pc - 1)?;
+                    "T05swbreak:;".to_string()
+                } else {
+                    // gdb's numbers match Linux's for the common signals
+                    format!("T{signal:02x}")
+                };
+                self.last_stop = reply.clone();
+                return Ok(reply);
+            }
+        };
+        self.exit_reply = Some(reply.clone());
+        Ok(reply)
+    }
+
+    /// Resume the target, first stepping past a breakpoint at the pc.
+    fn resume(&mut self, how: Resume, signal: c_uint) -> io::Result<String> {
+        let pc = self.target.getreg(Reg::Rip)?;
+        if let Some(&orig) = self.breakpoints.get(&pc) {
+            self.target.write_mem(pc, &[orig])?;
+            self.target.singlestep(signal)?;
+            let status = self.target.wait()?;
+            if let WaitStatus::Stopped { .. } = status {
+                self.target.write_mem(pc, &[INT3])?;
+            }
+            let stepped = status
+                == WaitStatus::Stopped {
+                    signal: libc::SIGTRAP,
+                };
+            if how == Resume::Step || !stepped {
+                // report the step, or whatever interrupted it
+                self.output.sync();
+                return self.stop_reply(status);
+            }
+            self.target.cont(0)?;
+            return self.wait();
+        }
+        match how {
+            Resume::Continue => self.target.cont(signal)?,
+            Resume::Step => self.target.singlestep(signal)?,
+        }
+        self.wait()
+    }
+
+    /// Handle a `vCont` request, which can have an action per thread; with a
+    /// single-threaded target only the first action matters.
+    fn vcont(&mut self, actions: &str) -> io::Result<Option<String>> {
+        let action = actions.split(';').nth(1).unwrap_or("");
+        let action = action.split(':').next().unwrap_or("");
+        let (how, signal) = match action.split_at(action.len().min(1)) {
+            ("c", _) => (Resume::Continue, 0),
+            ("s", _) => (Resume::Step, 0),
+            ("C", sig) => (Resume::Continue, parse_hex(sig).unwrap_or(0)),
+            ("S", sig) => (Resume::Step, parse_hex(sig).unwrap_or(0)),
+            _ => return Ok(Some("E01".to_string())),
+        };
+        self.resume(how, signal as c_uint).map(Some)
+    }
+
+    /// Read part of an object gdb asks for with `qXfer`: only the auxiliary
+    /// vector is supported, which gdb uses to find where a PIE was loaded.
+    fn read_auxv(&self, offset: usize, len: usize) -> io::Result<Vec<u8>> {
+        let auxv = fs::read(format!("/proc/{}/auxv", self.target.pid()))?;
+        let data = auxv.get(offset..).unwrap_or(&[]);
+        let mut reply = if data.len() > len {
+            b"m".to_vec()
+        } else {
+            b"l".to_vec()
+        };
+        reply.extend(&data[..data.len().min(len)]);
+        Ok(reply)
+    }
+
+    /// Handle a packet, returning the reply (or None to stop serving).
+    fn handle(&mut self, packet: &str) -> io::Result<Option<Vec<u8>>> {
+        let ok = || Ok(Some(b"OK".to_vec()));
+        let error = |err: String| {
+            eprintln!("{packet}: {err}");
+            Ok(Some(b"E01".to_vec()))
+        };
+        if let Some(reply) = &self.exit_reply {
+            // the target is gone, so there's nothing more to do
+            return match packet {
+                "k" => Ok(None),
+                _ => Ok(Some(reply.as_bytes().to_vec())),
+            };
+        }
+        let (kind, args) = packet.split_at(packet.len().min(1));
+        let reply = match kind {
+            "?" => self.last_stop.clone(),
+            "g" => self.regs_hex()?,
+            "G" => match self.set_regs_hex(args) {
+                Ok(()) => return ok(),
+                Err(err) => return error(err),
+            },
+            "p" => {
+                let n = match parse_hex(args) {
+                    Ok(n) => n as usize,
+                    Err(err) => return error(err),
+                };
+                match GDB_REGS.get(n) {
+                    Some(&r) => {
+                        let val = self.target.getreg(r)?;
+                        encode_hex(&val.to_le_bytes()[..reg_size(n)])
+                    }
+                    // not available
+                    None => "x".repeat(16),
+                }
+            }
+            "P" => {
+                let result = args
+                    .split_once('=')
+                    .ok_or_else(|| "expected N=VALUE".to_string())
+                    .and_then(|(n, val)| {
+                        let n = parse_hex(n)? as usize;
+                        let r = *GDB_REGS.get(n).ok_or("no such register")?;
+                        let mut buf = [0; 8];
+                        let val = decode_hex(val)?;
+                        buf[..val.len().min(8)].copy_from_slice(&val[..val.len().min(8)]);
+                        self.target
+                            .setreg(r, u64::from_le_bytes(buf))
+                            .map_err(|err| err.to_string())
+                    });
+                match result {
+                    Ok(()) => return ok(),
+                    Err(err) => return error(err),
+                }
+            }
+            "m" => {
+                let (addr, len) = match parse_addr_len(args) {
+                    Ok(r) => r,
+                    Err(err) => return error(err),
+                };
+                // a reply can be shorter than the request, and each byte
+                // takes two hex digits
+                let len = (len as usize).min(PACKET_SIZE / 2);
+                match self.read_mem(addr, len) {
+                    Ok(bytes) => encode_hex(&bytes),
+                    // EFAULT, which gdb reports as an inaccessible address
+                    Err(_) => "E0e".to_string(),
+                }
+            }
+            "M" => {
+                let result = args
+                    .split_once(':')
+                    .ok_or_else(|| "expected ADDR,LENGTH:DATA".to_string())
+                    .and_then(|(addr_len, data)| {
+                        let (addr, _) = parse_addr_len(addr_len)?;
+                        let data = decode_hex(data)?;
+                        self.target
+                            .write_mem(addr, &data)
+                            .map_err(|err| err.to_string())
+                    });
+                match result {
+                    Ok(()) => return ok(),
+                    Err(err) => return error(err),
+                }
+            }
+            "Z" | "z" => {
+                // only software breakpoints (type 0) are supported
+                let Some(loc) = args.strip_prefix("0,") else {
+                    return Ok(Some(vec![]));
+                };
+                let addr = match parse_addr_len(loc.split(';').next().unwrap_or(loc)) {
+                    Ok((addr, _kind)) => addr,
+                    Err(err) => return error(err),
+                };
+                let result = if kind == "Z" {
+                    self.insert_breakpoint(addr)
+                } else {
+                    self.remove_breakpoint(addr)
+                };
+                match result {
+                    Ok(()) => return ok(),
+                    Err(err) => return error(err.to_string()),
+                }
+            }
+            // the resume address isn't supported
+            "c" => self.resume(Resume::Continue, 0)?,
+            "s" => self.resume(Resume::Step, 0)?,
+            "C" | "S" => {
+                let sig = args.split(';').next().unwrap_or("");
+                let sig = parse_hex(sig).unwrap_or(0) as c_uint;
+                let how = if kind == "C" {
+                    Resume::Continue
+                } else {
+                    Resume::Step
+                };
+                self.resume(how, sig)?
+            }
+            // there's only one thread to select
+            "H" | "T" => return ok(),
+            "k" => {
+                _ = self.target.kill();
+                _ = self.target.wait();
+                self.exit_reply = Some("X09".to_string());
+                return Ok(None);
+            }
+            "D" => {
+                for addr in self.breakpoints.keys().copied().collect::<Vec<_>>() {
+                    self.remove_breakpoint(addr)?;
+                }
+                self.target.detach()?;
+                self.conn.send(b"OK")?;
+                self.exit_reply = Some("W00".to_string());
+                return Ok(None);
+            }
+            _ => return self.handle_general(packet),
+        };
+        Ok(Some(reply.into_bytes()))
+    }
+
+    /// Handle the packets with multi-letter names.
+    fn handle_general(&mut self, packet: &str) -> io::Result<Option<Vec<u8>>> {
+        let pid = self.target.pid();
+        let reply = if packet.starts_with("qSupported") {
+            format!("PacketSize={PACKET_SIZE:x};swbreak+;qXfer:auxv:read+;vContSupported+")
+        } else if packet == "vCont?" {
+            "vCont;c;C;s;S".to_string()
+        } else if let Some(actions) = packet.strip_prefix("vCont") {
+            return Ok(self.vcont(actions)?.map(String::into_bytes));
+        } else if let Some(range) = packet.strip_prefix("qXfer:auxv:read::") {
+            return match parse_addr_len(range) {
+                Ok((offset, len)) => Ok(Some(self.read_auxv(offset as usize, len as usize)?)),
+                Err(_) => Ok(Some(b"E01".to_vec())),
+            };
+        } else {
+            match packet {
+                // the target was launched, not attached to
+                "qAttached" => "0".to_string(),
+                "qC" => format!("QC{pid:x}"),
+                "qfThreadInfo" => format!("m{pid:x}"),
+                "qsThreadInfo" => "l".to_string(),
+                // an empty reply means the packet isn't supported
+                _ => String::new(),
+            }
+        };
+        Ok(Some(reply.into_bytes()))
+    }
+}
+
+/// Serve a target launched with [`crate::run_target`] to one gdb connection
+/// on addr.
+pub fn serve(target: Target, output: TargetOutput, addr: impl ToSocketAddrs) -> io::Result<()> {
+    // the target stops when it starts executing
+    let status = target.wait()?;
+    let listener = TcpListener::bind(addr)?;
+    println!("listening on {}", listener.local_addr()?);
+    let (stream, client) = listener.accept()?;
+    println!("remote debugging from {client}");
+    let mut server = Server {
+        conn: Connection::new(stream)?,
+        target,
+        breakpoints: HashMap::new(),
+        output,
+        last_stop: String::new(),
+        exit_reply: None,
+    };
+    server.last_stop = server.stop_reply(status)?;
+    loop {
+        let packet = match server.conn.receive() {
+            Ok(packet) => packet,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        };
+        match server.handle(&packet)? {
+            Some(reply) => server.conn.send(&reply)?,
+            None => break,
+        }
+    }
+    if server.exit_reply.is_none() {
+        // gdb disconnected without killing or detaching
+        _ = server.target.kill();
+    }
+    server.output.finish();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_body, decode_hex, encode_hex, encode_packet};
+
+    #[test]
+    fn test_packets() {
+        assert_eq!(encode_packet(b"g"), b"$g#67");
+        assert_eq!(encode_packet(b"a#b"), b"$a}\x03b#43");
+        assert_eq!(decode_body(b"a}\x03b").unwrap(), b"a#b");
+        // 0 repeated ' ' - 29 = 3 more times
+        assert_eq!(decode_body(b"0* 1").unwrap(), b"00001");
+        assert!(decode_body(b"*#").is_err());
+        assert_eq!(encode_hex(&[0xab, 0x01]), "ab01");
+        assert_eq!(decode_hex("ab01xx").unwrap(), vec![0xab, 0x01, 0]);
+        assert!(decode_hex("abc").is_err());
+    }
+}
@@ -0,0 +1,73 @@
+//! Forward Ctrl-C to the target while it is running.
+//!
+//! While the target runs, the debugger is blocked in `waitpid`, so there's no
+//! prompt for rustyline to interrupt. Instead we temporarily install a SIGINT
+//! handler that stops the target's current thread with a SIGSTOP, which the
+//! wait then reports like any other signal stop.
+
+use std::{
+    io, mem, ptr,
+    sync::atomic::{AtomicBool, AtomicI32, Ordering},
+};
+
+use libc::{c_int, pid_t};
+
+/// The thread to stop when SIGINT arrives (0 if no handler is installed).
+static TARGET_PID: AtomicI32 = AtomicI32::new(0);
+/// Whether the handler has sent a SIGSTOP since it was installed.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn forward_sigint(_signo: c_int) {
+    // only async-signal-safe operations are allowed here
+    let pid = TARGET_PID.load(Ordering::SeqCst);
+    if pid > 0 {
+        // stop just the thread, which is the one that's sure to be running
+        // (rather than a group stop of every thread)
+        unsafe { libc::syscall(libc::SYS_tkill, pid, libc::SIGSTOP) };
+        INTERRUPTED.store(true, Ordering::SeqCst);
+    }
+}
+
+/// RAII guard that forwards SIGINT to a target's thread as a SIGSTOP.
+///
+/// Dropping the guard restores the previous SIGINT disposition, so Ctrl-C at
+/// the prompt goes back to just aborting the current line.
+pub struct InterruptGuard {
+    old_action: libc::sigaction,
+}
+
+impl InterruptGuard {
+    pub fn install(pid: pid_t) -> io::Result<Self> {
+        TARGET_PID.store(pid, Ordering::SeqCst);
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        unsafe {
+            let mut action: libc::sigaction = mem::zeroed();
+            action.sa_sigaction = forward_sigint as *const () as usize;
+            // restart the debugger's waitpid rather than failing with EINTR;
+            // the wait will then see the target stop with SIGSTOP
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            let mut old_action: libc::sigaction = mem::zeroed();
+            if libc::sigaction(libc::SIGINT, &action, &mut old_action) < 0 {
+                TARGET_PID.store(0, Ordering::SeqCst);
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self { old_action })
+        }
+    }
+
+    /// Remove the handler, returning whether it interrupted the target.
+    pub fn finish(self) -> bool {
+        // dropping restores the old handler, after which INTERRUPTED can no
+        // longer change
+        drop(self);
+        INTERRUPTED.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        unsafe { libc::sigaction(libc::SIGINT, &self.old_action, ptr::null_mut()) };
+        TARGET_PID.store(0, Ordering::SeqCst);
+    }
+}
@@ -2,7 +2,7 @@
 use std::{
     ffi::{OsStr, OsString},
     fs,
-    io::{self},
+    io::{self, IsTerminal},
     os::unix::process::CommandExt,
     path::Path,
     process::{self, Stdio},
@@ -11,47 +11,1120 @@ use std::{
 use libc::pid_t;
 use object::Object;
 use rustyline::{error::ReadlineError, Editor};
+use serde_json::json;
 
 mod cli;
+mod completion;
+mod dap;
 pub mod debugger;
+mod debugregs;
+mod disasm;
 mod dwarf;
+mod expr;
+mod format;
+mod fpregs;
+mod gdbserver;
+mod interrupt;
+mod logging;
+mod maps;
+mod pager;
+mod procinfo;
 mod ptrace;
 mod source;
+mod threads;
+mod types;
+mod value;
+mod vecregs;
 
-use cli::{BreakpointLoc, Command, InfoCommand, RegisterCommand};
-use debugger::Dbg;
+use cli::{
+    BreakpointLoc, Command, Condition, DumpCommand, InfoCommand, ProcCommand, RegisterCommand,
+    RegisterGroup, SetCommand, ShowCommand,
+};
+use completion::RdbHelper;
+use debugger::{
+    BacktraceFrame, Dbg, DbgError, DisplayValue, NewBreakpoint, NewWatchpoint, Return, Stop,
+    StopReason, WatchKind, WatchpointHit,
+};
+use dwarf::VarLocation;
+use format::{MemFormat, RegFormat};
+use fpregs::FpReg;
+pub use logging::OutputPipes;
+pub use ptrace::Reg;
+use vecregs::{LaneFormat, VecReg};
 
 fn handle_command(dbg: &mut Dbg, cmd: cli::Command) {
+    let stops = matches!(
+        cmd,
+        Command::Continue
+            | Command::Stepi { .. }
+            | Command::Nexti
+            | Command::StepBranch
+            | Command::Finish
+            | Command::Return { .. }
+            | Command::Step { .. }
+            | Command::Next { .. }
+    );
+    dbg.reset_pager();
+    run_command(dbg, cmd);
+    print_warnings(dbg);
+    if stops {
+        if dbg.json_events() {
+            if let Some(event) = dbg.stop_event() {
+                println!("{event}");
+            }
+        }
+        print_next_instruction(dbg);
+        print_displays(dbg);
+    }
+}
+
+/// Print the problems the last commands ran into that didn't stop them.
+fn print_warnings(dbg: &mut Dbg) {
+    for warning in dbg.take_warnings() {
+        eprintln!("{warning}");
+    }
+}
+
+/// Print the source around the current pc, if there is any.
+fn print_current_source(dbg: &mut Dbg) {
+    match dbg.current_source() {
+        Ok(Some((path, line))) => source::print_source(&path, line, dbg.listsize()),
+        Ok(None) => {}
+        Err(err) => eprintln!("{err}"),
+    }
+}
+
+/// Describe a frame as lines of a backtrace, like `#1 0x15170 in countdown at
+/// src/bin/test.rs:54:8`. Functions inlined into the frame each get a line,
+/// innermost first.
+fn frame_lines(frame: &BacktraceFrame) -> Vec<String> {
+    let header = format!("#{} 0x{:x} ", frame.num, frame.pc);
+    if frame.functions.is_empty() {
+        return vec![format!(
+            "{header}in {}",
+            frame.symbol.as_deref().unwrap_or("??")
+        )];
+    }
+    // line up the callers of inlined functions under the first function
+    let indent = " ".repeat(header.len());
+    frame
+        .functions
+        .iter()
+        .enumerate()
+        .map(|(j, f)| {
+            let mut loc = f.file.as_deref().unwrap_or("??").to_string();
+            if let Some(line) = f.line {
+                loc.push_str(&format!(":{line}"));
+                if let Some(column) = f.column.filter(|&c| c != 0) {
+                    loc.push_str(&format!(":{column}"));
+                }
+            }
+            format!(
+                "{prefix}in {func} at {loc}{inlined}",
+                prefix = if j == 0 { &header } else { &indent },
+                func = f.function.as_deref().unwrap_or("??"),
+                inlined = if f.inlined { " (inlined)" } else { "" },
+            )
+        })
+        .collect()
+}
+
+/// Show a frame that was just selected, with its source.
+fn print_frame(dbg: &mut Dbg, result: Result<BacktraceFrame, DbgError>) {
+    let frame = match result {
+        Ok(frame) => frame,
+        Err(err) => return eprintln!("{err}"),
+    };
+    for line in frame_lines(&frame) {
+        println!("{line}");
+    }
+    match dbg.selected_frame_source() {
+        Ok(Some((path, line))) => source::print_source(&path, line, dbg.listsize()),
+        Ok(None) => {}
+        Err(err) => eprintln!("{err}"),
+    }
+}
+
+/// Print a backtrace, innermost frame first, showing at most limit frames.
+fn print_backtrace(dbg: &Dbg, limit: Option<usize>) {
+    let (frames, stopped) = dbg.backtrace_frames();
+    let limit = limit.unwrap_or(frames.len());
+    for frame in frames.iter().take(limit) {
+        for line in frame_lines(frame) {
+            dbg.pager().println(&line);
+        }
+        if dbg.pager().quit() {
+            return;
+        }
+    }
+    if frames.len() > limit {
+        dbg.pager().println("(more stack frames follow...)");
+    } else if let Some(reason) = stopped {
+        dbg.pager()
+            .println(&format!("(backtrace stopped: {reason})"));
+    }
+}
+
+/// Print the backtraces from frame pointers and from eh_frame side by side,
+/// to debug unwinding.
+fn print_backtrace_comparison(dbg: &Dbg) {
+    let show = |pc: Option<u64>| match pc {
+        Some(pc) => format!("0x{pc:x}"),
+        None => "-".to_string(),
+    };
+    println!(
+        "frame  {:<18} {:<18} function",
+        "frame pointers", "eh_frame"
+    );
+    let mut first_difference = None;
+    for (i, frame) in dbg.compare_unwinders().into_iter().enumerate() {
+        let marker = if frame.frame_pointer != frame.eh_frame && first_difference.is_none() {
+            first_difference = Some(i + 1);
+            "  <- first difference"
+        } else {
+            ""
+        };
+        println!(
+            "#{:<5} {:<18} {:<18} {}{marker}",
+            i + 1,
+            show(frame.frame_pointer),
+            show(frame.eh_frame),
+            frame.function.as_deref().unwrap_or("??"),
+        );
+    }
+    match first_difference {
+        Some(n) => println!("unwinders disagree starting at frame #{n}"),
+        None => println!("unwinders agree"),
+    }
+}
+
+/// Print a table of the user's breakpoints, by address.
+fn print_breakpoints(dbg: &Dbg) {
+    let bps = dbg.breakpoints();
+    if bps.is_empty() {
+        println!("no breakpoints");
+        return;
+    }
+    dbg.pager().println("Num  Enb  Address     What");
+    for bp in bps {
+        let desc = dbg.describe_pc(bp.pc).unwrap_or_else(|| "??".to_string());
+        let enabled = if bp.enabled { "y" } else { "n" };
+        let addr = format!("0x{:x}", bp.pc);
+        dbg.pager()
+            .println(&format!("{:<4} {enabled:<4} {addr:<11} {desc}", bp.id));
+        if let Some(cond) = bp.condition {
+            dbg.pager()
+                .println(&format!("\tstop only if {}", cond.describe()));
+        }
+        if bp.temporary {
+            dbg.pager().println("\ttemporary, deleted when hit");
+        }
+        if bp.ignore_count > 0 {
+            dbg.pager()
+                .println(&format!("\twill ignore next {} crossings", bp.ignore_count));
+        }
+    }
+}
+
+/// Print the value of an auto-display expression.
+fn print_display(display: &DisplayValue) {
+    println!("{}: {} = {}", display.num, display.desc, display.value);
+}
+
+/// Print all of the auto-display expressions, if the target is running.
+fn print_displays(dbg: &Dbg) {
+    for display in dbg.display_values() {
+        print_display(&display);
+    }
+}
+
+/// Print the instruction at the pc, if `disassemble-next-line` is on.
+fn print_next_instruction(dbg: &Dbg) {
+    if !dbg.disassemble_next_line() {
+        return;
+    }
+    match dbg.next_instruction_at_pc() {
+        Some((pc, Some(instr))) => println!("=> 0x{pc:x}:\t{}", instr.text),
+        Some((pc, None)) => println!("=> 0x{pc:x}:\t(bad)"),
+        None => {}
+    }
+}
+
+/// Report a watchpoint that triggered, with its old and new value.
+fn print_watchpoint_hit(hit: &WatchpointHit) {
+    let kind = hit.kind.map_or("software watchpoint", |kind| kind.name());
+    println!("{kind} {}: {}", hit.num, hit.text);
+    match &hit.old {
+        Some(old) => {
+            println!("old value = {old}");
+            println!("new value = {}", hit.new);
+        }
+        None => println!("value = {}", hit.new),
+    }
+}
+
+/// Report a watchpoint that was just set, or why it couldn't be.
+fn report_new_watchpoint(result: Result<NewWatchpoint, DbgError>) {
+    let wp = match result {
+        Ok(wp) => wp,
+        Err(err) => return eprintln!("{err}"),
+    };
+    if let Some(note) = &wp.note {
+        println!("{note}");
+    }
+    let kind = wp.kind.map_or("software watchpoint", |kind| kind.name());
+    println!(
+        "{kind} {}: {} (0x{:x}, {} bytes)",
+        wp.num, wp.text, wp.addr, wp.len
+    );
+}
+
+/// Report where a finish returned to, and the value the function returned.
+fn print_return(dbg: &mut Dbg, ret: &Return) {
+    let desc = dbg
+        .describe_pc(ret.pc)
+        .unwrap_or_else(|| "no source".to_string());
+    println!("returned to 0x{:x}: {desc}", ret.pc);
+    print_current_source(dbg);
+    match &ret.value {
+        Some(Ok((num, value))) => println!("Value returned is ${num} = {value}"),
+        Some(Err(err)) => eprintln!("{err}"),
+        None => {}
+    }
+}
+
+/// Report why the target stopped (or exited).
+///
+/// Steps that finish normally aren't reported here, since how to show
+/// where a step ended depends on the command.
+fn print_stop(dbg: &mut Dbg, stop: &Stop) {
+    if let Some(thread) = dbg.take_thread_switch() {
+        println!("[switching to thread {thread}]");
+    }
+    match &stop.reason {
+        StopReason::Exited(status) => {
+            if dbg.json_events() {
+                println!("{}", json!({"event": "exited", "status": status}));
+            }
+            if *status == 0 {
+                println!("program exited");
+            } else {
+                eprintln!("program exited with status {status}");
+            }
+        }
+        StopReason::Killed(signal) => {
+            if dbg.json_events() {
+                println!("{}", json!({"event": "exited", "signal": signal}));
+            }
+            eprintln!(
+                "program terminated with signal {signal} ({})",
+                debugger::signal_name(*signal)
+            );
+        }
+        StopReason::Breakpoint(pc) => {
+            println!("hit breakpoint 0x{pc:x}");
+            print_current_source(dbg);
+        }
+        // the stepping command that set the breakpoint reports where it
+        // stopped itself
+        StopReason::InternalBreakpoint(_) | StopReason::Step => {}
+        StopReason::Watchpoint(hits) => {
+            for hit in hits {
+                print_watchpoint_hit(hit);
+            }
+            print_current_source(dbg);
+        }
+        StopReason::Branch { addr, text, target } => {
+            println!("branch at 0x{addr:x}: {text} => 0x{target:x}");
+            print_current_source(dbg);
+        }
+        StopReason::Fault {
+            signal,
+            addr,
+            reason,
+        } => {
+            let what = if *signal == libc::SIGBUS {
+                "bus error"
+            } else {
+                "segfault"
+            };
+            println!("{what} accessing 0x{addr:x} ({reason})");
+            println!("at {}", dbg.describe_current_location());
+            print_current_source(dbg);
+        }
+        StopReason::Signal(0) => {}
+        StopReason::Signal(libc::SIGFPE) => {
+            println!("floating-point exception");
+            print_float_status(dbg);
+        }
+        StopReason::Signal(libc::SIGSTOP) => {
+            println!("interrupted at {}", dbg.describe_current_location());
+            print_current_source(dbg);
+        }
+        StopReason::Signal(signo) => {
+            println!("got signal {signo} ({})", debugger::signal_name(*signo));
+        }
+    }
+    if let Some(id) = stop.deleted_temporary {
+        println!("deleted temporary breakpoint {id}");
+    }
+    if let Some(ret) = &stop.returned {
+        print_return(dbg, ret);
+    }
+}
+
+/// Report how a command that runs the target stopped. If it was a step that
+/// finished normally, print_step shows where it ended.
+fn report_stop(dbg: &mut Dbg, result: Result<Stop, DbgError>, print_step: impl FnOnce(&mut Dbg)) {
+    match result {
+        Ok(stop) => {
+            print_stop(dbg, &stop);
+            if stop.reason == StopReason::Step {
+                print_step(dbg);
+            }
+        }
+        Err(err) => eprintln!("{err}"),
+    }
+}
+
+/// Report where a new breakpoint was set, and any locations it couldn't be
+/// set at.
+fn report_new_breakpoint(bp: &NewBreakpoint) {
+    if let Some((line, found_line)) = bp.moved {
+        println!("line {line} has no code, breakpoint set at line {found_line}");
+    }
+    if bp.locations.len() > 1 {
+        println!("breakpoint {} has {} locations", bp.id, bp.locations.len());
+    }
+    for loc in &bp.locations {
+        if let Some(err) = &loc.error {
+            eprintln!("{err}");
+        }
+        if let Some(desc) = &loc.desc {
+            println!("set breakpoint {} at 0x{:x}: {desc}", bp.id, loc.pc);
+        }
+    }
+}
+
+/// Set a user breakpoint, returning its id if it could be set.
+fn set_breakpoint(dbg: &mut Dbg, loc: BreakpointLoc) -> Option<usize> {
+    let result = match loc {
+        BreakpointLoc::Addr { pc } => Ok(dbg.set_user_breakpoint(pc)),
+
+        BreakpointLoc::Line { file, line } => dbg.set_breakpoint_at_source_location(&file, line),
+        BreakpointLoc::Function { name } => dbg.set_breakpoint_at_function(&name),
+    };
+    match result {
+        Ok(bp) => {
+            report_new_breakpoint(&bp);
+            Some(bp.id)
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            None
+        }
+    }
+}
+
+/// Set a software watchpoint from the arguments to `watch -sw ADDR LEN`.
+fn add_software_watchpoint(dbg: &mut Dbg, args: &[String]) {
+    let (addr, len) = match args.split_last() {
+        Some((len, addr)) if !addr.is_empty() => (addr.join(" "), len.parse::<usize>()),
+        _ => {
+            eprintln!("usage: watch -sw ADDR LEN");
+            return;
+        }
+    };
+    match len {
+        Ok(len) if len > 0 => report_new_watchpoint(dbg.add_software_watchpoint(&addr, len)),
+
+        _ => eprintln!("invalid length {}", args[args.len() - 1]),
+    }
+}
+
+/// Print the general-purpose registers, in padded hex unless another format is
+/// given.
+fn print_registers(dbg: &Dbg, format: Option<RegFormat>) {
+    let regs = match dbg.registers() {
+        Ok(regs) => regs,
+        Err(err) => {
+            eprintln!("could not read registers: {err}");
+            return;
+        }
+    };
+    let width = regs.iter().map(|(r, _)| r.name().len()).max().unwrap_or(0);
+    for (r, val) in regs {
+        let val_text = match format {
+            None => format!("0x{val:016x}"),
+            Some(format) => format.format(val),
+        };
+        let mut line = format!("{:width$} {val_text}", r.name(), width = width);
+        if r == Reg::Rflags {
+            line.push_str(&format!(" {}", ptrace::decode_rflags(val)));
+        } else if let Some(sym) = dbg.symbol_offset(val) {
+            line.push_str(&format!(" <{sym}>"));
+        }
+        dbg.pager().println(&line);
+    }
+}
+
+/// Print a single register (in hex unless another format is given).
+fn print_register(dbg: &Dbg, r: Reg, format: Option<RegFormat>) {
+    let val = match dbg.read_register(r) {
+        Ok(val) => val,
+        Err(err) => {
+            eprintln!("could not read {}: {err}", r.name());
+            return;
+        }
+    };
+    let text = format.unwrap_or(RegFormat::Hex).format(val);
+    if r == Reg::Rflags {
+        println!("{text} {}", ptrace::decode_rflags(val));
+    } else {
+        println!("{text}");
+    }
+}
+
+/// Print the x87 status word and MXCSR, decoded into flags.
+fn print_float_status(dbg: &Dbg) {
+    let regs = match dbg.float_registers() {
+        Ok(regs) => regs,
+        Err(err) => return eprintln!("{err}"),
+    };
+    for reg in [FpReg::Fctrl, FpReg::Fstat, FpReg::Mxcsr] {
+        println!("{}", debugger::format_float_register(&regs, reg));
+    }
+}
+
+/// Print the x87 stack registers, with their tags.
+fn print_float_registers(dbg: &Dbg) {
+    let regs = match dbg.float_registers() {
+        Ok(regs) => regs,
+        Err(err) => return eprintln!("{err}"),
+    };
+    for i in 0..8 {
+        dbg.pager()
+            .println(&debugger::format_float_register(&regs, FpReg::St(i)));
+    }
+}
+
+/// Print one floating-point register.
+fn print_float_register(dbg: &Dbg, reg: FpReg) {
+    match dbg.float_registers() {
+        Ok(regs) => println!("{}", debugger::format_float_register(&regs, reg)),
+        Err(err) => eprintln!("{err}"),
+    }
+}
+
+/// Print the x87 control and status words, MXCSR, and the x87 stack
+/// registers.
+fn print_float_info(dbg: &Dbg) {
+    print_float_status(dbg);
+    print_float_registers(dbg);
+}
+
+/// Print the vector registers (or just reg), split into lanes.
+fn print_vector_registers(dbg: &Dbg, reg: Option<VecReg>, lanes: Option<LaneFormat>) {
+    let regs = match dbg.vector_registers(reg) {
+        Ok(regs) => regs,
+        Err(err) => return eprintln!("{err}"),
+    };
+    let lanes = lanes.unwrap_or(LaneFormat::Hex);
+    for (reg, bytes) in regs {
+        dbg.pager()
+            .println(&format!("{:<5} {}", reg.name(), lanes.format(&bytes)));
+    }
+}
+
+/// Print each vector register in hex and as packed floats and doubles.
+fn print_vector_info(dbg: &Dbg) {
+    let regs = match dbg.vector_registers(None) {
+        Ok(regs) => regs,
+        Err(err) => return eprintln!("{err}"),
+    };
+    for (reg, bytes) in regs {
+        dbg.pager().println(&format!(
+            "{:<5} {}",
+            reg.name(),
+            LaneFormat::Hex.format(&bytes)
+        ));
+        dbg.pager()
+            .println(&format!("      f32 {}", LaneFormat::Floats.format(&bytes)));
+        dbg.pager()
+            .println(&format!("      f64 {}", LaneFormat::Doubles.format(&bytes)));
+        if dbg.pager().quit() {
+            return;
+        }
+    }
+}
+
+/// Print the debug registers, decoding the status and control registers.
+fn print_debug_registers(dbg: &Dbg) {
+    let regs = match dbg.debug_registers() {
+        Ok(regs) => regs,
+        Err(err) => return eprintln!("{err}"),
+    };
+    for (n, val) in regs {
+        let decoded = match n {
+            debugregs::DR6 => format!(" {}", debugregs::decode_dr6(val)),
+            debugregs::DR7 => format!(" {}", debugregs::decode_dr7(val)),
+            _ => String::new(),
+        };
+        dbg.pager()
+            .println(&format!("dr{n}      0x{val:016x}{decoded}"));
+    }
+}
+
+/// Print every register group.
+fn print_all_registers(dbg: &Dbg) {
+    print_registers(dbg, None);
+    print_float_registers(dbg);
+    print_vector_registers(dbg, None, None);
+    print_debug_registers(dbg);
+}
+
+/// Print the target's command line, as read from /proc.
+fn print_cmdline(result: Result<Vec<String>, DbgError>) {
+    match result {
+        Ok(args) => println!("command line: {}", procinfo::quote_args(&args)),
+        Err(err) => eprintln!("{err}"),
+    }
+}
+
+/// Print what the target process is running.
+fn print_target_info(dbg: &Dbg) {
+    let info = match dbg.target_info() {
+        Ok(info) => info,
+        Err(err) => return eprintln!("{err}"),
+    };
+    println!("process {}", info.pid);
+    match info.exe {
+        Ok(exe) => println!("executable: {}", exe.display()),
+        Err(err) => eprintln!("{err}"),
+    }
+    print_cmdline(info.cmdline);
+    println!("loaded at 0x{:x}", info.load_addr);
+}
+
+/// Describe the mapping an address is in, and what's there if it's in the
+/// program.
+fn print_mapping(dbg: &Dbg, text: &str) {
+    let found = match dbg.mapping(text) {
+        Ok(found) => found,
+        Err(err) => return eprintln!("{err}"),
+    };
+    let addr = found.addr;
+    let mapping = match found.mapping {
+        Some(mapping) => mapping,
+        None => return println!("0x{addr:x} is not mapped"),
+    };
+    print!(
+        "0x{addr:x} is in {} ({}), 0x{:x} bytes into the mapping",
+        mapping.name(),
+        mapping.perms,
+        addr - mapping.start
+    );
+    if mapping.path.starts_with('/') {
+        print!(
+            " (file offset 0x{:x})",
+            addr - mapping.start + mapping.offset
+        );
+    }
+    println!();
+    if let Some((pc, func, offset)) = found.function {
+        let desc = dbg
+            .describe_pc(pc)
+            .unwrap_or_else(|| "no source".to_string());
+        println!("0x{pc:x} is {func}+0x{offset:x}: {desc}");
+    }
+}
+
+/// List the variables in scope at a location, with their types and where
+/// they're stored.
+fn print_scope(dbg: &Dbg, loc: &BreakpointLoc) {
+    let (pc, vars) = match dbg.scope_variables(loc) {
+        Ok(scope) => scope,
+        Err(err) => return eprintln!("{err}"),
+    };
+    if vars.is_empty() {
+        println!("no variables in scope at 0x{pc:x}");
+        return;
+    }
+    for var in vars {
+        let location = match var.location {
+            Some(VarLocation::FrameOffset(offset)) => format!("at frame offset {offset}"),
+            Some(VarLocation::RegisterOffset(reg, offset)) => {
+                format!("at offset {offset} from {}", reg.name())
+            }
+            Some(VarLocation::Register(reg)) => format!("in {}", reg.name()),
+            Some(VarLocation::Addr(addr)) => format!("static at 0x{addr:x}"),
+            None => "location not supported".to_string(),
+        };
+        dbg.pager().println(&format!(
+            "{}: {} ({} bytes, {location})",
+            var.name,
+            var.ty.name(),
+            var.ty.size()
+        ));
+    }
+}
+
+/// Print "file:line:function" for each address, like addr2line.
+fn print_lines_for_addresses(dbg: &Dbg, addrs: &[u64], file: Option<&Path>) {
+    let lines = match dbg.lines_for_addresses(addrs, file) {
+        Ok(lines) => lines,
+        Err(err) => return eprintln!("{err}"),
+    };
+    for line in lines {
+        dbg.pager().println(&format!(
+            "0x{:x} {}:{}:{}",
+            line.addr,
+            line.file.as_deref().unwrap_or("??"),
+            line.line.unwrap_or(0),
+            line.function.as_deref().unwrap_or("??")
+        ));
+    }
+}
+
+/// Print the variables from info locals or info args, or say there are none.
+fn print_variables(dbg: &Dbg, result: Result<Vec<(String, String, String)>, DbgError>, none: &str) {
+    let vars = match result {
+        Ok(vars) => vars,
+        Err(err) => return eprintln!("{err}"),
+    };
+    if vars.is_empty() {
+        println!("{none}");
+        return;
+    }
+    for (name, _, val) in vars {
+        dbg.pager().println(&format!("{name} = {val}"));
+    }
+}
+
+/// Print the symbols from `symbol`, by name or matching a regex.
+fn print_symbols(dbg: &Dbg, name: &str, regex: bool) {
+    let syms = match dbg.symbols(name, regex) {
+        Ok(syms) => syms,
+        Err(err) => return eprintln!("{err}"),
+    };
+    if regex && syms.is_empty() {
+        println!("no symbols match {name}");
+    }
+    for sym in syms {
+        dbg.pager()
+            .println(&format!("{} {} 0x{:x}", sym.type_, sym.name, sym.addr));
+        if dbg.pager().quit() {
+            return;
+        }
+    }
+}
+
+/// List the functions whose names match a regex (or all of them), with their
+/// (offset) addresses.
+fn print_functions(dbg: &Dbg, pattern: Option<&str>) {
+    let funcs = match dbg.functions(pattern) {
+        Ok(funcs) => funcs,
+        Err(err) => return eprintln!("{err}"),
+    };
+    if funcs.is_empty() {
+        println!("no matching functions");
+        return;
+    }
+    for sym in funcs {
+        dbg.pager()
+            .println(&format!("0x{:x} {}", sym.addr, sym.name));
+        if dbg.pager().quit() {
+            return;
+        }
+    }
+}
+
+/// Disassemble the function containing a location (or the current pc),
+/// marking the current pc.
+fn print_disassembly(dbg: &Dbg, loc: Option<&BreakpointLoc>) {
+    let disas = match dbg.disassemble(loc) {
+        Ok(disas) => disas,
+        Err(err) => return eprintln!("{err}"),
+    };
+    for instr in disas.instrs {
+        let marker = if Some(instr.addr) == disas.pc {
+            "=>"
+        } else {
+            "  "
+        };
+        dbg.pager().println(&format!(
+            "{marker} 0x{:x} <+{}>:\t{}",
+            instr.addr,
+            instr.addr - disas.start,
+            instr.text
+        ));
+        if dbg.pager().quit() {
+            return;
+        }
+    }
+}
+
+/// Print memory starting at the address an expression evaluates to, like
+/// gdb's `x/FMT ADDR`.
+fn print_memory(dbg: &Dbg, text: &str, fmt: MemFormat) {
+    let examined = match dbg.examine_memory(text, fmt) {
+        Ok(examined) => examined,
+        Err(err) => return eprintln!("{err}"),
+    };
+    for (addr, units) in examined.lines {
+        if dbg.pager().quit() {
+            return;
+        }
+        dbg.pager()
+            .println(&format!("0x{addr:x}:\t{}", units.join("\t")));
+    }
+    if let Some(addr) = examined.unreadable {
+        eprintln!("cannot access memory at 0x{addr:x}");
+    }
+}
+
+/// Print the value of an expression, or with `str ADDR`, the NUL-terminated
+/// string at an address.
+fn print_expression(dbg: &mut Dbg, text: &str) {
+    let text = text.trim();
+    if let Some(addr) = text.strip_prefix("str ") {
+        match dbg.c_string_at(addr) {
+            Ok(bytes) => {
+                let truncated = if bytes.len() == debugger::MAX_STRING_LEN {
+                    "..."
+                } else {
+                    ""
+                };
+                println!(
+                    "\"{}\"{truncated} ({} bytes)",
+                    debugger::escape_c_string(&bytes),
+                    bytes.len()
+                );
+            }
+            Err(err) => eprintln!("{err}"),
+        }
+        return;
+    }
+    match dbg.evaluate(text) {
+        Ok(formatted) => println!("${}: {text} = {formatted}", dbg.history_len()),
+        Err(err) => eprintln!("{err}"),
+    }
+}
+
+/// Change how a signal is handled, then show its handling.
+fn handle_signal(dbg: &mut Dbg, signal: &str, actions: &[cli::SignalAction]) {
+    let (signo, handling) = match dbg.handle_signal(signal, actions) {
+        Ok(handled) => handled,
+        Err(err) => return eprintln!("{err}"),
+    };
+    let yes_no = |b: bool| if b { "yes" } else { "no" };
+    println!("signal   stop  pass");
+    println!(
+        "{:<8} {:<5} {}",
+        debugger::signal_name(signo),
+        yes_no(handling.stop),
+        yes_no(handling.pass)
+    );
+}
+
+/// Report an error from a command that prints nothing when it succeeds.
+fn report_error(result: Result<(), DbgError>) {
+    if let Err(err) = result {
+        eprintln!("{err}");
+    }
+}
+
+fn run_command(dbg: &mut Dbg, cmd: cli::Command) {
     match cmd {
-        Command::Continue => dbg.continue_execution().expect("continue failed"),
-        Command::Break { loc } => match loc {
-            BreakpointLoc::Addr { pc } => dbg.set_user_breakpoint(pc),
-            BreakpointLoc::Line { file, line } => {
-                dbg.set_breakpoint_at_source_location(&file, line)
+        Command::Continue => {
+            let stop = dbg.continue_execution();
+            report_stop(dbg, stop, |_| {});
+        }
+        Command::Break { loc, cond } => {
+            let cond = match Condition::parse_if(&cond) {
+                Ok(cond) => cond,
+                Err(err) => {
+                    eprintln!("{err}");
+                    return;
+                }
+            };
+            if let (Some(id), Some(cond)) = (set_breakpoint(dbg, loc), cond) {
+                dbg.set_breakpoint_condition(id, cond);
             }
-            BreakpointLoc::Function { name } => dbg.set_breakpoint_at_function(&name),
+        }
+        Command::Tbreak { loc } => {
+            if let Some(id) = set_breakpoint(dbg, loc) {
+                dbg.set_breakpoint_temporary(id);
+            }
+        }
+        Command::Condition { pc, cond } => {
+            if cond.is_empty() {
+                report_error(dbg.set_condition_at(pc, None));
+            } else {
+                match Condition::parse(&cond.join(" ")) {
+                    Ok(cond) => report_error(dbg.set_condition_at(pc, Some(cond))),
+                    Err(err) => eprintln!("{err}"),
+                }
+            }
+        }
+        Command::Handle { signal, actions } => handle_signal(dbg, &signal, &actions),
+        Command::Ignore { pc, count } => match dbg.ignore_breakpoint(pc, count) {
+            Ok(()) => println!("will ignore next {count} crossings of breakpoint at 0x{pc:x}"),
+            Err(err) => eprintln!("{err}"),
         },
-        Command::Disable { pc } => dbg.disable_user_breakpoint(pc),
+        Command::Disable { pc } => report_error(dbg.disable_user_breakpoint(pc)),
+        Command::Enable { pc } => report_error(dbg.enable_user_breakpoint(pc)),
+        Command::Delete { pc } => report_error(dbg.delete_user_breakpoint(pc)),
         Command::Register(cmd) => match cmd {
-            RegisterCommand::Dump => dbg.dump_registers(),
-            RegisterCommand::Read { reg } => dbg.read_register(reg),
-            RegisterCommand::Write { reg, val } => dbg.write_register(reg, val),
+            RegisterCommand::Dump { fp: true, .. } => print_float_info(dbg),
+            RegisterCommand::Dump { fp: false, format } => print_registers(dbg, format),
+            RegisterCommand::Read { reg, format } => print_register(dbg, reg, format),
+            RegisterCommand::Write { reg, val } => {
+                if let Err(err) = dbg.write_register(reg, val) {
+                    eprintln!("could not write {}: {err}", reg.name());
+                }
+            }
+        },
+        Command::Stepi { count } => {
+            let stop = dbg.step_instructions(count);
+            report_stop(dbg, stop, |_| {});
+        }
+        Command::Nexti => {
+            let stop = dbg.next_instruction();
+            report_stop(dbg, stop, |dbg| {
+                println!("{}", dbg.describe_current_location())
+            });
+        }
+        Command::StepBranch => {
+            let stop = dbg.step_to_branch();
+            report_stop(dbg, stop, |_| {});
+        }
+        Command::Finish => {
+            let stop = dbg.step_out();
+            report_stop(dbg, stop, |_| {});
+        }
+        Command::Return { value } => {
+            let result = dbg.prepare_return(value).and_then(|ret| {
+                println!("warning: skipping the rest of the function, including any destructors");
+                dbg.force_return(ret)
+            });
+            match result {
+                Ok(()) => {
+                    let frame = dbg.select_frame(None);
+                    print_frame(dbg, frame);
+                }
+                Err(err) => eprintln!("{err}"),
+            }
+        }
+
+        Command::Step { count } => {
+            let stop = dbg.step_in(count);
+            report_stop(dbg, stop, print_current_source);
+        }
+        Command::Next { count } => {
+            let stop = dbg.step_over(count);
+            report_stop(dbg, stop, print_current_source);
+        }
+        Command::Disassemble { loc } => print_disassembly(dbg, loc.as_ref()),
+        Command::Symbol { regex, name } => print_symbols(dbg, &name, regex),
+        Command::Print { expr } => print_expression(dbg, &expr.join(" ")),
+        Command::Whatis { expr } => match dbg.expression_type(&expr.join(" ")) {
+            Ok(ty) => println!("type = {}", ty.name()),
+            Err(err) => eprintln!("{err}"),
+        },
+        Command::Ptype { expr } => match dbg.expression_type(&expr.join(" ")) {
+            Ok(ty) => println!("type = {}", ty.definition()),
+            Err(err) => eprintln!("{err}"),
+        },
+        Command::Frame { num } => {
+            let frame = dbg.select_frame(num);
+            print_frame(dbg, frame);
+        }
+        Command::Up { count } => {
+            let frame = dbg.frame_up(count);
+            print_frame(dbg, frame);
+        }
+        Command::Down { count } => {
+            let frame = dbg.frame_down(count);
+            print_frame(dbg, frame);
+        }
+        Command::List { loc } => match dbg.list_source(loc.as_ref()) {
+            Ok(listing) => source::print_numbered_lines(listing.start, listing.end, &listing.lines),
+            Err(err) => eprintln!("{err}"),
+        },
+        Command::Directory { dir } => dbg.add_source_dir(dir),
+        Command::Backtrace { limit, compare } => {
+            if compare {
+                print_backtrace_comparison(dbg);
+            } else {
+                print_backtrace(dbg, limit);
+            }
+        }
+        Command::Display { format, expr } => {
+            if expr.is_empty() {
+                print_displays(dbg);
+            } else {
+                match dbg.add_display(&expr.join(" "), format) {
+                    Ok(Some(display)) => print_display(&display),
+                    Ok(None) => {}
+                    Err(err) => eprintln!("{err}"),
+                }
+            }
+        }
+        Command::Examine { format, addr } => {
+            let format = format.unwrap_or_else(|| MemFormat::parse("").unwrap());
+            print_memory(dbg, &addr.join(" "), format)
+        }
+        Command::Undisplay { num } => report_error(dbg.delete_display(num)),
+        Command::Watch { expr } => match expr.split_first() {
+            Some((flag, args)) if flag == "-sw" => add_software_watchpoint(dbg, args),
+            _ => report_new_watchpoint(dbg.add_watchpoint(&expr.join(" "), WatchKind::Write)),
         },
-        Command::Stepi => dbg.single_step(),
-        Command::Finish => dbg.step_out(),
-        Command::Step => dbg.step_in(),
-        Command::Next => dbg.step_over(),
-        Command::Symbol { name } => {
-            let matches = dbg.lookup_symbol(&name);
-            for sym in matches.into_iter() {
-                println!("{} {} 0x{:x}", sym.type_, sym.name, sym.addr);
+        Command::Rwatch { expr } => {
+            report_new_watchpoint(dbg.add_watchpoint(&expr.join(" "), WatchKind::Read))
+        }
+        Command::Awatch { expr } => {
+            report_new_watchpoint(dbg.add_watchpoint(&expr.join(" "), WatchKind::Access))
+        }
+        Command::Unwatch { num } => {
+            if let Err(err) = dbg.delete_watchpoint(num) {
+                eprintln!("{err}");
             }
         }
-        Command::Backtrace => dbg.print_backtrace(),
+        Command::Dump(DumpCommand::Memory { file, start, end }) => {
+            match dbg.dump_memory(&file, &start, &end) {
+                Ok((len, unread)) => {
+                    if let Some(unread) = unread {
+                        println!("could not read 0x{:x}-0x{:x}", unread.start, unread.end);
+                    }
+                    println!("wrote {len} bytes to {}", file.display());
+                }
+                Err(err) => eprintln!("{err}"),
+            }
+        }
+        Command::Restore { file, addr } => match dbg.restore_memory(&file, &addr) {
+            Ok((addr, len)) => println!("restored {len} bytes to 0x{addr:x}"),
+            Err(err) => eprintln!("{err}"),
+        },
+        Command::WriteMem {
+            bytes,
+            addr,
+            values,
+        } => match dbg.write_values(&addr, &values, bytes) {
+            Ok((addr, len)) => println!("wrote {len} bytes to 0x{addr:x}"),
+            Err(err) => eprintln!("{err}"),
+        },
         Command::Info(cmd) => match cmd {
-            InfoCommand::Breakpoints => dbg.print_breakpoints(),
-            InfoCommand::Backtrace => dbg.print_backtrace(),
+            InfoCommand::Breakpoints => print_breakpoints(dbg),
+            InfoCommand::Float => print_float_info(dbg),
+            InfoCommand::Vector => print_vector_info(dbg),
+            InfoCommand::Scope { loc } => print_scope(dbg, &loc),
+            InfoCommand::Mapping { expr } => print_mapping(dbg, &expr.join(" ")),
+            InfoCommand::LineForAddress { addrs, file } => {
+                print_lines_for_addresses(dbg, &addrs, file.as_deref())
+            }
+            InfoCommand::Registers { group, lanes } => {
+                match group.unwrap_or(RegisterGroup::General) {
+                    RegisterGroup::General => print_registers(dbg, None),
+                    RegisterGroup::Float => print_float_registers(dbg),
+                    RegisterGroup::Vector => print_vector_registers(dbg, None, lanes),
+                    RegisterGroup::Debug => print_debug_registers(dbg),
+                    RegisterGroup::VecReg(reg) => print_vector_registers(dbg, Some(reg), lanes),
+                    RegisterGroup::FpReg(reg) => print_float_register(dbg, reg),
+                }
+            }
+            InfoCommand::AllRegisters => print_all_registers(dbg),
+            InfoCommand::Proc(ProcCommand::Cmdline) => print_cmdline(dbg.cmdline()),
+            InfoCommand::Target => print_target_info(dbg),
+            InfoCommand::Backtrace => print_backtrace(dbg, None),
+            InfoCommand::Display => {
+                let displays = dbg.displays();
+                if displays.is_empty() {
+                    println!("no auto-display expressions");
+                }
+                for (num, desc) in displays {
+                    println!("{num}: {desc}");
+                }
+            }
+            InfoCommand::Locals => print_variables(dbg, dbg.locals(), "no locals"),
+            InfoCommand::Args => print_variables(dbg, dbg.arguments(), "no arguments"),
+            InfoCommand::Functions { pattern } => print_functions(dbg, pattern.as_deref()),
         },
+        Command::Set(cmd) => match cmd {
+            SetCommand::Args { .. } if dbg.is_attached() => {
+                eprintln!("the program was attached to, so it won't be run with new arguments");
+            }
+            SetCommand::Args { args } => match cli::parse_target_args(&args) {
+                Ok(args) => dbg.set_args(args),
+                Err(err) => eprintln!("{err}"),
+            },
+            SetCommand::Logging { on, file } => {
+                let file = if on {
+                    Some(file.unwrap_or_else(|| "rdb.log".into()))
+                } else {
+                    None
+                };
+                report_error(dbg.set_logging(file.as_deref()));
+            }
+            SetCommand::SchedulerLocking { mode } => dbg.set_scheduler_locking(mode),
+            SetCommand::Pagination { on } => dbg.set_pagination(on),
+            SetCommand::DisassembleNextLine { on } => dbg.set_disassemble_next_line(on),
+            SetCommand::SubstitutePath { from, to } => dbg.set_substitute_path(&from, &to),
+            SetCommand::Listsize { n } => dbg.set_listsize(n),
+            SetCommand::Var(words) => report_error(dbg.set_var(&words.join(" "))),
+        },
+        Command::Show(cmd) => match cmd {
+            ShowCommand::Args => {
+                let args: Vec<_> = dbg.args().iter().map(|a| a.to_string_lossy()).collect();
+                if args.is_empty() {
+                    println!("no program arguments");
+                } else {
+                    let quoted: Vec<_> = args
+                        .iter()
+                        .map(|a| shlex::try_quote(a).unwrap_or_else(|_| a.clone()))
+                        .collect();
+                    println!("program arguments: {}", quoted.join(" "));
+                }
+            }
+            ShowCommand::Logging => match dbg.logging_path() {
+                Some(path) => println!("logging program output to {}", path.display()),
+                None => println!("logging is off"),
+            },
+            ShowCommand::SchedulerLocking => {
+                println!("scheduler-locking is {}", dbg.scheduler_locking().name())
+            }
+            ShowCommand::Pagination => {
+                let state = if dbg.pagination() { "on" } else { "off" };
+                println!("pagination is {state}");
+            }
+            ShowCommand::DisassembleNextLine => {
+                let state = if dbg.disassemble_next_line() {
+                    "on"
+                } else {
+                    "off"
+                };
+                println!("disassemble-next-line is {state}");
+            }
+            ShowCommand::Listsize => println!("listsize is {}", dbg.listsize()),
+            ShowCommand::SubstitutePath => {
+                if dbg.substitute_paths().is_empty() {
+                    println!("no source path substitutions");
+                }
+                for (from, to) in dbg.substitute_paths() {
+                    println!("{from} -> {to}");
+                }
+            }
+            ShowCommand::Directories => {
+                if dbg.source_dirs().is_empty() {
+                    println!("no source directories");
+                }
+                for dir in dbg.source_dirs() {
+                    println!("{}", dir.display());
+                }
+            }
+        },
+        Command::Run => restart_target(dbg),
+        Command::Detach => detach(dbg),
         Command::Quit => {
             return;
         }
@@ -61,27 +1134,134 @@ fn handle_command(dbg: &mut Dbg, cmd: cli::Command) {
     }
 }
 
-fn interaction_loop(mut dbg: Dbg) {
+/// Kill the target and run the program again from the start, keeping the
+/// breakpoints.
+fn restart_target(dbg: &mut Dbg) {
+    if dbg.is_attached() {
+        eprintln!("the program was attached to, so it can't be run again");
+        return;
+    }
+    let pipes = match OutputPipes::new() {
+        Ok(pipes) => pipes,
+        Err(err) => {
+            eprintln!("could not create pipes for program output: {err}");
+            return;
+        }
+    };
+    dbg.kill_target();
+    dbg.finish_output();
+    let pid = unsafe { libc::fork() };
+    if pid < 0 {
+        eprintln!("could not fork");
+        return;
+    }
+    if pid == 0 {
+        let err = run_target(dbg.program().as_os_str(), dbg.args(), pipes);
+        eprintln!("could not start program: {err}");
+        process::exit(2);
+    }
+    let result = dbg.restart(pid, pipes.into_target_output());
+    print_warnings(dbg);
+    if let Err(err) = result {
+        dbg.finish_output();
+        eprintln!(
+            "could not start debugging: {}",
+            ptrace::describe_error(&err)
+        );
+        return;
+    }
+    println!("debugging pid {pid}");
+}
+
+/// Stop tracing the target and let it keep running.
+fn detach(dbg: &mut Dbg) {
+    let result = dbg.detach();
+    print_warnings(dbg);
+    match result {
+        Ok(()) => println!("detached from pid {}", dbg.target_pid()),
+        Err(err) => eprintln!("{err}"),
+    }
+}
+
+/// End the session: detach from an attached target, so it keeps running,
+/// and kill a launched one.
+fn release_target(dbg: &mut Dbg) {
+    if !dbg.is_attached() {
+        dbg.kill_target_if_running();
+    } else if dbg.is_running() {
+        detach(dbg);
+    }
+}
+
+/// Run each line as a command. Blank lines and lines starting with `#` are
+/// skipped.
+///
+/// Returns false if a command ends the session (like `quit`), in which case
+/// the rest of the lines are not run.
+fn run_commands<'a>(dbg: &mut Dbg, lines: impl IntoIterator<Item = &'a str>) -> bool {
+    for line in lines {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match cli::parse_line(line) {
+            Ok(Command::Quit) => return false,
+            Ok(Command::Detach) => {
+                detach(dbg);
+                // there's nothing left to debug
+                if !dbg.is_running() {
+                    return false;
+                }
+            }
+            Ok(cmd) => handle_command(dbg, cmd),
+            Err(err) => eprintln!("{}", err),
+        }
+    }
+    true
+}
+
+/// Run the commands given on the command line, then prompt for more (unless
+/// one of them ended the session).
+fn interaction_loop(mut dbg: Dbg, commands: &[String]) {
     println!("debugging pid {}", dbg.target_pid());
+    if !run_commands(&mut dbg, commands.iter().map(String::as_str)) {
+        release_target(&mut dbg);
+        dbg.finish_output();
+        return;
+    }
 
-    let mut rl = Editor::<()>::new();
+    let mut rl = Editor::<RdbHelper>::new();
+    // finding the names takes a moment, so skip it when there's no one to
+    // complete for
+    if io::stdin().is_terminal() {
+        rl.set_helper(Some(RdbHelper::new(dbg.completion_names())));
+    }
     _ = rl.load_history(".rdb.history");
+    // the last command to run again on an empty line
+    let mut last: Option<String> = None;
 
     loop {
         let readline = rl.readline("rdb> ");
         match readline {
             Ok(line) => {
-                if line.is_empty() {
-                    continue;
-                }
-                rl.add_history_entry(line.as_str());
-                match cli::parse_line(&line) {
-                    Ok(Command::Quit) => break,
-                    Ok(cmd) => handle_command(&mut dbg, cmd),
-                    Err(err) => {
-                        eprintln!("{}", err);
-                        continue;
+                let line = if line.trim().is_empty() {
+                    match &last {
+                        Some(last) => last.clone(),
+                        None => continue,
                     }
+                } else {
+                    rl.add_history_entry(line.as_str());
+                    last = match cli::parse_line(line.trim()) {
+                        // repeating a listing continues it rather than
+                        // listing the same lines again
+                        Ok(Command::List { .. }) => Some("list".to_string()),
+                        Ok(cmd) if cmd.is_repeatable() => Some(line.clone()),
+                        _ => None,
+                    };
+                    line
+                };
+                if !run_commands(&mut dbg, [line.as_str()]) {
+                    break;
                 }
             }
             Err(ReadlineError::Interrupted) => {}
@@ -92,28 +1272,165 @@ fn interaction_loop(mut dbg: Dbg) {
             }
         }
     }
-    dbg.kill_target_if_running();
+    release_target(&mut dbg);
+    dbg.finish_output();
     _ = rl.save_history(".rdb.history");
 }
 
-pub fn debugger<P: AsRef<Path>>(path: P, target: pid_t) {
+/// Set up debugging a target launched with [`run_target`] (or attached to,
+/// without pipes for its output), and run the frontend f on it.
+fn with_debugger<P: AsRef<Path>>(
+    path: P,
+    args: &[OsString],
+    target: pid_t,
+    pipes: Option<OutputPipes>,
+    f: impl FnOnce(Dbg),
+) {
     let file = fs::File::open(&path).unwrap();
     let mmap = unsafe { memmap::Mmap::map(&file).unwrap() };
     let object = object::File::parse(&*mmap).unwrap();
     if !object.is_little_endian() {
         panic!("only handling little endian");
     }
-    let dbg = Dbg::new(object, target);
-    interaction_loop(dbg);
+    let output = pipes.map(OutputPipes::into_target_output);
+    let mut dbg = match Dbg::new(object, target) {
+        Ok(dbg) => dbg,
+        Err(err) => {
+            // show the target's own explanation, if it printed one
+            if let Some(output) = output {
+                output.finish();
+            }
+            eprintln!("could not start debugging: {err}");
+            process::exit(1);
+        }
+    };
+    dbg.set_program(path.as_ref().to_path_buf());
+    dbg.set_args(args.to_vec());
+    if let Some(output) = output {
+        dbg.set_target_output(output);
+    }
+    f(dbg)
+}
+
+/// Debug a target launched with [`run_target`].
+///
+/// If json is set, stops and exits are also reported as JSON objects, one per
+/// line. The commands are run before prompting.
+pub fn debugger<P: AsRef<Path>>(
+    path: P,
+    args: &[OsString],
+    target: pid_t,
+    pipes: OutputPipes,
+    json: bool,
+    commands: &[String],
+) {
+    with_debugger(path, args, target, Some(pipes), |mut dbg| {
+        dbg.set_json_events(json);
+        interaction_loop(dbg, commands);
+    })
+}
+
+/// Debug a target launched with [`run_target`] by running the commands in a
+/// script, one per line, then exit.
+pub fn batch_debugger<P: AsRef<Path>>(
+    path: P,
+    args: &[OsString],
+    target: pid_t,
+    pipes: OutputPipes,
+    json: bool,
+    script: &str,
+) {
+    with_debugger(path, args, target, Some(pipes), |mut dbg| {
+        dbg.set_json_events(json);
+        // there's no one to page for
+        dbg.set_pagination(false);
+        println!("debugging pid {}", dbg.target_pid());
+        run_commands(&mut dbg, script.lines());
+        release_target(&mut dbg);
+        dbg.finish_output();
+    })
+}
+
+/// Debug a process that's already running, attaching to it by pid.
+///
+/// When the debugger quits, the process is detached from and keeps running.
+pub fn attach_debugger(pid: pid_t, json: bool, commands: &[String]) {
+    let path = match fs::read_link(format!("/proc/{pid}/exe")) {
+        Ok(path) => path,
+        Err(err) => {
+            eprintln!("could not find the program for pid {pid}: {err}");
+            process::exit(1);
+        }
+    };
+    if let Err(err) = ptrace::Target::attach(pid) {
+        eprintln!(
+            "could not attach to pid {pid}: {}",
+            ptrace::describe_error(&err)
+        );
+        process::exit(1);
+    }
+    with_debugger(&path, &[], pid, None, |mut dbg| {
+        dbg.set_attached(true);
+        if let Err(err) = dbg.attach_threads() {
+            eprintln!(
+                "could not attach to the threads of pid {pid}: {}",
+                ptrace::describe_error(&err)
+            );
+        }
+        dbg.set_json_events(json);
+        interaction_loop(dbg, commands);
+    })
+}
+
+/// Debug a target launched with [`run_target`] from an editor, over the Debug
+/// Adapter Protocol on stdin and stdout.
+pub fn debug_adapter<P: AsRef<Path>>(
+    path: P,
+    args: &[OsString],
+    target: pid_t,
+    pipes: OutputPipes,
+) {
+    with_debugger(path, args, target, Some(pipes), |mut dbg| {
+        // there's no one to page for
+        dbg.set_pagination(false);
+        if let Err(err) = dap::serve(&mut dbg) {
+            eprintln!("debug adapter failed: {err}");
+        }
+        dbg.kill_target_if_running();
+        dbg.finish_output();
+    })
 }
 
-pub fn run_target(prog: &OsStr, args: &[OsString]) -> io::Error {
+/// Serve a target launched with [`run_target`] to a gdb that connects to addr
+/// (with `target remote`), rather than debugging it interactively.
+pub fn serve_target(target: pid_t, pipes: OutputPipes, addr: &str) {
+    let output = pipes.into_target_output();
+    if let Err(err) = gdbserver::serve(ptrace::Target::new(target), output, addr) {
+        eprintln!("remote debugging failed: {err}");
+        process::exit(1);
+    }
+}
+
+/// Execute a program to be traced, in a forked child process.
+///
+/// The program's output goes to pipes, which the debugger copies to its own
+/// output. Only returns if the program can't be traced or executed.
+pub fn run_target(prog: &OsStr, args: &[OsString], pipes: OutputPipes) -> io::Error {
     unsafe { libc::personality(libc::ADDR_NO_RANDOMIZE as u64) };
-    ptrace::trace_me();
+    if let Err(err) = ptrace::trace_me() {
+        return io::Error::new(
+            err.kind(),
+            format!("could not trace: {}", ptrace::describe_error(&err)),
+        );
+    }
+    let (stdout, stderr) = pipes.into_target_stdio();
+    // run in a separate process group so Ctrl-C from the terminal only goes to
+    // the debugger, which forwards it to the target
     process::Command::new(prog)
+        .process_group(0)
         .args(args)
         .stdin(Stdio::null())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
+        .stdout(stdout)
+        .stderr(stderr)
         .exec()
 }
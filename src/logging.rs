@@ -0,0 +1,181 @@
+//! Copy the target's output to the terminal, and optionally to a log file.
+//!
+//! The target's stdout and stderr are pipes rather than the debugger's own
+//! file descriptors, so the debugger sees everything the target writes. A
+//! thread for each pipe copies the output to the debugger's stdout or stderr
+//! and to the log file, if logging is on.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    os::unix::io::{AsRawFd, FromRawFd, OwnedFd},
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    unsafe { Ok((OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1]))) }
+}
+
+/// Pipes for the target's stdout and stderr, created before forking.
+pub struct OutputPipes {
+    stdout: (OwnedFd, OwnedFd),
+    stderr: (OwnedFd, OwnedFd),
+}
+
+impl OutputPipes {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            stdout: pipe()?,
+            stderr: pipe()?,
+        })
+    }
+
+    /// The write ends of the pipes, for the target's stdout and stderr.
+    ///
+    /// The read ends are close-on-exec so the target doesn't keep them open.
+    pub fn into_target_stdio(self) -> (Stdio, Stdio) {
+        (Stdio::from(self.stdout.1), Stdio::from(self.stderr.1))
+    }
+
+    /// Start copying the target's output.
+    ///
+    /// Closes the write ends of the pipes in the debugger, so the copying
+    /// stops once the target exits.
+    pub fn into_target_output(self) -> TargetOutput {
+        let log = Arc::new(Mutex::new(None));
+        let stdout = Arc::new(File::from(self.stdout.0));
+        let stderr = Arc::new(File::from(self.stderr.0));
+        let threads = vec![
+            spawn_pump(stdout.clone(), io::stdout(), log.clone()),
+            spawn_pump(stderr.clone(), io::stderr(), log.clone()),
+        ];
+        TargetOutput {
+            log,
+            log_path: None,
+            pipes: vec![stdout, stderr],
+            threads,
+        }
+    }
+}
+
+/// Block until a pipe has data (or is closed).
+fn wait_readable(pipe: &File) -> bool {
+    let mut pollfd = libc::pollfd {
+        fd: pipe.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    loop {
+        let r = unsafe { libc::poll(&mut pollfd, 1, -1) };
+        if r >= 0 {
+            return true;
+        }
+        if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            return false;
+        }
+    }
+}
+
+fn spawn_pump(
+    src: Arc<File>,
+    mut term: impl Write + Send + 'static,
+    log: Arc<Mutex<Option<File>>>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        // Wait for data without holding the lock, then read and copy it while
+        // holding the lock. This way whenever the lock is free and the pipe is
+        // empty, everything the target wrote has been copied (see
+        // TargetOutput::sync).
+        while wait_readable(&src) {
+            let mut log = log.lock().unwrap();
+            let n = match (&*src).read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            _ = term.write_all(&buf[..n]);
+            _ = term.flush();
+            if let Some(f) = log.as_mut() {
+                if let Err(err) = f.write_all(&buf[..n]) {
+                    eprintln!("could not write to log file: {err}");
+                    *log = None;
+                }
+            }
+        }
+    })
+}
+
+/// Bytes waiting to be read from a pipe.
+fn pending_bytes(pipe: &File) -> usize {
+    let mut n: libc::c_int = 0;
+    let r = unsafe { libc::ioctl(pipe.as_raw_fd(), libc::FIONREAD, &mut n) };
+    if r < 0 {
+        0
+    } else {
+        n as usize
+    }
+}
+
+/// The target's output, being copied to the terminal (and log file).
+pub struct TargetOutput {
+    log: Arc<Mutex<Option<File>>>,
+    log_path: Option<PathBuf>,
+    /// read ends of the pipes, shared with the copying threads
+    pipes: Vec<Arc<File>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl TargetOutput {
+    /// Start appending the target's output to a file.
+    pub fn start_logging(&mut self, path: &Path) -> io::Result<()> {
+        let f = OpenOptions::new().create(true).append(true).open(path)?;
+        *self.log.lock().unwrap() = Some(f);
+        self.log_path = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    pub fn stop_logging(&mut self) {
+        *self.log.lock().unwrap() = None;
+        self.log_path = None;
+    }
+
+    /// The file being logged to, if logging is on.
+    pub fn log_path(&self) -> Option<&Path> {
+        self.log_path.as_deref()
+    }
+
+    /// Wait until everything the target has written so far is copied.
+    ///
+    /// Called when the target stops so its output appears before the
+    /// debugger's report of the stop.
+    pub fn sync(&self) {
+        let deadline = Instant::now() + Duration::from_millis(100);
+        while Instant::now() < deadline {
+            let guard = self.log.lock().unwrap();
+            let pending: usize = self.pipes.iter().map(|p| pending_bytes(p)).sum();
+            drop(guard);
+            if pending == 0 {
+                return;
+            }
+            thread::yield_now();
+        }
+    }
+
+    /// Wait for the copying to finish after the target has exited.
+    pub fn finish(self) {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        // the target's children could still have the pipes open, so don't
+        // wait for them forever
+        while !self.threads.iter().all(|t| t.is_finished()) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
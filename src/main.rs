@@ -1,10 +1,98 @@
-use std::{env, process};
+use std::{
+    env, fs,
+    io::{self, IsTerminal},
+    process,
+};
 
-use rdb::{debugger, run_target};
+use rdb::{
+    attach_debugger, batch_debugger, debug_adapter, debugger, run_target, serve_target, OutputPipes,
+};
 
 fn main() {
     // skip the debugger in the arguments
-    let args: Vec<_> = env::args_os().skip(1).collect();
+    let mut args: Vec<_> = env::args_os().skip(1).collect();
+    // when to color source code: auto (if stdout is a terminal), always, or never
+    if args.first().is_some_and(|arg| arg == "--color") {
+        if args.len() < 2 {
+            eprintln!("--color needs auto, always, or never");
+            process::exit(1);
+        }
+        args.remove(0);
+        let on = match args.remove(0).to_str() {
+            Some("auto") => io::stdout().is_terminal(),
+            Some("always") => true,
+            Some("never") => false,
+            _ => {
+                eprintln!("--color needs auto, always, or never");
+                process::exit(1);
+            }
+        };
+        console::set_colors_enabled(on);
+    }
+    // report events as JSON, for tools (and tests) that read the output
+    let json = args.first().is_some_and(|arg| arg == "--json");
+    if json {
+        args.remove(0);
+    }
+    // serve an editor over the Debug Adapter Protocol on stdin and stdout
+    let dap = args.first().is_some_and(|arg| arg == "--dap");
+    if dap {
+        args.remove(0);
+    }
+    // run the commands in a file, then exit (`rdb --source FILE prog`)
+    let script = if args.first().is_some_and(|arg| arg == "--source") {
+        if args.len() < 2 {
+            eprintln!("--source needs a file of commands");
+            process::exit(1);
+        }
+        args.remove(0);
+        let path = args.remove(0);
+        match fs::read_to_string(&path) {
+            Ok(script) => Some(script),
+            Err(err) => {
+                eprintln!("could not read {}: {err}", path.to_string_lossy());
+                process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+    // commands to run before the prompt (`rdb -ex 'break main' -ex run prog`)
+    let mut commands = vec![];
+    while args.first().is_some_and(|arg| arg == "-ex") {
+        if args.len() < 2 {
+            eprintln!("-ex needs a command");
+            process::exit(1);
+        }
+        args.remove(0);
+        commands.push(args.remove(0).to_string_lossy().into_owned());
+    }
+    // serve the program to gdb (`rdb --server HOST:PORT prog`)
+    let server = if args.first().is_some_and(|arg| arg == "--server") {
+        if args.len() < 2 {
+            eprintln!("--server needs an address, like localhost:1234");
+            process::exit(1);
+        }
+        args.remove(0);
+        Some(args.remove(0).to_string_lossy().into_owned())
+    } else {
+        None
+    };
+    // debug a process that's already running (`rdb --pid PID`)
+    if args
+        .first()
+        .is_some_and(|arg| arg == "--pid" || arg == "--attach")
+    {
+        let pid = args.get(1).and_then(|pid| pid.to_str()?.parse().ok());
+        match pid {
+            Some(pid) => attach_debugger(pid, json, &commands),
+            None => {
+                eprintln!("--pid needs a process id");
+                process::exit(1);
+            }
+        }
+        return;
+    }
     if args.is_empty() {
         eprintln!("not enough arguments");
         process::exit(1);
@@ -12,16 +100,30 @@ fn main() {
     let prog = &args[0];
     let args = &args[1..];
 
+    let pipes = match OutputPipes::new() {
+        Ok(pipes) => pipes,
+        Err(err) => {
+            eprintln!("could not create pipes for program output: {err}");
+            process::exit(2);
+        }
+    };
+
     let pid = unsafe { libc::fork() };
     if pid < 0 {
         eprintln!("could not fork");
         process::exit(2);
     }
     if pid == 0 {
-        let err = run_target(prog, args);
-        eprintln!("could not execute program: {err}");
+        let err = run_target(prog, args, pipes);
+        eprintln!("could not start program: {err}");
         process::exit(2);
+    } else if dap {
+        debug_adapter(prog, args, pid, pipes)
+    } else if let Some(addr) = server {
+        serve_target(pid, pipes, &addr)
+    } else if let Some(script) = script {
+        batch_debugger(prog, args, pid, pipes, json, &script)
     } else {
-        debugger(prog.to_str().unwrap(), pid)
+        debugger(prog.to_str().unwrap(), args, pid, pipes, json, &commands)
     }
 }
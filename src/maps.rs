@@ -0,0 +1,86 @@
+//! Parse a process's memory mappings from `/proc/<pid>/maps`.
+
+use std::{fs, io};
+
+use libc::pid_t;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    pub start: u64,
+    pub end: u64,
+    /// permissions, like `r-xp`
+    pub perms: String,
+    /// offset into the mapped file
+    pub offset: u64,
+    /// the mapped file or a pseudo-path like `[stack]` (empty for anonymous
+    /// mappings)
+    pub path: String,
+}
+
+impl Mapping {
+    pub fn contains(&self, addr: u64) -> bool {
+        (self.start..self.end).contains(&addr)
+    }
+
+    pub fn name(&self) -> &str {
+        if self.path.is_empty() {
+            "[anonymous]"
+        } else {
+            &self.path
+        }
+    }
+}
+
+fn parse_line(line: &str) -> Option<Mapping> {
+    // start-end perms offset dev inode path
+    let mut fields = line.splitn(6, ' ');
+    let (start, end) = fields.next()?.split_once('-')?;
+    let perms = fields.next()?;
+    let offset = fields.next()?;
+    let _dev = fields.next()?;
+    let _inode = fields.next()?;
+    let path = fields.next().unwrap_or("").trim_start();
+    Some(Mapping {
+        start: u64::from_str_radix(start, 16).ok()?,
+        end: u64::from_str_radix(end, 16).ok()?,
+        perms: perms.to_string(),
+        offset: u64::from_str_radix(offset, 16).ok()?,
+        path: path.to_string(),
+    })
+}
+
+/// Parse the contents of a maps file, skipping any malformed lines.
+pub fn parse_maps(contents: &str) -> Vec<Mapping> {
+    contents.lines().filter_map(parse_line).collect()
+}
+
+pub fn read_maps(pid: pid_t) -> io::Result<Vec<Mapping>> {
+    let contents = fs::read_to_string(format!("/proc/{pid}/maps"))?;
+    Ok(parse_maps(&contents))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_maps;
+
+    #[test]
+    fn test_parse_maps() {
+        let maps = parse_maps(
+            "555555554000-555555568000 r--p 00000000 00:2a 1234      /root/target/debug/test
+555555568000-5555555bd000 r-xp 00014000 00:2a 1234      /root/target/debug/test
+7ffff7fc1000-7ffff7fc3000 rw-p 00000000 00:00 0
+7ffffffde000-7ffffffff000 rw-p 00000000 00:00 0                          [stack]
+bogus line",
+        );
+        assert_eq!(maps.len(), 4);
+        assert_eq!(maps[1].start, 0x555555568000);
+        assert_eq!(maps[1].end, 0x5555555bd000);
+        assert_eq!(maps[1].perms, "r-xp");
+        assert_eq!(maps[1].offset, 0x14000);
+        assert_eq!(maps[1].path, "/root/target/debug/test");
+        assert_eq!(maps[2].name(), "[anonymous]");
+        assert_eq!(maps[3].name(), "[stack]");
+        assert!(maps[3].contains(0x7ffffffde000));
+        assert!(!maps[3].contains(0x7ffffffff000));
+    }
+}
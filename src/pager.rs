@@ -0,0 +1,149 @@
+//! Page long command output, like gdb's `--More--` prompt.
+//!
+//! Commands that can print many lines (backtraces, register dumps, and the
+//! like) print through a [`Pager`], which counts the lines printed by the
+//! current command and waits for the user after each screenful.
+
+use std::{
+    cell::Cell,
+    io::{self, BufRead, Write},
+};
+
+/// What the user asked for at the `--More--` prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Paging,
+    /// print the rest of this command's output without stopping
+    NoPaging,
+    /// drop the rest of this command's output
+    Quit,
+}
+
+pub struct Pager {
+    /// the `set pagination` setting
+    enabled: Cell<bool>,
+    /// lines printed since the last prompt (or the start of the command)
+    lines: Cell<usize>,
+    state: Cell<State>,
+}
+
+/// The terminal's height in lines, if stdout is a terminal.
+fn terminal_height() -> Option<usize> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let r = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if r < 0 || size.ws_row == 0 {
+        return None;
+    }
+    Some(size.ws_row as usize)
+}
+
+impl Pager {
+    /// Create a pager, which is only on if the debugger is used interactively
+    /// from a terminal.
+    pub fn new() -> Self {
+        let interactive = unsafe {
+            libc::isatty(libc::STDIN_FILENO) == 1 && libc::isatty(libc::STDOUT_FILENO) == 1
+        };
+        Self {
+            enabled: Cell::new(interactive),
+            lines: Cell::new(0),
+            state: Cell::new(State::Paging),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    pub fn set_enabled(&self, on: bool) {
+        self.enabled.set(on);
+    }
+
+    /// Start paging the output of a new command.
+    pub fn reset(&self) {
+        self.lines.set(0);
+        self.state.set(State::Paging);
+    }
+
+    /// Check if the user asked to drop the rest of the output, so a command
+    /// can stop early.
+    pub fn quit(&self) -> bool {
+        self.state.get() == State::Quit
+    }
+
+    /// Check if a screenful (of height lines) has been printed, leaving a line
+    /// for the prompt.
+    fn screen_full(&self, height: usize) -> bool {
+        self.enabled.get() && self.state.get() == State::Paging && self.lines.get() + 1 >= height
+    }
+
+    /// Handle the user's answer to the prompt.
+    fn respond(&self, answer: &str) {
+        let state = match answer.trim() {
+            "q" => State::Quit,
+            "c" => State::NoPaging,
+            _ => State::Paging,
+        };
+        self.state.set(state);
+        self.lines.set(0);
+    }
+
+    /// Print a line of output, first waiting for the user if a screenful has
+    /// been printed.
+    pub fn println(&self, line: &str) {
+        if let Some(height) = terminal_height() {
+            if self.screen_full(height) {
+                print!("--Type <RET> for more, q to quit, c to continue without paging--");
+                _ = io::stdout().flush();
+                let mut answer = String::new();
+                // on EOF there's no one to page for
+                match io::stdin().lock().read_line(&mut answer) {
+                    Ok(0) | Err(_) => self.respond("c"),
+                    Ok(_) => self.respond(&answer),
+                }
+            }
+        }
+        if self.quit() {
+            return;
+        }
+        println!("{line}");
+        self.lines.set(self.lines.get() + 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pager;
+
+    fn pager() -> Pager {
+        let pager = Pager::new();
+        pager.set_enabled(true);
+        pager
+    }
+
+    #[test]
+    fn test_screen_full() {
+        let pager = pager();
+        for _ in 0..3 {
+            assert!(!pager.screen_full(4));
+            pager.lines.set(pager.lines.get() + 1);
+        }
+        assert!(pager.screen_full(4));
+        pager.respond("\n");
+        assert!(!pager.screen_full(4));
+
+        pager.lines.set(3);
+        pager.respond("c\n");
+        pager.lines.set(10);
+        assert!(!pager.screen_full(4));
+
+        pager.respond("q\n");
+        assert!(pager.quit());
+        pager.reset();
+        assert!(!pager.quit());
+
+        pager.set_enabled(false);
+        pager.lines.set(10);
+        assert!(!pager.screen_full(4));
+    }
+}
@@ -0,0 +1,52 @@
+//! Read what a process is running from `/proc/<pid>`.
+
+use std::{fs, io, path::PathBuf};
+
+use libc::pid_t;
+
+/// Split the contents of `/proc/<pid>/cmdline`, where each argument ends with
+/// a NUL.
+fn parse_cmdline(contents: &[u8]) -> Vec<String> {
+    contents
+        .strip_suffix(b"\0")
+        .unwrap_or(contents)
+        .split(|&b| b == 0)
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect()
+}
+
+/// The process's command line, as it was executed.
+pub fn read_cmdline(pid: pid_t) -> io::Result<Vec<String>> {
+    let contents = fs::read(format!("/proc/{pid}/cmdline"))?;
+    if contents.is_empty() {
+        // zombies (and kernel threads) have no command line
+        return Ok(vec![]);
+    }
+    Ok(parse_cmdline(&contents))
+}
+
+/// The path of the executable the process is running.
+pub fn read_exe(pid: pid_t) -> io::Result<PathBuf> {
+    fs::read_link(format!("/proc/{pid}/exe"))
+}
+
+/// Quote a command line so it can be pasted into a shell.
+pub fn quote_args(args: &[String]) -> String {
+    let quoted: Vec<_> = args
+        .iter()
+        .map(|a| shlex::try_quote(a).unwrap_or_else(|_| a.into()))
+        .collect();
+    quoted.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_cmdline, quote_args};
+
+    #[test]
+    fn test_parse_cmdline() {
+        let args = parse_cmdline(b"/bin/prog\0foo\0bar baz\0\0");
+        assert_eq!(args, vec!["/bin/prog", "foo", "bar baz", ""]);
+        assert_eq!(quote_args(&args), "/bin/prog foo 'bar baz' ''");
+    }
+}
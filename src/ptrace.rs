@@ -1,10 +1,31 @@
 use enum_iterator::{all, Sequence};
-use std::{fmt::Display, io, mem::MaybeUninit};
-
-use libc::{c_long, c_uint, pid_t, user_regs_struct};
+use std::{
+    fmt::Display,
+    fs::File,
+    io,
+    mem::{self, MaybeUninit},
+    os::unix::fs::FileExt,
+};
+
+use libc::{c_long, c_uint, pid_t, user_fpregs_struct, user_regs_struct};
+
+pub fn trace_me() -> Result<()> {
+    let r = unsafe { libc::ptrace(libc::PTRACE_TRACEME) };
+    errno_result(r)
+}
 
-pub fn trace_me() {
-    unsafe { libc::ptrace(libc::PTRACE_TRACEME) };
+/// Explain a ptrace error, with a suggestion for the common causes.
+pub fn describe_error(err: &io::Error) -> String {
+    match err.raw_os_error() {
+        Some(libc::EPERM) => format!(
+            "{err}: tracing may be blocked by Yama (check /proc/sys/kernel/yama/ptrace_scope), \
+            by a seccomp policy, or may need CAP_SYS_PTRACE"
+        ),
+        Some(libc::ESRCH) | Some(libc::ECHILD) => {
+            format!("{err}: the program is gone (it may have already exited)")
+        }
+        _ => err.to_string(),
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -49,6 +70,35 @@ impl From<libc::c_int> for WaitStatus {
     }
 }
 
+/// How a thread changed state, from waiting on it with `__WALL`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ThreadStatus {
+    Wait(WaitStatus),
+    /// stopped creating a new thread, which is traced and starts out
+    /// stopped (with PTRACE_O_TRACECLONE)
+    Cloned(Target),
+}
+
+impl ThreadStatus {
+    /// Interpret a status from waitpid for thread.
+    fn new(thread: Target, stat_val: libc::c_int) -> Result<Self> {
+        if libc::WIFSTOPPED(stat_val) && stat_val >> 16 == libc::PTRACE_EVENT_CLONE {
+            let tid = thread.geteventmsg()?;
+            return Ok(ThreadStatus::Cloned(Target(tid as pid_t)));
+        }
+        Ok(ThreadStatus::Wait(stat_val.into()))
+    }
+}
+
+/// Wait for any thread of any traced process to change state.
+pub fn wait_any_thread() -> Result<(Target, ThreadStatus)> {
+    let mut status = 0;
+    let r = unsafe { libc::waitpid(-1, &mut status, libc::__WALL) };
+    errno_result(r as i64)?;
+    let thread = Target(r);
+    Ok((thread, ThreadStatus::new(thread, status)?))
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Sequence)]
 #[allow(non_camel_case_types)]
 pub enum Reg {
@@ -89,29 +139,102 @@ impl Reg {
     }
 }
 
+impl Reg {
+    /// The register's DWARF number, from the x86-64 System V psABI. Number 16
+    /// is the return address, which is rip. orig_rax isn't a real register,
+    /// so it has no number.
+    pub fn dwarf_number(&self) -> Option<u16> {
+        let n = match self {
+            Reg::Rax => 0,
+            Reg::Rdx => 1,
+            Reg::Rcx => 2,
+            Reg::Rbx => 3,
+            Reg::Rsi => 4,
+            Reg::Rdi => 5,
+            Reg::Rbp => 6,
+            Reg::Rsp => 7,
+            Reg::R8 => 8,
+            Reg::R9 => 9,
+            Reg::R10 => 10,
+            Reg::R11 => 11,
+            Reg::R12 => 12,
+            Reg::R13 => 13,
+            Reg::R14 => 14,
+            Reg::R15 => 15,
+            Reg::Rip => 16,
+            Reg::Rflags => 49,
+            Reg::Es => 50,
+            Reg::Cs => 51,
+            Reg::Ss => 52,
+            Reg::Ds => 53,
+            Reg::Fs => 54,
+            Reg::Gs => 55,
+            Reg::Fs_base => 58,
+            Reg::Gs_base => 59,
+            Reg::Orig_rax => return None,
+        };
+        Some(n)
+    }
+
+    /// Get the register with a DWARF register number.
+    pub fn from_dwarf(n: u16) -> Option<Reg> {
+        all::<Reg>().find(|r| r.dwarf_number() == Some(n))
+    }
+}
+
+/// Decode rflags into the names of the flags that are set, like
+/// `[ PF ZF IF ]`.
+pub fn decode_rflags(flags: u64) -> String {
+    const FLAGS: [(u32, &str); 16] = [
+        (0, "CF"),
+        (2, "PF"),
+        (4, "AF"),
+        (6, "ZF"),
+        (7, "SF"),
+        (8, "TF"),
+        (9, "IF"),
+        (10, "DF"),
+        (11, "OF"),
+        (14, "NT"),
+        (16, "RF"),
+        (17, "VM"),
+        (18, "AC"),
+        (19, "VIF"),
+        (20, "VIP"),
+        (21, "ID"),
+    ];
+    let mut items: Vec<String> = FLAGS
+        .iter()
+        .filter(|&&(bit, _)| flags & (1 << bit) != 0)
+        .map(|&(_, name)| name.to_string())
+        .collect();
+    let iopl = (flags >> 12) & 3;
+    if iopl != 0 {
+        items.push(format!("IOPL={iopl}"));
+    }
+    if items.is_empty() {
+        return "[ ]".to_string();
+    }
+    format!("[ {} ]", items.join(" "))
+}
+
 impl TryFrom<&str> for Reg {
     type Error = String;
 
     fn try_from(s: &str) -> std::result::Result<Self, Self::Error> {
+        // gdb's architecture-independent aliases
+        match s {
+            "pc" => return Ok(Reg::Rip),
+            "sp" => return Ok(Reg::Rsp),
+            "fp" => return Ok(Reg::Rbp),
+            _ => {}
+        }
         all::<Reg>()
             .find(|r| r.name() == s)
             .ok_or_else(|| "invalid register name".to_string())
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::Reg;
-
-    #[test]
-    fn test_register_parse() {
-        assert_eq!(Ok(Reg::Rbp), Reg::try_from("rbp"));
-        assert_eq!(Ok(Reg::Orig_rax), Reg::try_from("orig_rax"));
-        assert!(Reg::try_from("Rax").is_err());
-        assert!(Reg::try_from("ebp").is_err());
-    }
-}
-
 impl Reg {
     fn user_regs_ptr<'a>(&self, regs: &'a mut user_regs_struct) -> &'a mut u64 {
         match self {
@@ -176,6 +299,9 @@ fn errno_result(r: c_long) -> Result<()> {
     }
 }
 
+// from asm/ptrace-abi.h (not exposed by libc)
+const PTRACE_SINGLEBLOCK: c_uint = 33;
+
 fn ptrace(req: c_uint, pid: pid_t, addr: usize, data: usize) -> Result<()> {
     let r = unsafe { libc::ptrace(req, pid, addr, data) };
     errno_result(r)
@@ -186,6 +312,13 @@ impl Target {
         Self(pid)
     }
 
+    /// Start tracing a running process, which stops it (the caller still
+    /// needs to wait for the stop).
+    pub fn attach(pid: pid_t) -> Result<Self> {
+        ptrace(libc::PTRACE_ATTACH, pid, 0, 0)?;
+        Ok(Self(pid))
+    }
+
     pub fn kill(self) -> Result<()> {
         let r = unsafe { libc::kill(self.0, libc::SIGKILL) };
         errno_result(r as c_long)
@@ -195,22 +328,116 @@ impl Target {
         ptrace(req, self.0, addr, data)
     }
 
+    /// Stop tracing the target, letting it run.
+    pub fn detach(&self) -> Result<()> {
+        self.ptrace(libc::PTRACE_DETACH, 0, 0)
+    }
+
     pub fn cont(&self, signal: c_uint) -> Result<()> {
         self.ptrace(libc::PTRACE_CONT, 0, signal as usize)
     }
 
+    pub fn setoptions(&self, options: libc::c_int) -> Result<()> {
+        self.ptrace(libc::PTRACE_SETOPTIONS, 0, options as usize)
+    }
+
+    /// Get the message for the last ptrace event, like a new thread's id.
+    fn geteventmsg(&self) -> Result<u64> {
+        let mut msg: libc::c_ulong = 0;
+        let data = &mut msg as *mut libc::c_ulong as usize;
+        self.ptrace(libc::PTRACE_GETEVENTMSG, 0 /* addr is ignored */, data)?;
+        Ok(msg)
+    }
+
+    /// Send a signal to just this thread, rather than the whole process.
+    pub fn tkill(&self, signal: libc::c_int) -> Result<()> {
+        let r = unsafe { libc::syscall(libc::SYS_tkill, self.0, signal) };
+        errno_result(r)
+    }
+
     pub fn peekdata(&self, addr: u64) -> Result<u64> {
         // need to do everything manually since the return value does not signal
         // errno (it could be -1 as actual data)
         clear_errno();
         let data = unsafe { libc::ptrace(libc::PTRACE_PEEKDATA, self.0, addr) as u64 };
         let err = get_errno();
-        if err < 0 {
+        if err != 0 {
+            return Err(io::Error::from_raw_os_error(err));
+        }
+        Ok(data)
+    }
+
+    fn peekuser(&self, offset: usize) -> Result<u64> {
+        // like peekdata, errors are only signalled through errno
+        clear_errno();
+        let data = unsafe { libc::ptrace(libc::PTRACE_PEEKUSER, self.0, offset) as u64 };
+        let err = get_errno();
+        if err != 0 {
             return Err(io::Error::from_raw_os_error(err));
         }
         Ok(data)
     }
 
+    fn debugreg_offset(n: usize) -> usize {
+        mem::offset_of!(libc::user, u_debugreg) + n * 8
+    }
+
+    /// Read debug register DR`n`.
+    pub fn get_debugreg(&self, n: usize) -> Result<u64> {
+        self.peekuser(Self::debugreg_offset(n))
+    }
+
+    /// Write debug register DR`n`.
+    pub fn set_debugreg(&self, n: usize, val: u64) -> Result<()> {
+        self.ptrace(
+            libc::PTRACE_POKEUSER,
+            Self::debugreg_offset(n),
+            val as usize,
+        )
+    }
+
+    /// Read `len` bytes of the target's memory starting at `addr`.
+    ///
+    /// Reads in one process_vm_readv call, falling back to PEEKDATA a word at
+    /// a time if that isn't available or only part of the range is read.
+    pub fn read_mem(&self, addr: u64, len: usize) -> Result<Vec<u8>> {
+        let mut buf = vec![0u8; len];
+        let local = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: len,
+        };
+        let remote = libc::iovec {
+            iov_base: addr as *mut libc::c_void,
+            iov_len: len,
+        };
+        let r = unsafe { libc::process_vm_readv(self.0, &local, 1, &remote, 1, 0) };
+        let done = if r < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::ENOSYS) {
+                return Err(err);
+            }
+            0
+        } else {
+            r as usize
+        };
+        let mut pos = done;
+        while pos < len {
+            let word = self.peekdata(addr + pos as u64)?.to_le_bytes();
+            let n = (len - pos).min(word.len());
+            buf[pos..pos + n].copy_from_slice(&word[..n]);
+            pos += n;
+        }
+        Ok(buf)
+    }
+
+    /// Write bytes into the target's memory starting at `addr`.
+    pub fn write_mem(&self, addr: u64, data: &[u8]) -> Result<()> {
+        let mem = File::options()
+            .write(true)
+            .open(format!("/proc/{}/mem", self.0))?;
+        mem.write_all_at(data, addr)
+    }
+
     pub fn pokedata(&self, addr: u64, data: u64) -> Result<()> {
         self.ptrace(libc::PTRACE_POKEDATA, addr as usize, data as usize)
     }
@@ -222,6 +449,15 @@ impl Target {
         Ok(status.into())
     }
 
+    /// Wait for this thread, which (unlike with [`Target::wait`]) doesn't
+    /// have to be the process's main thread.
+    pub fn wait_thread(&self) -> Result<ThreadStatus> {
+        let mut status = 0;
+        let r = unsafe { libc::waitpid(self.0, &mut status, libc::__WALL) };
+        errno_result(r as i64)?;
+        ThreadStatus::new(*self, status)
+    }
+
     pub fn getregs(&self) -> Result<user_regs_struct> {
         let mut regs = MaybeUninit::<user_regs_struct>::uninit();
         let data = regs.as_mut_ptr() as usize;
@@ -234,7 +470,30 @@ impl Target {
         Ok(r.get_reg(&regs))
     }
 
-    fn setregs(&self, regs: &user_regs_struct) -> Result<()> {
+    pub fn getfpregs(&self) -> Result<user_fpregs_struct> {
+        let mut regs = MaybeUninit::<user_fpregs_struct>::uninit();
+        let data = regs.as_mut_ptr() as usize;
+        self.ptrace(libc::PTRACE_GETFPREGS, 0 /* addr is ignored */, data)?;
+        unsafe { Ok(regs.assume_init()) }
+    }
+
+    /// Get the XSAVE area, with the extended (AVX and later) register state.
+    pub fn getxstate(&self) -> Result<Vec<u8>> {
+        // from elf.h (not exposed by libc)
+        const NT_X86_XSTATE: usize = 0x202;
+        // larger than any XSAVE area, which the kernel truncates to fit
+        let mut buf = vec![0u8; 8192];
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        let data = &mut iov as *mut libc::iovec as usize;
+        self.ptrace(libc::PTRACE_GETREGSET, NT_X86_XSTATE, data)?;
+        buf.truncate(iov.iov_len);
+        Ok(buf)
+    }
+
+    pub fn setregs(&self, regs: &user_regs_struct) -> Result<()> {
         let data = regs as *const user_regs_struct as usize;
         self.ptrace(libc::PTRACE_SETREGS, 0 /* addr is ignored  */, data)
     }
@@ -245,11 +504,20 @@ impl Target {
         self.setregs(&regs)
     }
 
-    pub fn singlestep(&self) -> Result<()> {
+    /// Resume the target until it takes a branch.
+    ///
+    /// This uses the x86 branch trap flag (BTF), which the kernel sets in the
+    /// IA32_DEBUGCTL MSR along with the trap flag. If the CPU (or hypervisor)
+    /// doesn't support BTF this behaves like a single step.
+    pub fn singleblock(&self, signal: c_uint) -> Result<()> {
+        self.ptrace(PTRACE_SINGLEBLOCK, 0 /* ignored */, signal as usize)
+    }
+
+    pub fn singlestep(&self, signal: c_uint) -> Result<()> {
         self.ptrace(
             libc::PTRACE_SINGLESTEP,
             0, /* ignored */
-            0, /* ignored */
+            signal as usize,
         )
     }
 
@@ -260,3 +528,81 @@ impl Target {
         unsafe { Ok(info.assume_init()) }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use enum_iterator::all;
+
+    use super::{decode_rflags, describe_error, Reg};
+
+    #[test]
+    fn test_register_names() {
+        assert_eq!(Reg::try_from("rip"), Ok(Reg::Rip));
+        assert_eq!(Reg::try_from("pc"), Ok(Reg::Rip));
+        assert_eq!(Reg::try_from("sp"), Ok(Reg::Rsp));
+        assert_eq!(Reg::try_from("fp"), Ok(Reg::Rbp));
+        assert_eq!(Reg::try_from("orig_rax"), Ok(Reg::Orig_rax));
+        assert!(Reg::try_from("eax").is_err());
+    }
+
+    #[test]
+    fn test_decode_rflags() {
+        // bit 1 is reserved and always set
+        assert_eq!(decode_rflags(0x2), "[ ]");
+        // after comparing equal values
+        assert_eq!(decode_rflags(0x246), "[ PF ZF IF ]");
+        assert_eq!(decode_rflags(0x3297), "[ CF PF AF SF IF IOPL=3 ]");
+        assert_eq!(decode_rflags(0x200a81), "[ CF SF IF OF ID ]");
+    }
+
+    #[test]
+    fn test_dwarf_numbers() {
+        let general = [
+            Reg::Rax,
+            Reg::Rdx,
+            Reg::Rcx,
+            Reg::Rbx,
+            Reg::Rsi,
+            Reg::Rdi,
+            Reg::Rbp,
+            Reg::Rsp,
+            Reg::R8,
+            Reg::R9,
+            Reg::R10,
+            Reg::R11,
+            Reg::R12,
+            Reg::R13,
+            Reg::R14,
+            Reg::R15,
+            Reg::Rip,
+        ];
+        for (n, reg) in general.into_iter().enumerate() {
+            assert_eq!(reg.dwarf_number(), Some(n as u16));
+            assert_eq!(Reg::from_dwarf(n as u16), Some(reg));
+        }
+        for reg in all::<Reg>() {
+            if let Some(n) = reg.dwarf_number() {
+                assert_eq!(Reg::from_dwarf(n), Some(reg), "{} isn't unique", reg.name());
+            }
+        }
+        assert_eq!(Reg::from_dwarf(17), None);
+    }
+
+    #[test]
+    fn test_register_parse() {
+        assert_eq!(Ok(Reg::Rbp), Reg::try_from("rbp"));
+        assert_eq!(Ok(Reg::Orig_rax), Reg::try_from("orig_rax"));
+        assert!(Reg::try_from("Rax").is_err());
+        assert!(Reg::try_from("ebp").is_err());
+    }
+
+    #[test]
+    fn test_describe_error() {
+        let eperm = io::Error::from_raw_os_error(libc::EPERM);
+        assert!(describe_error(&eperm).contains("ptrace_scope"));
+        let esrch = io::Error::from_raw_os_error(libc::ESRCH);
+        assert!(describe_error(&esrch).contains("already exited"));
+    }
+}
@@ -1,29 +1,82 @@
+//! Print source code
+
 use std::{
     fs::File,
     io::{self, BufRead},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
-/// Print source code
+use console::style;
+
+/// Rust keywords, highlighted when printing source in color
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while",
+];
+
+/// Color the keywords and comments in a line of source, if colors are on.
+fn highlight(line: &str) -> String {
+    if !console::colors_enabled() {
+        return line.to_string();
+    }
+    let (code, comment) = match line.find("//") {
+        Some(i) => line.split_at(i),
+        None => (line, ""),
+    };
+    let mut out = String::new();
+    let mut word = String::new();
+    for c in code.chars() {
+        if c.is_alphanumeric() || c == '_' {
+            word.push(c);
+        } else {
+            push_word(&mut out, &mut word);
+            out.push(c);
+        }
+    }
+    push_word(&mut out, &mut word);
+    if !comment.is_empty() {
+        out.push_str(&style(comment).dim().to_string());
+    }
+    out
+}
+
+/// Move a word onto the highlighted output, coloring it if it's a keyword.
+fn push_word(out: &mut String, word: &mut String) {
+    if KEYWORDS.contains(&word.as_str()) {
+        out.push_str(&style(&word).magenta().to_string());
+    } else {
+        out.push_str(word);
+    }
+    word.clear();
+}
+
+/// Print one numbered line of source, marking it with `>` if it's the current
+/// line.
+fn print_line(num: usize, width: usize, current: bool, text: &str) {
+    if current {
+        let line = format!("{num:>width$} >  {text}");
+        println!("{}", style(line).bold());
+    } else {
+        let num = style(format!("{num:>width$}")).dim();
+        println!("{num}    {}", highlight(text));
+    }
+}
 
 fn try_print_source<P: AsRef<Path>>(path: P, line: usize, context: usize) -> Result<(), io::Error> {
     let path = path.as_ref();
-    let lineno = line as isize;
-    let context = context as isize;
-    let mut curr: isize = 1;
     let f = File::open(path)?;
     let f = io::BufReader::new(f);
     println!("{}:", path.display());
-    for line in f.lines() {
-        let line = line?;
-        if lineno - context <= curr && curr <= lineno + context {
-            let cursor = if lineno == curr { ">" } else { " " };
-            println!("{}  {}", cursor, line);
-        }
-        if curr > lineno + context {
-            break;
-        }
-        curr += 1;
+    let start = line.saturating_sub(context).max(1);
+    let width = (line + context).to_string().len();
+    for (curr, text) in (1..)
+        .zip(f.lines())
+        .skip(start - 1)
+        .take(line + context + 1 - start)
+    {
+        print_line(curr, width, curr == line, &text?);
     }
     Ok(())
 }
@@ -35,8 +88,113 @@ pub fn print_source<P: AsRef<Path>>(path: P, line: usize, context: usize) {
     }
 }
 
-pub fn print_source_loc(loc: &addr2line::Location, context: usize) {
-    let path = loc.file.unwrap();
-    let line = loc.line.unwrap() as usize;
-    print_source(path, line, context);
+/// Read lines `start..=end` of a file (numbered from 1), stopping early at
+/// the end of the file.
+pub fn read_lines<P: AsRef<Path>>(
+    path: P,
+    start: usize,
+    end: usize,
+) -> Result<Vec<String>, io::Error> {
+    let f = io::BufReader::new(File::open(path)?);
+    f.lines().skip(start - 1).take(end + 1 - start).collect()
+}
+
+/// Print lines of source numbered from start, with the numbers as wide as
+/// end's.
+pub fn print_numbered_lines(start: usize, end: usize, lines: &[String]) {
+    let width = end.to_string().len();
+    for (curr, text) in (start..).zip(lines) {
+        print_line(curr, width, false, text);
+    }
+}
+
+/// Find a source file from the debug info on this machine, trying the path
+/// rewritten by each matching substitution, then the path as is, then the
+/// path relative to the compilation directory and the bare file name in each
+/// search directory. A relative path is relative to the compilation
+/// directory, not rdb's working directory.
+///
+/// If none of these exist, returns the first one tried, for error messages.
+pub fn find_source(
+    path: &Path,
+    comp_dir: Option<&str>,
+    substitutions: &[(String, String)],
+    dirs: &[PathBuf],
+) -> PathBuf {
+    let joined;
+    let path = match comp_dir {
+        Some(dir) if path.is_relative() => {
+            joined = Path::new(dir).join(path);
+            &joined
+        }
+        _ => path,
+    };
+    let mut candidates = vec![];
+    for (from, to) in substitutions {
+        if let Ok(rest) = path.strip_prefix(from) {
+            candidates.push(Path::new(to).join(rest));
+        }
+    }
+    candidates.push(path.to_path_buf());
+    let relative = comp_dir.and_then(|dir| path.strip_prefix(dir).ok());
+    for dir in dirs {
+        if let Some(relative) = relative {
+            candidates.push(dir.join(relative));
+        }
+        if let Some(name) = path.file_name() {
+            candidates.push(dir.join(name));
+        }
+    }
+    candidates
+        .iter()
+        .find(|path| path.exists())
+        .unwrap_or(&candidates[0])
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+
+    #[test]
+    fn test_find_source() {
+        let dir = env::temp_dir().join(format!("rdb-find-source-{}", std::process::id()));
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src/main.rs"), "fn main() {}\n").unwrap();
+        fs::write(dir.join("lib.rs"), "\n").unwrap();
+        let built = Path::new("/build/proj/src/main.rs");
+        let found = dir.join("src/main.rs");
+        let dir_str = dir.to_str().unwrap().to_string();
+
+        // a missing file is returned as is
+        assert_eq!(find_source(built, None, &[], &[]), built);
+        let subst = [("/build/proj".to_string(), dir_str.clone())];
+        assert_eq!(find_source(built, None, &subst, &[]), found);
+        // only whole path components match
+        let subst = [("/build/pro".to_string(), dir_str.clone())];
+        assert_eq!(find_source(built, None, &subst, &[]), built);
+        // a rewritten path is reported even if it doesn't exist
+        let subst = [("/build".to_string(), "/elsewhere".to_string())];
+        assert_eq!(
+            find_source(built, None, &subst, &[]),
+            Path::new("/elsewhere/proj/src/main.rs")
+        );
+
+        let dirs = [dir.clone()];
+        assert_eq!(find_source(built, Some("/build/proj"), &[], &dirs), found);
+        let built = Path::new("/build/proj/lib.rs");
+        assert_eq!(find_source(built, None, &[], &dirs), dir.join("lib.rs"));
+
+        // relative paths are relative to the compilation directory
+        let relative = Path::new("src/main.rs");
+        assert_eq!(find_source(relative, Some(&dir_str), &[], &[]), found);
+        let subst = [("/build/proj".to_string(), dir_str.clone())];
+        assert_eq!(
+            find_source(relative, Some("/build/proj"), &subst, &[]),
+            found
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
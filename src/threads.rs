@@ -0,0 +1,395 @@
+//! Track the target's threads, and stop and resume them together.
+//!
+//! Threads the target creates are traced automatically (with
+//! PTRACE_O_TRACECLONE). The debugger only looks at the target while every
+//! thread is stopped: when one thread stops, the others are stopped with a
+//! SIGSTOP, which is hidden from the debugger when it arrives. Resuming can
+//! either run every thread or just one, which is how scheduler-locking keeps
+//! the other threads from running during a step.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs, io,
+};
+
+use libc::{c_uint, pid_t};
+
+use crate::debugger::TRAP_TRACE;
+use crate::ptrace::{self, Target, ThreadStatus, WaitStatus};
+
+/// How to resume a thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    Continue,
+    Step,
+    /// run until the next branch
+    Block,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// traced, but hasn't reported the stop it starts with yet
+    Starting,
+    Stopped,
+    Running(Resume),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Thread {
+    state: State,
+    /// a SIGSTOP was sent to stop the thread that it hasn't reported yet
+    stop_sent: bool,
+}
+
+pub struct Threads {
+    /// the process's pid, which is also the id of its main thread
+    pid: pid_t,
+    threads: BTreeMap<pid_t, Thread>,
+    /// stops that threads reported while the other threads were being
+    /// stopped, which are reported before anything runs again
+    pending: VecDeque<(Target, WaitStatus)>,
+    /// whether threads other than the resumed one are running, which new
+    /// threads should join
+    others_running: bool,
+    /// the debug registers set in every thread, to copy into new ones
+    debugregs: BTreeMap<usize, u64>,
+}
+
+impl Threads {
+    /// Track a process that was just traced, whose main thread is stopped.
+    pub fn new(pid: pid_t) -> Self {
+        let main = Thread {
+            state: State::Stopped,
+            stop_sent: false,
+        };
+        Self {
+            pid,
+            threads: BTreeMap::from([(pid, main)]),
+            pending: VecDeque::new(),
+            others_running: false,
+            debugregs: BTreeMap::new(),
+        }
+    }
+
+    /// The process's main thread, whose id is the pid.
+    pub fn main(&self) -> Target {
+        Target::new(self.pid)
+    }
+
+    /// Trace the threads the process creates from now on.
+    pub fn trace_clones(&self) -> io::Result<()> {
+        self.main().setoptions(libc::PTRACE_O_TRACECLONE)
+    }
+
+    /// Attach to the threads of a process that already existed when the
+    /// debugger attached to its main thread.
+    pub fn attach_existing(&mut self) -> io::Result<()> {
+        self.trace_clones()?;
+        // a thread could create another one before it's attached to, so look
+        // again until there are no new threads
+        loop {
+            let mut found = false;
+            for entry in fs::read_dir(format!("/proc/{}/task", self.pid))? {
+                let tid = match entry?.file_name().to_str().map(str::parse) {
+                    Some(Ok(tid)) => tid,
+                    _ => continue,
+                };
+                if self.threads.contains_key(&tid) {
+                    continue;
+                }
+                // the thread may have exited since the directory was read
+                let Ok(thread) = Target::attach(tid) else {
+                    continue;
+                };
+                found = true;
+                self.threads.insert(
+                    tid,
+                    Thread {
+                        state: State::Running(Resume::Continue),
+                        stop_sent: true,
+                    },
+                );
+                self.collect_stop(tid)?;
+                if self.threads.contains_key(&tid) {
+                    thread.setoptions(libc::PTRACE_O_TRACECLONE)?;
+                }
+            }
+            if !found {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Resume the current thread, and the others too if `others` is set.
+    ///
+    /// Returns false without resuming anything if some thread already has a
+    /// stop to report, which the next [`Threads::wait`] returns.
+    pub fn resume(
+        &mut self,
+        current: Target,
+        how: Resume,
+        signal: c_uint,
+        others: bool,
+    ) -> io::Result<bool> {
+        if !self.pending.is_empty() {
+            return Ok(false);
+        }
+        self.others_running = others;
+        if others {
+            let stopped: Vec<pid_t> = self
+                .threads
+                .iter()
+                .filter(|&(&tid, t)| tid != current.pid() && t.state == State::Stopped)
+                .map(|(&tid, _)| tid)
+                .collect();
+            for tid in stopped {
+                self.resume_thread(tid, Resume::Continue, 0)?;
+            }
+        }
+        self.resume_thread(current.pid(), how, signal)?;
+        Ok(true)
+    }
+
+    /// Single-step the current thread with the others stopped, and wait for
+    /// it. Any stops the other threads already have stay pending.
+    pub fn step_alone(
+        &mut self,
+        current: Target,
+        signal: c_uint,
+    ) -> io::Result<(Target, WaitStatus)> {
+        self.others_running = false;
+        self.resume_thread(current.pid(), Resume::Step, signal)?;
+        self.wait_running()
+    }
+
+    fn resume_thread(&mut self, tid: pid_t, how: Resume, signal: c_uint) -> io::Result<()> {
+        let thread = Target::new(tid);
+        match how {
+            Resume::Continue => thread.cont(signal)?,
+            Resume::Step => thread.singlestep(signal)?,
+            Resume::Block => thread.singleblock(signal)?,
+        }
+        if let Some(t) = self.threads.get_mut(&tid) {
+            t.state = State::Running(how);
+        }
+        Ok(())
+    }
+
+    /// Wait for a thread to stop, then stop all the others. Returns the
+    /// thread and how it stopped; if the main thread exited, so did the
+    /// process.
+    pub fn wait(&mut self) -> io::Result<(Target, WaitStatus)> {
+        if let Some(stop) = self.pending.pop_front() {
+            return Ok(stop);
+        }
+        self.wait_running()
+    }
+
+    fn wait_running(&mut self) -> io::Result<(Target, WaitStatus)> {
+        loop {
+            let (thread, status) = ptrace::wait_any_thread()?;
+            let tid = thread.pid();
+            let Some(&t) = self.threads.get(&tid) else {
+                // a new thread can report its first stop before its creation
+                // is reported
+                if status
+                    == ThreadStatus::Wait(WaitStatus::Stopped {
+                        signal: libc::SIGSTOP,
+                    })
+                {
+                    self.add_new(thread);
+                    self.started(tid)?;
+                }
+                continue;
+            };
+            match status {
+                ThreadStatus::Cloned(new) => {
+                    self.add_new(new);
+                    if let State::Running(how) = t.state {
+                        self.resume_thread(tid, how, 0)?;
+                    }
+                }
+                ThreadStatus::Wait(WaitStatus::Stopped {
+                    signal: libc::SIGSTOP,
+                }) if t.state == State::Starting => self.started(tid)?,
+                ThreadStatus::Wait(WaitStatus::Stopped {
+                    signal: libc::SIGSTOP,
+                }) if t.stop_sent => {
+                    // a SIGSTOP left over from stopping the thread earlier
+                    self.threads.get_mut(&tid).unwrap().stop_sent = false;
+                    if let State::Running(how) = t.state {
+                        self.resume_thread(tid, how, 0)?;
+                    }
+                }
+                ThreadStatus::Wait(WaitStatus::Exited { .. } | WaitStatus::Signaled { .. })
+                    if tid != self.pid =>
+                {
+                    self.threads.remove(&tid);
+                    // if that was the only thread running (because of
+                    // scheduler-locking), let the rest run rather than
+                    // waiting forever
+                    let running = self
+                        .threads
+                        .values()
+                        .any(|t| matches!(t.state, State::Running(_)));
+                    if !running {
+                        if let Some(stop) = self.pending.pop_front() {
+                            return Ok(stop);
+                        }
+                        let main = self.main();
+                        self.resume(main, Resume::Continue, 0, true)?;
+                    }
+                }
+                ThreadStatus::Wait(status) => {
+                    if matches!(
+                        status,
+                        WaitStatus::Exited { .. } | WaitStatus::Signaled { .. }
+                    ) {
+                        // the main thread exits last, so the process is gone
+                        self.threads.clear();
+                        self.pending.clear();
+                        return Ok((thread, status));
+                    }
+                    self.threads.get_mut(&tid).unwrap().state = State::Stopped;
+                    self.stop_others()?;
+                    return Ok((thread, status));
+                }
+            }
+        }
+    }
+
+    /// Track a thread the target created.
+    fn add_new(&mut self, thread: Target) {
+        self.threads.entry(thread.pid()).or_insert(Thread {
+            state: State::Starting,
+            stop_sent: false,
+        });
+    }
+
+    /// Set up a new thread once it reports its first stop, and let it run if
+    /// the other threads are running.
+    fn started(&mut self, tid: pid_t) -> io::Result<()> {
+        let thread = Target::new(tid);
+        for (&n, &val) in &self.debugregs {
+            thread.set_debugreg(n, val)?;
+        }
+        if self.others_running {
+            self.resume_thread(tid, Resume::Continue, 0)
+        } else {
+            self.threads.get_mut(&tid).unwrap().state = State::Stopped;
+            Ok(())
+        }
+    }
+
+    /// Stop every thread that's running, and wait for the threads that are
+    /// starting to stop too.
+    fn stop_others(&mut self) -> io::Result<()> {
+        for (&tid, t) in &mut self.threads {
+            if matches!(t.state, State::Running(_)) && !t.stop_sent {
+                // it may have just exited, which waiting for it reports
+                _ = Target::new(tid).tkill(libc::SIGSTOP);
+                t.stop_sent = true;
+            }
+        }
+        while let Some((&tid, _)) = self.threads.iter().find(|(_, t)| t.state != State::Stopped) {
+            self.collect_stop(tid)?;
+        }
+        Ok(())
+    }
+
+    /// Wait for a thread that was sent a SIGSTOP (or is starting) to stop.
+    /// If it stops for some other reason first, that stop is kept to report
+    /// later.
+    fn collect_stop(&mut self, tid: pid_t) -> io::Result<()> {
+        let thread = Target::new(tid);
+        let state = self.threads[&tid].state;
+        let starting = state == State::Starting;
+        match thread.wait_thread()? {
+            ThreadStatus::Cloned(new) => {
+                // its SIGSTOP is still on the way
+                self.add_new(new);
+            }
+            ThreadStatus::Wait(WaitStatus::Stopped {
+                signal: libc::SIGSTOP,
+            }) => {
+                if starting {
+                    for (&n, &val) in &self.debugregs {
+                        thread.set_debugreg(n, val)?;
+                    }
+                } else {
+                    self.threads.get_mut(&tid).unwrap().stop_sent = false;
+                }
+            }
+            ThreadStatus::Wait(WaitStatus::Stopped {
+                signal: libc::SIGTRAP,
+            }) if matches!(state, State::Running(Resume::Step | Resume::Block))
+                && thread
+                    .getsiginfo()
+                    .is_ok_and(|siginfo| siginfo.si_code == TRAP_TRACE) =>
+            {
+                // its step finished, which isn't worth reporting now that
+                // another thread stopped; the SIGSTOP is still on the way
+            }
+            ThreadStatus::Wait(
+                status @ (WaitStatus::Exited { .. } | WaitStatus::Signaled { .. }),
+            ) => {
+                self.threads.remove(&tid);
+                if tid == self.pid {
+                    self.pending.push_back((thread, status));
+                }
+                return Ok(());
+            }
+            ThreadStatus::Wait(status) => self.pending.push_back((thread, status)),
+        }
+        self.threads.get_mut(&tid).unwrap().state = State::Stopped;
+        Ok(())
+    }
+
+    /// Set a debug register in every thread (and in threads created later).
+    pub fn set_debugreg(&mut self, n: usize, val: u64) -> io::Result<()> {
+        for (&tid, t) in &self.threads {
+            if t.state != State::Starting {
+                Target::new(tid).set_debugreg(n, val)?;
+            }
+        }
+        self.debugregs.insert(n, val);
+        Ok(())
+    }
+
+    /// Stop tracing every thread, letting the process run.
+    pub fn detach(&mut self) -> io::Result<()> {
+        let mut stop_sent = false;
+        for (&tid, t) in &self.threads {
+            stop_sent |= t.stop_sent;
+            if tid != self.pid {
+                _ = Target::new(tid).detach();
+            }
+        }
+        self.main().detach()?;
+        if stop_sent {
+            // a SIGSTOP that hasn't been delivered would stop the process once
+            // it's no longer traced, and a SIGCONT discards it
+            unsafe { libc::kill(self.pid, libc::SIGCONT) };
+        }
+        self.threads.clear();
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Wait for a killed process's threads to exit, so the process doesn't
+    /// linger as a zombie.
+    pub fn reap(&mut self) {
+        while let Ok((thread, status)) = ptrace::wait_any_thread() {
+            if thread.pid() == self.pid
+                && matches!(
+                    status,
+                    ThreadStatus::Wait(WaitStatus::Exited { .. } | WaitStatus::Signaled { .. })
+                )
+            {
+                break;
+            }
+        }
+        self.threads.clear();
+        self.pending.clear();
+    }
+}
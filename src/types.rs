@@ -0,0 +1,309 @@
+//! Descriptions of DWARF types.
+//!
+//! The types are read out of the DWARF into these owned descriptions (see
+//! `DbgInfo::find_variable`), so reading values of a type (in the `value`
+//! module) doesn't need the DWARF.
+
+/// How the bytes of a base type are interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Signed,
+    Unsigned,
+    Bool,
+    Char,
+    Float,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    pub name: String,
+    pub offset: usize,
+    pub ty: Type,
+}
+
+/// One variant of a Rust enum.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    /// The discriminant value that selects this variant, or `None` for the
+    /// default variant.
+    ///
+    /// With niche optimization, the variant that holds the payload usually has
+    /// no discriminant value; it is selected whenever the discriminant
+    /// (really part of the payload) isn't one of the other variants' values.
+    pub discr_value: Option<u64>,
+    /// The variant's name, with a struct type holding its fields.
+    pub member: Member,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Base {
+        name: String,
+        encoding: Encoding,
+        size: usize,
+    },
+    Pointer {
+        name: String,
+        size: usize,
+    },
+    Struct {
+        name: String,
+        size: usize,
+        members: Vec<Member>,
+    },
+    /// A Rust enum (a struct with a `DW_TAG_variant_part`).
+    Enum {
+        name: String,
+        size: usize,
+        /// Where the discriminant is stored, if there is more than one variant.
+        discr: Option<Box<Member>>,
+        variants: Vec<Variant>,
+    },
+    /// A fixed-size array.
+    Array {
+        name: String,
+        size: usize,
+        elem: Box<Type>,
+        count: usize,
+    },
+    /// A slice reference (like `&[T]` or `&str`), which points to its
+    /// elements.
+    Slice {
+        name: String,
+        size: usize,
+        elem: Box<Type>,
+        /// offset of the pointer to the elements
+        ptr_offset: usize,
+        /// offset of the number of elements
+        len_offset: usize,
+    },
+    /// A C-like enum, with only named values.
+    Enumeration {
+        name: String,
+        size: usize,
+        signed: bool,
+        enumerators: Vec<(String, i64)>,
+    },
+    /// A type we don't know how to format.
+    Unknown {
+        name: String,
+        size: usize,
+    },
+}
+
+impl Type {
+    /// Rust's primitive types (up to 64 bits), which are available by name
+    /// even if the program doesn't use them.
+    pub fn primitive(name: &str) -> Option<Type> {
+        let (encoding, size) = match name {
+            "u8" => (Encoding::Unsigned, 1),
+            "u16" => (Encoding::Unsigned, 2),
+            "u32" => (Encoding::Unsigned, 4),
+            "u64" | "usize" => (Encoding::Unsigned, 8),
+            "i8" => (Encoding::Signed, 1),
+            "i16" => (Encoding::Signed, 2),
+            "i32" => (Encoding::Signed, 4),
+            "i64" | "isize" => (Encoding::Signed, 8),
+            "bool" => (Encoding::Bool, 1),
+            "char" => (Encoding::Char, 4),
+            "f32" => (Encoding::Float, 4),
+            "f64" => (Encoding::Float, 8),
+            _ => return None,
+        };
+        Some(Type::Base {
+            name: name.to_string(),
+            encoding,
+            size,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Type::Base { name, .. }
+            | Type::Pointer { name, .. }
+            | Type::Struct { name, .. }
+            | Type::Enum { name, .. }
+            | Type::Array { name, .. }
+            | Type::Slice { name, .. }
+            | Type::Enumeration { name, .. }
+            | Type::Unknown { name, .. } => name,
+        }
+    }
+
+    /// The size of the type in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            Type::Base { size, .. }
+            | Type::Pointer { size, .. }
+            | Type::Struct { size, .. }
+            | Type::Enum { size, .. }
+            | Type::Array { size, .. }
+            | Type::Slice { size, .. }
+            | Type::Enumeration { size, .. }
+            | Type::Unknown { size, .. } => *size,
+        }
+    }
+
+    /// Show how the type is defined, expanding the members of structs (with
+    /// their offsets) and enums (but not of the types they contain).
+    pub fn definition(&self) -> String {
+        match self {
+            Type::Struct { name, members, .. } => {
+                let fields = declare_fields(name, members, "", true);
+                if fields.ends_with('}') {
+                    format!("struct {fields}")
+                } else {
+                    format!("struct {fields};")
+                }
+            }
+            Type::Enum { name, variants, .. } => {
+                let mut def = format!("enum {name} {{\n");
+                for v in variants {
+                    let fields = match &v.member.ty {
+                        Type::Struct { members, .. } => {
+                            declare_fields(&v.member.name, members, "    ", false)
+                        }
+                        ty => format!("{}({})", v.member.name, ty.name()),
+                    };
+                    def.push_str(&format!("    {fields},\n"));
+                }
+                def.push('}');
+                def
+            }
+            Type::Enumeration {
+                name, enumerators, ..
+            } => {
+                let mut def = format!("enum {name} {{\n");
+                for (variant, val) in enumerators {
+                    def.push_str(&format!("    {variant} = {val},\n"));
+                }
+                def.push('}');
+                def
+            }
+            ty => ty.name().to_string(),
+        }
+    }
+}
+
+/// Declare the fields of a struct (or enum variant) named name, using Rust's
+/// tuple syntax for unnamed fields. Lines after the first are indented by
+/// indent, and named fields can be commented with their offsets.
+fn declare_fields(name: &str, members: &[Member], indent: &str, offsets: bool) -> String {
+    if members.is_empty() {
+        return name.to_string();
+    }
+    // rustc names tuple fields __0, __1, ...
+    if members.iter().all(|m| m.name.starts_with("__")) {
+        let tys: Vec<_> = members.iter().map(|m| m.ty.name()).collect();
+        return format!("{name}({})", tys.join(", "));
+    }
+    let mut decl = format!("{name} {{\n");
+    for m in members {
+        decl.push_str(&format!("{indent}    {}: {},", m.name, m.ty.name()));
+        if offsets {
+            decl.push_str(&format!(" // offset {}", m.offset));
+        }
+        decl.push('\n');
+    }
+    decl.push_str(&format!("{indent}}}"));
+    decl
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Encoding, Member, Type, Variant};
+
+    #[test]
+    fn test_primitive() {
+        let ty = Type::primitive("i8").unwrap();
+        assert_eq!(
+            ty,
+            Type::Base {
+                name: "i8".to_string(),
+                encoding: Encoding::Signed,
+                size: 1
+            }
+        );
+        assert_eq!(Type::primitive("char").unwrap().size(), 4);
+        assert_eq!(Type::primitive("Point"), None);
+    }
+
+    fn member(name: &str, offset: usize, ty: Type) -> Member {
+        Member {
+            name: name.to_string(),
+            offset,
+            ty,
+        }
+    }
+
+    fn tuple(name: &str, tys: Vec<Type>) -> Type {
+        let members = tys
+            .into_iter()
+            .enumerate()
+            .map(|(i, ty)| member(&format!("__{i}"), 0, ty))
+            .collect();
+        Type::Struct {
+            name: name.to_string(),
+            size: 0,
+            members,
+        }
+    }
+
+    #[test]
+    fn test_definition() {
+        let i32 = Type::primitive("i32").unwrap();
+        let point = Type::Struct {
+            name: "Point".to_string(),
+            size: 8,
+            members: vec![member("x", 0, i32.clone()), member("y", 4, i32.clone())],
+        };
+        assert_eq!(
+            point.definition(),
+            "struct Point {\n    x: i32, // offset 0\n    y: i32, // offset 4\n}"
+        );
+        assert_eq!(
+            tuple("Pair", vec![i32.clone(), i32.clone()]).definition(),
+            "struct Pair(i32, i32);"
+        );
+        assert_eq!(tuple("Unit", vec![]).definition(), "struct Unit;");
+
+        let variant = |discr_value, ty: Type| {
+            let name = ty.name().to_string();
+            Variant {
+                discr_value,
+                member: member(&name, 0, ty),
+            }
+        };
+        let option = Type::Enum {
+            name: "Option<Point>".to_string(),
+            size: 12,
+            discr: None,
+            variants: vec![
+                variant(Some(0), tuple("None", vec![])),
+                variant(Some(1), tuple("Some", vec![point.clone()])),
+            ],
+        };
+        assert_eq!(
+            option.definition(),
+            "enum Option<Point> {\n    None,\n    Some(Point),\n}"
+        );
+        let shape = Type::Enum {
+            name: "Shape".to_string(),
+            size: 8,
+            discr: None,
+            variants: vec![variant(
+                None,
+                Type::Struct {
+                    name: "Rect".to_string(),
+                    size: 8,
+                    members: vec![member("w", 0, i32.clone()), member("h", 4, i32.clone())],
+                },
+            )],
+        };
+        assert_eq!(
+            shape.definition(),
+            "enum Shape {\n    Rect {\n        w: i32,\n        h: i32,\n    },\n}"
+        );
+        assert_eq!(i32.definition(), "i32");
+    }
+}
@@ -0,0 +1,516 @@
+//! Reading values of DWARF types out of the target and formatting them.
+//!
+//! Everything that shows a typed value goes through [`read_typed_value`], which
+//! handles base types, pointers, structs, enums, arrays, and slices. Slices
+//! need to read their elements from memory, so formatting takes a [`Memory`]
+//! rather than just the bytes of the value.
+
+use std::fmt;
+
+use crate::types::{Encoding, Member, Type, Variant};
+
+/// How deeply to format nested values before eliding them.
+const MAX_DEPTH: usize = 8;
+/// How many elements of an array or slice to show.
+const MAX_ELEMENTS: usize = 32;
+
+/// Access to the target's memory.
+pub trait Memory {
+    fn read_memory(&self, addr: u64, len: usize) -> Result<Vec<u8>, String>;
+}
+
+/// A value printed by `print`, as saved in the value history.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Value {
+    pub bytes: Vec<u8>,
+    /// None for a plain number, from an expression
+    pub ty: Option<Type>,
+}
+
+impl Value {
+    pub fn number(n: u64) -> Self {
+        Self {
+            bytes: n.to_le_bytes().to_vec(),
+            ty: None,
+        }
+    }
+
+    /// Get the value as a number, to use it in an expression.
+    pub fn as_u64(&self) -> Result<u64, String> {
+        let signed = match &self.ty {
+            None => false,
+            Some(Type::Base {
+                encoding: Encoding::Float,
+                name,
+                ..
+            }) => return Err(format!("cannot use an {name} in an expression")),
+            Some(Type::Base { encoding, .. }) => *encoding == Encoding::Signed,
+            Some(Type::Pointer { .. }) => false,
+            Some(Type::Enumeration { signed, .. }) => *signed,
+            Some(ty) => return Err(format!("{} is not a number", ty.name())),
+        };
+        read_int(&self.bytes, signed).ok_or_else(|| "invalid value".to_string())
+    }
+}
+
+/// Where a value is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValueLocation {
+    /// in the target's memory, at an address
+    Addr(u64),
+    /// already read (for example, from a register)
+    Bytes(Vec<u8>),
+}
+
+/// A value read from the target, formatted for display.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormattedValue(String);
+
+impl fmt::Display for FormattedValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Read a value of type ty and format it.
+///
+/// Parts of the value that can't be read are shown as `<...>` errors rather
+/// than failing the whole value.
+pub fn read_typed_value(mem: &impl Memory, loc: ValueLocation, ty: &Type) -> FormattedValue {
+    let bytes = match loc {
+        ValueLocation::Addr(addr) => match mem.read_memory(addr, ty.size()) {
+            Ok(bytes) => bytes,
+            Err(_) => return FormattedValue(format!("<cannot access memory at 0x{addr:x}>")),
+        },
+        ValueLocation::Bytes(bytes) => bytes,
+    };
+    FormattedValue(Formatter { mem }.format(ty, &bytes, 0))
+}
+
+/// Read a little-endian integer, sign-extending if signed.
+fn read_int(bytes: &[u8], signed: bool) -> Option<u64> {
+    if bytes.is_empty() || bytes.len() > 8 {
+        return None;
+    }
+    let mut buf = [0u8; 8];
+    buf[..bytes.len()].copy_from_slice(bytes);
+    let val = u64::from_le_bytes(buf);
+    let bits = bytes.len() * 8;
+    if signed && bits < 64 {
+        let shift = 64 - bits;
+        return Some((((val << shift) as i64) >> shift) as u64);
+    }
+    Some(val)
+}
+
+/// Truncate a discriminant value to the width it is stored in.
+fn truncate(val: u64, size: usize) -> u64 {
+    if size >= 8 {
+        val
+    } else {
+        val & ((1 << (size * 8)) - 1)
+    }
+}
+
+/// Find the variant of an enum selected by the discriminant in bytes.
+fn active_variant<'a>(
+    discr: Option<&Member>,
+    variants: &'a [Variant],
+    bytes: &[u8],
+) -> Option<&'a Variant> {
+    let discr = match discr {
+        Some(discr) => discr,
+        // a single-variant enum has no discriminant
+        None => return variants.first(),
+    };
+    let size = discr.ty.size();
+    let val = read_int(bytes.get(discr.offset..discr.offset + size)?, false)?;
+    variants
+        .iter()
+        .find(|v| v.discr_value.map(|d| truncate(d, size)) == Some(val))
+        .or_else(|| variants.iter().find(|v| v.discr_value.is_none()))
+}
+
+struct Formatter<'a, M> {
+    mem: &'a M,
+}
+
+impl<M: Memory> Formatter<'_, M> {
+    fn format_members(&self, name: &str, members: &[Member], bytes: &[u8], depth: usize) -> String {
+        let fields = members.iter().map(|m| {
+            let val = match bytes.get(m.offset..m.offset + m.ty.size()) {
+                Some(bytes) => self.format(&m.ty, bytes, depth + 1),
+                None => "<invalid>".to_string(),
+            };
+            (m.name.as_str(), val)
+        });
+        // rustc names tuple fields __0, __1, ...
+        if members.iter().all(|m| m.name.starts_with("__")) {
+            if members.is_empty() {
+                return name.to_string();
+            }
+            let vals: Vec<_> = fields.map(|(_, val)| val).collect();
+            return format!("{name}({})", vals.join(", "));
+        }
+        let fields: Vec<_> = fields.map(|(n, val)| format!("{n}: {val}")).collect();
+        format!("{name} {{ {} }}", fields.join(", "))
+    }
+
+    /// Format count elements of type elem, stored contiguously in bytes.
+    fn format_elements(&self, elem: &Type, count: usize, bytes: &[u8], depth: usize) -> String {
+        let size = elem.size();
+        let mut vals: Vec<_> = (0..count.min(MAX_ELEMENTS))
+            .map(|i| match bytes.get(i * size..(i + 1) * size) {
+                Some(bytes) => self.format(elem, bytes, depth + 1),
+                None => "<invalid>".to_string(),
+            })
+            .collect();
+        if count > MAX_ELEMENTS {
+            vals.push(format!("...{} more", count - MAX_ELEMENTS));
+        }
+        format!("[{}]", vals.join(", "))
+    }
+
+    fn format_slice(&self, name: &str, elem: &Type, ptr: u64, len: u64, depth: usize) -> String {
+        let shown = (len as usize).min(MAX_ELEMENTS);
+        let bytes = match self.mem.read_memory(ptr, shown * elem.size()) {
+            Ok(bytes) => bytes,
+            Err(_) => return format!("<cannot access memory at 0x{ptr:x}>"),
+        };
+        if name == "&str" {
+            let s = String::from_utf8_lossy(&bytes);
+            if len as usize > shown {
+                return format!("{s:?}...");
+            }
+            return format!("{s:?}");
+        }
+        format!(
+            "&{}",
+            self.format_elements(elem, len as usize, &bytes, depth)
+        )
+    }
+
+    fn format(&self, ty: &Type, bytes: &[u8], depth: usize) -> String {
+        if depth > MAX_DEPTH {
+            return "...".to_string();
+        }
+        match ty {
+            Type::Base { encoding, size, .. } => {
+                let signed = matches!(encoding, Encoding::Signed);
+                let val = match bytes.get(..*size).and_then(|b| read_int(b, signed)) {
+                    Some(val) => val,
+                    None => return "<invalid>".to_string(),
+                };
+                match encoding {
+                    Encoding::Signed => format!("{}", val as i64),
+                    Encoding::Unsigned => format!("{val}"),
+                    Encoding::Bool => format!("{}", val != 0),
+                    Encoding::Char => match char::from_u32(val as u32) {
+                        Some(c) => format!("{c:?}"),
+                        None => format!("'\\u{{{val:x}}}'"),
+                    },
+                    Encoding::Float if *size == 4 => format!("{}", f32::from_bits(val as u32)),
+                    Encoding::Float => format!("{}", f64::from_bits(val)),
+                }
+            }
+            Type::Pointer { size, .. } => match bytes.get(..*size).and_then(|b| read_int(b, false))
+            {
+                Some(val) => format!("0x{val:x}"),
+                None => "<invalid>".to_string(),
+            },
+            Type::Struct { name, members, .. } => {
+                // show just the last path component, like Rust's Debug
+                let name = name.rsplit("::").next().unwrap_or(name);
+                self.format_members(name, members, bytes, depth)
+            }
+            Type::Enum {
+                discr, variants, ..
+            } => match active_variant(discr.as_deref(), variants, bytes) {
+                Some(v) => match &v.member.ty {
+                    Type::Struct { members, .. } => {
+                        let bytes = bytes.get(v.member.offset..).unwrap_or(&[]);
+                        self.format_members(&v.member.name, members, bytes, depth)
+                    }
+                    ty => format!("{}({})", v.member.name, self.format(ty, bytes, depth + 1)),
+                },
+                None => "<invalid discriminant>".to_string(),
+            },
+            Type::Array { elem, count, .. } => self.format_elements(elem, *count, bytes, depth),
+            Type::Slice {
+                name,
+                elem,
+                ptr_offset,
+                len_offset,
+                ..
+            } => {
+                let field = |offset: usize| {
+                    bytes
+                        .get(offset..offset + 8)
+                        .and_then(|b| read_int(b, false))
+                };
+                match (field(*ptr_offset), field(*len_offset)) {
+                    (Some(ptr), Some(len)) => self.format_slice(name, elem, ptr, len, depth),
+                    _ => "<invalid>".to_string(),
+                }
+            }
+            Type::Enumeration {
+                size,
+                signed,
+                enumerators,
+                ..
+            } => {
+                let val = match bytes.get(..*size).and_then(|b| read_int(b, *signed)) {
+                    Some(val) => val as i64,
+                    None => return "<invalid>".to_string(),
+                };
+                match enumerators.iter().find(|(_, v)| *v == val) {
+                    Some((name, _)) => name.clone(),
+                    None => format!("{val}"),
+                }
+            }
+            Type::Unknown { name, size } => {
+                let bytes = bytes.get(..*size).unwrap_or(bytes);
+                let hex: Vec<_> = bytes.iter().map(|b| format!("{b:02x}")).collect();
+                format!("<{name}: {}>", hex.join(" "))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_typed_value, Memory, Value, ValueLocation, MAX_ELEMENTS};
+    use crate::types::{Encoding, Member, Type, Variant};
+
+    /// Memory with a single region, starting at BASE.
+    struct Region(Vec<u8>);
+
+    const BASE: u64 = 0x1000;
+
+    impl Memory for Region {
+        fn read_memory(&self, addr: u64, len: usize) -> Result<Vec<u8>, String> {
+            let start = addr.checked_sub(BASE).ok_or("unmapped")? as usize;
+            self.0
+                .get(start..start + len)
+                .map(|b| b.to_vec())
+                .ok_or_else(|| "unmapped".to_string())
+        }
+    }
+
+    fn format(ty: &Type, bytes: &[u8]) -> String {
+        format_in(&Region(vec![]), ty, bytes)
+    }
+
+    fn format_in(mem: &Region, ty: &Type, bytes: &[u8]) -> String {
+        read_typed_value(mem, ValueLocation::Bytes(bytes.to_vec()), ty).to_string()
+    }
+
+    fn base(name: &str, encoding: Encoding, size: usize) -> Type {
+        Type::Base {
+            name: name.to_string(),
+            encoding,
+            size,
+        }
+    }
+
+    fn member(name: &str, offset: usize, ty: Type) -> Member {
+        Member {
+            name: name.to_string(),
+            offset,
+            ty,
+        }
+    }
+
+    fn variant(discr_value: Option<u64>, name: &str, fields: Vec<Member>) -> Variant {
+        let size = fields
+            .iter()
+            .map(|m| m.offset + m.ty.size())
+            .max()
+            .unwrap_or(0);
+        Variant {
+            discr_value,
+            member: member(
+                name,
+                0,
+                Type::Struct {
+                    name: name.to_string(),
+                    size,
+                    members: fields,
+                },
+            ),
+        }
+    }
+
+    fn slice(name: &str, elem: Type) -> Type {
+        Type::Slice {
+            name: name.to_string(),
+            size: 16,
+            elem: Box::new(elem),
+            ptr_offset: 0,
+            len_offset: 8,
+        }
+    }
+
+    fn slice_bytes(ptr: u64, len: u64) -> Vec<u8> {
+        [ptr.to_le_bytes(), len.to_le_bytes()].concat()
+    }
+
+    /// Option<Box<u64>>, which rustc describes with a niche: None is the
+    /// null pointer, and Some is the default variant.
+    fn option_box() -> Type {
+        let ptr = Type::Pointer {
+            name: "u64 *".to_string(),
+            size: 8,
+        };
+        Type::Enum {
+            name: "Option<Box<u64>>".to_string(),
+            size: 8,
+            discr: Some(Box::new(member("", 0, base("u64", Encoding::Unsigned, 8)))),
+            variants: vec![
+                variant(Some(0), "None", vec![]),
+                variant(None, "Some", vec![member("__0", 0, ptr)]),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_primitive() {
+        let ty = Type::primitive("i8").unwrap();
+        assert_eq!(format(&ty, &[0xff]), "-1");
+        let ty = Type::primitive("f32").unwrap();
+        assert_eq!(format(&ty, &1.5f32.to_le_bytes()), "1.5");
+    }
+
+    #[test]
+    fn test_value_as_u64() {
+        assert_eq!(Value::number(7).as_u64(), Ok(7));
+        let val = Value {
+            bytes: vec![0xfe],
+            ty: Type::primitive("i8"),
+        };
+        assert_eq!(val.as_u64(), Ok(-2i64 as u64));
+        let val = Value {
+            bytes: 1.5f32.to_le_bytes().to_vec(),
+            ty: Type::primitive("f32"),
+        };
+        assert!(val.as_u64().is_err());
+        let val = Value {
+            bytes: vec![0; 4],
+            ty: Some(Type::Struct {
+                name: "Point".to_string(),
+                size: 4,
+                members: vec![],
+            }),
+        };
+        assert!(val.as_u64().is_err());
+    }
+
+    #[test]
+    fn test_niche_enum() {
+        let ty = option_box();
+        assert_eq!(format(&ty, &0u64.to_le_bytes()), "None");
+        assert_eq!(format(&ty, &0x5000u64.to_le_bytes()), "Some(0x5000)");
+    }
+
+    #[test]
+    fn test_tagged_enum() {
+        // Result<u8, i16>, with a one-byte tag
+        let ty = Type::Enum {
+            name: "Result<u8, i16>".to_string(),
+            size: 4,
+            discr: Some(Box::new(member("", 0, base("u8", Encoding::Unsigned, 1)))),
+            variants: vec![
+                variant(
+                    Some(0),
+                    "Ok",
+                    vec![member("__0", 1, base("u8", Encoding::Unsigned, 1))],
+                ),
+                variant(
+                    Some(1),
+                    "Err",
+                    vec![member("__0", 2, base("i16", Encoding::Signed, 2))],
+                ),
+            ],
+        };
+        assert_eq!(format(&ty, &[0, 7, 0, 0]), "Ok(7)");
+        assert_eq!(format(&ty, &[1, 0, 0xfd, 0xff]), "Err(-3)");
+        assert_eq!(format(&ty, &[2, 0, 0, 0]), "<invalid discriminant>");
+    }
+
+    #[test]
+    fn test_negative_discr_value() {
+        // a niche in a signed byte, where the discriminant is recorded as -1
+        let ty = Type::Enum {
+            name: "E".to_string(),
+            size: 1,
+            discr: Some(Box::new(member("", 0, base("u8", Encoding::Unsigned, 1)))),
+            variants: vec![
+                variant(Some(-1i64 as u64), "A", vec![]),
+                variant(
+                    None,
+                    "B",
+                    vec![member("__0", 0, base("i8", Encoding::Signed, 1))],
+                ),
+            ],
+        };
+        assert_eq!(format(&ty, &[0xff]), "A");
+        assert_eq!(format(&ty, &[3]), "B(3)");
+    }
+
+    #[test]
+    fn test_struct() {
+        let ty = Type::Struct {
+            name: "test::Point".to_string(),
+            size: 8,
+            members: vec![
+                member("x", 0, base("i32", Encoding::Signed, 4)),
+                member("y", 4, base("bool", Encoding::Bool, 1)),
+            ],
+        };
+        assert_eq!(
+            format(&ty, &[2, 0, 0, 0, 1, 0, 0, 0]),
+            "Point { x: 2, y: true }"
+        );
+        // reading from memory
+        let mem = Region(vec![2, 0, 0, 0, 1, 0, 0, 0]);
+        let val = read_typed_value(&mem, ValueLocation::Addr(BASE), &ty);
+        assert_eq!(val.to_string(), "Point { x: 2, y: true }");
+        let val = read_typed_value(&mem, ValueLocation::Addr(0x10), &ty);
+        assert_eq!(val.to_string(), "<cannot access memory at 0x10>");
+    }
+
+    #[test]
+    fn test_array() {
+        let elem = base("u16", Encoding::Unsigned, 2);
+        let ty = Type::Array {
+            name: "[u16; 3]".to_string(),
+            size: 6,
+            elem: Box::new(elem),
+            count: 3,
+        };
+        assert_eq!(format(&ty, &[1, 0, 2, 0, 3, 0]), "[1, 2, 3]");
+    }
+
+    #[test]
+    fn test_slices() {
+        let mem = Region(b"hi\x05\x00\x06\x00".to_vec());
+        let ty = slice("&str", base("u8", Encoding::Unsigned, 1));
+        assert_eq!(format_in(&mem, &ty, &slice_bytes(BASE, 2)), "\"hi\"");
+        let ty = slice("&[u16]", base("u16", Encoding::Unsigned, 2));
+        assert_eq!(format_in(&mem, &ty, &slice_bytes(BASE + 2, 2)), "&[5, 6]");
+        assert_eq!(format_in(&mem, &ty, &slice_bytes(BASE, 0)), "&[]");
+        assert_eq!(
+            format_in(&mem, &ty, &slice_bytes(0x10, 1)),
+            "<cannot access memory at 0x10>"
+        );
+    }
+
+    #[test]
+    fn test_element_limit() {
+        let n = MAX_ELEMENTS + 3;
+        let mem = Region(vec![7; n]);
+        let ty = slice("&[u8]", base("u8", Encoding::Unsigned, 1));
+        let s = format_in(&mem, &ty, &slice_bytes(BASE, n as u64));
+        assert!(s.ends_with(", 7, ...3 more]"), "{s}");
+    }
+}
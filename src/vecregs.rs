@@ -0,0 +1,252 @@
+//! Decode the AVX vector registers from the XSAVE area.
+//!
+//! The legacy region of XSAVE (the FXSAVE layout) only has the low 128 bits
+//! of each register, as `xmm0`-`xmm15`. The upper halves of `ymm0`-`ymm15`
+//! are a separate state component, at an offset reported by CPUID. Whether
+//! the kernel enabled that component is in the XSAVE header's software
+//! reserved bytes; without it, only the xmm registers exist.
+//!
+//! TODO: AVX-512 (`zmm`, and `ymm16`-`ymm31`) is in further components and
+//! isn't shown yet.
+
+use std::arch::x86_64::__cpuid_count;
+
+/// Number of vector registers (without AVX-512).
+pub const NUM_REGS: usize = 16;
+
+/// Offset of `xmm0` in the legacy region.
+const XMM_OFFSET: usize = 160;
+/// Offset of the XSTATE_BV bitmap, which says which components are saved.
+const XSTATE_BV_OFFSET: usize = 512;
+/// Offset of the bitmap of components the kernel enabled (XCR0), which Linux
+/// stores in the legacy region's software-reserved bytes for ptrace (see
+/// `struct user_xstateregs`).
+const XFEATURES_OFFSET: usize = 464;
+const SSE_BIT: u64 = 1 << 1;
+/// The state component holding the upper halves of the ymm registers.
+const AVX_COMPONENT: u32 = 2;
+
+/// The offset of the AVX state component in the (standard format) XSAVE area,
+/// or None if the CPU doesn't support AVX.
+pub fn avx_offset() -> Option<usize> {
+    // CPUID leaf 0xD, sub-leaf i describes state component i: eax is its
+    // size and ebx its offset
+    let leaf = __cpuid_count(0xd, AVX_COMPONENT);
+    if leaf.eax == 0 {
+        return None;
+    }
+    Some(leaf.ebx as usize)
+}
+
+/// Check if an XSAVE area (from the kernel) has the AVX state component
+/// enabled.
+pub fn has_avx(xsave: &[u8]) -> bool {
+    xsave
+        .get(XFEATURES_OFFSET..XFEATURES_OFFSET + 8)
+        .is_some_and(|b| u64::from_le_bytes(b.try_into().unwrap()) & (1 << AVX_COMPONENT) != 0)
+}
+
+/// Get the 32 bytes of each of `ymm0`-`ymm15` from an XSAVE area. Without an
+/// AVX offset, only the low halves (the xmm registers) are filled in.
+///
+/// Components the XSTATE_BV bitmap marks as not saved are in their initial
+/// state, which is all zero.
+pub fn ymm_registers(xsave: &[u8], avx_offset: Option<usize>) -> Vec<[u8; 32]> {
+    let xstate_bv = xsave
+        .get(XSTATE_BV_OFFSET..XSTATE_BV_OFFSET + 8)
+        .map_or(0, |b| u64::from_le_bytes(b.try_into().unwrap()));
+    (0..NUM_REGS)
+        .map(|i| {
+            let mut ymm = [0u8; 32];
+            if xstate_bv & SSE_BIT != 0 {
+                let lo = XMM_OFFSET + i * 16;
+                if let Some(b) = xsave.get(lo..lo + 16) {
+                    ymm[..16].copy_from_slice(b);
+                }
+            }
+            if let Some(avx_offset) = avx_offset.filter(|_| xstate_bv & (1 << AVX_COMPONENT) != 0) {
+                let hi = avx_offset + i * 16;
+                if let Some(b) = xsave.get(hi..hi + 16) {
+                    ymm[16..].copy_from_slice(b);
+                }
+            }
+            ymm
+        })
+        .collect()
+}
+
+/// A vector register: the low half of a ymm register, as `xmmN`, or all of
+/// `ymmN`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VecReg {
+    pub num: usize,
+    /// 16 bytes for xmm, 32 for ymm
+    pub size: usize,
+}
+
+impl VecReg {
+    pub fn parse(name: &str) -> Option<Self> {
+        let (size, num) = if let Some(num) = name.strip_prefix("ymm") {
+            (32, num)
+        } else {
+            (16, name.strip_prefix("xmm")?)
+        };
+        let num: usize = num.parse().ok()?;
+        if num < NUM_REGS {
+            Some(Self { num, size })
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> String {
+        let prefix = if self.size == 16 { "xmm" } else { "ymm" };
+        format!("{prefix}{}", self.num)
+    }
+}
+
+/// How to split a vector register into lanes, using gdb's size letters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LaneFormat {
+    /// the whole register as one hex number (`x`)
+    Hex,
+    /// bytes (`b`)
+    Bytes,
+    /// 16-bit integers (`h`)
+    Halfwords,
+    /// 32-bit integers (`w`)
+    Words,
+    /// 64-bit integers (`g`)
+    Giants,
+    /// packed single-precision floats (`f`)
+    Floats,
+    /// packed double-precision floats (`d`)
+    Doubles,
+}
+
+impl LaneFormat {
+    /// Parse a format like `/f`.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s.strip_prefix('/').unwrap_or(s) {
+            "x" => Ok(Self::Hex),
+            "b" => Ok(Self::Bytes),
+            "h" => Ok(Self::Halfwords),
+            "w" => Ok(Self::Words),
+            "g" => Ok(Self::Giants),
+            "f" => Ok(Self::Floats),
+            "d" => Ok(Self::Doubles),
+            _ => Err(format!(
+                "unknown lane format '{s}' (expected /x, /b, /h, /w, /g, /f, or /d)"
+            )),
+        }
+    }
+
+    /// Format the bytes of a register, with lanes from lowest to highest.
+    pub fn format(&self, bytes: &[u8]) -> String {
+        let lanes: Vec<String> = match self {
+            Self::Hex => {
+                let hex: String = bytes.iter().rev().map(|b| format!("{b:02x}")).collect();
+                return format!("0x{hex}");
+            }
+            Self::Bytes => bytes.iter().map(|b| format!("0x{b:02x}")).collect(),
+            Self::Halfwords => bytes
+                .chunks(2)
+                .map(|c| format!("0x{:04x}", u16::from_le_bytes(c.try_into().unwrap())))
+                .collect(),
+            Self::Words => bytes
+                .chunks(4)
+                .map(|c| format!("0x{:08x}", u32::from_le_bytes(c.try_into().unwrap())))
+                .collect(),
+            Self::Giants => bytes
+                .chunks(8)
+                .map(|c| format!("0x{:016x}", u64::from_le_bytes(c.try_into().unwrap())))
+                .collect(),
+            Self::Floats => bytes
+                .chunks(4)
+                .map(|c| format!("{}", f32::from_le_bytes(c.try_into().unwrap())))
+                .collect(),
+            Self::Doubles => bytes
+                .chunks(8)
+                .map(|c| format!("{}", f64::from_le_bytes(c.try_into().unwrap())))
+                .collect(),
+        };
+        format!("{{{}}}", lanes.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{has_avx, ymm_registers, LaneFormat, VecReg, XFEATURES_OFFSET, XSTATE_BV_OFFSET};
+
+    #[test]
+    fn test_ymm_registers() {
+        let avx_offset = 576;
+        let mut xsave = vec![0u8; avx_offset + 16 * 16];
+        // xmm1 and the upper half of ymm1
+        xsave[160 + 16] = 0x11;
+        xsave[avx_offset + 16] = 0x22;
+        xsave[XSTATE_BV_OFFSET] = 0b111;
+        let ymm = ymm_registers(&xsave, Some(avx_offset));
+        assert_eq!(ymm.len(), 16);
+        assert_eq!(ymm[1][0], 0x11);
+        assert_eq!(ymm[1][16], 0x22);
+        assert_eq!(ymm[0], [0; 32]);
+
+        // the AVX component is in its initial state
+        xsave[XSTATE_BV_OFFSET] = 0b011;
+        let ymm = ymm_registers(&xsave, Some(avx_offset));
+        assert_eq!(ymm[1][0], 0x11);
+        assert_eq!(ymm[1][16], 0);
+
+        // without AVX, only the xmm registers are read
+        xsave[XSTATE_BV_OFFSET] = 0b111;
+        let ymm = ymm_registers(&xsave, None);
+        assert_eq!(ymm[1][0], 0x11);
+        assert_eq!(ymm[1][16], 0);
+    }
+
+    #[test]
+    fn test_has_avx() {
+        let mut xsave = vec![0u8; 576];
+        assert!(!has_avx(&xsave));
+        // x87 and SSE only
+        xsave[XFEATURES_OFFSET] = 0b011;
+        assert!(!has_avx(&xsave));
+        xsave[XFEATURES_OFFSET] = 0b111;
+        assert!(has_avx(&xsave));
+        // too short to have the header
+        assert!(!has_avx(&xsave[..256]));
+    }
+
+    #[test]
+    fn test_lane_format() {
+        let mut bytes = [0u8; 32];
+        for (i, lane) in bytes.chunks_mut(4).enumerate() {
+            lane.copy_from_slice(&(i as f32 + 0.5).to_le_bytes());
+        }
+        assert_eq!(
+            LaneFormat::Floats.format(&bytes),
+            "{0.5, 1.5, 2.5, 3.5, 4.5, 5.5, 6.5, 7.5}"
+        );
+        let bytes: Vec<u8> = (0..16).collect();
+        assert_eq!(
+            LaneFormat::Hex.format(&bytes),
+            "0x0f0e0d0c0b0a09080706050403020100"
+        );
+        assert_eq!(
+            LaneFormat::Giants.format(&bytes),
+            "{0x0706050403020100, 0x0f0e0d0c0b0a0908}"
+        );
+        assert_eq!(LaneFormat::parse("/d"), Ok(LaneFormat::Doubles));
+        assert!(LaneFormat::parse("/q").is_err());
+    }
+
+    #[test]
+    fn test_parse_reg() {
+        assert_eq!(VecReg::parse("ymm0"), Some(VecReg { num: 0, size: 32 }));
+        assert_eq!(VecReg::parse("xmm15"), Some(VecReg { num: 15, size: 16 }));
+        assert_eq!(VecReg::parse("ymm16"), None);
+        assert_eq!(VecReg::parse("rax"), None);
+        assert_eq!(VecReg::parse("xmm7").unwrap().name(), "xmm7");
+    }
+}
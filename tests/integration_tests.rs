@@ -1,10 +1,16 @@
 use std::{
-    env,
-    io::Write,
+    env, fs,
+    io::{BufRead, BufReader, Write},
+    os::unix::fs::PermissionsExt,
     path::PathBuf,
     process::{Child, Command, Stdio},
+    thread,
+    time::Duration,
 };
 
+use rdb::debugger::{Dbg, StopReason};
+use serde_json::Value;
+
 fn exe_path(name: &str) -> PathBuf {
     let bin_dir = env::current_exe()
         .unwrap()
@@ -22,8 +28,17 @@ fn wait_stdout(cmd: Child) -> String {
 }
 
 fn spawn_rdb() -> Child {
+    spawn_rdb_with("test")
+}
+
+fn spawn_rdb_with(target: &str) -> Child {
+    spawn_rdb_flags(&[], target)
+}
+
+fn spawn_rdb_flags(flags: &[&str], target: &str) -> Child {
     Command::new(exe_path("rdb"))
-        .arg(exe_path("test"))
+        .args(flags)
+        .arg(exe_path(target))
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::inherit())
@@ -32,8 +47,14 @@ fn spawn_rdb() -> Child {
 }
 
 fn run_rdb(lines: &[&str]) -> String {
-    let mut cmd = spawn_rdb();
+    run_rdb_with("test", lines)
+}
+
+fn run_rdb_with(target: &str, lines: &[&str]) -> String {
+    send_commands(spawn_rdb_with(target), lines)
+}
 
+fn send_commands(mut cmd: Child, lines: &[&str]) -> String {
     let mut stdin = cmd.stdin.take().expect("couldn't get stdin");
     let input = lines.to_vec().join("\n");
     std::thread::spawn(move || {
@@ -45,6 +66,97 @@ fn run_rdb(lines: &[&str]) -> String {
     wait_stdout(cmd)
 }
 
+/// The address rdb sets a breakpoint at for loc (like `main` or `test.rs:37`),
+/// which depends on how the target was compiled.
+fn breakpoint_addr(loc: &str) -> String {
+    breakpoint_addr_with("test", loc)
+}
+
+fn breakpoint_addr_with(target: &str, loc: &str) -> String {
+    let out = run_rdb_with(target, &[&format!("break {loc}"), "quit"]);
+    out.lines()
+        .find_map(|line| line.strip_prefix("set breakpoint 1 at "))
+        .and_then(|rest| rest.split(':').next())
+        .unwrap_or_else(|| panic!("no breakpoint set at {loc}:\n{out}"))
+        .to_string()
+}
+
+/// The address of a function, from the `symbol` command.
+fn symbol_addr(name: &str) -> String {
+    let out = run_rdb(&[&format!("symbol {name}"), "quit"]);
+    out.lines()
+        .find_map(|line| line.strip_prefix(&format!("func {name} ")))
+        .unwrap_or_else(|| panic!("function {name} not found:\n{out}"))
+        .to_string()
+}
+
+/// Parse an address printed as hex, like `0x15170`.
+fn hex(addr: &str) -> u64 {
+    u64::from_str_radix(addr.trim_start_matches("0x"), 16).expect("invalid address")
+}
+
+/// Whether a backtrace has frame num in desc, like
+/// `test::main at src/bin/test.rs:37:16`, at whatever address.
+fn has_frame(out: &str, num: usize, desc: &str) -> bool {
+    out.lines().any(|line| {
+        line.starts_with(&format!("#{num} 0x")) && line.ends_with(&format!(" in {desc}"))
+    })
+}
+
+/// Whether the output reports returning (from finish) to loc, like
+/// `src/bin/test.rs, line 37 (in test::main)`.
+fn returned_to(out: &str, loc: &str) -> bool {
+    out.lines()
+        .any(|line| line.starts_with("returned to 0x") && line.ends_with(&format!(": file {loc}")))
+}
+
+/// Run rdb in batch mode, with the commands in a script file.
+fn run_rdb_script(name: &str, target: &str, script: &str) -> String {
+    let path = env::temp_dir().join(format!("rdb-{}-{name}.rdb", std::process::id()));
+    fs::write(&path, script).expect("could not write script");
+    let cmd = Command::new(exe_path("rdb"))
+        .arg("--source")
+        .arg(&path)
+        .arg(exe_path(target))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .expect("failed to launch debugger");
+    let out = wait_stdout(cmd);
+    _ = fs::remove_file(&path);
+    out
+}
+
+/// Run rdb with --json, returning the events it reports.
+fn run_rdb_events(lines: &[&str]) -> Vec<Value> {
+    run_rdb_events_with("test", lines)
+}
+
+fn run_rdb_events_with(target: &str, lines: &[&str]) -> Vec<Value> {
+    let out = send_commands(spawn_rdb_flags(&["--json"], target), lines);
+    out.lines()
+        .filter(|line| line.starts_with('{'))
+        .map(|line| serde_json::from_str(line).expect("invalid JSON event"))
+        .collect()
+}
+
+/// The (function, line) of each stop event.
+fn stops(events: &[Value]) -> Vec<(&str, u64)> {
+    events
+        .iter()
+        .filter(|e| e["event"] == "stop")
+        .map(|e| {
+            let func = e["function"].as_str().unwrap_or("??");
+            (func, e["line"].as_u64().unwrap_or(0))
+        })
+        .collect()
+}
+
+fn exited(events: &[Value]) -> bool {
+    events.iter().any(|e| e["event"] == "exited")
+}
+
 #[test]
 fn continue_command() {
     let out = run_rdb(&["continue", "quit"]);
@@ -82,6 +194,25 @@ fn eof() {
     assert!(!out.contains("Hello"), "program should not have run");
 }
 
+#[test]
+fn untraceable_program() {
+    // a copy of the test program that can't be executed
+    let prog = env::temp_dir().join(format!("rdb-noexec-{}", std::process::id()));
+    fs::copy(exe_path("test"), &prog).unwrap();
+    fs::set_permissions(&prog, fs::Permissions::from_mode(0o644)).unwrap();
+    let out = Command::new(exe_path("rdb"))
+        .arg(&prog)
+        .stdin(Stdio::null())
+        .output()
+        .expect("failed to launch debugger");
+    _ = fs::remove_file(&prog);
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(!out.status.success());
+    assert!(stderr.contains("could not start program"));
+    assert!(stderr.contains("could not start debugging"));
+    assert!(!stderr.contains("panicked"));
+}
+
 #[test]
 fn single_stepping() {
     run_rdb(&["stepi", "stepi", "stepi", "quit"]);
@@ -90,32 +221,60 @@ fn single_stepping() {
 #[test]
 fn step_out_main() {
     // at the very beginning finish should return from main
-    let out = run_rdb(&["finish"]);
-    assert!(out.contains("program exited"));
+    let events = run_rdb_events(&["finish"]);
+    assert!(exited(&events));
 }
 
 #[test]
 fn function_breakpoint() {
-    let out = run_rdb(&["break use_vars", "continue", "next", "quit"]);
-    assert!(out.contains(">      let mut b: u64 = 2;"));
+    let events = run_rdb_events(&["break use_vars", "continue", "next", "quit"]);
+    assert!(stops(&events).contains(&("use_vars", 5)));
+}
+
+#[test]
+fn next_over_multi_row_line() {
+    // `let c = a + b;` is split into several line table rows by the overflow
+    // check, and next should step over all of them at once
+    let events = run_rdb_events(&["break test.rs:6", "continue", "next", "quit"]);
+    assert_eq!(stops(&events), vec![("use_vars", 6), ("use_vars", 7)]);
 }
 
 #[test]
 fn function_finish() {
     let out = run_rdb(&["break use_vars", "continue", "next", "finish", "quit"]);
-    assert!(out.contains(">      greeting();"));
+    assert!(out.contains("returned to"));
+    let events = run_rdb_events(&["break use_vars", "continue", "next", "finish", "quit"]);
+    assert_eq!(stops(&events).last(), Some(&("test::main", 34)));
+}
+
+#[test]
+fn finish_interrupted_by_breakpoint() {
+    let commands = [
+        "break b", "continue", "break a", "finish", "continue", "quit",
+    ];
+    // the breakpoint in a stops the finish, and continuing still stops when b
+    // returns
+    let events = run_rdb_events(&commands);
+    let stops = stops(&events);
+    assert_eq!(
+        stops[1..],
+        [("test::a", 17), ("test::call_little_functions", 29)]
+    );
+    let out = run_rdb(&commands);
+    assert!(out.contains("returned to"));
 }
 
 #[test]
 fn function_step_in() {
-    let out = run_rdb(&["break use_vars", "continue", "finish", "step", "quit"]);
-    assert!(out.contains("fn greeting()"));
+    let events = run_rdb_events(&["break use_vars", "continue", "finish", "step", "quit"]);
+    assert_eq!(stops(&events).last(), Some(&("greeting", 11)));
 }
 
 #[test]
 fn source_line() {
-    let out = run_rdb(&["break test.rs:6", "continue", "continue", "quit"]);
-    assert!(out.contains(">      let c = a + b"));
+    let events = run_rdb_events(&["break test.rs:6", "continue", "continue", "quit"]);
+    assert_eq!(stops(&events)[0], ("use_vars", 6));
+    assert!(exited(&events));
 }
 
 #[test]
@@ -132,11 +291,1977 @@ fn breakpoint_info() {
     assert!(out.contains("line 12"));
 }
 
+#[test]
+fn line_breakpoint_locations() {
+    // identity is generic, so its lines have code in each instantiation
+    let events = run_rdb_events(&["break test.rs:79", "continue", "continue", "continue"]);
+    assert_eq!(
+        stops(&events),
+        vec![("test::identity", 79), ("test::identity", 79)]
+    );
+    assert!(exited(&events));
+    let out = run_rdb(&["break test.rs:79", "info breakpoints", "quit"]);
+    assert!(out.contains("breakpoint 1 has 2 locations"));
+    let listed = out
+        .lines()
+        .filter(|line| line.starts_with("1    y    0x"))
+        .count();
+    assert_eq!(listed, 2);
+}
+
 #[test]
 fn backtrace() {
     let out = run_rdb(&["break a", "c", "bt"]);
-    assert!(out.contains("test::a"));
+    assert!(out.contains("#1 0x"));
+    assert!(out.contains(" in test::a at src/bin/test.rs:17"));
     assert!(out.contains("test::b"));
     assert!(out.contains("test::call_little_functions"));
     assert!(out.contains("test::main"));
 }
+
+#[test]
+fn backtrace_caller_lines() {
+    // each caller's line is its call, not the code after it returns
+    let out = run_rdb(&["break countdown", "c", "c", "bt", "quit"]);
+    let bp = breakpoint_addr("countdown");
+    assert!(out.contains(&format!("#1 {bp} in countdown at src/bin/test.rs:54:8\n")));
+    assert!(has_frame(&out, 2, "countdown at src/bin/test.rs:57:16"));
+    assert!(has_frame(&out, 3, "test::main at src/bin/test.rs:37:16"));
+}
+
+#[test]
+fn dbg_api() {
+    // drive the debugger as a library, checking what it returns rather than
+    // what a frontend would print
+    let path = exe_path("test");
+    let pipes = rdb::OutputPipes::new().unwrap();
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0, "could not fork");
+    if pid == 0 {
+        let err = rdb::run_target(path.as_os_str(), &[], pipes);
+        eprintln!("could not start program: {err}");
+        std::process::exit(2);
+    }
+    let file = fs::File::open(&path).unwrap();
+    let mmap = unsafe { memmap::Mmap::map(&file).unwrap() };
+    let object = object::File::parse(&*mmap).unwrap();
+    let mut dbg = Dbg::new(object, pid).unwrap();
+    dbg.set_target_output(pipes.into_target_output());
+
+    let bp = dbg.set_breakpoint_at_function("countdown").unwrap();
+    assert_eq!(bp.locations.len(), 1);
+    let pc = bp.locations[0].pc;
+    assert!(bp.locations[0].error.is_none());
+    let bps = dbg.breakpoints();
+    assert_eq!(bps.len(), 1);
+    assert_eq!((bps[0].id, bps[0].pc, bps[0].enabled), (bp.id, pc, true));
+
+    let stop = dbg.continue_execution().unwrap();
+    assert_eq!(stop.reason, StopReason::Breakpoint(pc));
+    let args = dbg.arguments().unwrap();
+    assert_eq!(
+        args,
+        [("n".to_string(), "u64".to_string(), "3".to_string())]
+    );
+
+    dbg.continue_execution().unwrap();
+    let (frames, _) = dbg.backtrace_frames();
+    let callers: Vec<_> = frames
+        .iter()
+        .take(3)
+        .map(|frame| frame.functions[0].function.as_deref().unwrap())
+        .collect();
+    assert_eq!(callers, ["countdown", "countdown", "test::main"]);
+    assert_eq!(frames[0].pc, pc);
+    assert_eq!(frames[1].functions[0].line, Some(57));
+
+    dbg.delete_user_breakpoint(pc).unwrap();
+    assert!(dbg.breakpoints().is_empty());
+    let stop = dbg.continue_execution().unwrap();
+    assert_eq!(stop.reason, StopReason::Exited(0));
+    assert!(dbg.take_warnings().is_empty());
+    dbg.finish_output();
+}
+
+#[test]
+fn set_args() {
+    let out = run_rdb(&[
+        "set args foo \"bar baz\"",
+        "show args",
+        "set args",
+        "show args",
+    ]);
+    assert!(out.contains("program arguments: foo 'bar baz'"));
+    assert!(out.contains("no program arguments"));
+}
+
+#[test]
+fn set_args_then_run() {
+    let out = run_rdb(&["set args foo \"bar baz\"", "run", "info proc cmdline"]);
+    assert!(out.contains("test foo 'bar baz'"), "new arguments not used");
+}
+
+#[test]
+fn proc_cmdline() {
+    let mut cmd = Command::new(exe_path("rdb"))
+        .args([
+            exe_path("test").as_os_str(),
+            "foo".as_ref(),
+            "bar baz".as_ref(),
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to launch debugger");
+    cmd.stdin
+        .take()
+        .unwrap()
+        .write_all(b"info proc cmdline\ninfo target\nquit\n")
+        .unwrap();
+    let out = wait_stdout(cmd);
+    let test = exe_path("test");
+    let cmdline = format!("command line: {} foo 'bar baz'", test.display());
+    assert_eq!(out.matches(&cmdline).count(), 2, "{out}");
+    assert!(out.contains(&format!("executable: {}", test.display())));
+}
+
+#[test]
+fn scheduler_locking() {
+    let out = run_rdb(&[
+        "show scheduler-locking",
+        "set scheduler-locking step",
+        "show scheduler-locking",
+        "quit",
+    ]);
+    assert!(out.contains("scheduler-locking is off"));
+    assert!(out.contains("scheduler-locking is step"));
+}
+
+/// Run the threads program past the call that counts while sleeping, with
+/// the commands that resume it there.
+fn count_while_sleeping(commands: &[&str]) -> String {
+    let mut lines = vec!["break threads.rs:38", "continue"];
+    lines.extend(commands);
+    lines.push("continue");
+    run_rdb_with("threads", &lines)
+}
+
+#[test]
+fn scheduler_locking_step() {
+    // the counting thread is stopped while the main thread steps, but not
+    // once scheduler-locking is off
+    let out = count_while_sleeping(&["set scheduler-locking step", "next"]);
+    assert!(out.contains("counted 0 while sleeping"), "{out}");
+    let out = count_while_sleeping(&["next"]);
+    assert!(out.contains("while sleeping"), "{out}");
+    assert!(!out.contains("counted 0 while sleeping"), "{out}");
+}
+
+#[test]
+fn scheduler_locking_on() {
+    // with scheduler-locking on, continuing only runs the current thread too
+    let out = count_while_sleeping(&["set scheduler-locking on"]);
+    assert!(out.contains("counted 0 while sleeping"), "{out}");
+}
+
+#[test]
+fn thread_breakpoint() {
+    let out = run_rdb_with(
+        "threads",
+        &["break threads.rs:16", "continue", "continue", "quit"],
+    );
+    assert!(out.contains("[switching to thread "), "{out}");
+    // the thread got past the breakpoint the first time
+    assert_eq!(out.matches("hit breakpoint 0x").count(), 2, "{out}");
+}
+
+#[test]
+fn pagination() {
+    // output to a pipe is never paged
+    let out = run_rdb(&[
+        "show pagination",
+        "set pagination on",
+        "show pagination",
+        "info registers",
+        "quit",
+    ]);
+    assert!(out.contains("pagination is off"));
+    assert!(out.contains("pagination is on"));
+    assert!(!out.contains("--Type <RET>"));
+    assert!(out.contains("rip"));
+}
+
+#[test]
+fn logging() {
+    let log = env::temp_dir().join(format!("rdb-logging-{}.log", std::process::id()));
+    _ = fs::remove_file(&log);
+    let set_logging = format!("set logging on {}", log.display());
+    let out = run_rdb(&[&set_logging, "show logging", "continue", "quit"]);
+    assert!(out.contains("logging program output to"));
+    assert!(
+        out.contains("Hello, world"),
+        "output should still go to the terminal"
+    );
+    let logged = fs::read_to_string(&log).expect("log file was not written");
+    _ = fs::remove_file(&log);
+    assert!(logged.contains("Hello, world"));
+}
+
+#[test]
+fn watch_variable() {
+    let out = run_rdb(&[
+        "break use_vars",
+        "continue",
+        "watch a",
+        "continue",
+        "continue",
+        "unwatch 1",
+        "continue",
+        "quit",
+    ]);
+    assert!(out.contains("note: a is a local"));
+    assert!(out.contains("old value = 3\nnew value = 4"));
+    assert!(out.contains("program exited"));
+}
+
+#[test]
+fn watch_places() {
+    let out = run_rdb(&[
+        "break test.rs:74",
+        "continue",
+        "watch p.y",
+        "watch pp->y",
+        "unwatch 1",
+        "unwatch 2",
+        "break test.rs:88",
+        "continue",
+        "watch slice[1]",
+        "watch arr[2]",
+        "quit",
+    ]);
+    let addr = |text: &str| {
+        let line = out
+            .lines()
+            .find(|line| line.contains(&format!(": {text} (0x")))
+            .unwrap_or_else(|| panic!("no watchpoint on {text}"));
+        line.split_once("(0x")
+            .unwrap()
+            .1
+            .split_once(',')
+            .unwrap()
+            .0
+            .to_string()
+    };
+    // a field through a pointer is the same memory as the field itself
+    assert_eq!(addr("p.y"), addr("pp->y"));
+    assert!(out.contains("watchpoint 2: pp->y (0x"));
+    assert!(out.contains(", 4 bytes)"));
+    assert!(out.contains("note: pp->y uses the local pp"));
+    // slice points into arr, starting at its second element
+    assert_eq!(addr("slice[1]"), addr("arr[2]"));
+}
+
+#[test]
+fn read_access_watchpoints() {
+    let out = run_rdb(&[
+        "break use_vars",
+        "continue",
+        "rwatch a",
+        "info registers debug",
+        "continue",
+        "quit",
+    ]);
+    assert!(out.contains("read watchpoint 1: a (0x"));
+    assert!(out.contains("[ dr0=rw/8(local) ]"));
+    // the write `a = 3` is skipped and the read in `a + b` is reported
+    assert!(out.contains("read watchpoint 1: a\nvalue = 3\n"));
+    assert!(!out.contains("new value = 3"));
+
+    let out = run_rdb(&[
+        "break use_vars",
+        "continue",
+        "awatch a",
+        "continue",
+        "continue",
+        "continue",
+        "quit",
+    ]);
+    // an access watchpoint reports both writes and reads
+    assert!(out.contains("access watchpoint 1: a\nold value = 0\nnew value = 3\n"));
+    assert!(out.contains("access watchpoint 1: a\nvalue = 3\n"));
+    assert!(out.contains("access watchpoint 1: a\nold value = 3\nnew value = 4\n"));
+}
+
+#[test]
+fn software_watchpoint() {
+    let out = run_rdb_with(
+        "globals",
+        &[
+            "break main",
+            "continue",
+            "watch -sw COUNTER 4",
+            "break globals.rs:9",
+            "continue",
+            "continue",
+            "unwatch 1",
+            "continue",
+            "quit",
+        ],
+    );
+    assert!(out.contains("software watchpoint 1: COUNTER (0x"));
+    assert!(out.contains("old value = 29 00 00 00\nnew value = 2a 00 00 00\n"));
+    // breakpoints still stop the target while it's single-stepped
+    let bp = breakpoint_addr_with("globals", "globals.rs:9");
+    assert!(out.contains(&format!("hit breakpoint {bp}")));
+    assert!(out.contains("program exited"));
+}
+
+#[test]
+fn debug_registers() {
+    let out = run_rdb(&[
+        "break use_vars",
+        "continue",
+        "watch a",
+        "info registers debug",
+        "unwatch 1",
+        "info all-registers",
+        "quit",
+    ]);
+    assert!(out.contains("[ dr0=write/8(local) ]"));
+    // all-registers includes the general registers, and the watchpoint is gone
+    assert!(out.contains("rip"));
+    assert!(out.contains("dr7      0x0000000000000000 [ ]"));
+}
+
+#[test]
+fn dump_restore_memory() {
+    let dir = env::temp_dir();
+    let pid = std::process::id();
+    let input = dir.join(format!("rdb-restore-{pid}.bin"));
+    let output = dir.join(format!("rdb-dump-{pid}.bin"));
+    let unmapped = dir.join(format!("rdb-dump-unmapped-{pid}.bin"));
+    fs::write(&input, b"rdb test").unwrap();
+    let restore = format!("restore {} $sp", input.display());
+    let dump = format!("dump memory {} $sp $fp", output.display());
+    let dump_unmapped = format!("dump memory {} 0x10 0x20", unmapped.display());
+    let out = run_rdb(&[
+        "break use_vars",
+        "continue",
+        &restore,
+        &dump,
+        &dump_unmapped,
+        "quit",
+    ]);
+    let dumped = fs::read(&output).expect("dump was not written");
+    let dumped_unmapped = fs::read(&unmapped).expect("dump was not written");
+    for path in [&input, &output, &unmapped] {
+        _ = fs::remove_file(path);
+    }
+    assert!(out.contains("restored 8 bytes"));
+    // use_vars has 32 bytes of locals
+    assert_eq!(dumped.len(), 32);
+    assert!(dumped.starts_with(b"rdb test"));
+    assert!(out.contains("could not read 0x10-0x20"));
+    assert!(dumped_unmapped.is_empty());
+}
+
+#[test]
+fn interrupt_continue() {
+    let mut cmd = spawn_rdb_with("spin");
+    let mut stdin = cmd.stdin.take().expect("couldn't get stdin");
+    stdin.write_all(b"continue\n").unwrap();
+    // give the target time to start running before pressing Ctrl-C
+    thread::sleep(Duration::from_millis(500));
+    unsafe { libc::kill(cmd.id() as libc::pid_t, libc::SIGINT) };
+    stdin.write_all(b"quit\n").unwrap();
+    drop(stdin);
+    let out = wait_stdout(cmd);
+    assert!(
+        out.contains("interrupted at 0x"),
+        "target was not interrupted"
+    );
+    // it's in libc's sleep
+    assert!(out.contains("outside the program"));
+}
+
+#[test]
+fn deliver_signal() {
+    // the signal the target stopped with is delivered when it continues
+    let out = run_rdb_with("signal", &["continue", "continue", "quit"]);
+    assert!(out.contains("got signal 10"));
+    assert!(out.contains("handled: true"));
+    // and when it steps, which enters the handler
+    let events = run_rdb_events_with("signal", &["continue", "stepi", "quit"]);
+    assert_eq!(stops(&events).last().map(|s| s.0), Some("handle_usr1"));
+}
+
+#[test]
+fn step_rep_instruction() {
+    let out = run_rdb_with("rep", &["symbol rep_stos", "quit"]);
+    let addr = out
+        .lines()
+        .find_map(|line| line.strip_prefix("notype rep_stos "))
+        .expect("rep_stos not found");
+    let out = run_rdb_with(
+        "rep",
+        &[
+            &format!("break {addr}"),
+            "continue",
+            "print $rcx",
+            "stepi",
+            "print $rcx",
+            "quit",
+        ],
+    );
+    assert!(out.contains("$1: $rcx = 0x40"));
+    // one stepi runs every iteration of the rep stosb
+    assert!(out.contains("$2: $rcx = 0x0"));
+}
+
+#[test]
+fn whatis_ptype() {
+    let out = run_rdb(&[
+        "break test.rs:75",
+        "continue",
+        "whatis n",
+        "whatis &n",
+        "whatis p",
+        "ptype p",
+        "whatis (u8)0x1234",
+        "quit",
+    ]);
+    assert!(out.contains("type = u64\n"));
+    assert!(out.contains("type = *mut u64\n"));
+    assert!(out.contains("type = Point\n"));
+    assert!(out
+        .contains("type = struct Point {\n    x: i32, // offset 0\n    y: i32, // offset 4\n}\n"));
+    assert!(out.contains("type = u8\n"));
+}
+
+#[test]
+fn display_format() {
+    let out = run_rdb(&[
+        "break use_vars",
+        "continue",
+        "display/i $pc",
+        "display/d 0x20",
+        "stepi",
+        "info display",
+        "quit",
+    ]);
+    // the breakpoint's int3 should not show up in the disassembly
+    assert!(out.contains("1: /i $pc = 0x"));
+    assert!(out.contains(": mov "));
+    assert!(!out.contains("int3"));
+    assert!(out.contains("2: /d 0x20 = 32"));
+    // displays are shown again after stepping
+    assert_eq!(out.matches("2: /d 0x20 = 32").count(), 2);
+}
+
+#[test]
+fn step_to_branch() {
+    let out = run_rdb(&["break use_vars", "continue", "step-branch", "sb", "quit"]);
+    // use_vars has no calls, so the first taken branch is its return
+    assert!(out.contains(": ret => 0x"));
+    assert!(out.contains(": call "));
+    assert!(out.contains("fn greeting()"));
+}
+
+#[test]
+fn print_enums() {
+    let out = run_rdb(&[
+        "break test.rs:49",
+        "continue",
+        "print some_box",
+        "print none_box",
+        "print some_int",
+        "print res",
+        "print ordering",
+        "quit",
+    ]);
+    assert!(out.contains("some_box = Some(0x"));
+    assert!(out.contains("none_box = None"));
+    assert!(out.contains("some_int = Some(5)"));
+    assert!(out.contains("res = Err(-3)"));
+    assert!(out.contains("ordering = Greater"));
+}
+
+#[test]
+fn backtrace_compare() {
+    let out = run_rdb(&["break a", "continue", "bt --compare", "quit"]);
+    assert!(out.contains("unwinders agree"));
+    // at the first instruction of a, rbp still points to b's caller's frame
+    let out = run_rdb(&[
+        "break test.rs:20",
+        "continue",
+        "stepi",
+        "bt --compare",
+        "quit",
+    ]);
+    assert!(out.contains("unwinders disagree starting at frame #2"));
+}
+
+#[test]
+fn x87_registers() {
+    let out = run_rdb_with(
+        "x87",
+        &[
+            "break x87.rs:15",
+            "continue",
+            "info registers float",
+            "quit",
+        ],
+    );
+    assert!(out.contains("st0  valid   3.14159"));
+    assert!(out.contains("st1  valid   1 "));
+    assert!(out.contains("st2  zero    0 "));
+    assert!(out.contains("st3  empty"));
+}
+
+#[test]
+fn vector_registers() {
+    if !is_x86_feature_detected!("avx") {
+        return;
+    }
+    let out = run_rdb_with(
+        "avx",
+        &[
+            "break avx.rs:20",
+            "continue",
+            "info registers ymm15 /f",
+            "info registers xmm15 /w",
+            "info registers vector",
+            "quit",
+        ],
+    );
+    assert!(out.contains("ymm15 {1, 2, 3, 4, 5, 6, 7, 8.5}"));
+    assert!(out.contains("xmm15 {0x3f800000, 0x40000000, 0x40400000, 0x40800000}"));
+    assert!(out.contains("ymm0  0x"));
+}
+
+#[test]
+fn info_vector() {
+    if !is_x86_feature_detected!("avx") {
+        return;
+    }
+    let out = run_rdb_with(
+        "avx",
+        &["break avx.rs:20", "continue", "info vector", "quit"],
+    );
+    assert!(out.contains(
+        "ymm15 0x4108000040e0000040c0000040a000004080000040400000400000003f800000\n      f32 {1, 2, 3, 4, 5, 6, 7, 8.5}\n      f64 {2.000000473111868, "
+    ));
+    assert!(out.contains("ymm0  0x"));
+}
+
+#[test]
+fn info_float() {
+    let out = run_rdb_with(
+        "x87",
+        &[
+            "break x87.rs:15",
+            "continue",
+            "info float",
+            "register dump --fp",
+            "info registers fstat",
+            "info registers st1",
+            "quit",
+        ],
+    );
+    // three values pushed
+    assert_eq!(out.matches("fstat 0x2800 [ top=5 ]").count(), 3);
+    assert!(out.contains("fctrl 0x037f [ masked=all precision=extended rounding=nearest ]"));
+    assert!(out.contains("mxcsr 0x1f80 [ masked=all rounding=nearest ]"));
+    assert_eq!(out.matches("st0  valid   3.14159").count(), 2);
+    assert_eq!(out.matches("st1  valid   1 ").count(), 3);
+}
+
+#[test]
+fn info_mapping() {
+    let out = run_rdb(&[
+        "break a",
+        "continue",
+        "info mapping $pc",
+        "info mapping $sp",
+        "info mapping 0x10",
+        "quit",
+    ]);
+    assert!(out.contains("/test (r-xp)"));
+    assert!(out.contains("is test::a+0x"));
+    assert!(out.contains("is in [stack] (rw-p)"));
+    assert!(out.contains("0x10 is not mapped"));
+}
+
+#[test]
+fn print_casts() {
+    let out = run_rdb(&[
+        "break use_struct",
+        "continue",
+        "print (u8)0x1234",
+        "print (i8)0xff",
+        "print (Point)0xfffffffc00000003",
+        "print (Nope)1",
+        "quit",
+    ]);
+    assert!(out.contains("(u8)0x1234 = 52"));
+    assert!(out.contains("(i8)0xff = -1"));
+    assert!(out.contains("(Point)0xfffffffc00000003 = Point { x: 3, y: -4 }"));
+
+    // read through a pointer, which we only know once the program is running
+    let mut cmd = spawn_rdb();
+    let mut stdin = cmd.stdin.take().expect("couldn't get stdin");
+    let stdout = cmd.stdout.take().expect("couldn't get stdout");
+    stdin
+        .write_all(b"break test.rs:75\ncontinue\nprint pp\n")
+        .unwrap();
+    let mut lines = BufReader::new(stdout).lines();
+    let addr = lines
+        .by_ref()
+        .map(|line| line.unwrap())
+        .find_map(|line| line.strip_prefix("$1: pp = ").map(|s| s.to_string()))
+        .expect("pp was not printed");
+    writeln!(
+        stdin,
+        "print (*test::Point){addr}\nprint (*i32){addr}+4\nquit"
+    )
+    .unwrap();
+    drop(stdin);
+    let out: Vec<String> = lines.map(|line| line.unwrap()).collect();
+    cmd.wait().unwrap();
+    assert!(out.contains(&format!(
+        "$2: (*test::Point){addr} = Point {{ x: 3, y: -4 }}"
+    )));
+    assert!(out.contains(&format!("$3: (*i32){addr}+4 = -4")));
+}
+
+#[test]
+fn value_history() {
+    let out = run_rdb(&[
+        "break use_struct",
+        "continue",
+        "print n",
+        "print $1 + 8",
+        "print (test::Point)0xfffffffc00000003",
+        "print $",
+        "print $3 + 1",
+        "print $9",
+        "quit",
+    ]);
+    assert!(out.contains("$1: n = 3"));
+    assert!(out.contains("$2: $1 + 8 = 0xb"));
+    // values from the history keep their types
+    assert!(out.contains("$4: $ = Point { x: 3, y: -4 }"));
+    assert!(!out.contains("$5:"));
+}
+
+#[test]
+fn convenience_variables() {
+    let out = run_rdb(&[
+        "break use_struct",
+        "continue",
+        "set $base = $sp + 8",
+        "print $base - $sp",
+        "print $count = n",
+        "print $count",
+        "set $sp = 1",
+        "print $unset",
+        "quit",
+    ]);
+    assert!(out.contains("$1: $base - $sp = 0x8"));
+    assert!(out.contains("$2: $count = n = 3"));
+    // variables keep the type of their value
+    assert!(out.contains("$3: $count = 3"));
+    assert!(!out.contains("$4:"));
+}
+
+#[test]
+fn print_collections() {
+    let out = run_rdb(&[
+        "break test.rs:89",
+        "continue",
+        "print arr",
+        "print slice",
+        "print s",
+        "whatis arr",
+        "whatis slice",
+        "quit",
+    ]);
+    assert!(out.contains("arr = [1, 2, 3]"));
+    assert!(out.contains("slice = &[2, 3]"));
+    assert!(out.contains("s = \"hi\""));
+    assert!(out.contains("type = [u16; 3]\n"));
+    assert!(out.contains("type = &[u16]\n"));
+}
+
+#[test]
+fn line_for_address() {
+    let out = run_rdb(&["symbol countdown", "symbol greeting", "quit"]);
+    let addr = |name: &str| {
+        out.lines()
+            .find_map(|line| line.strip_prefix(&format!("func {name} ")))
+            .expect("function not found")
+            .to_string()
+    };
+    let (countdown, greeting) = (addr("countdown"), addr("greeting"));
+    let path = env::temp_dir().join(format!("rdb-addrs-{}.txt", std::process::id()));
+    fs::write(&path, format!("{countdown}\n\n{greeting}\n")).unwrap();
+    let cmd = format!("info line-for-address --file {} 0x0", path.display());
+    let out = run_rdb(&[&cmd, "quit"]);
+    fs::remove_file(&path).unwrap();
+    let lines: Vec<_> = out.lines().skip(1).collect();
+    assert_eq!(lines.len(), 3, "{out}");
+    assert!(lines[0].starts_with(&format!("{countdown} src/bin/test.rs:")));
+    assert!(lines[0].ends_with(":countdown"));
+    assert!(lines[1].ends_with(":greeting"));
+    assert!(lines[2].starts_with("0x0 "));
+}
+
+#[test]
+fn info_scope() {
+    // works before the program is running
+    let out = run_rdb(&["info scope test.rs:6", "info scope countdown", "quit"]);
+    assert!(out.contains("a: u64 (8 bytes, at frame offset"));
+    assert!(out.contains("b: u64 (8 bytes, at frame offset"));
+    assert!(out.contains("n: u64 (8 bytes, at frame offset"));
+}
+
+#[test]
+fn next_over_recursion() {
+    let commands = [
+        "break test.rs:37",
+        "continue",
+        "step",
+        "next",
+        "next",
+        "next",
+        "quit",
+    ];
+    // the recursive call hits the same lines in a deeper frame, which next
+    // should skip over
+    let events = run_rdb_events(&commands);
+    assert_eq!(stops(&events).last(), Some(&("countdown", 58)));
+    let out = run_rdb(&[&commands[..6], &["print n"]].concat());
+    assert!(out.contains("n = 3"));
+}
+
+#[test]
+fn next_from_function_entry() {
+    // at the first instruction of a, rbp is still b's frame pointer
+    let events = run_rdb_events(&["break test.rs:20", "continue", "stepi", "next", "quit"]);
+    assert_eq!(stops(&events).last(), Some(&("test::a", 17)));
+}
+
+/// A minimal client for the gdb remote protocol, to test the server.
+struct RemoteClient {
+    reader: BufReader<std::net::TcpStream>,
+}
+
+impl RemoteClient {
+    fn request(&mut self, data: &str) -> String {
+        let sum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        let stream = self.reader.get_mut();
+        write!(stream, "${data}#{sum:02x}").unwrap();
+        let mut packet = vec![];
+        // the ack, then the reply
+        self.reader.read_until(b'$', &mut packet).unwrap();
+        assert_eq!(packet, b"+$");
+        packet.clear();
+        self.reader.read_until(b'#', &mut packet).unwrap();
+        packet.pop();
+        let mut sum = [0; 2];
+        std::io::Read::read_exact(&mut self.reader, &mut sum).unwrap();
+        self.reader.get_mut().write_all(b"+").unwrap();
+        String::from_utf8(packet).unwrap()
+    }
+}
+
+#[test]
+fn gdb_server() {
+    let mut cmd = spawn_rdb_flags(&["--server", "127.0.0.1:0"], "test");
+    let mut lines = BufReader::new(cmd.stdout.take().unwrap()).lines();
+    let addr = lines
+        .by_ref()
+        .map(|line| line.unwrap())
+        .find_map(|line| line.strip_prefix("listening on ").map(|s| s.to_string()))
+        .expect("server did not start");
+    let stream = std::net::TcpStream::connect(addr).unwrap();
+    let mut client = RemoteClient {
+        reader: BufReader::new(stream),
+    };
+    assert!(client.request("qSupported:swbreak+").contains("swbreak+"));
+    assert_eq!(client.request("?"), "T05");
+    // rax through r15 and rip, then the 32-bit rflags and segment registers
+    assert_eq!(client.request("g").len(), 2 * (17 * 8 + 7 * 4));
+    let pc_hex = client.request("p10");
+    let pc = u64::from_le_bytes(
+        (0..8)
+            .map(|i| u8::from_str_radix(&pc_hex[2 * i..2 * i + 2], 16).unwrap())
+            .collect::<Vec<_>>()
+            .try_into()
+            .unwrap(),
+    );
+    let code = client.request(&format!("m{pc:x},1"));
+    // the breakpoint's int3 is hidden from memory reads
+    assert_eq!(client.request(&format!("Z0,{pc:x},1")), "OK");
+    assert_eq!(client.request(&format!("m{pc:x},1")), code);
+    // stepping starts from the breakpoint without hitting it
+    assert_eq!(client.request("vCont;s"), "T05");
+    assert_ne!(client.request("p10"), pc_hex);
+    assert_eq!(client.request("m0,1"), "E0e");
+    // a large read is cut short to fit in a packet
+    assert!(client.request(&format!("m{pc:x},100000")).len() <= 0x4000);
+    assert_eq!(client.request("vCont;c"), "W00");
+    drop(client);
+    let out: Vec<String> = lines.map(|line| line.unwrap()).collect();
+    cmd.wait().unwrap();
+    assert!(out.contains(&"Hello, world".to_string()));
+}
+
+#[test]
+fn gdb_server_signal() {
+    let mut cmd = spawn_rdb_flags(&["--server", "127.0.0.1:0"], "segfault");
+    // keep reading the output, so the server can keep writing to it
+    let mut lines = BufReader::new(cmd.stdout.take().unwrap()).lines();
+    let addr = lines
+        .by_ref()
+        .map(|line| line.unwrap())
+        .find_map(|line| line.strip_prefix("listening on ").map(|s| s.to_string()))
+        .expect("server did not start");
+    let stream = std::net::TcpStream::connect(addr).unwrap();
+    let mut client = RemoteClient {
+        reader: BufReader::new(stream),
+    };
+    assert_eq!(client.request("vCont;c"), "T0b");
+    // gdb asks for the stop reason again, which is still the SIGSEGV
+    assert_eq!(client.request("?"), "T0b");
+    drop(client);
+    lines.for_each(drop);
+    cmd.wait().unwrap();
+}
+
+/// Read a Debug Adapter Protocol message.
+fn read_dap_message(reader: &mut impl BufRead) -> Value {
+    let mut header = String::new();
+    reader.read_line(&mut header).unwrap();
+    let len: usize = header
+        .trim()
+        .strip_prefix("Content-Length: ")
+        .expect("no Content-Length")
+        .parse()
+        .unwrap();
+    let mut blank = String::new();
+    reader.read_line(&mut blank).unwrap();
+    let mut body = vec![0; len];
+    std::io::Read::read_exact(reader, &mut body).unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[test]
+fn debug_adapter() {
+    let mut cmd = spawn_rdb_flags(&["--dap"], "test");
+    let mut stdin = cmd.stdin.take().unwrap();
+    let mut reader = BufReader::new(cmd.stdout.take().unwrap());
+    let mut seq = 0;
+    let mut request = |command: &str, args: Value| {
+        seq += 1;
+        let body = serde_json::json!({
+            "seq": seq, "type": "request", "command": command, "arguments": args,
+        })
+        .to_string();
+        write!(stdin, "Content-Length: {}\r\n\r\n{body}", body.len()).unwrap();
+    };
+    // the response to a request, skipping events
+    let response = |reader: &mut BufReader<_>, command: &str| loop {
+        let msg = read_dap_message(reader);
+        if msg["type"] == "response" {
+            assert_eq!(msg["command"], command);
+            assert_eq!(msg["success"], true, "{msg}");
+            return msg["body"].clone();
+        }
+    };
+    let event = |reader: &mut BufReader<_>, name: &str| loop {
+        let msg = read_dap_message(reader);
+        if msg["type"] == "event" && msg["event"] == name {
+            return msg["body"].clone();
+        }
+    };
+
+    request("initialize", serde_json::json!({"adapterID": "rdb"}));
+    response(&mut reader, "initialize");
+    event(&mut reader, "initialized");
+    request("launch", serde_json::json!({}));
+    response(&mut reader, "launch");
+    request(
+        "setBreakpoints",
+        serde_json::json!({"source": {"path": "test.rs"}, "breakpoints": [{"line": 75}]}),
+    );
+    let body = response(&mut reader, "setBreakpoints");
+    assert_eq!(body["breakpoints"][0]["verified"], true);
+    request("configurationDone", serde_json::json!({}));
+    response(&mut reader, "configurationDone");
+    let stopped = event(&mut reader, "stopped");
+    assert_eq!(stopped["reason"], "breakpoint");
+
+    request("stackTrace", serde_json::json!({"threadId": 1}));
+    let body = response(&mut reader, "stackTrace");
+    let top = &body["stackFrames"][0];
+    assert_eq!(top["name"], "use_struct");
+    assert_eq!(top["line"], 75);
+    assert!(top["source"]["path"]
+        .as_str()
+        .unwrap()
+        .ends_with("src/bin/test.rs"));
+
+    request("variables", serde_json::json!({"variablesReference": 1}));
+    let body = response(&mut reader, "variables");
+    let vars = body["variables"].as_array().unwrap();
+    let p = vars
+        .iter()
+        .find(|v| v["name"] == "p")
+        .expect("no variable p");
+    assert_eq!(p["value"], "Point { x: 3, y: -4 }");
+
+    request("evaluate", serde_json::json!({"expression": "n"}));
+    assert_eq!(response(&mut reader, "evaluate")["result"], "3");
+
+    request("continue", serde_json::json!({"threadId": 1}));
+    response(&mut reader, "continue");
+    event(&mut reader, "terminated");
+    request("disconnect", serde_json::json!({}));
+    response(&mut reader, "disconnect");
+    drop(stdin);
+    cmd.wait().unwrap();
+}
+
+#[test]
+fn examine_memory() {
+    let out = run_rdb(&[
+        "break test.rs:75",
+        "continue",
+        "print pp",
+        "x/2dw $1",
+        "x/8xb $1",
+        "x/4c $pc",
+        "x/4xw 0",
+        "quit",
+    ]);
+    let lines: Vec<_> = out.lines().collect();
+    let dw = lines
+        .iter()
+        .find(|line| line.ends_with(":\t3\t-4"))
+        .expect("p's fields not printed");
+    let addr = dw.split(':').next().unwrap();
+    assert!(out.contains(&format!(
+        "{addr}:\t0x03\t0x00\t0x00\t0x00\t0xfc\t0xff\t0xff\t0xff"
+    )));
+    // the breakpoint's int3 isn't shown
+    assert!(!out.contains("204 '"));
+}
+
+#[test]
+fn write_memory() {
+    let out = run_rdb(&[
+        "break test.rs:75",
+        "continue",
+        "print pp",
+        "write-mem --bytes $1 7 0",
+        "print p",
+        "write-mem $1 0xfffffff600000001",
+        "print p",
+        // these are rejected without writing anything
+        "write-mem $1 1 2",
+        "write-mem --bytes $1 256",
+        "print p",
+        "quit",
+    ]);
+    assert!(out.contains("wrote 2 bytes to 0x"));
+    assert!(out.contains("$2: p = Point { x: 7, y: -4 }"));
+    assert!(out.contains("wrote 8 bytes to 0x"));
+    assert!(out.contains("$3: p = Point { x: 1, y: -10 }"));
+    assert!(out.contains("$4: p = Point { x: 1, y: -10 }"));
+}
+
+#[test]
+fn write_memory_over_breakpoint() {
+    // overwriting a breakpoint's instruction keeps the breakpoint installed,
+    // and the new byte is what shows up and runs
+    let out = run_rdb(&[
+        "break test.rs:75",
+        "continue",
+        "write-mem --bytes $pc 0x90",
+        "x/1xb $pc",
+        "quit",
+    ]);
+    assert!(out.contains("wrote 1 bytes to 0x"));
+    assert!(out.contains(":\t0x90"));
+}
+
+#[test]
+fn examine_large_region() {
+    let out = run_rdb(&["break test.rs:75", "continue", "x/256xg $sp", "quit"]);
+    let rows = out.lines().filter(|line| line.starts_with("0x7ff")).count();
+    assert_eq!(rows, 128);
+    assert!(!out.contains("cannot access memory"));
+}
+
+#[test]
+fn print_c_string() {
+    // at the entry point, argv[0] is right above argc on the stack
+    let out = run_rdb(&["print (*u64)($sp+8)", "print str $1", "print str 0", "quit"]);
+    let line = out
+        .lines()
+        .find(|line| line.starts_with('"'))
+        .expect("string not printed");
+    let (s, count) = line.rsplit_once(" (").unwrap();
+    assert!(s.ends_with("/test\""), "wrong string {s}");
+    assert_eq!(count, format!("{} bytes)", s.len() - 2));
+}
+
+#[test]
+fn info_breakpoints() {
+    let main = breakpoint_addr("main");
+    let out = run_rdb(&[
+        "info breakpoints",
+        "break use_struct",
+        "break main",
+        &format!("disable {main}"),
+        "info breakpoints",
+        "quit",
+    ]);
+    assert!(out.contains("no breakpoints"));
+    let table: Vec<_> = out
+        .lines()
+        .skip_while(|line| !line.starts_with("Num  Enb"))
+        .skip(1)
+        .collect();
+    // sorted by address, with the disabled breakpoint still listed
+    assert!(table[0].starts_with(&format!("2    n    {main} ")));
+    assert!(table[0].ends_with("(in test::main)"));
+    assert!(table[1].starts_with("1    y    0x"));
+    assert!(table[1].ends_with("(in use_struct)"));
+}
+
+#[test]
+fn delete_breakpoint() {
+    let main = breakpoint_addr("main");
+    let out = run_rdb(&[
+        "break main",
+        &format!("delete {main}"),
+        "info breakpoints",
+        "break main",
+        "continue",
+        "quit",
+    ]);
+    assert!(out.contains("no breakpoints"));
+    // the address is free again, so this is a new breakpoint that gets hit
+    assert!(out.contains(&format!("set breakpoint 2 at {main}:")));
+    assert!(out.contains(&format!("hit breakpoint {main}\n")));
+    assert!(!out.contains("exited"));
+}
+
+#[test]
+fn delete_breakpoint_after_exit() {
+    let main = breakpoint_addr("main");
+    let out = run_rdb(&[
+        "break main",
+        "continue",
+        "continue",
+        &format!("delete {main}"),
+        "info breakpoints",
+        "quit",
+    ]);
+    assert!(out.contains("program exited"));
+    assert!(out.contains("no breakpoints"));
+}
+
+#[test]
+fn enable_breakpoint() {
+    let main = breakpoint_addr("main");
+    let out = run_rdb(&[
+        "break main",
+        &format!("disable {main}"),
+        &format!("enable {main}"),
+        "info breakpoints",
+        "continue",
+        "quit",
+    ]);
+    assert!(out.contains(&format!("1    y    {main} ")));
+    assert!(out.contains(&format!("hit breakpoint {main}\n")));
+}
+
+#[test]
+fn conditional_breakpoint() {
+    let out = run_rdb(&[
+        "break countdown if rdi == 1",
+        "info breakpoints",
+        "continue",
+        "register read rdi",
+        "continue",
+        "quit",
+    ]);
+    assert!(out.contains("stop only if rdi == 1"));
+    assert_eq!(out.matches("hit breakpoint").count(), 1);
+    assert!(out.contains("0x1\n"));
+    assert!(out.contains("program exited"));
+
+    let countdown = breakpoint_addr("countdown");
+    let out = run_rdb(&[
+        "break countdown",
+        &format!("condition {countdown} rdi == 2"),
+        "continue",
+        "register read rdi",
+        // without the condition, the next call stops
+        &format!("condition {countdown}"),
+        "continue",
+        "register read rdi",
+        "quit",
+    ]);
+    let regs: Vec<_> = out.lines().filter(|line| line.starts_with("0x")).collect();
+    assert_eq!(regs, vec!["0x2", "0x1"]);
+}
+
+#[test]
+fn temporary_breakpoint() {
+    let countdown = breakpoint_addr("countdown");
+    let out = run_rdb(&[
+        "tbreak countdown",
+        "info breakpoints",
+        "continue",
+        "info breakpoints",
+        "continue",
+        "quit",
+    ]);
+    assert!(out.contains("temporary, deleted when hit"));
+    // countdown is recursive, but only the first call stops
+    assert_eq!(
+        out.matches(&format!("hit breakpoint {countdown}\n"))
+            .count(),
+        1
+    );
+    assert!(out.contains("deleted temporary breakpoint 1"));
+    assert!(out.contains("no breakpoints"));
+    assert!(out.contains("program exited"));
+}
+
+#[test]
+fn temporary_breakpoint_while_stepping() {
+    // next stops at the temporary breakpoint, which goes away along with
+    // next's own breakpoints
+    let out = run_rdb(&[
+        "break test.rs:37",
+        "continue",
+        "tbreak countdown",
+        "next",
+        "finish",
+        "continue",
+        "quit",
+    ]);
+    assert!(out.contains("deleted temporary breakpoint 2"));
+    assert!(returned_to(
+        &out,
+        "src/bin/test.rs, line 37 (in test::main)"
+    ));
+    assert!(out.contains("program exited"));
+}
+
+#[test]
+fn ignore_breakpoint() {
+    let countdown = breakpoint_addr("countdown");
+    let out = run_rdb(&[
+        "break countdown",
+        &format!("ignore {countdown} 2"),
+        "info breakpoints",
+        "continue",
+        "register read rdi",
+        "continue",
+        "register read rdi",
+        "quit",
+    ]);
+    assert!(out.contains(&format!(
+        "will ignore next 2 crossings of breakpoint at {countdown}\n"
+    )));
+    assert!(out.contains("\twill ignore next 2 crossings\n"));
+    // the calls with n = 3 and 2 are passed
+    let regs: Vec<_> = out.lines().filter(|line| line.starts_with("0x")).collect();
+    assert_eq!(regs, vec!["0x1", "0x0"]);
+    assert_eq!(out.matches("hit breakpoint").count(), 2);
+}
+
+#[test]
+fn backtrace_at_function_entry() {
+    // before countdown's prologue saves rbp, following frame pointers would
+    // skip main, but eh_frame gets the return address right
+    let countdown = symbol_addr("countdown");
+    let out = run_rdb(&[
+        &format!("tbreak {countdown}"),
+        "continue",
+        "bt",
+        "finish",
+        "quit",
+    ]);
+    assert!(out.contains(&format!(
+        "#1 {countdown} in countdown at src/bin/test.rs:53\n"
+    )));
+    assert!(has_frame(&out, 2, "test::main at src/bin/test.rs:37:16"));
+    assert!(!out.contains("#3 0x"));
+    assert!(returned_to(
+        &out,
+        "src/bin/test.rs, line 37 (in test::main)"
+    ));
+}
+
+#[test]
+fn breakpoint_at_unmapped_address() {
+    // reading the original instruction fails, so the breakpoint can't be
+    // installed
+    let out = run_rdb(&["break 0x100000000000", "info breakpoints", "continue"]);
+    assert!(out.contains("1    n    0x100000000000"));
+    assert!(out.contains("program exited"));
+}
+
+#[test]
+fn program_killed_by_signal() {
+    let mut cmd = Command::new(exe_path("rdb"))
+        .arg(exe_path("abort"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to launch debugger");
+    let mut stdin = cmd.stdin.take().unwrap();
+    stdin.write_all(b"continue\ncontinue\nquit\n").unwrap();
+    drop(stdin);
+    let out = cmd.wait_with_output().unwrap();
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    // the debugger sees the signal first, then the program dies when it's
+    // delivered
+    assert!(stdout.contains("got signal 6 (SIGABRT)"));
+    assert!(stderr.contains("program terminated with signal 6 (SIGABRT)"));
+    assert!(!stderr.contains("panicked"));
+}
+
+#[test]
+fn handle_signal() {
+    let out = run_rdb_with(
+        "signal",
+        &["handle SIGUSR1 nopass", "continue", "continue", "quit"],
+    );
+    assert!(out.contains("SIGUSR1  yes   no"));
+    assert!(out.contains("got signal 10 (SIGUSR1)"));
+    assert!(out.contains("handled: false"));
+
+    let out = run_rdb_with("signal", &["handle usr1 nostop", "continue", "quit"]);
+    assert!(!out.contains("got signal"));
+    assert!(out.contains("handled: true"));
+
+    let out = run_rdb_with("signal", &["handle 10 nostop nopass", "continue", "quit"]);
+    assert!(!out.contains("got signal"));
+    assert!(out.contains("handled: false"));
+}
+
+#[test]
+fn segfault_report() {
+    let out = run_rdb_with("segfault", &["continue", "quit"]);
+    assert!(out.contains("segfault accessing 0x10 (address not mapped)"));
+    assert!(out.contains("file src/bin/segfault.rs, line 5 (in segfault::main)"));
+}
+
+#[test]
+fn attach_to_pid() {
+    let mut target = Command::new(exe_path("spin"))
+        .spawn()
+        .expect("failed to launch target");
+    let rdb = Command::new(exe_path("rdb"))
+        .args(["--pid", &target.id().to_string()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .expect("failed to launch debugger");
+    let out = send_commands(rdb, &["break main", "quit"]);
+    // the target keeps running after the debugger detaches
+    let still_running = target.try_wait().unwrap().is_none();
+    _ = target.kill();
+    _ = target.wait();
+    assert!(out.contains("detached from pid"));
+    assert!(still_running, "target exited after detaching");
+}
+
+#[test]
+fn detach_runs_to_completion() {
+    let out = run_rdb(&[
+        "break main",
+        "break greeting",
+        "continue",
+        "detach",
+        "info breakpoints",
+    ]);
+    assert!(out.contains("detached from pid"));
+    // the breakpoint on greeting was removed, so the target finishes, and the
+    // debugger stops reading commands
+    assert!(out.contains("Hello, world"), "target did not finish");
+    assert!(!out.contains("Num  Enb"));
+}
+
+#[test]
+fn run_restarts_program() {
+    let main = breakpoint_addr("main");
+    let out = run_rdb(&[
+        "break main",
+        "tbreak greeting",
+        "continue",
+        "run",
+        "info breakpoints",
+        "continue",
+        "continue",
+        "quit",
+    ]);
+    assert_eq!(out.matches("debugging pid").count(), 2);
+    // the breakpoint on main is hit again in the new run
+    assert_eq!(out.matches(&format!("hit breakpoint {main}\n")).count(), 2);
+    // but the temporary breakpoint was dropped
+    let table: Vec<_> = out
+        .lines()
+        .skip_while(|line| !line.starts_with("Num  Enb"))
+        .skip(1)
+        .take_while(|line| line.starts_with(|c: char| c.is_ascii_digit() || c == '\t'))
+        .collect();
+    assert_eq!(table.len(), 1, "{table:?}");
+    assert!(table[0].starts_with(&format!("1    y    {main} ")));
+    assert!(table[0].ends_with("(in test::main)"));
+    assert!(out.contains("Hello, world"));
+    assert!(out.contains("program exited"));
+}
+
+#[test]
+fn nexti_over_call() {
+    // line 37 is `mov edi, 3; call countdown`, and the recursive countdown
+    // runs to completion before nexti stops after the call
+    let events = run_rdb_events(&["break test.rs:37", "continue", "nexti", "nexti", "quit"]);
+    assert_eq!(stops(&events), vec![("test::main", 37); 3]);
+    let pcs: Vec<_> = events.iter().filter_map(|e| e["pc"].as_u64()).collect();
+    // the second nexti stops right after the 5-byte call
+    assert_eq!(pcs[2] - pcs[1], 5);
+    // a breakpoint in the called function still stops it
+    let events = run_rdb_events(&[
+        "break test.rs:37",
+        "continue",
+        "break countdown",
+        "nexti",
+        "nexti",
+        "quit",
+    ]);
+    assert_eq!(stops(&events).last().map(|s| s.0), Some("countdown"));
+}
+
+#[test]
+fn step_counts() {
+    let events = run_rdb_events(&["break use_vars", "continue", "next 3", "quit"]);
+    assert_eq!(stops(&events), vec![("use_vars", 5), ("use_vars", 7)]);
+    // only the final location is printed
+    let out = run_rdb(&["break use_vars", "continue", "next 3", "quit"]);
+    let current_lines = out.lines().filter(|line| line.contains(" >  "));
+    assert_eq!(current_lines.count(), 2);
+    // a user breakpoint ends the steps early
+    let events = run_rdb_events(&[
+        "break use_vars",
+        "break test.rs:6",
+        "continue",
+        "next 3",
+        "quit",
+    ]);
+    assert_eq!(stops(&events), vec![("use_vars", 5), ("use_vars", 6)]);
+    let events = run_rdb_events(&["break use_vars", "continue", "step 2", "quit"]);
+    assert_eq!(stops(&events).last(), Some(&("use_vars", 6)));
+}
+
+#[test]
+fn step_over_function_without_source() {
+    // getpid is in libc, which has no debug info, so step runs until it
+    // returns rather than stopping inside it
+    let events = run_rdb_events_with("libc_call", &["break main", "continue", "step", "quit"]);
+    assert_eq!(
+        stops(&events),
+        vec![("libc_call::main", 4), ("libc_call::main", 5)]
+    );
+}
+
+#[test]
+fn step_over_library_code() {
+    // println! calls into core::fmt code instantiated in the program (whose
+    // source isn't here) and into the precompiled std, neither of which step
+    // should stop in
+    let events = run_rdb_events(&["break test.rs:12", "continue", "step", "quit"]);
+    assert_eq!(stops(&events), vec![("greeting", 12), ("greeting", 13)]);
+}
+
+#[test]
+fn function_breakpoint_after_prologue() {
+    // the breakpoint goes where countdown's body starts, at the second
+    // is_stmt row, after the prologue
+    assert!(hex(&breakpoint_addr("countdown")) > hex(&symbol_addr("countdown")));
+    let out = run_rdb(&["break countdown", "continue", "print n", "bt", "quit"]);
+    // the argument and frame are already set up
+    assert!(out.contains("n = 3"));
+    assert!(out.contains(" in test::main at "));
+}
+
+#[test]
+fn line_breakpoint_without_code() {
+    // line 16 is a comment, so the breakpoint goes on the next line with code
+    let out = run_rdb(&["break test.rs:16", "quit"]);
+    assert!(out.contains("line 16 has no code, breakpoint set at line 17"));
+    assert!(out.contains("line 17 (in test::a)"));
+    let events = run_rdb_events(&["break test.rs:16", "continue", "quit"]);
+    assert_eq!(stops(&events), vec![("test::a", 17)]);
+}
+
+#[test]
+fn info_locals() {
+    let out = run_rdb(&["break test.rs:7", "continue", "info locals", "quit"]);
+    assert!(out.contains("a = 3\nb = 2\nc = 5\n"));
+    let out = run_rdb(&["info locals", "quit"]);
+    assert!(out.contains("no locals"));
+}
+
+#[test]
+fn print_globals() {
+    let out = run_rdb_with(
+        "globals",
+        &[
+            "print COUNTER",
+            "break globals.rs:8",
+            "continue",
+            "print COUNTER",
+            "print globals::ENABLED",
+            "print globals::other::ENABLED",
+            "print other::ENABLED",
+            "whatis COUNTER",
+            "quit",
+        ],
+    );
+    assert!(out.contains("COUNTER = 41"));
+    assert!(out.contains("COUNTER = 42"));
+    assert!(out.contains("globals::ENABLED = true"));
+    // a qualified name has to match the whole path
+    assert!(out.contains("globals::other::ENABLED = false"));
+    assert!(!out.contains(": other::ENABLED ="));
+    assert!(out.contains("type = u32"));
+}
+
+#[test]
+fn set_var() {
+    let out = run_rdb(&[
+        "break test.rs:6",
+        "continue",
+        "set var a = 10",
+        "print a",
+        "next",
+        "print c",
+        "quit",
+    ]);
+    assert!(out.contains("a = 10"));
+    assert!(out.contains("c = 12"));
+
+    let out = run_rdb_with(
+        "globals",
+        &[
+            "break globals.rs:8",
+            "continue",
+            "set var COUNTER = 4294967296",
+            "print COUNTER",
+            "set var COUNTER = 7",
+            "continue",
+            "quit",
+        ],
+    );
+    // the value that doesn't fit in a u32 is rejected
+    assert!(out.contains("COUNTER = 42"));
+    assert!(out.contains("counter is 7"));
+}
+
+#[test]
+fn info_args() {
+    let out = run_rdb(&[
+        "break countdown",
+        "continue",
+        "info args",
+        "info locals",
+        "continue",
+        "info args",
+        "quit",
+    ]);
+    assert!(out.contains("n = 3\nno locals\n"));
+    assert!(out.contains("n = 2\n"));
+    let out = run_rdb(&["break use_vars", "continue", "info args", "quit"]);
+    assert!(out.contains("no arguments"));
+}
+
+#[test]
+fn registers_show_symbols() {
+    let offset = hex(&breakpoint_addr("countdown")) - hex(&symbol_addr("countdown"));
+    let out = run_rdb(&["break countdown", "continue", "info registers", "quit"]);
+    assert!(out.lines().any(|line| line.starts_with("rip      0x0000")
+        && line.ends_with(&format!(" <countdown+0x{offset:x}>"))));
+    // a small value isn't an address in the program
+    assert!(out.contains("rdi      0x0000000000000003\n"));
+}
+
+#[test]
+fn info_functions() {
+    let names = ["use_collections", "use_enums", "use_struct", "use_vars"];
+    let out = run_rdb(&[
+        "info functions ^use_",
+        "info functions ^nonexistent$",
+        "quit",
+    ]);
+    // sorted by name, each at the address symbol finds
+    let expected: String = names
+        .iter()
+        .map(|name| format!("{} {name}\n", symbol_addr(name)))
+        .collect();
+    assert!(out.contains(&expected), "{out}");
+    assert!(out.contains("no matching functions"));
+}
+
+#[test]
+fn symbol_lookup_regex() {
+    let out = run_rdb(&[
+        "symbol use_",
+        "symbol -r use_",
+        "symbol -r ^test::ma",
+        "quit",
+    ]);
+    // an exact lookup finds nothing, while a regex matches anywhere
+    assert_eq!(out.matches("func use_").count(), 4);
+    // regex matches are at the same addresses as exact lookups
+    for name in ["use_vars", "use_struct"] {
+        assert!(out.contains(&format!("func {name} {}\n", symbol_addr(name))));
+    }
+    assert!(out.contains("func test::main 0x"));
+}
+
+#[test]
+fn disassemble_function() {
+    let out = run_rdb(&[
+        "disassemble use_vars",
+        "break countdown",
+        "continue",
+        "disassemble",
+        "quit",
+    ]);
+    let use_vars = symbol_addr("use_vars");
+    let countdown = breakpoint_addr("countdown");
+    let offset = hex(&countdown) - hex(&symbol_addr("countdown"));
+    assert!(out.contains(&format!("   {use_vars} <+0>:\tpush rbp\n")));
+    assert!(out.contains(">:\tmov rcx,[rbp-18h]\n"));
+    // the breakpoint's int3 isn't shown
+    assert!(out.contains(&format!("=> {countdown} <+{offset}>:\tcmp rdi,0\n")));
+    assert!(out
+        .lines()
+        .any(|line| line.starts_with("   0x") && line.ends_with(">:\tret")));
+}
+
+#[test]
+fn disassemble_next_line() {
+    let out = run_rdb(&[
+        "break countdown",
+        "continue",
+        "set disassemble-next-line on",
+        "show disassemble-next-line",
+        "continue",
+        "stepi",
+        "set disassemble-next-line off",
+        "stepi",
+        "quit",
+    ]);
+    assert!(out.contains("disassemble-next-line is on"));
+    // the breakpoint's int3 isn't shown
+    let countdown = hex(&breakpoint_addr("countdown"));
+    assert_eq!(
+        out.matches(&format!("=> 0x{countdown:x}:\tcmp rdi,0\n"))
+            .count(),
+        1
+    );
+    // after the 4-byte cmp
+    assert!(out.contains(&format!("=> 0x{:x}:\tje short ", countdown + 4)));
+    assert_eq!(out.matches("=> ").count(), 2);
+}
+
+#[test]
+fn rflags_decoded() {
+    // step over countdown's `cmp rdi,0` with n = 3
+    let out = run_rdb(&[
+        "break countdown",
+        "continue",
+        "stepi",
+        "register read rflags",
+        "info registers",
+        "quit",
+    ]);
+    // 3 - 0 is positive, nonzero, and has even parity, so the only
+    // arithmetic flag set is PF
+    let flags = |line: &str| {
+        let (val, names) = line.split_once(" [ ").unwrap();
+        (hex(val), names.trim_end_matches(" ]").to_string())
+    };
+    let (val, names) = flags(out.lines().find(|line| line.starts_with("0x")).unwrap());
+    assert_eq!(val & 0x8d5, 0x4, "{out}");
+    assert_eq!(names, "PF IF");
+    let info = out
+        .lines()
+        .find(|line| line.starts_with("rflags "))
+        .unwrap();
+    let (info_val, info_names) = flags(info.trim_start_matches("rflags").trim());
+    assert_eq!((info_val, info_names), (val, names));
+}
+
+#[test]
+fn register_aliases() {
+    let out = run_rdb(&[
+        "break countdown",
+        "continue",
+        "register read pc",
+        "register read rip",
+        "register read sp",
+        "print $sp",
+        "quit",
+    ]);
+    let regs: Vec<_> = out.lines().filter(|line| line.starts_with("0x")).collect();
+    // pc is rip, which is somewhere in the program rather than on the stack
+    assert_eq!(regs[0], regs[1]);
+    assert_ne!(regs[0], regs[2]);
+    let sp = out.lines().find(|line| line.starts_with("0x7ff"));
+    assert!(sp.is_some_and(|sp| out.contains(&format!("$1: $sp = {sp}\n"))));
+}
+
+#[test]
+fn register_formats() {
+    let out = run_rdb(&[
+        "break countdown",
+        "continue",
+        "register write rax 0xfffffffffffffffe",
+        "register read rax -f s",
+        "register read rax -f d",
+        "register read rdi --format b",
+        "register read rdi -f x",
+        "register dump -f s",
+        "quit",
+    ]);
+    assert!(out.contains("-2\n18446744073709551614\n0b11\n0x3\n"));
+    assert!(out.contains("rax      -2\n"));
+    assert!(out.contains("rdi      3\n"));
+}
+
+#[test]
+fn select_frame() {
+    let out = run_rdb(&[
+        "break countdown",
+        "continue",
+        "continue",
+        "continue",
+        "info args",
+        "up",
+        "info args",
+        "up",
+        "print n",
+        "down",
+        "info args",
+        "frame 1",
+        "info args",
+        "up",
+        "continue",
+        "info args",
+        "quit",
+    ]);
+    let args: Vec<&str> = out.lines().filter(|l| l.starts_with("n = ")).collect();
+    assert_eq!(args, vec!["n = 1", "n = 2", "n = 2", "n = 1", "n = 0"]);
+    assert!(out.contains("$1: n = 3\n"));
+    assert!(has_frame(&out, 3, "countdown at src/bin/test.rs:57:16"));
+}
+
+#[test]
+fn backtrace_limit() {
+    let out = run_rdb(&[
+        "break countdown",
+        "continue",
+        "continue",
+        "continue",
+        "bt 2",
+        "bt",
+        "quit",
+    ]);
+    let (limited, full) = out.split_once("(more stack frames follow...)\n").unwrap();
+    let frames = |out: &str| out.lines().filter(|l| l.starts_with('#')).count();
+    assert_eq!(frames(limited), 2);
+    assert!(has_frame(limited, 2, "countdown at src/bin/test.rs:57:16"));
+    assert_eq!(frames(full), 4);
+    assert!(has_frame(full, 4, "test::main at src/bin/test.rs:37:16"));
+}
+
+#[test]
+fn backtrace_inlined() {
+    let out = run_rdb_with("inline", &["break inline.rs:6", "continue", "bt", "quit"]);
+    let bp = breakpoint_addr_with("inline", "inline.rs:6");
+    assert!(out.contains(&format!(
+        "#1 {bp} in inline::double at src/bin/inline.rs:6:5 (inlined)\n"
+    )));
+    assert!(out.contains("           in inline::compute at src/bin/inline.rs:11:5\n"));
+    assert!(has_frame(
+        &out,
+        2,
+        "inline::main at src/bin/inline.rs:15:20"
+    ));
+}
+
+#[test]
+fn commands_after_exit_keep_session() {
+    // stepping a target that's gone is an error, not a crash
+    let out = run_rdb(&[
+        "continue",
+        "disassemble",
+        "stepi",
+        "finish",
+        "next",
+        "print 1",
+        "quit",
+    ]);
+    assert!(out.contains("program exited"));
+    assert!(out.contains("$1: 1 = 0x1"));
+}
+
+#[test]
+fn source_script() {
+    let script = "\
+# stop in the first call
+break countdown
+
+continue
+print n
+";
+    let out = run_rdb_script("countdown", "test", script);
+    assert!(out.contains(&format!("hit breakpoint {}", breakpoint_addr("countdown"))));
+    assert!(out.contains("$1: n = 3"));
+    // the session ends with the script, killing the target
+    assert!(!out.contains("program exited"));
+    let out = run_rdb_script("quit", "test", "quit\ncontinue\n");
+    assert!(!out.contains("Hello, world"));
+}
+
+#[test]
+fn ex_commands() {
+    let rdb = spawn_rdb_flags(&["-ex", "break countdown", "-ex", "continue"], "test");
+    let out = send_commands(rdb, &["print n", "quit"]);
+    assert!(out.contains(&format!("hit breakpoint {}", breakpoint_addr("countdown"))));
+    assert!(out.contains("$1: n = 3"));
+    // quitting in a command skips the prompt
+    let rdb = spawn_rdb_flags(&["-ex", "print 1", "-ex", "quit"], "test");
+    let out = send_commands(rdb, &["continue"]);
+    assert!(out.contains("$1: 1 = 0x1"));
+    assert!(!out.contains("Hello, world"));
+}
+
+#[test]
+fn repeat_last_command() {
+    let out = run_rdb(&[
+        "break countdown",
+        "continue",
+        "",
+        "register read rip",
+        "",
+        "continue",
+        "print n",
+        "quit",
+    ]);
+    // the second continue is a repeat, and the breakpoint isn't set twice
+    let hit = format!("hit breakpoint {}", breakpoint_addr("countdown"));
+    assert_eq!(out.matches(&hit).count(), 3);
+    // the repeated register read shows the same pc
+    let rips: Vec<&str> = out.lines().filter(|line| line.starts_with("0x")).collect();
+    assert_eq!(rips.len(), 2);
+    assert_eq!(rips[0], rips[1]);
+    assert!(out.contains("n = 1"));
+    assert!(!out.contains("set breakpoint 2"));
+}
+
+#[test]
+fn listsize() {
+    let out = run_rdb(&[
+        "show listsize",
+        "set listsize 0",
+        "show listsize",
+        "break countdown",
+        "continue",
+        "set listsize 5",
+        "continue",
+        "quit",
+    ]);
+    assert!(out.contains("listsize is 3\n"));
+    assert!(out.contains("listsize is 0\n"));
+    // with no context only the current line is shown
+    assert!(out.contains(":\n54 >      if n == 0 {\n"));
+    // five lines either side reaches the closing brace of countdown
+    assert!(out.contains("58        rest + 1\n59    }\n"));
+}
+
+#[test]
+fn list_source() {
+    let out = run_rdb(&[
+        "list countdown",
+        "",
+        "list test.rs:90",
+        "list",
+        "break countdown",
+        "continue",
+        "list",
+        "quit",
+    ]);
+    // centered on the first line of countdown's body
+    assert!(out.contains(
+        "51    \n52    #[no_mangle]\n53    fn countdown(n: u64) -> u64 {\n54        if n == 0 {\n"
+    ));
+    // repeating the listing continues it
+    assert!(out.contains("57        let rest = countdown(n - 1);\n58        rest + 1\n59    }\n60    \n61    #[allow(dead_code)]\n"));
+    // listing stops at the end of the file
+    assert!(out.contains("90    }\n"));
+    assert!(!out.contains("\n91  "));
+    // after a stop, list continues past the source shown for it
+    assert_eq!(out.matches("58        rest + 1\n").count(), 2);
+}
+
+#[test]
+fn color_source() {
+    let lines = ["break countdown", "continue", "quit"];
+    let out = send_commands(spawn_rdb_flags(&["--color", "always"], "test"), &lines);
+    // keywords are colored and the current line is bold
+    assert!(out.contains("\x1b[35mreturn\x1b[0m 0;"));
+    assert!(out.contains("\x1b[1m54 >      if n == 0 {\x1b[0m"));
+    // output that isn't to a terminal is plain by default
+    let out = run_rdb(&lines);
+    assert!(!out.contains('\x1b'));
+}
+
+#[test]
+fn substitute_path() {
+    // a copy of the source, marked so it's clear which one is shown
+    let dir = env::temp_dir().join(format!("rdb-{}-moved-src", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let src = fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/src/bin/test.rs")).unwrap();
+    let src = src.replace("if n == 0 {", "if n == 0 { // moved");
+    fs::write(dir.join("test.rs"), src).unwrap();
+    let subst = format!(
+        "set substitute-path {}/src/bin {}",
+        env!("CARGO_MANIFEST_DIR"),
+        dir.display()
+    );
+    let out = run_rdb(&[
+        &subst,
+        "show substitute-path",
+        "break countdown",
+        "continue",
+        "list",
+        "quit",
+    ]);
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(out.contains(&format!("/src/bin -> {}\n", dir.display())));
+    assert!(out.contains(&format!("{}/test.rs:\n", dir.display())));
+    assert!(out.contains("54 >      if n == 0 { // moved\n"));
+}
+
+#[test]
+fn source_from_other_dir() {
+    let dir = env::temp_dir().join(format!("rdb-{}-cwd", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let rdb = Command::new(exe_path("rdb"))
+        .arg(exe_path("test"))
+        .current_dir(&dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .expect("failed to launch debugger");
+    let out = send_commands(
+        rdb,
+        &["break test.rs:54", "continue", "list test.rs:6", "quit"],
+    );
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(out.contains("54 >      if n == 0 {\n"));
+    assert!(out.contains("6        let c = a + b;\n"));
+}
+
+#[test]
+fn finish_return_value() {
+    let delete = format!("delete {}", breakpoint_addr("countdown"));
+    let out = run_rdb(&[
+        "break countdown",
+        "continue",
+        &delete,
+        "finish",
+        "print $1",
+        "break use_vars",
+        "run",
+        "continue",
+        "finish",
+        "quit",
+    ]);
+    // countdown(3), called from main
+    assert!(returned_to(
+        &out,
+        "src/bin/test.rs, line 37 (in test::main)"
+    ));
+    assert!(out.contains("Value returned is $1 = 3\n"));
+    assert!(out.contains("$2: $1 = 3\n"));
+    // use_vars returns nothing
+    assert!(returned_to(
+        &out,
+        "src/bin/test.rs, line 34 (in test::main)"
+    ));
+    assert_eq!(out.matches("Value returned").count(), 1);
+}
+
+#[test]
+fn force_return() {
+    let out = run_rdb(&[
+        "break countdown",
+        "continue",
+        "return 7",
+        "register read rax",
+        "break use_struct",
+        "continue",
+        "print n",
+        "quit",
+    ]);
+    assert!(out.contains("warning: skipping the rest of the function"));
+    assert!(has_frame(&out, 1, "test::main at src/bin/test.rs:37:16"));
+    assert!(out.contains("0x7\n"));
+    // main carries on with the returned value
+    assert!(out.contains("n = 7"));
+
+    // returning from a caller pops the frames it called too
+    let out = run_rdb(&[
+        "break countdown",
+        "continue",
+        "continue",
+        "up",
+        "return",
+        "backtrace",
+        "quit",
+    ]);
+    // the new location, and then a backtrace with only main
+    let main_frames = out
+        .lines()
+        .filter(|line| {
+            line.starts_with("#1 0x") && line.ends_with(" in test::main at src/bin/test.rs:37:16")
+        })
+        .count();
+    assert_eq!(main_frames, 2);
+    assert!(!out.contains("#3 "));
+
+    // the caller gets back the callee-saved registers the popped frame saved
+    let out = run_rdb_with(
+        "callee_saved",
+        &["break stop_here", "continue", "up", "return", "continue"],
+    );
+    assert!(out.contains("r12 = 0x12, r13 = 0x13, r14 = 0x14, r15 = 0x15"));
+}